@@ -0,0 +1,84 @@
+use reqwest::{
+    blocking::{Client, RequestBuilder, Response},
+    header::{self, HeaderMap, HeaderValue},
+};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::TSFSContext;
+
+pub const MSGPACK: &str = "application/msgpack";
+pub const JSON: &str = "application/json";
+
+/// Build the HTTP client used to talk with the server
+///
+/// Structured messages are sent as MessagePack, MessagePack responses are preferred
+/// but JSON is accepted as a fallback.
+pub fn http_client(ctx: &TSFSContext) -> Client {
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        header::ACCEPT,
+        HeaderValue::from_static("application/msgpack, application/json;q=0.5"),
+    );
+
+    Client::builder()
+        .danger_accept_invalid_certs(ctx.accept_invalid_cert)
+        .default_headers(headers)
+        .build()
+        .unwrap()
+}
+
+pub trait RequestBuilderExt {
+    /// Send a structured message as a MessagePack body, used in place of reqwest `json`
+    fn msg<T: Serialize + ?Sized>(self, body: &T) -> Self;
+}
+
+impl RequestBuilderExt for RequestBuilder {
+    fn msg<T: Serialize + ?Sized>(self, body: &T) -> Self {
+        self.header(header::CONTENT_TYPE, MSGPACK)
+            .body(rmp_serde::to_vec_named(body).unwrap())
+    }
+}
+
+pub trait ResponseExt {
+    /// Decode a structured message according to the response `Content-Type`
+    fn msg<T: DeserializeOwned>(self) -> Result<T, String>;
+}
+
+impl ResponseExt for Response {
+    fn msg<T: DeserializeOwned>(self) -> Result<T, String> {
+        let is_json = self
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|content_type| content_type.to_str().ok())
+            .map_or(false, |content_type| content_type.starts_with(JSON));
+
+        let body = self.bytes().map_err(|e| e.to_string())?;
+
+        if is_json {
+            serde_json::from_slice(&body).map_err(|e| e.to_string())
+        } else {
+            rmp_serde::from_slice(&body).map_err(|e| e.to_string())
+        }
+    }
+}
+
+/// Serialize a pair of byte buffers as raw bytes, like `serde_bytes` does for a single one
+pub mod bytes_pair {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use serde_bytes::{ByteBuf, Bytes};
+
+    pub fn serialize<S: Serializer>(
+        pair: &(Vec<u8>, Vec<u8>),
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        (Bytes::new(&pair.0), Bytes::new(&pair.1)).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<(Vec<u8>, Vec<u8>), D::Error> {
+        let (first, second) = <(ByteBuf, ByteBuf)>::deserialize(deserializer)?;
+
+        Ok((first.into_vec(), second.into_vec()))
+    }
+}
@@ -0,0 +1,156 @@
+use clap::Parser;
+use colored::Colorize;
+use serde::Serialize;
+
+use crate::{
+    codec::{http_client, RequestBuilderExt},
+    files, log,
+    models::{Permission, SigningKeys},
+    TSFSContext,
+};
+
+use super::{
+    encrypt_key_for, encrypt_signing_keys_for, invites::find_invitation, resolve_folder,
+    sign_entry_for, update_keyring, Command,
+};
+
+#[derive(Serialize)]
+pub struct AcceptInvitationRequest {
+    file_uid: String,
+    keyring_id: i32,
+    #[serde(with = "serde_bytes")]
+    encrypted_key: Vec<u8>,
+    signing_keys: SigningKeys,
+    /// Signature of the entry, when accepted in root
+    #[serde(with = "serde_bytes")]
+    entry_signature: Option<Vec<u8>>,
+    /// Challenge of the destination folder signed with its write key
+    #[serde(with = "serde_bytes")]
+    signature: Option<Vec<u8>>,
+}
+
+/// Accept a share, the file is added to the given folder
+#[derive(Parser, Debug)]
+pub struct AcceptArgs {
+    name: String,
+    /// Folder to put the shared file in, the current folder by default
+    folder: Option<String>,
+}
+
+pub struct AcceptCommand;
+
+impl Command for AcceptCommand {
+    fn execute(&self, args: &Vec<String>, ctx: &mut TSFSContext) {
+        match AcceptArgs::try_parse_from(args) {
+            Ok(args) => {
+                let Some(keyring_tree) = &ctx.keyring_tree else {
+                    log::error("Missing Keyring Tree, not logged ?");
+                    return;
+                };
+
+                let path = args.folder.as_deref().unwrap_or(".");
+                let Some(destination) = resolve_folder(ctx, path) else {
+                    log::error(&format!("Can't find folder {}", path.red()));
+                    return;
+                };
+
+                let destination_folder = destination
+                    .last()
+                    .map(|folder_id| keyring_tree.get_file(folder_id).unwrap());
+                let destination_keyring = match &destination_folder {
+                    Some(folder) => folder.file.keyring.as_ref().unwrap(),
+                    None => keyring_tree,
+                };
+
+                if destination_keyring.get_file_by_name(&args.name).is_some() {
+                    log::error(&format!("A file named {} already exists", args.name.red()));
+                    return;
+                }
+
+                let Some(invitation) = find_invitation(ctx, &args.name) else {
+                    return;
+                };
+
+                // Encrypt the file key for the destination keyring
+                let (keyring_id, encrypted_key) =
+                    encrypt_key_for(ctx, destination_folder.as_ref(), &invitation.key.key);
+                let signing_keys = encrypt_signing_keys_for(
+                    ctx,
+                    destination_folder.as_ref(),
+                    &invitation.key.signing_keys,
+                );
+                // Once accepted, the entry is signed by the user at the level granted by the sender
+                let entry_signature = sign_entry_for(
+                    ctx,
+                    destination_folder.as_ref(),
+                    &invitation.key.file.id,
+                    invitation.key.permission,
+                    &encrypted_key,
+                );
+
+                let client = http_client(ctx);
+                let signature = files::sign_challenge(
+                    ctx,
+                    &client,
+                    destination_folder.as_ref(),
+                    Permission::Write,
+                );
+
+                let res = client
+                    .post(format!(
+                        "{}:{}/invitations/accept",
+                        ctx.endpoint_url.as_ref().unwrap(),
+                        ctx.endpoint_port
+                    ))
+                    .header(
+                        "Authorization",
+                        format!("Bearer {}", ctx.session_token.as_ref().unwrap()),
+                    )
+                    .msg(&AcceptInvitationRequest {
+                        file_uid: invitation.key.file.id,
+                        keyring_id,
+                        encrypted_key,
+                        signing_keys,
+                        entry_signature,
+                        signature,
+                    })
+                    .send();
+
+                match res {
+                    Ok(res) => match res.error_for_status() {
+                        Ok(_) => {
+                            log::info(&format!(
+                                "{} shared by {} accepted",
+                                args.name.green(),
+                                invitation.sender.green()
+                            ));
+
+                            update_keyring(ctx);
+                        }
+
+                        Err(e) => {
+                            let status = e.status().unwrap();
+
+                            log::error(&format!(
+                                "Can't accept invitation: {}",
+                                status.to_string().red()
+                            ));
+                        }
+                    },
+
+                    Err(e) => {
+                        log::error(&format!("Error on accept: {}", e.to_string().red()));
+                    }
+                }
+            }
+
+            Err(e) => {
+                println!("{e}");
+            }
+        }
+    }
+
+    fn description(&self) -> String {
+        "Accept a share, adding the file to the current or given folder".into()
+    }
+}
@@ -0,0 +1,100 @@
+use std::time::{Duration, UNIX_EPOCH};
+
+use chrono::prelude::*;
+use clap::Parser;
+use colored::Colorize;
+
+use crate::{
+    codec::http_client,
+    files, log,
+    models::{Grant, Permission},
+    TSFSContext,
+};
+
+use super::{find_in_current_folder, Command};
+
+/// List the users with access to a file or folder of the current folder
+#[derive(Parser, Debug)]
+pub struct AccessArgs {
+    name: String,
+}
+
+pub struct AccessCommand;
+
+impl Command for AccessCommand {
+    fn execute(&self, args: &Vec<String>, ctx: &mut TSFSContext) {
+        match AccessArgs::try_parse_from(args) {
+            Ok(args) => {
+                let Some(keyring_tree) = &ctx.keyring_tree else {
+                    log::error("Missing Keyring Tree, not logged ?");
+                    return;
+                };
+
+                let Some(file) = find_in_current_folder(ctx, &args.name) else {
+                    log::error(&format!("Can't find file {}", args.name.red()));
+                    return;
+                };
+
+                let client = http_client(ctx);
+
+                let Some(access) = files::get_access(ctx, &client, &file.file.id) else {
+                    return;
+                };
+
+                log::info(&format!("Access to {}:", args.name.green()));
+                print_grants(&access.users, 2);
+
+                for folder in access.folders {
+                    // Folders outside the user tree have unknown names
+                    match keyring_tree.get_file(&folder.id) {
+                        Some(folder_file) => {
+                            println!("  Through folder {}:", folder_file.file.name.cyan())
+                        }
+                        None => println!("  Through a folder you can't see:"),
+                    }
+
+                    print_grants(&folder.users, 4);
+                }
+            }
+
+            Err(e) => {
+                println!("{e}");
+            }
+        }
+    }
+
+    fn description(&self) -> String {
+        "List the users with access to a file or folder".into()
+    }
+}
+
+fn print_grants(grants: &[Grant], indent: usize) {
+    if grants.is_empty() {
+        println!("{:indent$}no direct grant", "");
+    }
+
+    for grant in grants {
+        let name = if grant.group {
+            format!("{} (group)", grant.username)
+        } else {
+            grant.username.clone()
+        };
+
+        let permission = match grant.permission {
+            Permission::Read => "read".yellow(),
+            Permission::Write => "write".cyan(),
+            Permission::Owner => "owner".green(),
+        };
+
+        match grant.expires_at {
+            Some(expires_at) => {
+                let expires_at =
+                    DateTime::<Local>::from(UNIX_EPOCH + Duration::from_millis(expires_at as u64))
+                        .format("%Y-%m-%d %H:%M:%S");
+
+                println!("{:indent$}{} {} until {}", "", name, permission, expires_at);
+            }
+            None => println!("{:indent$}{} {}", "", name, permission),
+        }
+    }
+}
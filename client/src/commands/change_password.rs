@@ -11,7 +11,10 @@ use opaque_ke::{
 use rand::rngs::OsRng;
 use serde::{Deserialize, Serialize};
 
-use crate::{log, DefaultCS, TSFSContext};
+use crate::{
+    codec::{http_client, RequestBuilderExt, ResponseExt},
+    log, DefaultCS, TSFSContext,
+};
 
 use super::Command;
 
@@ -20,6 +23,7 @@ pub struct ChangePasswordCommand;
 #[derive(Serialize, Deserialize, Debug)]
 pub struct PasswordChangeFinishRequest {
     registration_upload: RegistrationUpload<DefaultCS>,
+    #[serde(with = "serde_bytes")]
     user_new_private_key: Vec<u8>,
 }
 
@@ -40,10 +44,7 @@ impl Command for ChangePasswordCommand {
                 ClientRegistration::<DefaultCS>::start(&mut client_rng, password.as_bytes())
                     .unwrap();
 
-            let client = reqwest::blocking::Client::builder()
-                .danger_accept_invalid_certs(ctx.accept_invalid_cert)
-                .build()
-                .unwrap();
+            let client = http_client(ctx);
 
             // Send RegistrationRequest to the Server
             let res = client
@@ -55,7 +56,7 @@ impl Command for ChangePasswordCommand {
                     "Authorization",
                     format!("Bearer {}", ctx.session_token.as_ref().unwrap()),
                 )
-                .json(&client_registration_start_result.message)
+                .msg(&client_registration_start_result.message)
                 .send();
 
             if res.is_err() {
@@ -74,7 +75,7 @@ impl Command for ChangePasswordCommand {
                             &mut client_rng,
                             password.as_bytes(),
                             // Get RegistrationResponse from Server
-                            res.json::<RegistrationResponse<DefaultCS>>().unwrap(),
+                            res.msg::<RegistrationResponse<DefaultCS>>().unwrap(),
                             ClientRegistrationFinishParameters::new(
                                 Identifiers {
                                     client: Some(ctx.username.as_ref().unwrap().as_bytes()),
@@ -121,7 +122,7 @@ impl Command for ChangePasswordCommand {
                             "Authorization",
                             format!("Bearer {}", ctx.session_token.as_ref().unwrap()),
                         )
-                        .json(&PasswordChangeFinishRequest {
+                        .msg(&PasswordChangeFinishRequest {
                             registration_upload: client_registration_finish_result.message,
                             user_new_private_key: private_key_cipher,
                         })
@@ -0,0 +1,123 @@
+use clap::Parser;
+use colored::Colorize;
+use reqwest::blocking::Client;
+
+use crate::{codec::http_client, files, log, models::KeyWithFile, TSFSContext};
+
+use super::{find_in_current_folder, resolve_folder, update_keyring, Command};
+
+/// Copy a file or folder of the current folder
+#[derive(Parser, Debug)]
+pub struct CpArgs {
+    name: String,
+    /// Destination folder, or path of the copy to give it another name
+    destination: String,
+    /// Copy a folder and everything inside
+    #[arg(short, long)]
+    recursive: bool,
+}
+
+pub struct CpCommand;
+
+impl Command for CpCommand {
+    fn execute(&self, args: &Vec<String>, ctx: &mut TSFSContext) {
+        match CpArgs::try_parse_from(args) {
+            Ok(args) => {
+                let Some(keyring_tree) = &ctx.keyring_tree else {
+                    log::error("Missing Keyring Tree, not logged ?");
+                    return;
+                };
+
+                let Some(file) = find_in_current_folder(ctx, &args.name) else {
+                    log::error(&format!("Can't find file {}", args.name.red()));
+                    return;
+                };
+
+                if file.file.is_folder() && !args.recursive {
+                    log::error(&format!(
+                        "{} is a folder, use {} to copy it",
+                        args.name.red(),
+                        "--recursive".green()
+                    ));
+                    return;
+                }
+
+                // Copy into the destination folder, or as the last part of the path
+                let destination = if let Some(folders) = resolve_folder(ctx, &args.destination) {
+                    Some((folders, args.name.clone()))
+                } else {
+                    let (parent, name) = match args.destination.rsplit_once('/') {
+                        Some(("", name)) => ("/", name),
+                        Some((parent, name)) => (parent, name),
+                        None => (".", args.destination.as_str()),
+                    };
+
+                    resolve_folder(ctx, parent).map(|folders| (folders, name.to_string()))
+                };
+
+                let Some((destination, name)) = destination else {
+                    log::error(&format!("Can't find folder {}", args.destination.red()));
+                    return;
+                };
+
+                if destination.contains(&file.file.id) {
+                    log::error("Can't copy a folder inside itself");
+                    return;
+                }
+
+                let destination_folder = destination
+                    .last()
+                    .map(|folder_id| keyring_tree.get_file(folder_id).unwrap());
+                let destination_keyring = match &destination_folder {
+                    Some(folder) => folder.file.keyring.as_ref().unwrap(),
+                    None => keyring_tree,
+                };
+
+                if destination_keyring.get_file_by_name(&name).is_some() {
+                    log::error(&format!("A file named {} already exists", name.red()));
+                    return;
+                }
+
+                let client = http_client(ctx);
+
+                if copy_entry(ctx, &client, &file, destination_folder.as_ref(), &name) {
+                    log::info(&format!("{} copied to {}", args.name.green(), name.green()));
+                }
+
+                // Refresh even on failure, part of a folder may have been copied
+                update_keyring(ctx);
+            }
+
+            Err(e) => {
+                println!("{e}");
+            }
+        }
+    }
+
+    fn description(&self) -> String {
+        "Copy a file or folder of the current folder".into()
+    }
+}
+
+/// Copy a file to `destination` (root if None), or create a matching folder
+/// and copy everything inside if it's a folder
+fn copy_entry(
+    ctx: &TSFSContext,
+    client: &Client,
+    file: &KeyWithFile,
+    destination: Option<&KeyWithFile>,
+    name: &str,
+) -> bool {
+    let Some(keyring) = &file.file.keyring else {
+        return files::copy_file(ctx, client, file, destination, name).is_some();
+    };
+
+    let Some(folder) = files::create_folder(ctx, client, destination, name) else {
+        return false;
+    };
+
+    keyring
+        .keys
+        .iter()
+        .all(|child| copy_entry(ctx, client, child, Some(&folder), &child.file.name))
+}
@@ -0,0 +1,90 @@
+use clap::Parser;
+use colored::Colorize;
+use serde::Serialize;
+
+use crate::{
+    codec::{http_client, RequestBuilderExt},
+    log, TSFSContext,
+};
+
+use super::{invites::find_invitation, Command};
+
+#[derive(Serialize)]
+pub struct DeclineInvitationRequest {
+    file_uid: String,
+}
+
+/// Decline a share
+#[derive(Parser, Debug)]
+pub struct DeclineArgs {
+    name: String,
+}
+
+pub struct DeclineCommand;
+
+impl Command for DeclineCommand {
+    fn execute(&self, args: &Vec<String>, ctx: &mut TSFSContext) {
+        match DeclineArgs::try_parse_from(args) {
+            Ok(args) => {
+                if ctx.keyring_tree.is_none() {
+                    log::error("Missing Keyring Tree, not logged ?");
+                    return;
+                }
+
+                let Some(invitation) = find_invitation(ctx, &args.name) else {
+                    return;
+                };
+
+                let client = http_client(ctx);
+
+                let res = client
+                    .post(format!(
+                        "{}:{}/invitations/decline",
+                        ctx.endpoint_url.as_ref().unwrap(),
+                        ctx.endpoint_port
+                    ))
+                    .header(
+                        "Authorization",
+                        format!("Bearer {}", ctx.session_token.as_ref().unwrap()),
+                    )
+                    .msg(&DeclineInvitationRequest {
+                        file_uid: invitation.key.file.id,
+                    })
+                    .send();
+
+                match res {
+                    Ok(res) => match res.error_for_status() {
+                        Ok(_) => {
+                            log::info(&format!(
+                                "{} shared by {} declined",
+                                args.name.green(),
+                                invitation.sender.green()
+                            ));
+                        }
+
+                        Err(e) => {
+                            let status = e.status().unwrap();
+
+                            log::error(&format!(
+                                "Can't decline invitation: {}",
+                                status.to_string().red()
+                            ));
+                        }
+                    },
+
+                    Err(e) => {
+                        log::error(&format!("Error on decline: {}", e.to_string().red()));
+                    }
+                }
+            }
+
+            Err(e) => {
+                println!("{e}");
+            }
+        }
+    }
+
+    fn description(&self) -> String {
+        "Decline a share".into()
+    }
+}
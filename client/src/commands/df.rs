@@ -0,0 +1,96 @@
+use clap::Parser;
+use colored::Colorize;
+use serde::Deserialize;
+
+use crate::{
+    codec::{http_client, ResponseExt},
+    log, TSFSContext,
+};
+
+use super::{format_bytes, Command};
+
+#[derive(Deserialize, Debug)]
+pub struct QuotaResponse {
+    used: i64,
+    limit: i64,
+}
+
+/// Display the storage usage and quota
+#[derive(Parser, Debug)]
+pub struct DfArgs {}
+
+pub struct DfCommand;
+
+impl Command for DfCommand {
+    fn execute(&self, args: &Vec<String>, ctx: &mut TSFSContext) {
+        match DfArgs::try_parse_from(args) {
+            Ok(_) => {
+                if ctx.session_token.is_none() {
+                    log::info("Not connected");
+                    return;
+                }
+
+                let client = http_client(ctx);
+
+                let res = client
+                    .get(format!(
+                        "{}:{}/quota",
+                        ctx.endpoint_url.as_ref().unwrap(),
+                        ctx.endpoint_port
+                    ))
+                    .header(
+                        "Authorization",
+                        format!("Bearer {}", ctx.session_token.as_ref().unwrap()),
+                    )
+                    .send();
+
+                match res {
+                    Ok(res) => match res.error_for_status() {
+                        Ok(res) => {
+                            let quota = res.msg::<QuotaResponse>().unwrap();
+                            let percent = if quota.limit > 0 {
+                                quota.used as f64 * 100.0 / quota.limit as f64
+                            } else {
+                                100.0
+                            };
+
+                            let usage = format!("{:.1}%", percent);
+                            println!(
+                                "Used {} of {} ({})",
+                                format_bytes(quota.used).cyan(),
+                                format_bytes(quota.limit).cyan(),
+                                if percent >= 90.0 {
+                                    usage.red()
+                                } else {
+                                    usage.green()
+                                }
+                            );
+                            println!(
+                                "Available: {}",
+                                format_bytes((quota.limit - quota.used).max(0)).green()
+                            );
+                        }
+
+                        Err(e) => {
+                            let status = e.status().unwrap();
+
+                            log::error(&format!("Can't get quota: {}", status.to_string().red()));
+                        }
+                    },
+
+                    Err(e) => {
+                        log::error(&format!("Error on df: {}", e.to_string().red()));
+                    }
+                }
+            }
+
+            Err(e) => {
+                println!("{e}");
+            }
+        }
+    }
+
+    fn description(&self) -> String {
+        "Display the storage usage and quota".into()
+    }
+}
@@ -1,24 +1,18 @@
 use clap::Parser;
 use colored::Colorize;
-use serde::Serialize;
-use std::{
-    fs::{self, create_dir_all},
-    io::Write,
-};
+use std::fs::{self, create_dir_all};
 
-use crate::{log, TSFSContext};
+use crate::{codec::http_client, files, log, TSFSContext};
 
 use super::{download_file, Command};
 
-#[derive(Serialize)]
-pub struct DownloadFileRequest {
-    file_uid: String,
-}
-
 /// Download a file
 #[derive(Parser, Debug)]
 pub struct DownloadArgs {
     name: String,
+    /// Download a previous version instead, saved as `<name>.v<version>`
+    #[arg(short, long)]
+    version: Option<i32>,
 }
 
 pub struct DownloadCommand;
@@ -40,27 +34,69 @@ impl Command for DownloadCommand {
                     };
 
                     if let Some(file) = current_keyring.get_file_by_name(&args.name) {
-                        if let Some(file) = download_file(ctx, file) {
-                            let dir_path = &format!(
-                                "{}{}",
-                                ctx.local_folder.as_ref().unwrap(),
-                                ctx.get_path()
-                            );
-
-                            // Create all dirs
-                            create_dir_all(dir_path).expect("Can't create directories");
-
-                            // Create file
-                            let file_path = format!("{}{}", dir_path, file.name);
-
-                            log::info(&format!("Creating file at {}", file_path.green()));
-                            let mut local_file =
-                                fs::File::create(&file_path).expect("Can't create file");
-                            local_file
-                                .write_all(&file.data.unwrap())
-                                .expect("Can't write to file");
+                        if file.file.is_folder() {
+                            log::error("Can't download a folder");
+                            return;
+                        }
+
+                        let dir_path = &format!(
+                            "{}{}",
+                            ctx.local_folder.as_ref().unwrap(),
+                            ctx.get_path()
+                        );
+
+                        // Create all dirs
+                        create_dir_all(dir_path).expect("Can't create directories");
+
+                        // Get the requested version
+                        let client = http_client(ctx);
+                        let version = if let Some(version) = args.version {
+                            let Some(versions) = files::get_versions(ctx, &client, &file.file.id)
+                            else {
+                                return;
+                            };
+
+                            let Some(version) = versions.into_iter().find(|v| v.version == version)
+                            else {
+                                log::error(&format!("Can't find version {}", version));
+                                return;
+                            };
+
+                            Some(version)
+                        } else {
+                            None
+                        };
+
+                        // Create file
+                        let file_path = if let Some(version) = &version {
+                            format!("{}{}.v{}", dir_path, file.file.name, version.version)
+                        } else {
+                            format!("{}{}", dir_path, file.file.name)
+                        };
+
+                        log::info(&format!("Creating file at {}", file_path.green()));
+                        let mut local_file =
+                            fs::File::create(&file_path).expect("Can't create file");
+
+                        // Content is decrypted and written chunk by chunk
+                        let success = if let Some(version) = version {
+                            files::download_version_stream(
+                                ctx,
+                                &client,
+                                &version,
+                                &file.key,
+                                &mut local_file,
+                            )
+                        } else {
+                            download_file(ctx, file, &mut local_file).is_some()
+                        };
 
+                        if success {
                             log::info(&format!("File downloaded at {}", file_path.green()));
+                        } else {
+                            // Don't leave a partial file behind
+                            drop(local_file);
+                            fs::remove_file(&file_path).ok();
                         }
                     } else {
                         log::error(&format!("Can't find file {}", args.name.red()));
@@ -0,0 +1,127 @@
+use std::{fs, path::Path};
+
+use base64::prelude::*;
+use clap::Parser;
+use colored::Colorize;
+
+use crate::{
+    codec::{http_client, ResponseExt},
+    crypto, files, log,
+    models::PublicFile,
+    TSFSContext,
+};
+
+use super::Command;
+
+/// Download and decrypt a file from a public link, no account needed
+#[derive(Parser, Debug)]
+pub struct FetchArgs {
+    /// Link created with `link`, the key of the file is after the #
+    url: String,
+}
+
+pub struct FetchCommand;
+
+impl Command for FetchCommand {
+    fn execute(&self, args: &Vec<String>, ctx: &mut TSFSContext) {
+        match FetchArgs::try_parse_from(args) {
+            Ok(args) => {
+                let Some((link_url, fragment)) = args.url.split_once('#') else {
+                    log::error("Missing the key of the file at the end of the link");
+                    return;
+                };
+
+                let key = match BASE64_URL_SAFE_NO_PAD.decode(fragment) {
+                    Ok(key) if key.len() == 32 => key,
+                    _ => {
+                        log::error("Invalid key at the end of the link");
+                        return;
+                    }
+                };
+
+                let client = http_client(ctx);
+
+                let res = client.get(link_url).send();
+
+                let public_file = match res {
+                    Ok(res) => match res.error_for_status() {
+                        Ok(res) => res.msg::<PublicFile>().unwrap(),
+
+                        Err(e) => {
+                            let status = e.status().unwrap();
+
+                            if status == reqwest::StatusCode::GONE {
+                                log::error("The link expired or has no download left");
+                            } else if status == reqwest::StatusCode::SERVICE_UNAVAILABLE {
+                                log::error("The server has too many downloads, retry later");
+                            } else {
+                                log::error(&format!(
+                                    "Can't fetch file: {}",
+                                    status.to_string().red()
+                                ));
+                            }
+                            return;
+                        }
+                    },
+
+                    Err(e) => {
+                        log::error(&format!("Error on fetch: {}", e.to_string().red()));
+                        return;
+                    }
+                };
+
+                let name = BASE64_STANDARD
+                    .decode(&public_file.name)
+                    .ok()
+                    .and_then(|raw_name| crypto::chacha_decrypt(&raw_name, &key).ok())
+                    .and_then(|name| String::from_utf8(name).ok());
+
+                let Some(name) = name else {
+                    log::error("Can't decrypt the file, the key of the link is wrong");
+                    return;
+                };
+
+                // The name comes from the link, keep it from escaping the download folder
+                let Some(name) = Path::new(&name).file_name() else {
+                    log::error(&format!("Invalid file name {}", name.red()));
+                    return;
+                };
+
+                let dir_path = ctx.local_folder.clone().unwrap_or(".".to_string());
+                let file_path = Path::new(&dir_path).join(name);
+
+                log::info(&format!(
+                    "Creating file at {}",
+                    file_path.display().to_string().green()
+                ));
+                let mut local_file = fs::File::create(&file_path).expect("Can't create file");
+
+                if files::download_public_stream(
+                    ctx,
+                    &client,
+                    link_url,
+                    &public_file,
+                    &key,
+                    &mut local_file,
+                ) {
+                    log::info(&format!(
+                        "File downloaded at {}",
+                        file_path.display().to_string().green()
+                    ));
+                } else {
+                    // Don't leave a partial file behind
+                    drop(local_file);
+                    fs::remove_file(&file_path).ok();
+                }
+            }
+
+            Err(e) => {
+                println!("{e}");
+            }
+        }
+    }
+
+    fn description(&self) -> String {
+        "Download a file from a public link".into()
+    }
+}
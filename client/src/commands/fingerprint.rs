@@ -0,0 +1,79 @@
+use clap::Parser;
+use colored::Colorize;
+
+use crate::{codec::http_client, crypto, files, log, trust::TrustStore, TSFSContext};
+
+use super::Command;
+
+/// Show the fingerprint of the public key of a user, to compare it out of band
+#[derive(Parser, Debug)]
+pub struct FingerprintArgs {
+    /// User to check, yours if omitted
+    username: Option<String>,
+}
+
+pub struct FingerprintCommand;
+
+impl Command for FingerprintCommand {
+    fn execute(&self, args: &Vec<String>, ctx: &mut TSFSContext) {
+        match FingerprintArgs::try_parse_from(args) {
+            Ok(args) => {
+                if ctx.session_token.is_none() {
+                    log::error("Not connected");
+                    return;
+                }
+
+                let Some(username) = args.username else {
+                    println!(
+                        "Your fingerprint: {}",
+                        crypto::fingerprint(ctx.public_key.as_ref().unwrap()).cyan()
+                    );
+                    return;
+                };
+
+                let client = http_client(ctx);
+
+                let Some(pubkey) = files::fetch_user_public_key(ctx, &client, &username) else {
+                    return;
+                };
+
+                let fingerprint = crypto::fingerprint(&pubkey);
+                println!("Key of {}: {}", username.green(), fingerprint.cyan());
+
+                match TrustStore::load().get(ctx, &username) {
+                    Some(pinned) if pinned.fingerprint == fingerprint && pinned.verified => {
+                        log::info("Pinned and verified");
+                    }
+
+                    Some(pinned) if pinned.fingerprint == fingerprint => {
+                        log::info(&format!(
+                            "Pinned, ask {} for his fingerprint and use {} once it matches",
+                            username.green(),
+                            "verify <user> <fingerprint>".green()
+                        ));
+                    }
+
+                    Some(pinned) => {
+                        log::warning(&format!(
+                            "It differs from the pinned key {}, don't share with {} until you verify it",
+                            pinned.fingerprint.red(),
+                            username.red()
+                        ));
+                    }
+
+                    None => {
+                        log::info("Not pinned yet, it will be on the first share");
+                    }
+                }
+            }
+
+            Err(e) => {
+                println!("{e}");
+            }
+        }
+    }
+
+    fn description(&self) -> String {
+        "Show the fingerprint of the public key of a user".into()
+    }
+}
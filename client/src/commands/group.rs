@@ -0,0 +1,370 @@
+use clap::{Parser, Subcommand};
+use colored::Colorize;
+use reqwest::blocking::Client;
+use serde::Serialize;
+
+use crate::{
+    codec::{http_client, RequestBuilderExt},
+    crypto::{self, EntryKeyring},
+    files, log,
+    models::{Group, SigningKeys},
+    trust, TSFSContext,
+};
+
+use super::{update_keyring, Command};
+
+#[derive(Serialize)]
+pub struct CreateGroupRequest {
+    name: String,
+    /// RSA public key of the group
+    #[serde(with = "serde_bytes")]
+    pub_key: Vec<u8>,
+    /// Group private key, wrapped with the user public key
+    #[serde(with = "serde_bytes")]
+    priv_key: Vec<u8>,
+}
+
+#[derive(Serialize)]
+pub struct AddMemberRequest {
+    group: String,
+    username: String,
+    /// Group private key, wrapped with the new member public key
+    #[serde(with = "serde_bytes")]
+    priv_key: Vec<u8>,
+}
+
+#[derive(Serialize)]
+pub struct RemoveMemberRequest {
+    group: String,
+    username: String,
+    /// New RSA public key of the group
+    #[serde(with = "serde_bytes")]
+    pub_key: Vec<u8>,
+    /// New group private key of each remaining member
+    members: Vec<RenewedMember>,
+    /// Keys of the group keyring, encrypted with the new group public key
+    keys: Vec<RenewedGroupKey>,
+}
+
+#[derive(Serialize)]
+pub struct RenewedMember {
+    username: String,
+    #[serde(with = "serde_bytes")]
+    priv_key: Vec<u8>,
+}
+
+#[derive(Serialize)]
+pub struct RenewedGroupKey {
+    file_uid: String,
+    #[serde(with = "serde_bytes")]
+    encrypted_key: Vec<u8>,
+    signing_keys: SigningKeys,
+    /// Signature of the entry, the members check it comes from the owner
+    #[serde(with = "serde_bytes")]
+    entry_signature: Option<Vec<u8>>,
+}
+
+/// Manage the groups of users, share a file with a group to share it with all its members
+#[derive(Parser, Debug)]
+pub struct GroupArgs {
+    #[command(subcommand)]
+    action: GroupAction,
+}
+
+#[derive(Subcommand, Debug)]
+enum GroupAction {
+    /// List your groups and their members
+    Ls,
+    /// Create a group, you manage its members
+    Create { name: String },
+    /// Add a user to a group you manage
+    Add { group: String, username: String },
+    /// Remove a user from a group you manage, the group keys are rotated
+    Remove { group: String, username: String },
+}
+
+pub struct GroupCommand;
+
+impl Command for GroupCommand {
+    fn execute(&self, args: &Vec<String>, ctx: &mut TSFSContext) {
+        match GroupArgs::try_parse_from(args) {
+            Ok(args) => {
+                if ctx.keyring_tree.is_none() {
+                    log::error("Missing Keyring Tree, not logged ?");
+                    return;
+                }
+
+                match args.action {
+                    GroupAction::Ls => list_groups(ctx),
+                    GroupAction::Create { name } => create_group(ctx, &name),
+                    GroupAction::Add { group, username } => add_member(ctx, &group, &username),
+                    GroupAction::Remove { group, username } => {
+                        remove_member(ctx, &group, &username)
+                    }
+                }
+            }
+
+            Err(e) => {
+                println!("{e}");
+            }
+        }
+    }
+
+    fn description(&self) -> String {
+        "List, create and manage the members of groups of users".into()
+    }
+}
+
+fn list_groups(ctx: &TSFSContext) {
+    let client = http_client(ctx);
+
+    let Some(groups) = files::get_groups(ctx, &client) else {
+        return;
+    };
+
+    if groups.is_empty() {
+        log::info("You are not a member of any group");
+        return;
+    }
+
+    for group in groups {
+        println!(
+            "{} (owner {}, {} shared files)",
+            group.name.cyan(),
+            group.owner.green(),
+            group.keyring.keys.len()
+        );
+
+        for member in group.members {
+            println!("  {}", member);
+        }
+    }
+}
+
+fn create_group(ctx: &TSFSContext, name: &str) {
+    log::info("Generating RSA Keypair...");
+    let (priv_key, pub_key) = crypto::generate_rsa_keypair();
+
+    // The creator is the first member, he holds the group private key
+    let wrapped_key = crypto::rsa_wrap(&priv_key, ctx.public_key.as_ref().unwrap()).unwrap();
+
+    let client = http_client(ctx);
+
+    let res = client
+        .post(format!(
+            "{}:{}/groups/create",
+            ctx.endpoint_url.as_ref().unwrap(),
+            ctx.endpoint_port
+        ))
+        .header(
+            "Authorization",
+            format!("Bearer {}", ctx.session_token.as_ref().unwrap()),
+        )
+        .msg(&CreateGroupRequest {
+            name: name.to_string(),
+            pub_key: pub_key.clone(),
+            priv_key: wrapped_key,
+        })
+        .send();
+
+    match res {
+        Ok(res) => match res.error_for_status() {
+            Ok(_) => {
+                // The creator knows the group key, no need to check it
+                trust::pin_key(ctx, name, &pub_key, true);
+
+                log::info(&format!("Group {} created", name.green()));
+            }
+
+            Err(e) => {
+                let status = e.status().unwrap();
+
+                log::error(&format!("Can't create group: {}", status.to_string().red()));
+            }
+        },
+
+        Err(e) => {
+            log::error(&format!("Error on group: {}", e.to_string().red()));
+        }
+    }
+}
+
+fn add_member(ctx: &mut TSFSContext, group: &str, username: &str) {
+    let client = http_client(ctx);
+
+    let Some(group) = find_group(ctx, &client, group) else {
+        return;
+    };
+
+    let Some(user_pubkey) = files::get_user_public_key(ctx, &client, username) else {
+        return;
+    };
+
+    let res = client
+        .post(format!(
+            "{}:{}/groups/add",
+            ctx.endpoint_url.as_ref().unwrap(),
+            ctx.endpoint_port
+        ))
+        .header(
+            "Authorization",
+            format!("Bearer {}", ctx.session_token.as_ref().unwrap()),
+        )
+        .msg(&AddMemberRequest {
+            group: group.name.clone(),
+            username: username.to_string(),
+            priv_key: crypto::rsa_wrap(&group.priv_key, &user_pubkey).unwrap(),
+        })
+        .send();
+
+    match res {
+        Ok(res) => match res.error_for_status() {
+            Ok(_) => {
+                log::info(&format!(
+                    "{} added to {}",
+                    username.green(),
+                    group.name.green()
+                ));
+            }
+
+            Err(e) => {
+                let status = e.status().unwrap();
+
+                log::error(&format!("Can't add member: {}", status.to_string().red()));
+            }
+        },
+
+        Err(e) => {
+            log::error(&format!("Error on group: {}", e.to_string().red()));
+        }
+    }
+}
+
+fn remove_member(ctx: &mut TSFSContext, group: &str, username: &str) {
+    let client = http_client(ctx);
+
+    let Some(group) = find_group(ctx, &client, group) else {
+        return;
+    };
+
+    if !group.members.iter().any(|member| member == username) {
+        log::error(&format!(
+            "{} is not a member of {}",
+            username.red(),
+            group.name.red()
+        ));
+        return;
+    }
+
+    // The removed member holds the group private key, a new keypair replaces it
+    log::info("Generating RSA Keypair...");
+    let (priv_key, pub_key) = crypto::generate_rsa_keypair();
+
+    let mut members = Vec::new();
+    for member in group.members.iter().filter(|member| *member != username) {
+        let Some(member_pubkey) = files::get_user_public_key(ctx, &client, member) else {
+            return;
+        };
+
+        members.push(RenewedMember {
+            username: member.clone(),
+            priv_key: crypto::rsa_wrap(&priv_key, &member_pubkey).unwrap(),
+        });
+    }
+
+    let keys = group
+        .keyring
+        .keys
+        .iter()
+        .map(|key| {
+            let encrypted_key = crypto::rsa_encrypt(&key.key, &pub_key).unwrap();
+            let entry_signature = crypto::sign_entry(
+                &key.file.id,
+                &EntryKeyring::Root(&group.name),
+                key.permission,
+                &encrypted_key,
+                ctx.private_key.as_ref().unwrap(),
+            );
+
+            RenewedGroupKey {
+                file_uid: key.file.id.clone(),
+                encrypted_key,
+                signing_keys: key.signing_keys.rsa_encrypt(&pub_key),
+                entry_signature: Some(entry_signature),
+            }
+        })
+        .collect();
+
+    let res = client
+        .post(format!(
+            "{}:{}/groups/remove",
+            ctx.endpoint_url.as_ref().unwrap(),
+            ctx.endpoint_port
+        ))
+        .header(
+            "Authorization",
+            format!("Bearer {}", ctx.session_token.as_ref().unwrap()),
+        )
+        .msg(&RemoveMemberRequest {
+            group: group.name.clone(),
+            username: username.to_string(),
+            pub_key: pub_key.clone(),
+            members,
+            keys,
+        })
+        .send();
+
+    match res {
+        Ok(res) => match res.error_for_status() {
+            Ok(_) => {
+                // The pinned group key is replaced by the new one, the other members have
+                // to verify it
+                trust::pin_key(ctx, &group.name, &pub_key, true);
+
+                log::info(&format!(
+                    "{} removed from {}, the group keys are rotated",
+                    username.green(),
+                    group.name.green()
+                ));
+
+                update_keyring(ctx);
+            }
+
+            Err(e) => {
+                let status = e.status().unwrap();
+
+                log::error(&format!(
+                    "Can't remove member: {}",
+                    status.to_string().red()
+                ));
+            }
+        },
+
+        Err(e) => {
+            log::error(&format!("Error on group: {}", e.to_string().red()));
+        }
+    }
+}
+
+/// Find a group of the user by name, it must be managed by the user
+fn find_group(ctx: &TSFSContext, client: &Client, name: &str) -> Option<Group> {
+    let group = files::get_groups(ctx, client)?
+        .into_iter()
+        .find(|group| group.name == name);
+
+    let Some(group) = group else {
+        log::error(&format!("Can't find group {}", name.red()));
+        return None;
+    };
+
+    if Some(&group.owner) != ctx.username.as_ref() {
+        log::error(&format!(
+            "Only the owner of {} can manage its members, ask {}",
+            name.red(),
+            group.owner.green()
+        ));
+        return None;
+    }
+
+    Some(group)
+}
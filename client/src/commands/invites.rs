@@ -0,0 +1,175 @@
+use std::time::{Duration, UNIX_EPOCH};
+
+use chrono::prelude::*;
+use clap::Parser;
+use colored::Colorize;
+
+use crate::{
+    codec::{http_client, ResponseExt},
+    crypto::EntryKeyring,
+    log,
+    models::{KeyringWithKeysAndFiles, PendingInvitation, Permission},
+    TSFSContext,
+};
+
+use super::{signer_keys, Command};
+
+/// List the shares waiting for an answer
+#[derive(Parser, Debug)]
+pub struct InvitesArgs {}
+
+pub struct InvitesCommand;
+
+impl Command for InvitesCommand {
+    fn execute(&self, args: &Vec<String>, ctx: &mut TSFSContext) {
+        match InvitesArgs::try_parse_from(args) {
+            Ok(_) => {
+                if ctx.keyring_tree.is_none() {
+                    log::error("Missing Keyring Tree, not logged ?");
+                    return;
+                }
+
+                let Some(invitations) = get_invitations(ctx) else {
+                    return;
+                };
+
+                if invitations.is_empty() {
+                    log::info("No pending invitation");
+                    return;
+                }
+
+                println!("{} {}", "----".cyan(), "Invitations".cyan());
+
+                for invitation in invitations {
+                    let created_at = DateTime::<Local>::from(
+                        UNIX_EPOCH + Duration::from_millis(invitation.created_at as u64),
+                    )
+                    .format("%Y-%m-%d %H:%M:%S");
+
+                    let name = if invitation.key.file.is_folder() {
+                        invitation.key.file.name.cyan()
+                    } else {
+                        invitation.key.file.name.normal()
+                    };
+                    // The level of the key is the signed one
+                    let permission = match invitation.key.permission {
+                        Permission::Read => "read",
+                        Permission::Write => "write",
+                        Permission::Owner => "owner",
+                    };
+
+                    println!(
+                        "{} {} from {} ({})",
+                        created_at,
+                        name,
+                        invitation.sender.green(),
+                        permission
+                    );
+                }
+                log::info(&format!(
+                    "Use {} or {} to answer",
+                    "accept <name> [folder]".green(),
+                    "decline <name>".green()
+                ));
+            }
+
+            Err(e) => {
+                println!("{e}");
+            }
+        }
+    }
+
+    fn description(&self) -> String {
+        "List the shares waiting for an answer".into()
+    }
+}
+
+/// Get the pending invitations, with their key and name decrypted
+pub fn get_invitations(ctx: &TSFSContext) -> Option<Vec<PendingInvitation>> {
+    let client = http_client(ctx);
+
+    let res = client
+        .get(format!(
+            "{}:{}/invitations",
+            ctx.endpoint_url.as_ref().unwrap(),
+            ctx.endpoint_port
+        ))
+        .header(
+            "Authorization",
+            format!("Bearer {}", ctx.session_token.as_ref().unwrap()),
+        )
+        .send();
+
+    match res {
+        Ok(res) => match res.error_for_status() {
+            Ok(res) => {
+                let invitations = res.msg::<Vec<PendingInvitation>>().unwrap();
+
+                // Pending keys are encrypted with user public key, like the root keyring
+                let keyring = KeyringWithKeysAndFiles {
+                    id: 0,
+                    keys: invitations.iter().map(|i| i.key.clone()).collect(),
+                };
+                let signer_keys = signer_keys(ctx, &client, &keyring, None);
+                let keyring = KeyringWithKeysAndFiles::from_encrypted(
+                    keyring,
+                    ctx.private_key.as_ref().unwrap(),
+                    None,
+                    &EntryKeyring::Inbox(ctx.username.as_ref().unwrap()),
+                    &signer_keys,
+                );
+
+                // The keys with an invalid signature are dropped along with their invitation,
+                // like the ones signed by another user than the sender
+                Some(
+                    invitations
+                        .into_iter()
+                        .filter_map(|invitation| {
+                            let key = keyring.keys.iter().find(|key| {
+                                key.file.id == invitation.key.file.id
+                                    && key.signer.as_ref() == Some(&invitation.sender)
+                            })?;
+
+                            Some(PendingInvitation {
+                                key: key.clone(),
+                                ..invitation
+                            })
+                        })
+                        .collect(),
+                )
+            }
+
+            Err(e) => {
+                let status = e.status().unwrap();
+
+                log::error(&format!(
+                    "Can't get invitations: {}",
+                    status.to_string().red()
+                ));
+
+                None
+            }
+        },
+
+        Err(e) => {
+            log::error(&format!("Error on invitations: {}", e.to_string().red()));
+
+            None
+        }
+    }
+}
+
+/// Find a pending invitation by the name of the shared file
+pub fn find_invitation(ctx: &TSFSContext, name: &str) -> Option<PendingInvitation> {
+    let invitations = get_invitations(ctx)?;
+
+    let invitation = invitations
+        .into_iter()
+        .find(|invitation| invitation.key.file.name == name);
+
+    if invitation.is_none() {
+        log::error(&format!("No pending invitation for {}", name.red()));
+    }
+
+    invitation
+}
@@ -0,0 +1,132 @@
+use std::time::Duration;
+
+use base64::prelude::*;
+use clap::Parser;
+use colored::Colorize;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    codec::{http_client, RequestBuilderExt, ResponseExt},
+    files, log,
+    models::Permission,
+    TSFSContext,
+};
+
+use super::{expiration_millis, find_in_current_folder, parse_duration, Command};
+
+#[derive(Serialize)]
+pub struct CreateLinkRequest {
+    file_uid: String,
+    /// End of the link in millis, None if it doesn't expire
+    expires_at: Option<i64>,
+    /// Downloads allowed, None if unlimited
+    max_downloads: Option<i32>,
+    /// Challenge of the file signed with its read key
+    #[serde(with = "serde_bytes")]
+    signature: Option<Vec<u8>>,
+}
+
+#[derive(Deserialize)]
+pub struct CreateLinkResponse {
+    token: String,
+}
+
+/// Create an anonymous download link for a file of the current folder
+#[derive(Parser, Debug)]
+pub struct LinkArgs {
+    name: String,
+    /// Disable the link after the given time, e.g. 30m, 12h, 7d or 2w
+    #[arg(long, value_parser = parse_duration)]
+    expires: Option<Duration>,
+    /// Disable the link after this number of downloads
+    #[arg(long)]
+    max_downloads: Option<i32>,
+}
+
+pub struct LinkCommand;
+
+impl Command for LinkCommand {
+    fn execute(&self, args: &Vec<String>, ctx: &mut TSFSContext) {
+        match LinkArgs::try_parse_from(args) {
+            Ok(args) => {
+                if ctx.keyring_tree.is_none() {
+                    log::error("Missing Keyring Tree, not logged ?");
+                    return;
+                }
+
+                let Some(file) = find_in_current_folder(ctx, &args.name) else {
+                    log::error(&format!("Can't find file {}", args.name.red()));
+                    return;
+                };
+
+                if file.file.is_folder() {
+                    log::error("Can't create a link to a folder");
+                    return;
+                }
+
+                let client = http_client(ctx);
+                let signature = files::sign_challenge(ctx, &client, Some(&file), Permission::Read);
+
+                let res = client
+                    .post(format!(
+                        "{}:{}/file/link",
+                        ctx.endpoint_url.as_ref().unwrap(),
+                        ctx.endpoint_port
+                    ))
+                    .header(
+                        "Authorization",
+                        format!("Bearer {}", ctx.session_token.as_ref().unwrap()),
+                    )
+                    .msg(&CreateLinkRequest {
+                        file_uid: file.file.id,
+                        expires_at: args.expires.map(expiration_millis),
+                        max_downloads: args.max_downloads,
+                        signature,
+                    })
+                    .send();
+
+                match res {
+                    Ok(res) => match res.error_for_status() {
+                        Ok(res) => {
+                            let link = res.msg::<CreateLinkResponse>().unwrap();
+
+                            // The fragment is never sent to the server, only the link holder
+                            // knows the key
+                            let url = format!(
+                                "{}:{}/public/{}#{}",
+                                ctx.endpoint_url.as_ref().unwrap(),
+                                ctx.endpoint_port,
+                                link.token,
+                                BASE64_URL_SAFE_NO_PAD.encode(&file.key)
+                            );
+
+                            log::info(&format!(
+                                "Link to {}, anyone holding it can download the file:",
+                                args.name.green()
+                            ));
+                            println!("{}", url);
+                        }
+
+                        Err(e) => {
+                            let status = e.status().unwrap();
+
+                            log::error(&format!("Can't create link: {}", status.to_string().red()));
+                        }
+                    },
+
+                    Err(e) => {
+                        log::error(&format!("Error on link: {}", e.to_string().red()));
+                    }
+                }
+            }
+
+            Err(e) => {
+                println!("{e}");
+            }
+        }
+    }
+
+    fn description(&self) -> String {
+        "Create an anonymous download link for a file of the current folder".into()
+    }
+}
@@ -10,9 +10,15 @@ use opaque_ke::{
 use rand::rngs::OsRng;
 use serde::{Deserialize, Serialize};
 
-use crate::{crypto, log, models::KeyringWithKeysAndFiles, DefaultCS, TSFSContext};
+use crate::{
+    codec::{self, http_client, RequestBuilderExt, ResponseExt},
+    crypto::{self, EntryKeyring},
+    log,
+    models::KeyringWithKeysAndFiles,
+    transparency, DefaultCS, TSFSContext,
+};
 
-use super::Command;
+use super::{mount_groups, signer_keys, warn_expired_shares, Command};
 
 pub struct LoginCommand;
 
@@ -30,6 +36,7 @@ pub struct LoginRequestFinish {
 
 #[derive(Deserialize, Debug)]
 pub struct LoginRequestResult {
+    #[serde(with = "codec::bytes_pair")]
     keypair: (Vec<u8>, Vec<u8>),
     keyring_tree: KeyringWithKeysAndFiles,
 }
@@ -58,10 +65,7 @@ impl Command for LoginCommand {
             let client_login_start_result =
                 ClientLogin::<DefaultCS>::start(&mut client_rng, password.as_bytes()).unwrap();
 
-            let client = reqwest::blocking::Client::builder()
-                .danger_accept_invalid_certs(ctx.accept_invalid_cert)
-                .build()
-                .unwrap();
+            let client = http_client(ctx);
 
             // Send CredentialRequest to the Server
             let res = client
@@ -69,7 +73,7 @@ impl Command for LoginCommand {
                     "{}:{}/auth/login/start",
                     endpoint_url, ctx.endpoint_port
                 ))
-                .json(&LoginRequest {
+                .msg(&LoginRequest {
                     username: username.clone(),
                     credential_request: client_login_start_result.message,
                 })
@@ -96,7 +100,7 @@ impl Command for LoginCommand {
             match client_login_start_result.state.finish(
                 password.as_bytes(),
                 // Get CredentialResponse from Server
-                res.json::<CredentialResponse<DefaultCS>>().unwrap(),
+                res.msg::<CredentialResponse<DefaultCS>>().unwrap(),
                 ClientLoginFinishParameters::new(
                     None,
                     Identifiers {
@@ -113,14 +117,14 @@ impl Command for LoginCommand {
                             "{}:{}/auth/login/finish",
                             endpoint_url, ctx.endpoint_port
                         ))
-                        .json(&LoginRequestFinish {
+                        .msg(&LoginRequestFinish {
                             username: username.clone(),
                             credential_finalization: client_login_finish_result.message,
                         })
                         .send()
                         .unwrap();
 
-                    let login_result = res.json::<LoginRequestResult>().unwrap();
+                    let login_result = res.msg::<LoginRequestResult>().unwrap();
                     let user_keypair = login_result.keypair;
 
                     // Get the Export Key from ClientRegistration
@@ -144,28 +148,51 @@ impl Command for LoginCommand {
                         }
                     };
 
-                    // Decrypt keyring
-                    log::info("Decrypting Keyring...");
-                    let decrypted_keyring = KeyringWithKeysAndFiles::from_encrypted(login_result.keyring_tree, private_key.as_slice(), true);
-
-                    decrypted_keyring.get_file("hihi");
-
                     // Update Context with keys
                     ctx.private_key = Some(private_key);
                     ctx.public_key = Some(user_keypair.0);
-                    ctx.keyring_tree = Some(decrypted_keyring);
+                    ctx.username = Some(username.clone());
 
                     // Here is our Session Key that will be used as Session Token
                     let b64_token = general_purpose::STANDARD_NO_PAD
                         .encode(client_login_finish_result.session_key);
 
-                    ctx.username = Some(username.clone());
                     ctx.session_token = Some(b64_token.clone());
+
+                    // Decrypt keyring, the entries of the root keyring are signed by the user,
+                    // the files shared with him come through his inbox
+                    log::info("Decrypting Keyring...");
+                    let signer_keys = signer_keys(
+                        ctx,
+                        &http_client(ctx),
+                        &login_result.keyring_tree,
+                        Some(&[]),
+                    );
+                    let decrypted_keyring = KeyringWithKeysAndFiles::from_encrypted(
+                        login_result.keyring_tree,
+                        ctx.private_key.as_ref().unwrap(),
+                        None,
+                        &EntryKeyring::Root(&username),
+                        &signer_keys,
+                    );
+
+                    decrypted_keyring.get_file("hihi");
+
+                    ctx.keyring_tree = Some(decrypted_keyring);
                     log::info(&format!(
                         "Login {} ! Welcome back {} !",
                         "OK".bright_green(),
                         username.bright_green()
                     ));
+
+                    // Files shared with the groups of the user are listed in his root
+                    let mut keyring_tree = ctx.keyring_tree.take().unwrap();
+                    mount_groups(ctx, &mut keyring_tree);
+                    ctx.keyring_tree = Some(keyring_tree);
+
+                    warn_expired_shares(ctx, ctx.keyring_tree.as_ref().unwrap());
+
+                    check_logged_key(ctx);
                 }
 
                 Err(e) => {
@@ -181,3 +208,16 @@ impl Command for LoginCommand {
         "Login to the endpoint".into()
     }
 }
+
+/// Check the key log gives the key of the user to the others, otherwise the server may
+/// be giving them its own key to read what they share with him
+fn check_logged_key(ctx: &TSFSContext) {
+    if !transparency::verify_key(
+        ctx,
+        &http_client(ctx),
+        ctx.username.as_ref().unwrap(),
+        ctx.public_key.as_ref().unwrap(),
+    ) {
+        log::warning("The users sharing with you may not be using your key, be careful");
+    }
+}
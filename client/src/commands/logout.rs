@@ -1,6 +1,6 @@
 use colored::Colorize;
 
-use crate::{log, TSFSContext};
+use crate::{codec::http_client, log, TSFSContext};
 
 use super::Command;
 
@@ -11,10 +11,7 @@ impl Command for LogoutCommand {
         if ctx.session_token.is_none() {
             log::info("You are not connected");
         } else {
-            let client = reqwest::blocking::Client::builder()
-                .danger_accept_invalid_certs(ctx.accept_invalid_cert)
-                .build()
-                .unwrap();
+            let client = http_client(ctx);
 
             // Revoke current Session Token
             client
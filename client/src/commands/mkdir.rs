@@ -1,27 +1,9 @@
-use base64::prelude::*;
-use chacha20poly1305::{
-    aead::{Aead, AeadCore, KeyInit, OsRng},
-    ChaCha20Poly1305,
-};
 use clap::Parser;
-use colored::Colorize;
-use serde::Serialize;
 
-use crate::{crypto, log, TSFSContext};
+use crate::{codec::http_client, files, log, TSFSContext};
 
 use super::{update_keyring, Command};
 
-#[derive(Serialize)]
-pub struct CreateFolderRequest {
-    /// The parent folder to put the file in.
-    /// None = root
-    parent_uid: Option<String>,
-    /// Encrypted filename
-    filename: String,
-    /// Encrypted symmetric key with user pubkey
-    encrypted_key: Vec<u8>,
-}
-
 /// List the content of the current folder
 #[derive(Parser, Debug)]
 pub struct MkdirArgs {
@@ -42,71 +24,14 @@ impl Command for MkdirCommand {
                         current_folder = keyring_tree.get_file(current_folder_id);
                     };
 
-                    let mut rng = OsRng;
-
-                    // Create new asymmetric key for new folder
-                    let key = ChaCha20Poly1305::generate_key(&mut rng);
-
-                    // Encrypt folder name
-                    let cipher = ChaCha20Poly1305::new(&key);
-                    let nonce = ChaCha20Poly1305::generate_nonce(&mut rng);
-                    let enc_name = cipher.encrypt(&nonce, args.name.as_bytes()).unwrap();
-                    let enc_name = [nonce.to_vec(), enc_name].concat();
-                    let enc_name_b64 = BASE64_STANDARD.encode(enc_name);
-
-                    // Encrypt key with user public key or parent symmetric key
-                    let enc_key;
-                    if let Some(parent_folder) = current_folder {
-                        let parent_key = parent_folder.key.as_slice();
-                        enc_key = crypto::chacha_encrypt(key.as_slice(), parent_key).unwrap();
-                    } else {
-                        let pubkey = ctx.public_key.as_ref().unwrap();
-                        enc_key = crypto::rsa_encrypt(&key, pubkey).unwrap();
-                    }
-
-                    let client = reqwest::blocking::Client::builder()
-                        .danger_accept_invalid_certs(ctx.accept_invalid_cert)
-                        .build()
-                        .unwrap();
-
-                    let res = client
-                        .post(format!(
-                            "{}:{}/folder/create",
-                            ctx.endpoint_url.as_ref().unwrap(),
-                            ctx.endpoint_port
-                        ))
-                        .header(
-                            "Authorization",
-                            format!("Bearer {}", ctx.session_token.as_ref().unwrap()),
-                        )
-                        .json(&CreateFolderRequest {
-                            parent_uid: ctx.current_folder.last().cloned(),
-                            filename: enc_name_b64,
-                            encrypted_key: enc_key,
-                        })
-                        .send();
-
-                    match res {
-                        Ok(res) => match res.error_for_status() {
-                            Ok(_) => {
-                                log::info("Folder created !");
-
-                                update_keyring(ctx);
-                            }
-
-                            Err(e) => {
-                                let status = e.status().unwrap();
+                    let client = http_client(ctx);
 
-                                log::error(&format!(
-                                    "Can't create folder: {}",
-                                    status.to_string().red()
-                                ));
-                            }
-                        },
+                    if files::create_folder(ctx, &client, current_folder.as_ref(), &args.name)
+                        .is_some()
+                    {
+                        log::info("Folder created !");
 
-                        Err(e) => {
-                            log::error(&format!("Error on mkdir: {}", e.to_string().red()));
-                        }
+                        update_keyring(ctx);
                     }
                 } else {
                     log::error("Missing Keyring Tree, not logged ?");
@@ -1,31 +1,54 @@
-use std::time::SystemTime;
+use std::{
+    collections::{HashMap, HashSet},
+    io::Write,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
 use colored::Colorize;
-use serde::Serialize;
+use reqwest::blocking::Client;
 
 use crate::{
-    log,
-    models::{File, KeyringWithKeysAndFiles, KeyWithFile},
+    codec::{http_client, ResponseExt},
+    crypto::{self, EntryKeyring},
+    files, log,
+    models::{File, KeyWithFile, KeyringWithKeysAndFiles, Permission, PublicKeys, SigningKeys},
     TSFSContext,
 };
 
+pub mod accept;
+pub mod access;
 pub mod cd;
 pub mod change_password;
+pub mod cp;
+pub mod decline;
+pub mod df;
 pub mod download;
 pub mod exit;
+pub mod fetch;
+pub mod fingerprint;
+pub mod group;
 pub mod help;
+pub mod invites;
+pub mod link;
 pub mod login;
 pub mod logout;
 pub mod ls;
 pub mod mkdir;
+pub mod mv;
 pub mod ping;
 pub mod register;
+pub mod rename;
+pub mod restore;
 pub mod rm;
 pub mod sessions;
 pub mod set;
 pub mod share;
+pub mod trash;
 pub mod unshare;
 pub mod upload_file;
+pub mod users;
+pub mod verify;
+pub mod versions;
 
 pub trait Command {
     fn execute(&self, args: &Vec<String>, ctx: &mut TSFSContext);
@@ -49,10 +72,7 @@ pub fn update_keyring(ctx: &mut TSFSContext) {
 
     log::info("Updating keyring...");
 
-    let client = reqwest::blocking::Client::builder()
-        .danger_accept_invalid_certs(ctx.accept_invalid_cert)
-        .build()
-        .unwrap();
+    let client = http_client(ctx);
 
     let res = client
         .get(format!(
@@ -69,13 +89,19 @@ pub fn update_keyring(ctx: &mut TSFSContext) {
     match res {
         Ok(res) => match res.error_for_status() {
             Ok(res) => {
-                let keyring = res.json::<KeyringWithKeysAndFiles>().unwrap();
-                let dec_keyring = KeyringWithKeysAndFiles::from_encrypted(
+                let keyring = res.msg::<KeyringWithKeysAndFiles>().unwrap();
+                let signer_keys = signer_keys(ctx, &client, &keyring, Some(&[]));
+                let mut dec_keyring = KeyringWithKeysAndFiles::from_encrypted(
                     keyring,
                     ctx.private_key.as_ref().unwrap(),
-                    true,
+                    None,
+                    &EntryKeyring::Root(ctx.username.as_ref().unwrap()),
+                    &signer_keys,
                 );
 
+                mount_groups(ctx, &mut dec_keyring);
+                warn_expired_shares(ctx, &dec_keyring);
+
                 ctx.keyring_tree = Some(dec_keyring);
                 ctx.last_keyring_update = SystemTime::now();
             }
@@ -90,59 +116,293 @@ pub fn update_keyring(ctx: &mut TSFSContext) {
     }
 }
 
-#[derive(Serialize)]
-pub struct DownloadFileRequest {
-    file_uid: String,
+/// Add the files shared with the groups of the user to his root keyring
+pub fn mount_groups(ctx: &TSFSContext, keyring: &mut KeyringWithKeysAndFiles) {
+    let client = http_client(ctx);
+
+    let Some(groups) = files::get_groups(ctx, &client) else {
+        return;
+    };
+
+    for group in groups {
+        keyring.keys.extend(group.keyring.keys);
+    }
 }
 
-pub fn download_file(ctx: &mut TSFSContext, file: KeyWithFile) -> Option<File> {
-    let client = reqwest::blocking::Client::builder()
-        .danger_accept_invalid_certs(ctx.accept_invalid_cert)
-        .build()
-        .unwrap();
+/// Ask the user to rotate the keys of his files whose share expired
+///
+/// The server removed the expired keys, but the recipient may have kept the file key.
+/// Only the owner can rotate them, with `unshare`.
+pub fn warn_expired_shares(ctx: &TSFSContext, keyring: &KeyringWithKeysAndFiles) {
+    for key in &keyring.keys {
+        let is_owner = key.file.owner == ctx.username || key.signing_keys.owner.is_some();
 
-    let res = client
-        .get(format!(
-            "{}:{}/file/download",
-            ctx.endpoint_url.as_ref().unwrap(),
-            ctx.endpoint_port
-        ))
-        .header(
-            "Authorization",
-            format!("Bearer {}", ctx.session_token.as_ref().unwrap()),
-        )
-        .json(&DownloadFileRequest {
-            file_uid: file.file.id,
-        })
-        .send();
+        if key.file.expired_share_at.is_some() && is_owner {
+            log::warning(&format!(
+                "A share of {} expired, run {} in its folder to rotate its keys",
+                key.file.name.yellow(),
+                format!("unshare {}", key.file.name).green()
+            ));
+        }
 
-    match res {
-        Ok(res) => match res.error_for_status() {
-            Ok(res) => {
-                let mut downloaded_file = res.json::<File>().unwrap();
+        if let Some(folder_keyring) = &key.file.keyring {
+            warn_expired_shares(ctx, folder_keyring);
+        }
+    }
+}
 
-                // Decrypt file
-                downloaded_file.decrypt(&file.key);
+/// Find a file or folder by name in the current folder
+pub fn find_in_current_folder(ctx: &TSFSContext, name: &str) -> Option<KeyWithFile> {
+    let keyring_tree = ctx.keyring_tree.as_ref()?;
 
-               Some(downloaded_file)
-            }
+    if let Some(current_folder_id) = ctx.current_folder.last() {
+        keyring_tree
+            .get_file(current_folder_id)?
+            .file
+            .keyring?
+            .get_file_by_name(name)
+    } else {
+        keyring_tree.get_file_by_name(name)
+    }
+}
 
-            Err(e) => {
-                let status = e.status().unwrap();
+/// Download a file and write its decrypted content to `writer`
+///
+/// Return the file informations with its decrypted name
+pub fn download_file(
+    ctx: &TSFSContext,
+    file: KeyWithFile,
+    writer: &mut impl Write,
+) -> Option<File> {
+    let client = http_client(ctx);
 
-                log::error(&format!(
-                    "Can't download file: {}",
-                    status.to_string().red()
-                ));
+    let mut downloaded_file = files::get_file_info(ctx, &client, &file.file.id)?;
 
-                None
-            }
-        },
+    if !files::download_stream(ctx, &client, &downloaded_file, &file.key, writer) {
+        return None;
+    }
 
-        Err(e) => {
-            log::error(&format!("Error on download: {}", e.to_string().red()));
+    // Decrypt file name
+    if !downloaded_file.decrypt(&file.key) {
+        log::error("Can't decrypt the name of the file");
+        return None;
+    }
 
-            None
+    Some(downloaded_file)
+}
+
+/// Parse a duration made of a number and a unit: m, h, d or w
+pub fn parse_duration(duration: &str) -> Result<Duration, String> {
+    let unit = duration.chars().last().ok_or("empty duration")?;
+    let seconds = match unit {
+        'm' => 60,
+        'h' => 3600,
+        'd' => 24 * 3600,
+        'w' => 7 * 24 * 3600,
+        _ => return Err(format!("unknown unit {unit}, expected m, h, d or w")),
+    };
+
+    let count = duration[..duration.len() - unit.len_utf8()]
+        .parse::<u64>()
+        .map_err(|e| e.to_string())?;
+    if count == 0 {
+        return Err("duration must be positive".into());
+    }
+
+    Ok(Duration::from_secs(count * seconds))
+}
+
+/// Time in millis once `duration` is elapsed from now
+pub fn expiration_millis(duration: Duration) -> i64 {
+    (SystemTime::now() + duration)
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as i64
+}
+
+/// Format a size in bytes in a human readable way
+pub fn format_bytes(bytes: i64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.2} {}", size, UNITS[unit])
+    }
+}
+
+/// Resolve a folder path from the current folder, `..` goes to the parent folder and
+/// a leading `/` starts from root
+///
+/// Return the folder uuids from root, empty for root itself.
+pub fn resolve_folder(ctx: &TSFSContext, path: &str) -> Option<Vec<String>> {
+    let keyring_tree = ctx.keyring_tree.as_ref()?;
+
+    let mut folders = if path.starts_with('/') {
+        Vec::new()
+    } else {
+        ctx.current_folder.clone()
+    };
+
+    for part in path.split('/').filter(|p| !p.is_empty() && *p != ".") {
+        if part == ".." {
+            folders.pop()?;
+            continue;
+        }
+
+        let folder = if let Some(folder_id) = folders.last() {
+            keyring_tree
+                .get_file(folder_id)?
+                .file
+                .keyring?
+                .get_file_by_name(part)?
+        } else {
+            keyring_tree.get_file_by_name(part)?
+        };
+
+        if !folder.file.is_folder() {
+            return None;
+        }
+
+        folders.push(folder.file.id);
+    }
+
+    Some(folders)
+}
+
+/// Encrypt a file key for the keyring of `folder`, with the folder key
+/// or with user public key for root if None
+///
+/// Return the id of the keyring along with the encrypted key.
+pub fn encrypt_key_for(
+    ctx: &TSFSContext,
+    folder: Option<&KeyWithFile>,
+    key: &[u8],
+) -> (i32, Vec<u8>) {
+    if let Some(folder) = folder {
+        (
+            folder.file.keyring.as_ref().unwrap().id,
+            crypto::chacha_encrypt(key, &folder.key).unwrap(),
+        )
+    } else {
+        (
+            ctx.keyring_tree.as_ref().unwrap().id,
+            crypto::rsa_encrypt(key, ctx.public_key.as_ref().unwrap()).unwrap(),
+        )
+    }
+}
+
+/// Sign a key of `target` encrypted for the keyring of `folder`, only the entries of the
+/// root keyring are signed, the other ones are encrypted with the folder key
+pub fn sign_entry_for(
+    ctx: &TSFSContext,
+    folder: Option<&KeyWithFile>,
+    target: &str,
+    permission: Permission,
+    encrypted_key: &[u8],
+) -> Option<Vec<u8>> {
+    if folder.is_some() {
+        return None;
+    }
+
+    Some(crypto::sign_entry(
+        target,
+        &EntryKeyring::Root(ctx.username.as_ref().unwrap()),
+        permission,
+        encrypted_key,
+        ctx.private_key.as_ref().unwrap(),
+    ))
+}
+
+/// Public keys of the users allowed to sign the entries of a root keyring
+///
+/// `signers` are the users who may sign besides the user: nobody for his root and trash,
+/// the members for a group, None for an inbox where anyone can send a file. The key of the
+/// user is his own one, the keys of the other signers must be their latest keys in the key
+/// log and match the pinned ones. The entries signed by anyone else, or by a signer whose
+/// key can't be checked, are dropped.
+pub fn signer_keys(
+    ctx: &TSFSContext,
+    client: &Client,
+    keyring: &KeyringWithKeysAndFiles,
+    signers: Option<&[String]>,
+) -> HashMap<String, Vec<u8>> {
+    let mut keys = HashMap::new();
+
+    if let (Some(username), Some(public_key)) = (&ctx.username, &ctx.public_key) {
+        keys.insert(username.clone(), public_key.clone());
+    }
+
+    let others: HashSet<&String> = keyring
+        .keys
+        .iter()
+        .filter_map(|key| key.signer.as_ref())
+        .filter(|signer| !keys.contains_key(*signer))
+        .filter(|signer| signers.is_none_or(|signers| signers.contains(*signer)))
+        .collect();
+
+    for signer in others {
+        if let Some(public_key) = files::get_user_public_key(ctx, client, signer) {
+            keys.insert(signer.clone(), public_key);
         }
     }
+
+    keys
+}
+
+/// Encrypt the signing keys of a file for the keyring of `folder`, with the keys derived
+/// from the folder ones or with user public key for root if None
+pub fn encrypt_signing_keys_for(
+    ctx: &TSFSContext,
+    folder: Option<&KeyWithFile>,
+    keys: &SigningKeys,
+) -> SigningKeys {
+    if let Some(folder) = folder {
+        keys.encrypt_for(&folder.signing_keys)
+    } else {
+        keys.rsa_encrypt(ctx.public_key.as_ref().unwrap())
+    }
+}
+
+/// Generate the signing keys of a new file in `folder`, or in root if None
+///
+/// Files created in a folder without signing keys get none, nobody could decrypt them.
+pub fn generate_signing_keys(folder: Option<&KeyWithFile>) -> (SigningKeys, PublicKeys) {
+    if folder.is_some_and(|folder| folder.signing_keys.is_empty()) {
+        return (SigningKeys::default(), PublicKeys::default());
+    }
+
+    SigningKeys::generate()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_duration_units() {
+        assert_eq!(parse_duration("30m"), Ok(Duration::from_secs(30 * 60)));
+        assert_eq!(parse_duration("2h"), Ok(Duration::from_secs(2 * 3600)));
+        assert_eq!(parse_duration("7d"), Ok(Duration::from_secs(7 * 24 * 3600)));
+        assert_eq!(parse_duration("1w"), Ok(Duration::from_secs(7 * 24 * 3600)));
+    }
+
+    #[test]
+    fn parse_duration_invalid() {
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("d").is_err());
+        assert!(parse_duration("10").is_err());
+        assert!(parse_duration("10s").is_err());
+        assert!(parse_duration("-1d").is_err());
+        assert!(parse_duration("1.5h").is_err());
+        assert!(parse_duration("0w").is_err());
+        assert!(parse_duration("1é").is_err());
+    }
 }
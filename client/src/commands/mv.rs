@@ -0,0 +1,174 @@
+use clap::Parser;
+use colored::Colorize;
+use serde::Serialize;
+
+use crate::{
+    codec::{http_client, RequestBuilderExt},
+    files, log,
+    models::{Permission, SigningKeys},
+    TSFSContext,
+};
+
+use super::{
+    encrypt_key_for, encrypt_signing_keys_for, find_in_current_folder, resolve_folder,
+    sign_entry_for, update_keyring, Command,
+};
+
+#[derive(Serialize)]
+pub struct MoveFileRequest {
+    file_uid: String,
+    keyring_id: i32,
+    destination_keyring_id: i32,
+    #[serde(with = "serde_bytes")]
+    encrypted_key: Vec<u8>,
+    signing_keys: SigningKeys,
+    /// Signature of the entry, when moving to root
+    #[serde(with = "serde_bytes")]
+    entry_signature: Option<Vec<u8>>,
+    /// Challenge of the current folder signed with its write key
+    #[serde(with = "serde_bytes")]
+    signature: Option<Vec<u8>>,
+    /// Challenge of the destination folder signed with its write key
+    #[serde(with = "serde_bytes")]
+    destination_signature: Option<Vec<u8>>,
+}
+
+/// Move a file or folder of the current folder to another folder
+#[derive(Parser, Debug)]
+pub struct MvArgs {
+    name: String,
+    /// Destination folder, `..` for the parent folder and `/` for root
+    destination: String,
+}
+
+pub struct MvCommand;
+
+impl Command for MvCommand {
+    fn execute(&self, args: &Vec<String>, ctx: &mut TSFSContext) {
+        match MvArgs::try_parse_from(args) {
+            Ok(args) => {
+                let Some(keyring_tree) = &ctx.keyring_tree else {
+                    log::error("Missing Keyring Tree, not logged ?");
+                    return;
+                };
+
+                let Some(file) = find_in_current_folder(ctx, &args.name) else {
+                    log::error(&format!("Can't find file {}", args.name.red()));
+                    return;
+                };
+
+                let Some(destination) = resolve_folder(ctx, &args.destination) else {
+                    log::error(&format!("Can't find folder {}", args.destination.red()));
+                    return;
+                };
+
+                if destination.contains(&file.file.id) {
+                    log::error("Can't move a folder inside itself");
+                    return;
+                }
+
+                let destination_folder = destination
+                    .last()
+                    .map(|folder_id| keyring_tree.get_file(folder_id).unwrap());
+                let destination_keyring = match &destination_folder {
+                    Some(folder) => folder.file.keyring.as_ref().unwrap(),
+                    None => keyring_tree,
+                };
+
+                if destination_keyring.id == file.keyring_id {
+                    log::error(&format!("{} is already in this folder", args.name.red()));
+                    return;
+                }
+
+                if destination_keyring.get_file_by_name(&args.name).is_some() {
+                    log::error(&format!(
+                        "A file named {} already exists in {}",
+                        args.name.red(),
+                        args.destination.red()
+                    ));
+                    return;
+                }
+
+                // Wrap the file key for its new parent keyring
+                let (destination_keyring_id, encrypted_key) =
+                    encrypt_key_for(ctx, destination_folder.as_ref(), &file.key);
+                let signing_keys =
+                    encrypt_signing_keys_for(ctx, destination_folder.as_ref(), &file.signing_keys);
+                let entry_signature = sign_entry_for(
+                    ctx,
+                    destination_folder.as_ref(),
+                    &file.file.id,
+                    file.permission,
+                    &encrypted_key,
+                );
+
+                // Both folders are modified
+                let current_folder = keyring_tree.get_folder_by_keyring(file.keyring_id);
+
+                let client = http_client(ctx);
+                let signature =
+                    files::sign_challenge(ctx, &client, current_folder.as_ref(), Permission::Write);
+                let destination_signature = files::sign_challenge(
+                    ctx,
+                    &client,
+                    destination_folder.as_ref(),
+                    Permission::Write,
+                );
+
+                let res = client
+                    .post(format!(
+                        "{}:{}/file/move",
+                        ctx.endpoint_url.as_ref().unwrap(),
+                        ctx.endpoint_port
+                    ))
+                    .header(
+                        "Authorization",
+                        format!("Bearer {}", ctx.session_token.as_ref().unwrap()),
+                    )
+                    .msg(&MoveFileRequest {
+                        file_uid: file.file.id,
+                        keyring_id: file.keyring_id,
+                        destination_keyring_id,
+                        encrypted_key,
+                        signing_keys,
+                        entry_signature,
+                        signature,
+                        destination_signature,
+                    })
+                    .send();
+
+                match res {
+                    Ok(res) => match res.error_for_status() {
+                        Ok(_) => {
+                            log::info(&format!(
+                                "{} moved to {}",
+                                args.name.green(),
+                                args.destination.green()
+                            ));
+
+                            update_keyring(ctx);
+                        }
+
+                        Err(e) => {
+                            let status = e.status().unwrap();
+
+                            log::error(&format!("Can't move file: {}", status.to_string().red()));
+                        }
+                    },
+
+                    Err(e) => {
+                        log::error(&format!("Error on mv: {}", e.to_string().red()));
+                    }
+                }
+            }
+
+            Err(e) => {
+                println!("{e}");
+            }
+        }
+    }
+
+    fn description(&self) -> String {
+        "Move a file or folder of the current folder to another folder".into()
+    }
+}
@@ -14,7 +14,10 @@ use rsa::{
 };
 use serde::{Deserialize, Serialize};
 
-use crate::{crypto, log, DefaultCS, TSFSContext};
+use crate::{
+    codec::{self, http_client, RequestBuilderExt, ResponseExt},
+    crypto, log, DefaultCS, TSFSContext,
+};
 
 use super::Command;
 
@@ -31,6 +34,7 @@ pub struct RegisterFinishRequest {
     username: String,
     registration_upload: RegistrationUpload<DefaultCS>,
     // (pub_key, priv_key)
+    #[serde(with = "codec::bytes_pair")]
     user_keypair: (Vec<u8>, Vec<u8>),
 }
 
@@ -62,10 +66,7 @@ impl Command for RegisterCommand {
                 ClientRegistration::<DefaultCS>::start(&mut client_rng, password.as_bytes())
                     .unwrap();
 
-            let client = reqwest::blocking::Client::builder()
-                .danger_accept_invalid_certs(ctx.accept_invalid_cert)
-                .build()
-                .unwrap();
+            let client = http_client(ctx);
 
             // Send RegistrationRequest to the Server
             let res = client
@@ -73,7 +74,7 @@ impl Command for RegisterCommand {
                     "{}:{}/auth/register/start",
                     endpoint_url, ctx.endpoint_port
                 ))
-                .json(&RegisterRequest {
+                .msg(&RegisterRequest {
                     username: username.clone(),
                     registration_request: client_registration_start_result.message,
                 })
@@ -95,7 +96,7 @@ impl Command for RegisterCommand {
                             &mut client_rng,
                             password.as_bytes(),
                             // Get RegistrationResponse from Server
-                            res.json::<RegistrationResponse<DefaultCS>>().unwrap(),
+                            res.msg::<RegistrationResponse<DefaultCS>>().unwrap(),
                             ClientRegistrationFinishParameters::new(
                                 Identifiers {
                                     client: Some(username.as_bytes()),
@@ -136,7 +137,7 @@ impl Command for RegisterCommand {
                             "{}:{}/auth/register/finish",
                             endpoint_url, ctx.endpoint_port
                         ))
-                        .json(&RegisterFinishRequest {
+                        .msg(&RegisterFinishRequest {
                             username,
                             registration_upload: client_registration_finish_result.message,
                             user_keypair: (
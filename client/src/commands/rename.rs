@@ -0,0 +1,114 @@
+use base64::prelude::*;
+use clap::Parser;
+use colored::Colorize;
+use serde::Serialize;
+
+use crate::{
+    codec::{http_client, RequestBuilderExt},
+    crypto, files, log,
+    models::Permission,
+    TSFSContext,
+};
+
+use super::{find_in_current_folder, update_keyring, Command};
+
+#[derive(Serialize)]
+pub struct RenameFileRequest {
+    file_uid: String,
+    /// Encrypted filename
+    filename: String,
+    /// Challenge of the file signed with its write key
+    #[serde(with = "serde_bytes")]
+    signature: Option<Vec<u8>>,
+}
+
+/// Rename a file or folder of the current folder
+#[derive(Parser, Debug)]
+pub struct RenameArgs {
+    name: String,
+    new_name: String,
+}
+
+pub struct RenameCommand;
+
+impl Command for RenameCommand {
+    fn execute(&self, args: &Vec<String>, ctx: &mut TSFSContext) {
+        match RenameArgs::try_parse_from(args) {
+            Ok(args) => {
+                if ctx.keyring_tree.is_none() {
+                    log::error("Missing Keyring Tree, not logged ?");
+                    return;
+                }
+
+                let Some(file) = find_in_current_folder(ctx, &args.name) else {
+                    log::error(&format!("Can't find file {}", args.name.red()));
+                    return;
+                };
+
+                if find_in_current_folder(ctx, &args.new_name).is_some() {
+                    log::error(&format!(
+                        "A file named {} already exists",
+                        args.new_name.red()
+                    ));
+                    return;
+                }
+
+                // Names are encrypted with the file key
+                let encrypted_name =
+                    crypto::chacha_encrypt(args.new_name.as_bytes(), &file.key).unwrap();
+
+                let client = http_client(ctx);
+                let signature = files::sign_challenge(ctx, &client, Some(&file), Permission::Write);
+
+                let res = client
+                    .post(format!(
+                        "{}:{}/file/rename",
+                        ctx.endpoint_url.as_ref().unwrap(),
+                        ctx.endpoint_port
+                    ))
+                    .header(
+                        "Authorization",
+                        format!("Bearer {}", ctx.session_token.as_ref().unwrap()),
+                    )
+                    .msg(&RenameFileRequest {
+                        file_uid: file.file.id,
+                        filename: BASE64_STANDARD.encode(encrypted_name),
+                        signature,
+                    })
+                    .send();
+
+                match res {
+                    Ok(res) => match res.error_for_status() {
+                        Ok(_) => {
+                            log::info(&format!(
+                                "{} renamed to {}",
+                                args.name.green(),
+                                args.new_name.green()
+                            ));
+
+                            update_keyring(ctx);
+                        }
+
+                        Err(e) => {
+                            let status = e.status().unwrap();
+
+                            log::error(&format!("Can't rename file: {}", status.to_string().red()));
+                        }
+                    },
+
+                    Err(e) => {
+                        log::error(&format!("Error on rename: {}", e.to_string().red()));
+                    }
+                }
+            }
+
+            Err(e) => {
+                println!("{e}");
+            }
+        }
+    }
+
+    fn description(&self) -> String {
+        "Rename a file or folder of the current folder".into()
+    }
+}
@@ -0,0 +1,100 @@
+use clap::Parser;
+use colored::Colorize;
+use serde::Serialize;
+
+use crate::{
+    codec::{http_client, RequestBuilderExt},
+    files, log,
+    models::Permission,
+    TSFSContext,
+};
+
+use super::{find_in_current_folder, update_keyring, Command};
+
+#[derive(Serialize)]
+pub struct RestoreVersionRequest {
+    /// Challenge of the file signed with its write key
+    #[serde(with = "serde_bytes")]
+    signature: Option<Vec<u8>>,
+}
+
+/// Restore a previous version of a file
+#[derive(Parser, Debug)]
+pub struct RestoreArgs {
+    name: String,
+    /// Version to restore, as listed by `versions`
+    version: i32,
+}
+
+pub struct RestoreCommand;
+
+impl Command for RestoreCommand {
+    fn execute(&self, args: &Vec<String>, ctx: &mut TSFSContext) {
+        match RestoreArgs::try_parse_from(args) {
+            Ok(args) => {
+                if ctx.keyring_tree.is_none() {
+                    log::error("Missing Keyring Tree, not logged ?");
+                    return;
+                }
+
+                let Some(file) = find_in_current_folder(ctx, &args.name) else {
+                    log::error(&format!("Can't find file {}", args.name.red()));
+                    return;
+                };
+
+                let client = http_client(ctx);
+                let signature = files::sign_challenge(ctx, &client, Some(&file), Permission::Write);
+
+                let res = client
+                    .post(format!(
+                        "{}:{}/file/{}/versions/{}/restore",
+                        ctx.endpoint_url.as_ref().unwrap(),
+                        ctx.endpoint_port,
+                        file.file.id,
+                        args.version
+                    ))
+                    .header(
+                        "Authorization",
+                        format!("Bearer {}", ctx.session_token.as_ref().unwrap()),
+                    )
+                    .msg(&RestoreVersionRequest { signature })
+                    .send();
+
+                match res {
+                    Ok(res) => match res.error_for_status() {
+                        Ok(_) => {
+                            log::info(&format!(
+                                "Version {} of {} restored",
+                                args.version,
+                                args.name.green()
+                            ));
+
+                            update_keyring(ctx);
+                        }
+
+                        Err(e) => {
+                            let status = e.status().unwrap();
+
+                            log::error(&format!(
+                                "Can't restore version: {}",
+                                status.to_string().red()
+                            ));
+                        }
+                    },
+
+                    Err(e) => {
+                        log::error(&format!("Error on restore: {}", e.to_string().red()));
+                    }
+                }
+            }
+
+            Err(e) => {
+                println!("{e}");
+            }
+        }
+    }
+
+    fn description(&self) -> String {
+        "Restore a previous version of a file".into()
+    }
+}
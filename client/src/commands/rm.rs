@@ -2,13 +2,29 @@ use clap::Parser;
 use colored::Colorize;
 use serde::Serialize;
 
-use crate::{log, TSFSContext};
+use crate::{
+    codec::{http_client, RequestBuilderExt},
+    crypto::{self, EntryKeyring},
+    files, log,
+    models::{Permission, SigningKeys},
+    TSFSContext,
+};
 
 use super::{update_keyring, Command};
 
 #[derive(Serialize)]
 pub struct DeleteFileRequest {
     file_uid: String,
+    keyring_id: i32,
+    #[serde(with = "serde_bytes")]
+    encrypted_key: Vec<u8>,
+    signing_keys: SigningKeys,
+    /// Signature of the entry, the trash is a root keyring
+    #[serde(with = "serde_bytes")]
+    entry_signature: Option<Vec<u8>>,
+    /// Challenge of the current folder signed with its write key
+    #[serde(with = "serde_bytes")]
+    signature: Option<Vec<u8>>,
 }
 
 /// List the content of the current folder
@@ -36,10 +52,28 @@ impl Command for RmCommand {
                     };
 
                     if let Some(file) = current_keyring.get_file_by_name(&args.name) {
-                        let client = reqwest::blocking::Client::builder()
-                            .danger_accept_invalid_certs(ctx.accept_invalid_cert)
-                            .build()
-                            .unwrap();
+                        // The key is kept in the trash, encrypted with user public key
+                        let encrypted_key =
+                            crypto::rsa_encrypt(&file.key, ctx.public_key.as_ref().unwrap())
+                                .unwrap();
+                        let signing_keys = file
+                            .signing_keys
+                            .rsa_encrypt(ctx.public_key.as_ref().unwrap());
+                        let entry_signature = crypto::sign_entry(
+                            &file.file.id,
+                            &EntryKeyring::Trash(ctx.username.as_ref().unwrap()),
+                            file.permission,
+                            &encrypted_key,
+                            ctx.private_key.as_ref().unwrap(),
+                        );
+
+                        let client = http_client(ctx);
+                        let signature = files::sign_challenge(
+                            ctx,
+                            &client,
+                            current_folder.as_ref(),
+                            Permission::Write,
+                        );
 
                         let res = client
                             .delete(format!(
@@ -51,15 +85,23 @@ impl Command for RmCommand {
                                 "Authorization",
                                 format!("Bearer {}", ctx.session_token.as_ref().unwrap()),
                             )
-                            .json(&DeleteFileRequest {
+                            .msg(&DeleteFileRequest {
                                 file_uid: file.file.id,
+                                keyring_id: file.keyring_id,
+                                encrypted_key,
+                                signing_keys,
+                                entry_signature: Some(entry_signature),
+                                signature,
                             })
                             .send();
 
                         match res {
                             Ok(res) => match res.error_for_status() {
                                 Ok(_) => {
-                                    log::info("File deleted !");
+                                    log::info(&format!(
+                                        "File moved to trash, use {} to get it back",
+                                        "trash restore <name>".green()
+                                    ));
 
                                     update_keyring(ctx);
                                 }
@@ -93,6 +135,6 @@ impl Command for RmCommand {
     }
 
     fn description(&self) -> String {
-        "Remove the given file in the current folder".into()
+        "Move the given file in the current folder to the trash".into()
     }
 }
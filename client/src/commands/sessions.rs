@@ -5,7 +5,10 @@ use clap::Parser;
 use colored::Colorize;
 use serde::Deserialize;
 
-use crate::{log, TSFSContext};
+use crate::{
+    codec::{http_client, ResponseExt},
+    log, TSFSContext,
+};
 
 use super::Command;
 
@@ -58,10 +61,7 @@ impl Command for SessionsCommand {
 }
 
 fn clear_sessions(ctx: &mut TSFSContext) {
-    let client = reqwest::blocking::Client::builder()
-        .danger_accept_invalid_certs(ctx.accept_invalid_cert)
-        .build()
-        .unwrap();
+    let client = http_client(ctx);
 
     let res = client
         .post(format!(
@@ -97,10 +97,7 @@ fn clear_sessions(ctx: &mut TSFSContext) {
 }
 
 fn get_sessions(ctx: &mut TSFSContext) {
-    let client = reqwest::blocking::Client::builder()
-        .danger_accept_invalid_certs(ctx.accept_invalid_cert)
-        .build()
-        .unwrap();
+    let client = http_client(ctx);
 
     let res = client
         .get(format!(
@@ -134,7 +131,7 @@ fn get_sessions(ctx: &mut TSFSContext) {
         }
     };
 
-    let sessions = res.json::<Vec<SessionInfo>>().unwrap();
+    let sessions = res.msg::<Vec<SessionInfo>>().unwrap();
 
     log::info(&format!("You have {} active sessions: ", sessions.len()));
     for session in sessions {
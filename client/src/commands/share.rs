@@ -1,19 +1,42 @@
+use std::time::Duration;
+
 use clap::Parser;
 use colored::Colorize;
+use reqwest::{blocking::Client, StatusCode};
 use serde::Serialize;
 
-use crate::{crypto, log, TSFSContext};
+use crate::{
+    codec::{http_client, RequestBuilderExt},
+    crypto::{self, EntryKeyring},
+    files, log,
+    models::{DirectoryEntry, Permission, SigningKeys},
+    TSFSContext,
+};
 
-use super::Command;
+use super::{expiration_millis, parse_duration, update_keyring, Command};
 
 #[derive(Serialize)]
 pub struct ShareFileRequest {
     /// File to share
     file_uid: String,
     /// Symmetric key of the file, encrypted with target_user public key
+    #[serde(with = "serde_bytes")]
     encrypted_key: Vec<u8>,
     /// The user to share the file with
     target_user: String,
+    /// Access level granted to the user, read or write
+    permission: Permission,
+    /// Signing private keys of the file up to the granted level, encrypted with
+    /// target_user public key
+    signing_keys: SigningKeys,
+    /// Signature of the entry, target_user checks it comes from the user
+    #[serde(with = "serde_bytes")]
+    entry_signature: Option<Vec<u8>>,
+    /// Challenge of the file signed with the key of the granted level
+    #[serde(with = "serde_bytes")]
+    signature: Option<Vec<u8>>,
+    /// End of the share in millis, None if it doesn't expire
+    expires_at: Option<i64>,
 }
 
 /// Share a file
@@ -21,6 +44,12 @@ pub struct ShareFileRequest {
 pub struct ShareArgs {
     filename: String,
     username: String,
+    /// Only allow the user to read the file, not to modify it
+    #[arg(long)]
+    read_only: bool,
+    /// Revoke the share after the given time, e.g. 30m, 12h, 7d or 2w
+    #[arg(long, value_parser = parse_duration)]
+    expires: Option<Duration>,
 }
 
 pub struct ShareCommand;
@@ -42,45 +71,49 @@ impl Command for ShareCommand {
                     };
 
                     if let Some(file) = current_keyring.get_file_by_name(&args.filename) {
-                        let client = reqwest::blocking::Client::builder()
-                            .danger_accept_invalid_certs(ctx.accept_invalid_cert)
-                            .build()
-                            .unwrap();
-
-                        // First, request the public key of the user
-                        let user_pubkey = match client
-                            .get(format!(
-                                "{}:{}/pubkey/{}",
-                                ctx.endpoint_url.as_ref().unwrap(),
-                                ctx.endpoint_port,
-                                args.username
-                            ))
-                            .header(
-                                "Authorization",
-                                format!("Bearer {}", ctx.session_token.as_ref().unwrap()),
-                            )
-                            .send()
-                        {
-                            Ok(res) => match res.error_for_status() {
-                                Ok(res) => res.json::<Vec<u8>>().unwrap(),
+                        let client = http_client(ctx);
 
-                                Err(e) => {
-                                    log::error(&format!(
-                                        "Error while requesting user pubkey {}",
-                                        e
-                                    ));
-                                    return;
-                                }
-                            },
+                        // The server gives a dummy key for unknown users, check the name first
+                        let Some(target) = find_user(ctx, &client, &args.username) else {
+                            return;
+                        };
 
-                            Err(e) => {
-                                log::error(&format!("Error while requesting user pubkey {}", e));
-                                return;
-                            }
+                        // Then, request the public key of the user
+                        let Some(user_pubkey) =
+                            files::get_user_public_key(ctx, &client, &args.username)
+                        else {
+                            return;
+                        };
+
+                        // Give the signing keys up to the granted level
+                        let permission = if args.read_only {
+                            Permission::Read
+                        } else {
+                            Permission::Write
                         };
 
                         // Encrypt the file symmetric key with user pubkey
+                        // Groups get the key in their keyring, users in their inbox
                         let enc_key = crypto::rsa_encrypt(&file.key, &user_pubkey).unwrap();
+                        let keyring = if target.group {
+                            EntryKeyring::Root(&target.username)
+                        } else {
+                            EntryKeyring::Inbox(&target.username)
+                        };
+                        let entry_signature = crypto::sign_entry(
+                            &file.file.id,
+                            &keyring,
+                            permission,
+                            &enc_key,
+                            ctx.private_key.as_ref().unwrap(),
+                        );
+                        let signing_keys = file
+                            .signing_keys
+                            .restrict(permission)
+                            .rsa_encrypt(&user_pubkey);
+                        let signature =
+                            files::sign_challenge(ctx, &client, Some(&file), permission);
+                        let expires_at = args.expires.map(expiration_millis);
 
                         // Send the share request
                         let res = client
@@ -93,31 +126,53 @@ impl Command for ShareCommand {
                                 "Authorization",
                                 format!("Bearer {}", ctx.session_token.as_ref().unwrap()),
                             )
-                            .json(&ShareFileRequest {
+                            .msg(&ShareFileRequest {
                                 file_uid: file.file.id,
                                 encrypted_key: enc_key,
                                 target_user: args.username.clone(),
+                                permission,
+                                signing_keys,
+                                entry_signature: Some(entry_signature),
+                                signature,
+                                expires_at,
                             })
                             .send();
 
                         match res {
                             Ok(res) => match res.error_for_status() {
+                                // Groups get the file right away, users get an invitation
+                                Ok(res) if res.status() == StatusCode::ACCEPTED => {
+                                    log::info(&format!(
+                                        "Invitation sent to {}, the file is shared once accepted",
+                                        args.username.green()
+                                    ));
+                                }
+
                                 Ok(_) => {
                                     log::info(&format!(
-                                        "File shared with {} !",
+                                        "{} shared with group {}",
+                                        args.filename.green(),
                                         args.username.green()
                                     ));
 
-                                    // update_keyring(ctx);
+                                    update_keyring(ctx);
                                 }
 
                                 Err(e) => {
                                     let status = e.status().unwrap();
 
-                                    log::error(&format!(
-                                        "Can't share file: {}",
-                                        status.to_string().red()
-                                    ));
+                                    if status == StatusCode::CONFLICT {
+                                        log::error(&format!(
+                                            "{} already has access to {}",
+                                            args.username.red(),
+                                            args.filename.red()
+                                        ));
+                                    } else {
+                                        log::error(&format!(
+                                            "Can't share file: {}",
+                                            status.to_string().red()
+                                        ));
+                                    }
                                 }
                             },
 
@@ -143,3 +198,44 @@ impl Command for ShareCommand {
         "Share the given file in the current folder to the given user".into()
     }
 }
+
+/// Find a user or a group in the directory of the server, suggest the close names
+/// otherwise
+fn find_user(ctx: &TSFSContext, client: &Client, username: &str) -> Option<DirectoryEntry> {
+    let users_page = files::search_users(ctx, client, username, 0)?;
+
+    // The search ignores the case, the exact name isn't always the first one
+    if let Some(entry) = users_page
+        .users
+        .iter()
+        .find(|entry| entry.username == username)
+    {
+        return Some(entry.clone());
+    }
+
+    // No name starts with it, suggest the ones starting with the same letter
+    let suggestions = if users_page.users.is_empty() {
+        let first_letter: String = username.chars().take(1).collect();
+
+        files::search_users(ctx, client, &first_letter, 0)
+            .map(|users_page| users_page.users)
+            .unwrap_or_default()
+    } else {
+        users_page.users
+    };
+
+    log::error(&format!("Unknown user {}", username.red()));
+
+    if !suggestions.is_empty() {
+        log::info(&format!(
+            "Did you mean {} ?",
+            suggestions
+                .iter()
+                .map(|entry| entry.username.green().to_string())
+                .collect::<Vec<String>>()
+                .join(", ")
+        ));
+    }
+
+    None
+}
@@ -0,0 +1,292 @@
+use std::time::{Duration, UNIX_EPOCH};
+
+use chrono::prelude::*;
+use clap::{Parser, Subcommand};
+use colored::Colorize;
+use serde::Serialize;
+
+use crate::{
+    codec::{http_client, RequestBuilderExt, ResponseExt},
+    crypto::EntryKeyring,
+    files, log,
+    models::{KeyringWithKeysAndFiles, Permission, SigningKeys, TrashedFile},
+    TSFSContext,
+};
+
+use super::{
+    encrypt_key_for, encrypt_signing_keys_for, sign_entry_for, signer_keys, update_keyring, Command,
+};
+
+#[derive(Serialize)]
+pub struct RestoreFileRequest {
+    file_uid: String,
+    keyring_id: i32,
+    #[serde(with = "serde_bytes")]
+    encrypted_key: Vec<u8>,
+    signing_keys: SigningKeys,
+    /// Signature of the entry, when restored in root
+    #[serde(with = "serde_bytes")]
+    entry_signature: Option<Vec<u8>>,
+    /// Challenge of the destination folder signed with its write key
+    #[serde(with = "serde_bytes")]
+    signature: Option<Vec<u8>>,
+}
+
+/// Manage the deleted files
+#[derive(Parser, Debug)]
+pub struct TrashArgs {
+    #[command(subcommand)]
+    action: TrashAction,
+}
+
+#[derive(Subcommand, Debug)]
+enum TrashAction {
+    /// List the files in the trash
+    Ls,
+    /// Restore a file where it was deleted from
+    Restore { name: String },
+    /// Delete the files in the trash for good
+    Empty,
+}
+
+pub struct TrashCommand;
+
+impl Command for TrashCommand {
+    fn execute(&self, args: &Vec<String>, ctx: &mut TSFSContext) {
+        match TrashArgs::try_parse_from(args) {
+            Ok(args) => {
+                if ctx.keyring_tree.is_none() {
+                    log::error("Missing Keyring Tree, not logged ?");
+                    return;
+                }
+
+                match args.action {
+                    TrashAction::Ls => list_trash(ctx),
+                    TrashAction::Restore { name } => restore_file(ctx, &name),
+                    TrashAction::Empty => empty_trash(ctx),
+                }
+            }
+
+            Err(e) => {
+                println!("{e}");
+            }
+        }
+    }
+
+    fn description(&self) -> String {
+        "List, restore or delete for good the files in the trash".into()
+    }
+}
+
+/// Get the files in the trash, with their key and name decrypted
+fn get_trash(ctx: &TSFSContext) -> Option<Vec<TrashedFile>> {
+    let client = http_client(ctx);
+
+    let res = client
+        .get(format!(
+            "{}:{}/trash",
+            ctx.endpoint_url.as_ref().unwrap(),
+            ctx.endpoint_port
+        ))
+        .header(
+            "Authorization",
+            format!("Bearer {}", ctx.session_token.as_ref().unwrap()),
+        )
+        .send();
+
+    match res {
+        Ok(res) => match res.error_for_status() {
+            Ok(res) => {
+                let trashed = res.msg::<Vec<TrashedFile>>().unwrap();
+
+                // Trashed keys are encrypted with user public key, like the root keyring
+                let keyring = KeyringWithKeysAndFiles {
+                    id: 0,
+                    keys: trashed.iter().map(|t| t.key.clone()).collect(),
+                };
+                let signer_keys = signer_keys(ctx, &client, &keyring, Some(&[]));
+                let keyring = KeyringWithKeysAndFiles::from_encrypted(
+                    keyring,
+                    ctx.private_key.as_ref().unwrap(),
+                    None,
+                    &EntryKeyring::Trash(ctx.username.as_ref().unwrap()),
+                    &signer_keys,
+                );
+
+                // The keys with an invalid signature are dropped along with their file
+                Some(
+                    trashed
+                        .into_iter()
+                        .filter_map(|trashed_file| {
+                            let key = keyring
+                                .keys
+                                .iter()
+                                .find(|key| key.file.id == trashed_file.key.file.id)?;
+
+                            Some(TrashedFile {
+                                key: key.clone(),
+                                ..trashed_file
+                            })
+                        })
+                        .collect(),
+                )
+            }
+
+            Err(e) => {
+                let status = e.status().unwrap();
+
+                log::error(&format!("Can't get trash: {}", status.to_string().red()));
+
+                None
+            }
+        },
+
+        Err(e) => {
+            log::error(&format!("Error on trash: {}", e.to_string().red()));
+
+            None
+        }
+    }
+}
+
+fn list_trash(ctx: &TSFSContext) {
+    let Some(trashed) = get_trash(ctx) else {
+        return;
+    };
+
+    if trashed.is_empty() {
+        log::info("Trash is empty");
+        return;
+    }
+
+    println!("{} {}", "----".cyan(), "Trash".cyan());
+
+    for trashed_file in trashed {
+        let trashed_at = DateTime::<Local>::from(
+            UNIX_EPOCH + Duration::from_millis(trashed_file.trashed_at as u64),
+        )
+        .format("%Y-%m-%d %H:%M:%S");
+
+        if trashed_file.key.file.is_folder() {
+            println!("{} {}", trashed_at, trashed_file.key.file.name.cyan());
+        } else {
+            println!("{} {}", trashed_at, trashed_file.key.file.name);
+        }
+    }
+}
+
+fn restore_file(ctx: &mut TSFSContext, name: &str) {
+    let Some(trashed) = get_trash(ctx) else {
+        return;
+    };
+
+    // Restore the last deleted file with this name
+    let Some(trashed_file) = trashed
+        .into_iter()
+        .filter(|t| t.key.file.name == name)
+        .max_by_key(|t| t.trashed_at)
+    else {
+        log::error(&format!("Can't find {} in the trash", name.red()));
+        return;
+    };
+
+    // Encrypt the file key for its origin keyring, the root if the folder is gone
+    let keyring_tree = ctx.keyring_tree.as_ref().unwrap();
+    let origin_folder = trashed_file
+        .origin
+        .filter(|origin| *origin != keyring_tree.id)
+        .and_then(|origin| keyring_tree.get_folder_by_keyring(origin));
+
+    if origin_folder.is_none() && trashed_file.origin.is_some_and(|o| o != keyring_tree.id) {
+        log::warning("Original folder can't be found, restoring to root");
+    }
+
+    let (keyring_id, encrypted_key) =
+        encrypt_key_for(ctx, origin_folder.as_ref(), &trashed_file.key.key);
+    let signing_keys =
+        encrypt_signing_keys_for(ctx, origin_folder.as_ref(), &trashed_file.key.signing_keys);
+    let entry_signature = sign_entry_for(
+        ctx,
+        origin_folder.as_ref(),
+        &trashed_file.key.file.id,
+        trashed_file.key.permission,
+        &encrypted_key,
+    );
+
+    let client = http_client(ctx);
+    let signature = files::sign_challenge(ctx, &client, origin_folder.as_ref(), Permission::Write);
+
+    let res = client
+        .post(format!(
+            "{}:{}/trash/restore",
+            ctx.endpoint_url.as_ref().unwrap(),
+            ctx.endpoint_port
+        ))
+        .header(
+            "Authorization",
+            format!("Bearer {}", ctx.session_token.as_ref().unwrap()),
+        )
+        .msg(&RestoreFileRequest {
+            file_uid: trashed_file.key.file.id,
+            keyring_id,
+            encrypted_key,
+            signing_keys,
+            entry_signature,
+            signature,
+        })
+        .send();
+
+    match res {
+        Ok(res) => match res.error_for_status() {
+            Ok(_) => {
+                log::info(&format!("{} restored", name.green()));
+
+                update_keyring(ctx);
+            }
+
+            Err(e) => {
+                let status = e.status().unwrap();
+
+                log::error(&format!("Can't restore file: {}", status.to_string().red()));
+            }
+        },
+
+        Err(e) => {
+            log::error(&format!("Error on trash restore: {}", e.to_string().red()));
+        }
+    }
+}
+
+fn empty_trash(ctx: &TSFSContext) {
+    let client = http_client(ctx);
+
+    let res = client
+        .delete(format!(
+            "{}:{}/trash",
+            ctx.endpoint_url.as_ref().unwrap(),
+            ctx.endpoint_port
+        ))
+        .header(
+            "Authorization",
+            format!("Bearer {}", ctx.session_token.as_ref().unwrap()),
+        )
+        .send();
+
+    match res {
+        Ok(res) => match res.error_for_status() {
+            Ok(_) => {
+                log::info("Trash emptied !");
+            }
+
+            Err(e) => {
+                let status = e.status().unwrap();
+
+                log::error(&format!("Can't empty trash: {}", status.to_string().red()));
+            }
+        },
+
+        Err(e) => {
+            log::error(&format!("Error on trash empty: {}", e.to_string().red()));
+        }
+    }
+}
@@ -1,15 +1,24 @@
 use base64::prelude::*;
 use chacha20poly1305::{
-    aead::{Aead, AeadCore, KeyInit, OsRng},
+    aead::{KeyInit, OsRng},
     ChaCha20Poly1305,
 };
 use clap::Parser;
 use colored::Colorize;
+use reqwest::{blocking::Client, StatusCode};
 use serde::Serialize;
 
-use crate::{crypto, log, TSFSContext};
+use crate::{
+    codec::{http_client, RequestBuilderExt},
+    crypto::{self, EntryKeyring},
+    files, log,
+    models::{KeyWithFile, Permission, PublicKeys, SigningKeys},
+    TSFSContext,
+};
 
-use super::{download_file, update_keyring, Command};
+use super::{
+    encrypt_signing_keys_for, generate_signing_keys, sign_entry_for, update_keyring, Command,
+};
 
 #[derive(Serialize)]
 pub struct RevokeShareFileRequest {
@@ -21,17 +30,70 @@ pub struct RevokeShareFileRequest {
     /// This will move the file with this current implementation
     parent_uid: Option<String>,
     /// New Symmetric key of the file, encrypted with parent
+    #[serde(with = "serde_bytes")]
+    encrypted_key: Vec<u8>,
+    /// New encrypted filename
+    filename: String,
+    /// Upload holding the new encrypted file content, None if folder
+    upload_id: Option<String>,
+    /// New signing public keys of the file
+    public_keys: PublicKeys,
+    /// New signing private keys of the file, encrypted with parent
+    signing_keys: SigningKeys,
+    /// Signature of the new encrypted key, when kept in root
+    #[serde(with = "serde_bytes")]
+    entry_signature: Option<Vec<u8>>,
+    /// Challenge of the file signed with its current owner key
+    #[serde(with = "serde_bytes")]
+    signature: Option<Vec<u8>>,
+    /// New keys of everything inside a revoked folder, empty for a file
+    descendants: Vec<RevokedDescendant>,
+    /// User to revoke, the other users with a direct grant keep their access
+    /// None revokes everyone
+    user: Option<String>,
+    /// New key of each remaining user with a direct grant, empty when revoking everyone
+    grants: Vec<RenewedGrant>,
+}
+
+#[derive(Serialize)]
+pub struct RevokedDescendant {
+    /// File or folder inside the revoked folder, at any depth
+    file_uid: String,
+    /// New Symmetric key of the file, encrypted with the new key of its folder
+    #[serde(with = "serde_bytes")]
     encrypted_key: Vec<u8>,
     /// New encrypted filename
     filename: String,
-    /// New encrypted file content
-    file: Option<Vec<u8>>,
+    /// Upload holding the new encrypted file content, None if folder
+    upload_id: Option<String>,
+    /// New signing public keys of the file
+    public_keys: PublicKeys,
+    /// New signing private keys of the file, encrypted with the new ones of its folder
+    signing_keys: SigningKeys,
 }
 
-/// Unshare a file
+#[derive(Serialize)]
+pub struct RenewedGrant {
+    /// User or group keeping his access to the file, a user gets the new key as an
+    /// invitation
+    username: String,
+    /// New Symmetric key of the file, encrypted with the user public key
+    #[serde(with = "serde_bytes")]
+    encrypted_key: Vec<u8>,
+    /// New signing private keys of the file up to his access level, encrypted with the
+    /// user public key
+    signing_keys: SigningKeys,
+    /// Signature of the new encrypted key, the user checks it comes from the owner
+    #[serde(with = "serde_bytes")]
+    entry_signature: Option<Vec<u8>>,
+}
+
+/// Unshare a file or a folder, re-encrypting everything inside
 #[derive(Parser, Debug)]
 pub struct UnshareArgs {
     filename: String,
+    /// Only revoke this user, the other users keep their access
+    username: Option<String>,
 }
 
 pub struct UnshareCommand;
@@ -53,97 +115,206 @@ impl Command for UnshareCommand {
                     };
 
                     if let Some(file) = current_keyring.get_file_by_name(&args.filename) {
-                        if file.file.is_folder() {
-                            log::warning("Unshare for folders not implemented yet :(");
+                        // Only the owner holds the owner signing key, legacy files are
+                        // checked by the server
+                        let is_owner = file.file.owner == ctx.username
+                            || file.signing_keys.owner.is_some()
+                            || file.signing_keys.is_empty();
+
+                        if !is_owner {
+                            log::error(&not_owner_message(&file.file.name, &file.file.owner));
                             return;
                         }
 
-                        // Get file
-                        if let Some(file) = download_file(ctx, file) {
+                        let client = http_client(ctx);
 
-                            // Encrypt file
-                            let mut rng = OsRng;
+                        // Users keeping their access get the new keys with their public key,
+                        // in their inbox as they may hold the file in one of their folders
+                        let mut remaining_grants = Vec::new();
+                        if let Some(username) = &args.username {
+                            let Some(access) = files::get_access(ctx, &client, &file.file.id)
+                            else {
+                                return;
+                            };
+                            let grants = access.users;
 
-                            let file_key = ChaCha20Poly1305::generate_key(&mut OsRng);
-                            let cipher = ChaCha20Poly1305::new(&file_key);
+                            if !grants.iter().any(|grant| grant.username == *username) {
+                                log::error(&format!(
+                                    "{} has no direct access to {}",
+                                    username.red(),
+                                    args.filename.red()
+                                ));
+                                return;
+                            }
 
-                            let file_content_ciphertext = if let Some(data) = file.data {
-                                let nonce = ChaCha20Poly1305::generate_nonce(&mut rng);
+                            for grant in grants {
+                                if grant.username == *username
+                                    || Some(&grant.username) == ctx.username.as_ref()
+                                {
+                                    continue;
+                                }
 
-                                let encrypted_file = cipher
-                                    .encrypt(&nonce, data.as_slice())
-                                    .unwrap();
+                                let Some(pubkey) =
+                                    files::get_user_public_key(ctx, &client, &grant.username)
+                                else {
+                                    return;
+                                };
 
-                                Some([nonce.to_vec(), encrypted_file].concat())
-                            } else {
-                                None
-                            };
+                                remaining_grants.push((grant, pubkey));
+                            }
+                        }
 
-                            let nonce = ChaCha20Poly1305::generate_nonce(&mut rng);
+                        let file_key = ChaCha20Poly1305::generate_key(&mut OsRng).to_vec();
 
-                            let encrypted_filename =
-                                cipher.encrypt(&nonce, file.name.as_bytes()).unwrap();
-                            let filename_ciphertext = [nonce.to_vec(), encrypted_filename].concat();
-                            let filename_base64 = BASE64_STANDARD.encode(filename_ciphertext);
+                        // Re-encrypt file name and content with the new key
+                        let Some((filename, upload_id)) =
+                            rekey_file(ctx, &client, &file, &file_key)
+                        else {
+                            return;
+                        };
 
-                            let encrypted_key;
-                            if let Some(current_folder) = ctx.current_folder.last() {
-                                let current_folder = ctx
-                                    .keyring_tree
-                                    .as_ref()
-                                    .unwrap()
-                                    .get_file(&current_folder)
+                        let encrypted_key;
+                        if let Some(current_folder) = &current_folder {
+                            encrypted_key =
+                                crypto::chacha_encrypt(&file_key, &current_folder.key).unwrap();
+                        } else {
+                            // Encrypt file key with user public key
+                            encrypted_key =
+                                crypto::rsa_encrypt(&file_key, ctx.public_key.as_ref().unwrap())
                                     .unwrap();
+                        }
+                        let entry_signature = sign_entry_for(
+                            ctx,
+                            current_folder.as_ref(),
+                            &file.file.id,
+                            Permission::Owner,
+                            &encrypted_key,
+                        );
 
-                                let key = current_folder.key;
-                                encrypted_key = crypto::chacha_encrypt(&file_key, &key).unwrap();
-                            } else {
-                                // Encrypt file key with user public key
-                                encrypted_key = crypto::rsa_encrypt(
-                                    &file_key,
-                                    ctx.public_key.as_ref().unwrap(),
-                                )
-                                .unwrap();
-                            }
+                        // The revoked users may hold the signing keys, they are replaced
+                        let (new_signing_keys, public_keys) =
+                            generate_signing_keys(current_folder.as_ref());
+                        let signing_keys = encrypt_signing_keys_for(
+                            ctx,
+                            current_folder.as_ref(),
+                            &new_signing_keys,
+                        );
 
-                            let client = reqwest::blocking::Client::builder()
-                                .danger_accept_invalid_certs(ctx.accept_invalid_cert)
-                                .build()
-                                .unwrap();
-
-                            match client
-                                .post(format!(
-                                    "{}:{}/file/unshare",
-                                    ctx.endpoint_url.as_ref().unwrap(),
-                                    ctx.endpoint_port
-                                ))
-                                .header(
-                                    "Authorization",
-                                    format!("Bearer {}", ctx.session_token.as_ref().unwrap()),
-                                )
-                                .json(&RevokeShareFileRequest {
-                                    file_uid: file.id,
-                                    parent_uid: ctx.current_folder.last().cloned(),
-                                    filename: filename_base64,
-                                    file: file_content_ciphertext,
+                        let grants = remaining_grants
+                            .iter()
+                            .map(|(grant, pubkey)| {
+                                let encrypted_key = crypto::rsa_encrypt(&file_key, pubkey).unwrap();
+                                let keyring = if grant.group {
+                                    EntryKeyring::Root(&grant.username)
+                                } else {
+                                    EntryKeyring::Inbox(&grant.username)
+                                };
+                                let entry_signature = crypto::sign_entry(
+                                    &file.file.id,
+                                    &keyring,
+                                    grant.permission,
+                                    &encrypted_key,
+                                    ctx.private_key.as_ref().unwrap(),
+                                );
+
+                                RenewedGrant {
+                                    username: grant.username.clone(),
                                     encrypted_key,
-                                })
-                                .send()
-                            {
-                                Ok(res) => match res.error_for_status() {
-                                    Ok(_res) => {
-                                        log::info("File unshare success !");
+                                    signing_keys: new_signing_keys
+                                        .restrict(grant.permission)
+                                        .rsa_encrypt(pubkey),
+                                    entry_signature: Some(entry_signature),
+                                }
+                            })
+                            .collect();
 
-                                        update_keyring(ctx);
-                                    }
+                        // Everything inside a folder gets new keys too, the revoked users
+                        // know the previous ones
+                        let mut descendants = Vec::new();
+                        if file.file.is_folder() {
+                            log::info("Re-encrypting the folder content...");
+
+                            let new_folder = KeyWithFile {
+                                key: file_key,
+                                signing_keys: new_signing_keys,
+                                ..file.clone()
+                            };
+
+                            if !rekey_descendants(
+                                ctx,
+                                &client,
+                                &file,
+                                &new_folder,
+                                &mut descendants,
+                            ) {
+                                return;
+                            }
+                        }
+
+                        let signature =
+                            files::sign_challenge(ctx, &client, Some(&file), Permission::Owner);
 
-                                    Err(e) => {
-                                        log::error(&format!(
-                                            "Error on file unshare: {}",
-                                            e.to_string().red()
+                        match client
+                            .post(format!(
+                                "{}:{}/file/unshare",
+                                ctx.endpoint_url.as_ref().unwrap(),
+                                ctx.endpoint_port
+                            ))
+                            .header(
+                                "Authorization",
+                                format!("Bearer {}", ctx.session_token.as_ref().unwrap()),
+                            )
+                            .msg(&RevokeShareFileRequest {
+                                file_uid: file.file.id.clone(),
+                                parent_uid: ctx.current_folder.last().cloned(),
+                                filename,
+                                upload_id,
+                                encrypted_key,
+                                public_keys,
+                                signing_keys,
+                                entry_signature,
+                                signature,
+                                descendants,
+                                user: args.username.clone(),
+                                grants,
+                            })
+                            .send()
+                        {
+                            Ok(res) => match res.error_for_status() {
+                                Ok(_res) => {
+                                    if let Some(username) = &args.username {
+                                        log::info(&format!(
+                                            "Access of {} to {} revoked",
+                                            username.green(),
+                                            args.filename.green()
                                         ));
+
+                                        if remaining_grants.iter().any(|(grant, _)| !grant.group) {
+                                            log::info(
+                                                "The others get the file back as an invitation",
+                                            );
+                                        }
+                                    } else {
+                                        log::info("File unshare success !");
                                     }
-                                },
+
+                                    update_keyring(ctx);
+                                }
+
+                                Err(e) if e.status() == Some(StatusCode::FORBIDDEN) => {
+                                    log::error(&not_owner_message(
+                                        &file.file.name,
+                                        &file.file.owner,
+                                    ));
+                                }
+
+                                // Only the members of a group can give it the new key
+                                Err(e) if e.status() == Some(StatusCode::CONFLICT) => {
+                                    log::error(&format!(
+                                        "A group you aren't in holds {}, revoke every share",
+                                        args.filename.red()
+                                    ));
+                                }
 
                                 Err(e) => {
                                     log::error(&format!(
@@ -151,8 +322,15 @@ impl Command for UnshareCommand {
                                         e.to_string().red()
                                     ));
                                 }
-                            };
-                        }
+                            },
+
+                            Err(e) => {
+                                log::error(&format!(
+                                    "Error on file unshare: {}",
+                                    e.to_string().red()
+                                ));
+                            }
+                        };
                     } else {
                         log::error(&format!("Can't find file {}", args.filename.red()));
                     }
@@ -168,6 +346,89 @@ impl Command for UnshareCommand {
     }
 
     fn description(&self) -> String {
-        "Share the given file in the current folder to the given user".into()
+        "Revoke the shares of the given file in the current folder, or only the given user".into()
+    }
+}
+
+/// Error shown when a user who doesn't own a file tries to revoke its shares
+fn not_owner_message(name: &str, owner: &Option<String>) -> String {
+    match owner {
+        Some(owner) => format!(
+            "Only {} can revoke the shares of {}",
+            owner.green(),
+            name.red()
+        ),
+        None => format!("Only the owner of {} can revoke its shares", name.red()),
     }
 }
+
+/// Encrypt the name and the content of a file with a new key
+///
+/// Return the encrypted name and the upload holding the re-encrypted content, None if folder
+fn rekey_file(
+    ctx: &TSFSContext,
+    client: &Client,
+    file: &KeyWithFile,
+    new_key: &[u8],
+) -> Option<(String, Option<String>)> {
+    let encrypted_name = crypto::chacha_encrypt(file.file.name.as_bytes(), new_key).unwrap();
+    let filename = BASE64_STANDARD.encode(encrypted_name);
+
+    if file.file.is_folder() {
+        return Some((filename, None));
+    }
+
+    // Re-encrypt file content with the new key, chunk by chunk
+    let file_info = files::get_file_info(ctx, client, &file.file.id)?;
+    let upload_id = files::reencrypt_stream(ctx, client, &file_info, &file.key, new_key)?;
+
+    Some((filename, Some(upload_id)))
+}
+
+/// Generate new keys for everything inside `folder`, recursively
+///
+/// `new_folder` holds the new keys of the folder, the new keys of its content are encrypted
+/// with them. Return false if a content couldn't be re-encrypted.
+fn rekey_descendants(
+    ctx: &TSFSContext,
+    client: &Client,
+    folder: &KeyWithFile,
+    new_folder: &KeyWithFile,
+    descendants: &mut Vec<RevokedDescendant>,
+) -> bool {
+    let keyring = folder.file.keyring.as_ref().unwrap();
+
+    for child in &keyring.keys {
+        let child_key = ChaCha20Poly1305::generate_key(&mut OsRng).to_vec();
+
+        let Some((filename, upload_id)) = rekey_file(ctx, client, child, &child_key) else {
+            log::error(&format!("Can't re-encrypt {}", child.file.name.red()));
+            return false;
+        };
+
+        let (signing_keys, public_keys) = generate_signing_keys(Some(new_folder));
+
+        descendants.push(RevokedDescendant {
+            file_uid: child.file.id.clone(),
+            encrypted_key: crypto::chacha_encrypt(&child_key, &new_folder.key).unwrap(),
+            filename,
+            upload_id,
+            public_keys,
+            signing_keys: encrypt_signing_keys_for(ctx, Some(new_folder), &signing_keys),
+        });
+
+        if child.file.is_folder() {
+            let new_child = KeyWithFile {
+                key: child_key,
+                signing_keys,
+                ..child.clone()
+            };
+
+            if !rekey_descendants(ctx, client, child, &new_child, descendants) {
+                return false;
+            }
+        }
+    }
+
+    true
+}
@@ -1,17 +1,26 @@
 use base64::prelude::*;
 use chacha20poly1305::{
     aead::{Aead, AeadCore, KeyInit},
-    ChaCha20Poly1305,
+    ChaCha20Poly1305, Key,
 };
 use clap::Parser;
 use colored::Colorize;
 use rsa::rand_core::OsRng;
 use serde::Serialize;
 use std::{fs, path::Path};
+use uuid::Uuid;
 
-use crate::{crypto, log, TSFSContext};
+use crate::{
+    codec::{http_client, RequestBuilderExt},
+    crypto, files, log,
+    models::{Permission, PublicKeys, SigningKeys},
+    TSFSContext,
+};
 
-use super::{update_keyring, Command};
+use super::{
+    encrypt_signing_keys_for, find_in_current_folder, generate_signing_keys, sign_entry_for,
+    update_keyring, Command,
+};
 
 pub struct UploadFileCommand;
 
@@ -22,16 +31,30 @@ pub struct UploadFileArgs {
 }
 
 #[derive(Serialize)]
-pub struct UploadFileRequest {
+pub struct CommitUploadRequest {
     /// The parent folder to put the file in.
     /// None = root
     parent_uid: Option<String>,
     /// Encrypted filename
     filename: String,
-    /// Encrypted file content
-    file: Vec<u8>,
     /// Encrypted symmetric key with user pubkey
+    #[serde(with = "serde_bytes")]
     encrypted_key: Vec<u8>,
+    /// Existing file to overwrite, its previous content is kept as a version
+    file_uid: Option<String>,
+    /// Id of a new file, its entry is signed with it
+    new_file_uid: Option<String>,
+    /// Signing public keys of a new file
+    public_keys: PublicKeys,
+    /// Signing private keys of a new file, encrypted like its key
+    signing_keys: SigningKeys,
+    /// Signature of the entry, for a new file in root
+    #[serde(with = "serde_bytes")]
+    entry_signature: Option<Vec<u8>>,
+    /// Challenge of the file to overwrite, or of the parent folder for a new file,
+    /// signed with its write key
+    #[serde(with = "serde_bytes")]
+    signature: Option<Vec<u8>>,
 }
 
 impl Command for UploadFileCommand {
@@ -49,39 +72,52 @@ impl Command for UploadFileCommand {
                 log::debug(file_path.to_str().unwrap());
 
                 // Get local file
-                if let Ok(file_content) = fs::read(file_path) {
-                    // Encrypt file
+                if let Ok(mut local_file) = fs::File::open(file_path) {
                     let mut rng = OsRng;
 
-                    let file_key = ChaCha20Poly1305::generate_key(&mut OsRng);
+                    // A file with the same name is overwritten, the new content is encrypted
+                    // with its key so that previous versions stay readable
+                    let filename = file_path.file_name().unwrap().to_str().unwrap();
+                    let existing_file = find_in_current_folder(ctx, filename);
+
+                    if existing_file.as_ref().is_some_and(|f| f.file.is_folder()) {
+                        log::error(&format!("A folder named {} already exists", filename.red()));
+                        return;
+                    }
+
+                    let file_key = if let Some(existing_file) = &existing_file {
+                        log::info("File already exists, uploading a new version");
+                        Key::clone_from_slice(&existing_file.key)
+                    } else {
+                        ChaCha20Poly1305::generate_key(&mut OsRng)
+                    };
                     let cipher = ChaCha20Poly1305::new(&file_key);
-                    let nonce = ChaCha20Poly1305::generate_nonce(&mut rng);
 
-                    let encrypted_file = cipher.encrypt(&nonce, file_content.as_ref()).unwrap();
-                    let file_content_ciphertext = [nonce.to_vec(), encrypted_file].concat();
+                    let client = http_client(ctx);
+
+                    // Encrypt and upload file content chunk by chunk
+                    log::info("Uploading file content...");
+                    let Some(upload_id) =
+                        files::upload_stream(ctx, &client, &mut local_file, &file_key)
+                    else {
+                        return;
+                    };
 
                     let nonce = ChaCha20Poly1305::generate_nonce(&mut rng);
 
-                    let encrypted_filename = cipher
-                        .encrypt(
-                            &nonce,
-                            file_path.file_name().unwrap().to_str().unwrap().as_bytes(),
-                        )
-                        .unwrap();
+                    let encrypted_filename = cipher.encrypt(&nonce, filename.as_bytes()).unwrap();
                     let filename_ciphertext = [nonce.to_vec(), encrypted_filename].concat();
                     let filename_base64 = BASE64_STANDARD.encode(filename_ciphertext);
 
+                    let current_folder = ctx
+                        .current_folder
+                        .last()
+                        .and_then(|folder| ctx.keyring_tree.as_ref().unwrap().get_file(folder));
+
                     let encrypted_key;
-                    if let Some(current_folder) = ctx.current_folder.last() {
-                        let current_folder = ctx
-                            .keyring_tree
-                            .as_ref()
-                            .unwrap()
-                            .get_file(&current_folder)
-                            .unwrap();
-
-                        let key = current_folder.key;
-                        encrypted_key = crypto::chacha_encrypt(&file_key, &key).unwrap();
+                    if let Some(current_folder) = &current_folder {
+                        let key = &current_folder.key;
+                        encrypted_key = crypto::chacha_encrypt(&file_key, key).unwrap();
                     } else {
                         // Encrypt file key with user public key
                         encrypted_key =
@@ -89,25 +125,72 @@ impl Command for UploadFileCommand {
                                 .unwrap();
                     }
 
-                    let client = reqwest::blocking::Client::builder()
-                        .danger_accept_invalid_certs(ctx.accept_invalid_cert)
-                        .build()
-                        .unwrap();
+                    // A new file gets its id from the client, its entry is signed with it
+                    let new_file_uid = existing_file.is_none().then(|| Uuid::new_v4().to_string());
+                    let entry_signature = new_file_uid.as_ref().and_then(|file_uid| {
+                        sign_entry_for(
+                            ctx,
+                            current_folder.as_ref(),
+                            file_uid,
+                            Permission::Owner,
+                            &encrypted_key,
+                        )
+                    });
+
+                    // Signing keys are only generated for a new file, the overwritten one
+                    // keeps its keys and needs its write key
+                    let (public_keys, signing_keys, signature) = match &existing_file {
+                        Some(existing_file) => (
+                            PublicKeys::default(),
+                            SigningKeys::default(),
+                            files::sign_challenge(
+                                ctx,
+                                &client,
+                                Some(existing_file),
+                                Permission::Write,
+                            ),
+                        ),
+
+                        None => {
+                            let (signing_keys, public_keys) =
+                                generate_signing_keys(current_folder.as_ref());
+
+                            (
+                                public_keys,
+                                encrypt_signing_keys_for(
+                                    ctx,
+                                    current_folder.as_ref(),
+                                    &signing_keys,
+                                ),
+                                files::sign_challenge(
+                                    ctx,
+                                    &client,
+                                    current_folder.as_ref(),
+                                    Permission::Write,
+                                ),
+                            )
+                        }
+                    };
 
                     match client
                         .post(format!(
-                            "{}:{}/file/upload",
-                            endpoint_url, ctx.endpoint_port
+                            "{}:{}/file/upload/{}/commit",
+                            endpoint_url, ctx.endpoint_port, upload_id
                         ))
                         .header(
                             "Authorization",
                             format!("Bearer {}", ctx.session_token.as_ref().unwrap()),
                         )
-                        .json(&UploadFileRequest {
+                        .msg(&CommitUploadRequest {
                             parent_uid: ctx.current_folder.last().cloned(),
                             filename: filename_base64,
-                            file: file_content_ciphertext,
                             encrypted_key,
+                            file_uid: existing_file.map(|f| f.file.id),
+                            new_file_uid,
+                            public_keys,
+                            signing_keys,
+                            entry_signature,
+                            signature,
                         })
                         .send()
                     {
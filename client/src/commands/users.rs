@@ -0,0 +1,92 @@
+use clap::Parser;
+use colored::Colorize;
+
+use crate::{
+    codec::http_client,
+    files, log,
+    trust::{same_fingerprint, TrustStore},
+    TSFSContext,
+};
+
+use super::Command;
+
+/// List the users and groups you can share with, along with the fingerprints of their keys
+#[derive(Parser, Debug)]
+pub struct UsersArgs {
+    /// Only list the names starting with it
+    prefix: Option<String>,
+    /// Page to list, from 1
+    #[arg(long, default_value_t = 1)]
+    page: usize,
+}
+
+pub struct UsersCommand;
+
+impl Command for UsersCommand {
+    fn execute(&self, args: &Vec<String>, ctx: &mut TSFSContext) {
+        match UsersArgs::try_parse_from(args) {
+            Ok(args) => {
+                if ctx.session_token.is_none() {
+                    log::error("Not connected");
+                    return;
+                }
+
+                let client = http_client(ctx);
+
+                let Some(users_page) = files::search_users(
+                    ctx,
+                    &client,
+                    args.prefix.as_deref().unwrap_or_default(),
+                    args.page.saturating_sub(1),
+                ) else {
+                    return;
+                };
+
+                if users_page.users.is_empty() {
+                    log::info("No user found");
+                    return;
+                }
+
+                let store = TrustStore::load();
+
+                for entry in users_page.users {
+                    let name = if entry.group {
+                        format!("{} (group)", entry.username).cyan()
+                    } else {
+                        entry.username.green()
+                    };
+
+                    // Compare with the pinned key, a different one must be verified
+                    let status = match store.get(ctx, &entry.username) {
+                        Some(pinned)
+                            if !same_fingerprint(&pinned.fingerprint, &entry.fingerprint) =>
+                        {
+                            " (key changed)".red()
+                        }
+                        Some(pinned) if pinned.verified => " (verified)".green(),
+                        Some(_) => " (pinned)".normal(),
+                        None => "".normal(),
+                    };
+
+                    println!("{}{}", name, status);
+                    println!("  {}", entry.fingerprint);
+                }
+
+                if users_page.has_more {
+                    log::info(&format!(
+                        "More users on the next page, use {}",
+                        format!("--page {}", args.page + 1).green()
+                    ));
+                }
+            }
+
+            Err(e) => {
+                println!("{e}");
+            }
+        }
+    }
+
+    fn description(&self) -> String {
+        "List the users you can share with, searching them by prefix".into()
+    }
+}
@@ -0,0 +1,60 @@
+use clap::Parser;
+use colored::Colorize;
+
+use crate::{codec::http_client, crypto, files, log, trust, TSFSContext};
+
+use super::Command;
+
+/// Confirm the key of a user with the fingerprint he gave you out of band
+#[derive(Parser, Debug)]
+pub struct VerifyArgs {
+    username: String,
+    /// Fingerprint given by the user, the separators are ignored
+    fingerprint: String,
+}
+
+pub struct VerifyCommand;
+
+impl Command for VerifyCommand {
+    fn execute(&self, args: &Vec<String>, ctx: &mut TSFSContext) {
+        match VerifyArgs::try_parse_from(args) {
+            Ok(args) => {
+                if ctx.session_token.is_none() {
+                    log::error("Not connected");
+                    return;
+                }
+
+                let client = http_client(ctx);
+
+                let Some(pubkey) = files::fetch_user_public_key(ctx, &client, &args.username)
+                else {
+                    return;
+                };
+
+                let fingerprint = crypto::fingerprint(&pubkey);
+
+                if !trust::same_fingerprint(&fingerprint, &args.fingerprint) {
+                    log::error(&format!(
+                        "The key given by the server for {} doesn't match, don't share with him",
+                        args.username.red()
+                    ));
+                    log::error(&format!("Key of server: {}", fingerprint.red()));
+                    return;
+                }
+
+                // The key replaces the pinned one, if any
+                trust::pin_key(ctx, &args.username, &pubkey, true);
+
+                log::info(&format!("Key of {} verified", args.username.green()));
+            }
+
+            Err(e) => {
+                println!("{e}");
+            }
+        }
+    }
+
+    fn description(&self) -> String {
+        "Confirm the key of a user with his fingerprint".into()
+    }
+}
@@ -0,0 +1,80 @@
+use std::time::{Duration, UNIX_EPOCH};
+
+use chrono::prelude::*;
+use clap::Parser;
+use colored::Colorize;
+
+use crate::{codec::http_client, files, log, TSFSContext};
+
+use super::{find_in_current_folder, format_bytes, Command};
+
+/// List the previous versions of a file
+#[derive(Parser, Debug)]
+pub struct VersionsArgs {
+    name: String,
+}
+
+pub struct VersionsCommand;
+
+impl Command for VersionsCommand {
+    fn execute(&self, args: &Vec<String>, ctx: &mut TSFSContext) {
+        match VersionsArgs::try_parse_from(args) {
+            Ok(args) => {
+                if ctx.keyring_tree.is_none() {
+                    log::error("Missing Keyring Tree, not logged ?");
+                    return;
+                }
+
+                let Some(file) = find_in_current_folder(ctx, &args.name) else {
+                    log::error(&format!("Can't find file {}", args.name.red()));
+                    return;
+                };
+
+                if file.file.is_folder() {
+                    log::error("Folders have no versions");
+                    return;
+                }
+
+                let client = http_client(ctx);
+
+                let Some(versions) = files::get_versions(ctx, &client, &file.file.id) else {
+                    return;
+                };
+
+                if versions.is_empty() {
+                    log::info(&format!("{} has no previous versions", args.name.green()));
+                    return;
+                }
+
+                log::info(&format!("Previous versions of {}:", args.name.green()));
+                for version in versions {
+                    let mtime = version.mtime.map_or("unknown date".to_string(), |mtime| {
+                        DateTime::<Local>::from(UNIX_EPOCH + Duration::from_millis(mtime as u64))
+                            .format("%Y-%m-%d %H:%M:%S")
+                            .to_string()
+                    });
+
+                    println!(
+                        "  {} {} {}",
+                        format!("v{}", version.version).cyan(),
+                        mtime,
+                        format_bytes(version.sz)
+                    );
+                }
+                log::info(&format!(
+                    "Use {} to restore a version or {} to download it",
+                    "restore <name> <version>".green(),
+                    "download <name> --version <version>".green()
+                ));
+            }
+
+            Err(e) => {
+                println!("{e}");
+            }
+        }
+    }
+
+    fn description(&self) -> String {
+        "List the previous versions of a file".into()
+    }
+}
@@ -1,13 +1,19 @@
 use chacha20poly1305::{
-    aead::{generic_array::GenericArray, Aead, AeadCore, KeyInit, OsRng},
+    aead::{generic_array::GenericArray, Aead, AeadCore, KeyInit, OsRng, Payload},
     ChaCha20Poly1305,
 };
+use ed25519_dalek::{Signer, SigningKey};
 use rsa::{
-    pkcs1::{DecodeRsaPrivateKey, DecodeRsaPublicKey},
-    sha2::Sha256,
+    pkcs1::{DecodeRsaPrivateKey, DecodeRsaPublicKey, EncodeRsaPrivateKey, EncodeRsaPublicKey},
+    pkcs1v15,
+    sha2::{Digest, Sha256},
+    signature::{SignatureEncoding, Verifier},
+    traits::PublicKeyParts,
     Oaep, RsaPrivateKey, RsaPublicKey,
 };
 
+use crate::models::Permission;
+
 pub fn rsa_encrypt(data: &[u8], pubkey: &[u8]) -> Result<Vec<u8>, rsa::Error> {
     let pubkey = RsaPublicKey::from_pkcs1_der(pubkey)?;
     let padding = Oaep::new::<Sha256>();
@@ -22,6 +28,41 @@ pub fn rsa_decrypt(data: &[u8], privkey: &[u8]) -> Result<Vec<u8>, rsa::Error> {
     privkey.decrypt(padding, data)
 }
 
+/// Generate a 3072 bits RSA keypair, return the private key and the public key in PKCS#1 DER
+pub fn generate_rsa_keypair() -> (Vec<u8>, Vec<u8>) {
+    let priv_key = RsaPrivateKey::new(&mut OsRng, 3072).expect("failed to generate a key");
+    let pub_key = RsaPublicKey::from(&priv_key);
+
+    (
+        priv_key.to_pkcs1_der().unwrap().as_bytes().to_vec(),
+        pub_key.to_pkcs1_der().unwrap().to_vec(),
+    )
+}
+
+/// Encrypt data too large for RSA, like a private key
+/// The data is encrypted with a new ChaCha20 key, itself encrypted with the RSA public key
+pub fn rsa_wrap(data: &[u8], pubkey: &[u8]) -> Option<Vec<u8>> {
+    let key = ChaCha20Poly1305::generate_key(&mut OsRng);
+
+    let enc_key = rsa_encrypt(&key, pubkey).ok()?;
+    let enc_data = chacha_encrypt(data, &key).ok()?;
+
+    Some([enc_key, enc_data].concat())
+}
+
+/// Decrypt data encrypted with `rsa_wrap`
+pub fn rsa_unwrap(data: &[u8], privkey: &[u8]) -> Option<Vec<u8>> {
+    let key_size = RsaPrivateKey::from_pkcs1_der(privkey).ok()?.size();
+    if data.len() < key_size {
+        return None;
+    }
+
+    let (enc_key, enc_data) = data.split_at(key_size);
+    let key = rsa_decrypt(enc_key, privkey).ok()?;
+
+    chacha_decrypt(enc_data, &key).ok()
+}
+
 pub fn chacha_encrypt(data: &[u8], key: &[u8]) -> Result<Vec<u8>, chacha20poly1305::Error> {
     let cipher = ChaCha20Poly1305::new(GenericArray::from_slice(key));
     let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
@@ -31,10 +72,174 @@ pub fn chacha_encrypt(data: &[u8], key: &[u8]) -> Result<Vec<u8>, chacha20poly13
 }
 
 pub fn chacha_decrypt(data: &[u8], key: &[u8]) -> Result<Vec<u8>, chacha20poly1305::Error> {
+    // 12 bytes nonce is concatened with data, the server may send anything
+    if data.len() < 12 || key.len() != 32 {
+        return Err(chacha20poly1305::Error);
+    }
+
     let cipher = ChaCha20Poly1305::new(GenericArray::from_slice(key));
-    // 12 bytes nonce is concatened with data
-    let nonce = &data[..12];
-    let data = &data[12..];
+    let (nonce, data) = data.split_at(12);
 
     cipher.decrypt(GenericArray::from_slice(nonce), data)
-}
\ No newline at end of file
+}
+
+/// Encrypt a file chunk
+/// The chunk index and whether it's the last chunk are authenticated along with it,
+/// so chunks can't be reordered and the file can't be truncated without being noticed
+pub fn chacha_encrypt_chunk(
+    data: &[u8],
+    key: &[u8],
+    index: u64,
+    last: bool,
+) -> Result<Vec<u8>, chacha20poly1305::Error> {
+    let cipher = ChaCha20Poly1305::new(GenericArray::from_slice(key));
+    let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let aad = chunk_aad(index, last);
+    let enc_data = cipher.encrypt(&nonce, Payload { msg: data, aad: &aad })?;
+
+    Ok([nonce.to_vec(), enc_data].concat())
+}
+
+pub fn chacha_decrypt_chunk(
+    data: &[u8],
+    key: &[u8],
+    index: u64,
+    last: bool,
+) -> Result<Vec<u8>, chacha20poly1305::Error> {
+    // 12 bytes nonce is concatened with data, a shorter chunk was truncated
+    if data.len() < 12 {
+        return Err(chacha20poly1305::Error);
+    }
+
+    let cipher = ChaCha20Poly1305::new(GenericArray::from_slice(key));
+    let (nonce, data) = data.split_at(12);
+    let aad = chunk_aad(index, last);
+
+    cipher.decrypt(GenericArray::from_slice(nonce), Payload { msg: data, aad: &aad })
+}
+
+/// Associated data of a chunk: big endian index followed by the last chunk flag
+fn chunk_aad(index: u64, last: bool) -> [u8; 9] {
+    let mut aad = [0u8; 9];
+    aad[..8].copy_from_slice(&index.to_be_bytes());
+    aad[8] = last as u8;
+
+    aad
+}
+
+/// Generate an Ed25519 signing keypair, return the private key and the public key
+pub fn generate_signing_key() -> (Vec<u8>, Vec<u8>) {
+    let signing_key = SigningKey::generate(&mut OsRng);
+
+    (
+        signing_key.to_bytes().to_vec(),
+        signing_key.verifying_key().to_bytes().to_vec(),
+    )
+}
+
+/// Sign data with an Ed25519 private key, None if the key is invalid
+pub fn sign(data: &[u8], privkey: &[u8]) -> Option<Vec<u8>> {
+    let signing_key = SigningKey::from_bytes(privkey.try_into().ok()?);
+
+    Some(signing_key.sign(data).to_bytes().to_vec())
+}
+
+/// Symmetric key derived from a signing private key of a folder
+/// Used to encrypt the signing private keys of the same level of the files inside
+pub fn signing_wrap_key(privkey: &[u8]) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    hasher.update(b"tsfs signing key wrap");
+    hasher.update(privkey);
+
+    hasher.finalize().to_vec()
+}
+
+/// Keyring a signed entry is meant for, its signature isn't valid in another one
+///
+/// Users and groups share their names, the keyring of a group is its root keyring.
+pub enum EntryKeyring<'a> {
+    Root(&'a str),
+    Trash(&'a str),
+    /// Pending invitations of a user
+    Inbox(&'a str),
+}
+
+/// Sign a keyring entry with the RSA private key of the user
+///
+/// The signature covers the file, the keyring the entry is meant for, its access level and
+/// the encrypted key, so the server can't move the entry or raise its level. The name and
+/// content of the file are authenticated by the file key.
+pub fn sign_entry(
+    target: &str,
+    keyring: &EntryKeyring,
+    permission: Permission,
+    encrypted_key: &[u8],
+    rsa_privkey: &[u8],
+) -> Vec<u8> {
+    let privkey = RsaPrivateKey::from_pkcs1_der(rsa_privkey).unwrap();
+    let payload = entry_payload(target, keyring, permission, encrypted_key);
+
+    pkcs1v15::SigningKey::<Sha256>::new(privkey)
+        .sign(&payload)
+        .to_vec()
+}
+
+/// Verify the signature of a keyring entry with the RSA public key of the signer, see
+/// `sign_entry`
+pub fn verify_entry(
+    target: &str,
+    keyring: &EntryKeyring,
+    permission: Permission,
+    encrypted_key: &[u8],
+    signature: &[u8],
+    rsa_pubkey: &[u8],
+) -> bool {
+    let Ok(pubkey) = RsaPublicKey::from_pkcs1_der(rsa_pubkey) else {
+        return false;
+    };
+    let Ok(signature) = pkcs1v15::Signature::try_from(signature) else {
+        return false;
+    };
+    let payload = entry_payload(target, keyring, permission, encrypted_key);
+
+    pkcs1v15::VerifyingKey::<Sha256>::new(pubkey)
+        .verify(&payload, &signature)
+        .is_ok()
+}
+
+fn entry_payload(
+    target: &str,
+    keyring: &EntryKeyring,
+    permission: Permission,
+    encrypted_key: &[u8],
+) -> Vec<u8> {
+    let (kind, owner) = match keyring {
+        EntryKeyring::Root(owner) => (0u8, owner),
+        EntryKeyring::Trash(owner) => (1u8, owner),
+        EntryKeyring::Inbox(owner) => (2u8, owner),
+    };
+
+    // The variable length fields are prefixed with their length, they can't be confused
+    let mut payload = b"tsfs keyring entry".to_vec();
+    payload.extend_from_slice(&(target.len() as u32).to_be_bytes());
+    payload.extend_from_slice(target.as_bytes());
+    payload.push(kind);
+    payload.extend_from_slice(&(owner.len() as u32).to_be_bytes());
+    payload.extend_from_slice(owner.as_bytes());
+    payload.push(permission as u8);
+    payload.extend_from_slice(encrypted_key);
+
+    payload
+}
+
+/// Fingerprint of a public key, to compare it out of band: the SHA-256 of the key in
+/// groups of 4 hex digits
+pub fn fingerprint(pubkey: &[u8]) -> String {
+    let digest = Sha256::digest(pubkey);
+
+    digest
+        .chunks(2)
+        .map(|group| format!("{:02X}{:02X}", group[0], group[1]))
+        .collect::<Vec<String>>()
+        .join(" ")
+}
@@ -1 +1,939 @@
+use std::io::{self, Read, Write};
 
+use base64::prelude::*;
+use chacha20poly1305::{
+    aead::{KeyInit, OsRng},
+    ChaCha20Poly1305,
+};
+use colored::Colorize;
+use reqwest::{blocking::Client, StatusCode};
+use serde::{Deserialize, Serialize};
+use serde_bytes::ByteBuf;
+use uuid::Uuid;
+
+use crate::{
+    codec::{RequestBuilderExt, ResponseExt},
+    commands::{
+        encrypt_key_for, encrypt_signing_keys_for, generate_signing_keys, sign_entry_for,
+        signer_keys,
+    },
+    crypto::{self, EntryKeyring},
+    log,
+    models::{
+        File, FileAccess, FileVersion, FileWithoutDataWithKeyring, Group, KeyWithFile,
+        KeyringWithKeysAndFiles, Permission, PublicFile, PublicKeys, SigningKeys, UsersPage,
+    },
+    transparency, trust, TSFSContext,
+};
+
+/// Size of the plaintext chunks, each chunk is encrypted separately
+/// The last chunk of a file is always smaller than this (it can be empty)
+pub const CHUNK_SIZE: usize = 1024 * 1024;
+
+#[derive(Deserialize)]
+struct BeginUploadResponse {
+    upload_id: String,
+}
+
+/// Start a chunked upload, return the upload id
+pub fn begin_upload(ctx: &TSFSContext, client: &Client) -> Option<String> {
+    let res = client
+        .post(format!(
+            "{}:{}/file/upload",
+            ctx.endpoint_url.as_ref().unwrap(),
+            ctx.endpoint_port
+        ))
+        .header(
+            "Authorization",
+            format!("Bearer {}", ctx.session_token.as_ref().unwrap()),
+        )
+        .send();
+
+    match res {
+        Ok(res) => match res.error_for_status() {
+            Ok(res) => Some(res.msg::<BeginUploadResponse>().unwrap().upload_id),
+
+            Err(e) => {
+                let status = e.status().unwrap();
+
+                log::error(&format!("Can't start upload: {}", status.to_string().red()));
+
+                None
+            }
+        },
+
+        Err(e) => {
+            log::error(&format!("Error on upload: {}", e.to_string().red()));
+
+            None
+        }
+    }
+}
+
+/// Send an encrypted chunk to a pending upload
+fn upload_chunk(
+    ctx: &TSFSContext,
+    client: &Client,
+    upload_id: &str,
+    index: u64,
+    chunk: Vec<u8>,
+) -> bool {
+    let res = client
+        .put(format!(
+            "{}:{}/file/upload/{}/chunk/{}",
+            ctx.endpoint_url.as_ref().unwrap(),
+            ctx.endpoint_port,
+            upload_id,
+            index
+        ))
+        .header(
+            "Authorization",
+            format!("Bearer {}", ctx.session_token.as_ref().unwrap()),
+        )
+        .body(chunk)
+        .send();
+
+    match res {
+        Ok(res) => match res.error_for_status() {
+            Ok(_) => true,
+
+            Err(e) => {
+                let status = e.status().unwrap();
+
+                if status == StatusCode::INSUFFICIENT_STORAGE {
+                    log::error(&format!("Storage quota exceeded, see {}", "df".green()));
+                } else {
+                    log::error(&format!(
+                        "Can't upload chunk {}: {}",
+                        index,
+                        status.to_string().red()
+                    ));
+                }
+
+                false
+            }
+        },
+
+        Err(e) => {
+            log::error(&format!("Error on upload: {}", e.to_string().red()));
+
+            false
+        }
+    }
+}
+
+/// Encrypt the content of `reader` chunk by chunk with `key` and stream it to a new upload
+///
+/// Only one chunk is kept in memory at a time.
+/// Return the id of the upload, ready to be committed
+pub fn upload_stream(
+    ctx: &TSFSContext,
+    client: &Client,
+    reader: &mut impl Read,
+    key: &[u8],
+) -> Option<String> {
+    let upload_id = begin_upload(ctx, client)?;
+
+    let mut buffer = vec![0u8; CHUNK_SIZE];
+    let mut index = 0;
+
+    loop {
+        let read = match read_chunk(reader, &mut buffer) {
+            Ok(read) => read,
+
+            Err(e) => {
+                log::error(&format!("Can't read local file: {}", e));
+                return None;
+            }
+        };
+
+        let last = read < CHUNK_SIZE;
+        let enc_chunk = crypto::chacha_encrypt_chunk(&buffer[..read], key, index, last).unwrap();
+
+        if !upload_chunk(ctx, client, &upload_id, index, enc_chunk) {
+            return None;
+        }
+
+        if last {
+            break;
+        }
+
+        index += 1;
+    }
+
+    Some(upload_id)
+}
+
+/// Fill `buffer` from `reader`, return less than the buffer size only at the end of the reader
+fn read_chunk(reader: &mut impl Read, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut read = 0;
+
+    while read < buffer.len() {
+        match reader.read(&mut buffer[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(read)
+}
+
+/// Get the informations of a file, the content is downloaded separately
+pub fn get_file_info(ctx: &TSFSContext, client: &Client, file_uid: &str) -> Option<File> {
+    let res = client
+        .get(format!(
+            "{}:{}/file/download/{}",
+            ctx.endpoint_url.as_ref().unwrap(),
+            ctx.endpoint_port,
+            file_uid
+        ))
+        .header(
+            "Authorization",
+            format!("Bearer {}", ctx.session_token.as_ref().unwrap()),
+        )
+        .send();
+
+    match res {
+        Ok(res) => match res.error_for_status() {
+            Ok(res) => Some(res.msg::<File>().unwrap()),
+
+            Err(e) => {
+                let status = e.status().unwrap();
+
+                log::error(&format!(
+                    "Can't download file: {}",
+                    status.to_string().red()
+                ));
+
+                None
+            }
+        },
+
+        Err(e) => {
+            log::error(&format!("Error on download: {}", e.to_string().red()));
+
+            None
+        }
+    }
+}
+
+/// Get the previous versions of a file, newest first
+pub fn get_versions(
+    ctx: &TSFSContext,
+    client: &Client,
+    file_uid: &str,
+) -> Option<Vec<FileVersion>> {
+    let res = client
+        .get(format!(
+            "{}:{}/file/{}/versions",
+            ctx.endpoint_url.as_ref().unwrap(),
+            ctx.endpoint_port,
+            file_uid
+        ))
+        .header(
+            "Authorization",
+            format!("Bearer {}", ctx.session_token.as_ref().unwrap()),
+        )
+        .send();
+
+    match res {
+        Ok(res) => match res.error_for_status() {
+            Ok(res) => Some(res.msg::<Vec<FileVersion>>().unwrap()),
+
+            Err(e) => {
+                let status = e.status().unwrap();
+
+                log::error(&format!("Can't get versions: {}", status.to_string().red()));
+
+                None
+            }
+        },
+
+        Err(e) => {
+            log::error(&format!("Error on versions: {}", e.to_string().red()));
+
+            None
+        }
+    }
+}
+
+/// Get the users with access to a file, directly or through the folders containing it
+pub fn get_access(ctx: &TSFSContext, client: &Client, file_uid: &str) -> Option<FileAccess> {
+    let res = client
+        .get(format!(
+            "{}:{}/file/{}/access",
+            ctx.endpoint_url.as_ref().unwrap(),
+            ctx.endpoint_port,
+            file_uid
+        ))
+        .header(
+            "Authorization",
+            format!("Bearer {}", ctx.session_token.as_ref().unwrap()),
+        )
+        .send();
+
+    match res {
+        Ok(res) => match res.error_for_status() {
+            Ok(res) => Some(res.msg::<FileAccess>().unwrap()),
+
+            Err(e) => {
+                let status = e.status().unwrap();
+
+                log::error(&format!("Can't get access: {}", status.to_string().red()));
+
+                None
+            }
+        },
+
+        Err(e) => {
+            log::error(&format!("Error on access: {}", e.to_string().red()));
+
+            None
+        }
+    }
+}
+
+/// Get the groups of the user, with their private key and their keyring decrypted
+pub fn get_groups(ctx: &TSFSContext, client: &Client) -> Option<Vec<Group>> {
+    let res = client
+        .get(format!(
+            "{}:{}/groups",
+            ctx.endpoint_url.as_ref().unwrap(),
+            ctx.endpoint_port
+        ))
+        .header(
+            "Authorization",
+            format!("Bearer {}", ctx.session_token.as_ref().unwrap()),
+        )
+        .send();
+
+    match res {
+        Ok(res) => match res.error_for_status() {
+            Ok(res) => {
+                let groups = res.msg::<Vec<Group>>().unwrap();
+
+                Some(
+                    groups
+                        .into_iter()
+                        .filter_map(|group| {
+                            let Some(priv_key) = crypto::rsa_unwrap(
+                                &group.priv_key,
+                                ctx.private_key.as_ref().unwrap(),
+                            ) else {
+                                log::warning(&format!(
+                                    "Ignoring group {}, its key can't be decrypted",
+                                    group.name
+                                ));
+                                return None;
+                            };
+
+                            // Keys shared with a group are encrypted with its pubkey, like a root
+                            // keyring, only its members can share with it
+                            let signer_keys =
+                                signer_keys(ctx, client, &group.keyring, Some(&group.members));
+                            let keyring = KeyringWithKeysAndFiles::from_encrypted(
+                                group.keyring,
+                                &priv_key,
+                                None,
+                                &EntryKeyring::Root(&group.name),
+                                &signer_keys,
+                            );
+
+                            Some(Group {
+                                priv_key,
+                                keyring,
+                                ..group
+                            })
+                        })
+                        .collect(),
+                )
+            }
+
+            Err(e) => {
+                let status = e.status().unwrap();
+
+                log::error(&format!("Can't get groups: {}", status.to_string().red()));
+
+                None
+            }
+        },
+
+        Err(e) => {
+            log::error(&format!("Error on groups: {}", e.to_string().red()));
+
+            None
+        }
+    }
+}
+
+/// Search the users and groups whose name starts with the prefix, by page
+pub fn search_users(
+    ctx: &TSFSContext,
+    client: &Client,
+    prefix: &str,
+    page: usize,
+) -> Option<UsersPage> {
+    let res = client
+        .get(format!(
+            "{}:{}/users",
+            ctx.endpoint_url.as_ref().unwrap(),
+            ctx.endpoint_port
+        ))
+        .query(&[("prefix", prefix), ("page", &page.to_string())])
+        .header(
+            "Authorization",
+            format!("Bearer {}", ctx.session_token.as_ref().unwrap()),
+        )
+        .send();
+
+    match res {
+        Ok(res) => match res.error_for_status() {
+            Ok(res) => Some(res.msg::<UsersPage>().unwrap()),
+
+            Err(e) => {
+                log::error(&format!("Error while searching users {}", e));
+
+                None
+            }
+        },
+
+        Err(e) => {
+            log::error(&format!("Error while searching users {}", e));
+
+            None
+        }
+    }
+}
+
+/// Get the public key of a user, to encrypt the keys shared with him
+///
+/// The key must be the latest one of the user in the key log, and match the one pinned
+/// for the user, it is pinned on first use.
+pub fn get_user_public_key(ctx: &TSFSContext, client: &Client, username: &str) -> Option<Vec<u8>> {
+    let pubkey = fetch_user_public_key(ctx, client, username)?;
+
+    if !transparency::verify_key(ctx, client, username, &pubkey) {
+        return None;
+    }
+
+    if !trust::check_key(ctx, username, &pubkey) {
+        return None;
+    }
+
+    Some(pubkey)
+}
+
+/// Get the public key of a user as given by the server, without checking it
+pub fn fetch_user_public_key(
+    ctx: &TSFSContext,
+    client: &Client,
+    username: &str,
+) -> Option<Vec<u8>> {
+    let res = client
+        .get(format!(
+            "{}:{}/pubkey/{}",
+            ctx.endpoint_url.as_ref().unwrap(),
+            ctx.endpoint_port,
+            username
+        ))
+        .header(
+            "Authorization",
+            format!("Bearer {}", ctx.session_token.as_ref().unwrap()),
+        )
+        .send();
+
+    match res {
+        Ok(res) => match res.error_for_status() {
+            Ok(res) => Some(res.msg::<ByteBuf>().unwrap().into_vec()),
+
+            Err(e) => {
+                log::error(&format!("Error while requesting user pubkey {}", e));
+
+                None
+            }
+        },
+
+        Err(e) => {
+            log::error(&format!("Error while requesting user pubkey {}", e));
+
+            None
+        }
+    }
+}
+
+/// Download an encrypted chunk, `chunks_url` is the url of the chunks of a file or of a version
+fn download_chunk(
+    ctx: &TSFSContext,
+    client: &Client,
+    chunks_url: &str,
+    index: u64,
+) -> Option<Vec<u8>> {
+    let mut req = client.get(format!("{}/{}", chunks_url, index));
+
+    // Public links are downloaded without a session
+    if let Some(session_token) = &ctx.session_token {
+        req = req.header("Authorization", format!("Bearer {}", session_token));
+    }
+
+    let res = req.send();
+
+    match res {
+        Ok(res) => match res.error_for_status() {
+            Ok(res) => Some(res.bytes().unwrap().to_vec()),
+
+            Err(e) => {
+                let status = e.status().unwrap();
+
+                log::error(&format!(
+                    "Can't download chunk {}: {}",
+                    index,
+                    status.to_string().red()
+                ));
+
+                None
+            }
+        },
+
+        Err(e) => {
+            log::error(&format!("Error on download: {}", e.to_string().red()));
+
+            None
+        }
+    }
+}
+
+/// Download the content of a file chunk by chunk and call `on_chunk` with each decrypted chunk
+///
+/// Stop on the first error or when `on_chunk` return false.
+/// Return whether all chunks have been processed
+pub fn download_chunks(
+    ctx: &TSFSContext,
+    client: &Client,
+    file: &File,
+    key: &[u8],
+    on_chunk: impl FnMut(u64, bool, &[u8]) -> bool,
+) -> bool {
+    let chunks_url = format!(
+        "{}:{}/file/download/{}",
+        ctx.endpoint_url.as_ref().unwrap(),
+        ctx.endpoint_port,
+        file.id
+    );
+
+    download_chunks_from(
+        ctx,
+        client,
+        &chunks_url,
+        file.chunks.unwrap_or(0) as u64,
+        key,
+        on_chunk,
+    )
+}
+
+/// Download and decrypt `chunk_count` chunks from `chunks_url`, see `download_chunks`
+fn download_chunks_from(
+    ctx: &TSFSContext,
+    client: &Client,
+    chunks_url: &str,
+    chunk_count: u64,
+    key: &[u8],
+    mut on_chunk: impl FnMut(u64, bool, &[u8]) -> bool,
+) -> bool {
+    for index in 0..chunk_count {
+        let Some(enc_chunk) = download_chunk(ctx, client, chunks_url, index) else {
+            return false;
+        };
+
+        let last = index + 1 == chunk_count;
+        let chunk = match crypto::chacha_decrypt_chunk(&enc_chunk, key, index, last) {
+            Ok(chunk) => chunk,
+
+            // Files uploaded before chunked uploads are a single chunk encrypted at once
+            Err(_) if chunk_count == 1 => match crypto::chacha_decrypt(&enc_chunk, key) {
+                Ok(chunk) => chunk,
+
+                Err(_) => {
+                    log::error("Can't decrypt file content");
+                    return false;
+                }
+            },
+
+            Err(_) => {
+                log::error(&format!(
+                    "Can't decrypt chunk {}, the file has been tampered with",
+                    index
+                ));
+                return false;
+            }
+        };
+
+        if !on_chunk(index, last, &chunk) {
+            return false;
+        }
+    }
+
+    true
+}
+
+/// Download the content of a file and write it decrypted to `writer`
+pub fn download_stream(
+    ctx: &TSFSContext,
+    client: &Client,
+    file: &File,
+    key: &[u8],
+    writer: &mut impl Write,
+) -> bool {
+    download_chunks(ctx, client, file, key, |_, _, chunk| {
+        write_chunk(writer, chunk)
+    })
+}
+
+/// Download the content of a file from a public link and write it decrypted to `writer`
+///
+/// The chunks are fetched with the download token given along with the file.
+pub fn download_public_stream(
+    ctx: &TSFSContext,
+    client: &Client,
+    link_url: &str,
+    public_file: &PublicFile,
+    key: &[u8],
+    writer: &mut impl Write,
+) -> bool {
+    let chunks_url = format!("{}/{}", link_url, public_file.download);
+
+    download_chunks_from(
+        ctx,
+        client,
+        &chunks_url,
+        public_file.chunks.unwrap_or(0) as u64,
+        key,
+        |_, _, chunk| write_chunk(writer, chunk),
+    )
+}
+
+/// Download the content of a previous version of a file and write it decrypted to `writer`
+pub fn download_version_stream(
+    ctx: &TSFSContext,
+    client: &Client,
+    version: &FileVersion,
+    key: &[u8],
+    writer: &mut impl Write,
+) -> bool {
+    let chunks_url = format!(
+        "{}:{}/file/{}/versions/{}",
+        ctx.endpoint_url.as_ref().unwrap(),
+        ctx.endpoint_port,
+        version.file_id,
+        version.version
+    );
+
+    download_chunks_from(
+        ctx,
+        client,
+        &chunks_url,
+        version.chunks as u64,
+        key,
+        |_, _, chunk| write_chunk(writer, chunk),
+    )
+}
+
+fn write_chunk(writer: &mut impl Write, chunk: &[u8]) -> bool {
+    match writer.write_all(chunk) {
+        Ok(_) => true,
+
+        Err(e) => {
+            log::error(&format!("Can't write to local file: {}", e));
+            false
+        }
+    }
+}
+
+/// Re-encrypt the content of a file with a new key into a new upload, chunk by chunk
+///
+/// Return the id of the upload holding the re-encrypted content
+pub fn reencrypt_stream(
+    ctx: &TSFSContext,
+    client: &Client,
+    file: &File,
+    old_key: &[u8],
+    new_key: &[u8],
+) -> Option<String> {
+    let upload_id = begin_upload(ctx, client)?;
+
+    let success = download_chunks(ctx, client, file, old_key, |index, last, chunk| {
+        let enc_chunk = crypto::chacha_encrypt_chunk(chunk, new_key, index, last).unwrap();
+        upload_chunk(ctx, client, &upload_id, index, enc_chunk)
+    });
+
+    success.then_some(upload_id)
+}
+
+#[derive(Deserialize)]
+struct ChallengeResponse {
+    #[serde(with = "serde_bytes")]
+    challenge: Vec<u8>,
+}
+
+/// Get a challenge for `file` and sign it with its signing key of the given level
+///
+/// Return None for root (None), for files without signing keys or if the user doesn't
+/// hold this level. The server refuses the action if the file has signing keys.
+pub fn sign_challenge(
+    ctx: &TSFSContext,
+    client: &Client,
+    file: Option<&KeyWithFile>,
+    permission: Permission,
+) -> Option<Vec<u8>> {
+    let file = file?;
+    let signing_key = file.signing_keys.get(permission)?;
+
+    let res = client
+        .post(format!(
+            "{}:{}/file/{}/challenge",
+            ctx.endpoint_url.as_ref().unwrap(),
+            ctx.endpoint_port,
+            file.file.id
+        ))
+        .header(
+            "Authorization",
+            format!("Bearer {}", ctx.session_token.as_ref().unwrap()),
+        )
+        .send();
+
+    match res {
+        Ok(res) => match res.error_for_status() {
+            Ok(res) => {
+                let challenge = res.msg::<ChallengeResponse>().unwrap().challenge;
+
+                crypto::sign(&challenge, signing_key)
+            }
+
+            Err(e) => {
+                let status = e.status().unwrap();
+
+                log::error(&format!(
+                    "Can't get challenge: {}",
+                    status.to_string().red()
+                ));
+
+                None
+            }
+        },
+
+        Err(e) => {
+            log::error(&format!("Error on challenge: {}", e.to_string().red()));
+
+            None
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct CreateFolderRequest {
+    /// The parent folder to put the file in.
+    /// None = root
+    parent_uid: Option<String>,
+    /// Encrypted filename
+    filename: String,
+    /// Encrypted symmetric key with user pubkey
+    #[serde(with = "serde_bytes")]
+    encrypted_key: Vec<u8>,
+    /// Id of the folder, its entry is signed with it
+    folder_uid: String,
+    public_keys: PublicKeys,
+    signing_keys: SigningKeys,
+    /// Signature of the entry, for a folder in root
+    #[serde(with = "serde_bytes")]
+    entry_signature: Option<Vec<u8>>,
+    /// Challenge of the parent folder signed with its write key
+    #[serde(with = "serde_bytes")]
+    signature: Option<Vec<u8>>,
+}
+
+#[derive(Deserialize)]
+struct CreateFolderResponse {
+    folder_uid: String,
+    keyring_id: i32,
+}
+
+/// Create an empty folder in `parent`, or in root if None
+///
+/// Return the created folder, with its key decrypted
+pub fn create_folder(
+    ctx: &TSFSContext,
+    client: &Client,
+    parent: Option<&KeyWithFile>,
+    name: &str,
+) -> Option<KeyWithFile> {
+    // Create new symmetric key for new folder
+    let key = ChaCha20Poly1305::generate_key(&mut OsRng).to_vec();
+
+    // Encrypt folder name
+    let enc_name = crypto::chacha_encrypt(name.as_bytes(), &key).unwrap();
+
+    // Encrypt key with user public key or parent symmetric key
+    let (parent_keyring_id, enc_key) = encrypt_key_for(ctx, parent, &key);
+    let folder_uid = Uuid::new_v4().to_string();
+    let entry_signature = sign_entry_for(ctx, parent, &folder_uid, Permission::Owner, &enc_key);
+
+    let (signing_keys, public_keys) = generate_signing_keys(parent);
+
+    let res = client
+        .post(format!(
+            "{}:{}/folder/create",
+            ctx.endpoint_url.as_ref().unwrap(),
+            ctx.endpoint_port
+        ))
+        .header(
+            "Authorization",
+            format!("Bearer {}", ctx.session_token.as_ref().unwrap()),
+        )
+        .msg(&CreateFolderRequest {
+            parent_uid: parent.map(|folder| folder.file.id.clone()),
+            filename: BASE64_STANDARD.encode(enc_name),
+            encrypted_key: enc_key,
+            folder_uid,
+            public_keys,
+            signing_keys: encrypt_signing_keys_for(ctx, parent, &signing_keys),
+            entry_signature,
+            signature: sign_challenge(ctx, client, parent, Permission::Write),
+        })
+        .send();
+
+    match res {
+        Ok(res) => match res.error_for_status() {
+            Ok(res) => {
+                let created = res.msg::<CreateFolderResponse>().unwrap();
+
+                Some(KeyWithFile {
+                    file: FileWithoutDataWithKeyring {
+                        id: created.folder_uid,
+                        name: name.to_string(),
+                        keyring: Some(KeyringWithKeysAndFiles {
+                            id: created.keyring_id,
+                            keys: Vec::new(),
+                        }),
+                        owner: ctx.username.clone(),
+                        expired_share_at: None,
+                    },
+                    key,
+                    keyring_id: parent_keyring_id,
+                    permission: Permission::Owner,
+                    signing_keys,
+                    signer: None,
+                    signature: None,
+                })
+            }
+
+            Err(e) => {
+                let status = e.status().unwrap();
+
+                log::error(&format!(
+                    "Can't create folder: {}",
+                    status.to_string().red()
+                ));
+
+                None
+            }
+        },
+
+        Err(e) => {
+            log::error(&format!("Error on mkdir: {}", e.to_string().red()));
+
+            None
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct CopyFileRequest {
+    file_uid: String,
+    destination_keyring_id: i32,
+    #[serde(with = "serde_bytes")]
+    encrypted_key: Vec<u8>,
+    /// Id of the copy, its entry is signed with it
+    copy_uid: String,
+    /// Encrypted filename of the copy
+    filename: String,
+    public_keys: PublicKeys,
+    signing_keys: SigningKeys,
+    /// Signature of the entry, for a copy in root
+    #[serde(with = "serde_bytes")]
+    entry_signature: Option<Vec<u8>>,
+    /// Challenge of the destination folder signed with its write key
+    #[serde(with = "serde_bytes")]
+    signature: Option<Vec<u8>>,
+}
+
+#[derive(Deserialize)]
+struct CopyFileResponse {
+    file_uid: String,
+}
+
+/// Copy a file server side to `destination`, or to root if None
+///
+/// The copy keeps the key of the original, only its name is encrypted again. It gets its
+/// own signing keys, a share of the original doesn't give access to the copy.
+/// Return the id of the copy.
+pub fn copy_file(
+    ctx: &TSFSContext,
+    client: &Client,
+    file: &KeyWithFile,
+    destination: Option<&KeyWithFile>,
+    name: &str,
+) -> Option<String> {
+    let enc_name = crypto::chacha_encrypt(name.as_bytes(), &file.key).unwrap();
+    let (destination_keyring_id, enc_key) = encrypt_key_for(ctx, destination, &file.key);
+    let copy_uid = Uuid::new_v4().to_string();
+    let entry_signature = sign_entry_for(ctx, destination, &copy_uid, Permission::Owner, &enc_key);
+    let (signing_keys, public_keys) = generate_signing_keys(destination);
+
+    let res = client
+        .post(format!(
+            "{}:{}/file/copy",
+            ctx.endpoint_url.as_ref().unwrap(),
+            ctx.endpoint_port
+        ))
+        .header(
+            "Authorization",
+            format!("Bearer {}", ctx.session_token.as_ref().unwrap()),
+        )
+        .msg(&CopyFileRequest {
+            file_uid: file.file.id.clone(),
+            destination_keyring_id,
+            encrypted_key: enc_key,
+            copy_uid,
+            filename: BASE64_STANDARD.encode(enc_name),
+            public_keys,
+            signing_keys: encrypt_signing_keys_for(ctx, destination, &signing_keys),
+            entry_signature,
+            signature: sign_challenge(ctx, client, destination, Permission::Write),
+        })
+        .send();
+
+    match res {
+        Ok(res) => match res.error_for_status() {
+            Ok(res) => Some(res.msg::<CopyFileResponse>().unwrap().file_uid),
+
+            Err(e) => {
+                let status = e.status().unwrap();
+
+                if status == StatusCode::INSUFFICIENT_STORAGE {
+                    log::error("Storage quota exceeded, see df");
+                } else {
+                    log::error(&format!("Can't copy file: {}", status.to_string().red()));
+                }
+
+                None
+            }
+        },
+
+        Err(e) => {
+            log::error(&format!("Error on cp: {}", e.to_string().red()));
+
+            None
+        }
+    }
+}
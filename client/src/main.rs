@@ -1,9 +1,14 @@
 use crate::commands::{
-    cd::CdCommand, change_password::ChangePasswordCommand, download::DownloadCommand,
-    exit::ExitCommand, help::HelpCommand, login::LoginCommand, logout::LogoutCommand,
-    ls::LsCommand, mkdir::MkdirCommand, ping::PingCommand, register::RegisterCommand,
-    rm::RmCommand, sessions::SessionsCommand, set::SetCommand, share::ShareCommand,
-    unshare::UnshareCommand, upload_file::UploadFileCommand, Command,
+    accept::AcceptCommand, access::AccessCommand, cd::CdCommand,
+    change_password::ChangePasswordCommand, cp::CpCommand, decline::DeclineCommand, df::DfCommand,
+    download::DownloadCommand, exit::ExitCommand, fetch::FetchCommand,
+    fingerprint::FingerprintCommand, group::GroupCommand, help::HelpCommand,
+    invites::InvitesCommand, link::LinkCommand, login::LoginCommand, logout::LogoutCommand,
+    ls::LsCommand, mkdir::MkdirCommand, mv::MvCommand, ping::PingCommand,
+    register::RegisterCommand, rename::RenameCommand, restore::RestoreCommand, rm::RmCommand,
+    sessions::SessionsCommand, set::SetCommand, share::ShareCommand, trash::TrashCommand,
+    unshare::UnshareCommand, upload_file::UploadFileCommand, users::UsersCommand,
+    verify::VerifyCommand, versions::VersionsCommand, Command,
 };
 use argon2::Argon2;
 use colored::Colorize;
@@ -13,16 +18,20 @@ use opaque_ke::CipherSuite;
 use serde::{Deserialize, Serialize};
 use std::{
     collections::HashMap,
+    env,
     io::{self, Write},
     path::PathBuf,
     time::SystemTime,
 };
 
+mod codec;
 mod commands;
 mod crypto;
 mod files;
 mod log;
 mod models;
+mod transparency;
+mod trust;
 
 // Initialize static `COMMANDS` HashMap
 lazy_static! {
@@ -45,18 +54,29 @@ lazy_static! {
         map.insert("share", Box::new(ShareCommand));
         map.insert("download", Box::new(DownloadCommand));
         map.insert("unshare", Box::new(UnshareCommand));
+        map.insert("df", Box::new(DfCommand));
+        map.insert("versions", Box::new(VersionsCommand));
+        map.insert("restore", Box::new(RestoreCommand));
+        map.insert("trash", Box::new(TrashCommand));
+        map.insert("mv", Box::new(MvCommand));
+        map.insert("rename", Box::new(RenameCommand));
+        map.insert("cp", Box::new(CpCommand));
+        map.insert("access", Box::new(AccessCommand));
+        map.insert("invites", Box::new(InvitesCommand));
+        map.insert("accept", Box::new(AcceptCommand));
+        map.insert("decline", Box::new(DeclineCommand));
+        map.insert("group", Box::new(GroupCommand));
+        map.insert("link", Box::new(LinkCommand));
+        map.insert("fetch", Box::new(FetchCommand));
+        map.insert("fingerprint", Box::new(FingerprintCommand));
+        map.insert("verify", Box::new(VerifyCommand));
+        map.insert("users", Box::new(UsersCommand));
 
         map
     };
 }
 
 fn main() {
-    println!(
-        "Welcome to {} (Totally Secure File Storage) !",
-        "TSFS".cyan()
-    );
-    println!("Type {} for the command list", "help".green());
-
     // Load config file
     let cfg = match confy::load::<Config>("tsfs_cli", "settings") {
         Ok(config) => config,
@@ -85,6 +105,19 @@ fn main() {
         local_folder: cfg.local_folder,
     };
 
+    // `tsfs fetch <url>` downloads a public link without starting the shell
+    let cli_args: Vec<String> = env::args().skip(1).collect();
+    if cli_args.first().is_some_and(|arg| arg == "fetch") {
+        FetchCommand.execute(&cli_args, &mut ctx);
+        return;
+    }
+
+    println!(
+        "Welcome to {} (Totally Secure File Storage) !",
+        "TSFS".cyan()
+    );
+    println!("Type {} for the command list", "help".green());
+
     if ctx.local_folder.is_none() {
         log::warning("No local_folder configured");
         loop {
@@ -1,13 +1,19 @@
+use std::collections::HashMap;
+
 use base64::prelude::*;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
-use crate::crypto;
+use crate::{
+    crypto::{self, EntryKeyring},
+    log,
+};
 
 /// These models replicate the ones in the Server
 
 #[derive(Deserialize, Clone, Debug)]
 pub struct Key {
     pub target: String,
+    #[serde(with = "serde_bytes")]
     pub key: Vec<u8>,
     pub keyring_id: i32,
 }
@@ -23,27 +29,62 @@ pub struct File {
     pub id: String,
     pub name: String,
     pub mtime: Option<i64>,
-    pub sz: Option<i32>,
-    pub data: Option<Vec<u8>>,
+    pub sz: Option<i64>,
+    pub chunks: Option<i32>,
     pub keyring_id: Option<i32>,
 }
 
+/// File served by a public link, its name is encrypted with the file key
+#[derive(Deserialize, Clone, Debug)]
+pub struct PublicFile {
+    pub name: String,
+    pub mtime: Option<i64>,
+    pub sz: Option<i64>,
+    pub chunks: Option<i32>,
+    /// Token of the download, needed to fetch the chunks
+    pub download: String,
+}
+
+/// Previous content of a file, encrypted with the file key
+#[derive(Deserialize, Clone, Debug)]
+pub struct FileVersion {
+    pub file_id: String,
+    pub version: i32,
+    pub mtime: Option<i64>,
+    pub sz: i64,
+    pub chunks: i32,
+}
+
 impl File {
-    pub fn decrypt(&mut self, key: &[u8]) {
-        let raw_name = BASE64_STANDARD.decode(&self.name).unwrap();
-        self.name = String::from_utf8(crypto::chacha_decrypt(&raw_name, key).unwrap()).unwrap();
+    /// Decrypt the file name, the content is decrypted chunk by chunk on download
+    ///
+    /// Return false if the name can't be decrypted
+    pub fn decrypt(&mut self, key: &[u8]) -> bool {
+        let Some(name) = decrypt_name(&self.name, key) else {
+            return false;
+        };
 
-        if self.data.is_some() {
-            self.data = crypto::chacha_decrypt(self.data.as_ref().unwrap(), key).ok();
-        }
+        self.name = name;
+        true
     }
 }
 
+/// Decrypt a base64 encrypted file name, None if the server sent something else
+fn decrypt_name(name: &str, key: &[u8]) -> Option<String> {
+    let raw_name = BASE64_STANDARD.decode(name).ok()?;
+
+    String::from_utf8(crypto::chacha_decrypt(&raw_name, key).ok()?).ok()
+}
+
 #[derive(Deserialize, Clone, Debug)]
 pub struct FileWithoutDataWithKeyring {
     pub id: String,
     pub name: String,
     pub keyring: Option<KeyringWithKeysAndFiles>,
+    /// User who created the file, unknown for files created before owners were recorded
+    pub owner: Option<String>,
+    /// Time a share of the file expired, its keys should be rotated
+    pub expired_share_at: Option<i64>,
 }
 
 impl FileWithoutDataWithKeyring {
@@ -55,8 +96,215 @@ impl FileWithoutDataWithKeyring {
 #[derive(Deserialize, Clone, Debug)]
 pub struct KeyWithFile {
     pub file: FileWithoutDataWithKeyring,
+    #[serde(with = "serde_bytes")]
     pub key: Vec<u8>,
     pub keyring_id: i32,
+    /// Access level granted by the entry
+    pub permission: Permission,
+    pub signing_keys: SigningKeys,
+    /// User who signed the entry, None for an unsigned entry
+    pub signer: Option<String>,
+    #[serde(with = "serde_bytes")]
+    pub signature: Option<Vec<u8>>,
+}
+
+impl KeyWithFile {
+    /// Check the signature of an entry of a root keyring, with the key the user trusts for
+    /// the signer
+    ///
+    /// An unsigned entry, or one signed by a user whose key isn't trusted, is invalid.
+    fn verify_signature(
+        &self,
+        keyring: &EntryKeyring,
+        signer_keys: &HashMap<String, Vec<u8>>,
+    ) -> bool {
+        let (Some(signer), Some(signature)) = (&self.signer, &self.signature) else {
+            return false;
+        };
+        let Some(signer_key) = signer_keys.get(signer) else {
+            return false;
+        };
+
+        crypto::verify_entry(
+            &self.file.id,
+            keyring,
+            self.permission,
+            &self.key,
+            signature,
+            signer_key,
+        )
+    }
+}
+
+/// Access level on a file, each level includes the lower ones
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Permission {
+    Read,
+    Write,
+    Owner,
+}
+
+/// User or group holding a key to a file in his root keyring or in one of his folders
+#[derive(Deserialize, Clone, Debug)]
+pub struct Grant {
+    pub username: String,
+    /// Groups get their renewed keys in their keyring, users in their inbox
+    pub group: bool,
+    pub permission: Permission,
+    /// End of the grant in millis, None if it doesn't expire
+    pub expires_at: Option<i64>,
+}
+
+/// Users with access to a file, directly or through the folders containing it
+#[derive(Deserialize, Clone, Debug)]
+pub struct FileAccess {
+    pub users: Vec<Grant>,
+    pub folders: Vec<FolderAccess>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct FolderAccess {
+    pub id: String,
+    pub users: Vec<Grant>,
+}
+
+/// Signing private keys of a file, one per access level
+///
+/// Keys above the access level of the user are None, files created before signing keys
+/// have none. The server checks the challenges signed with them before writes, deletes
+/// and shares.
+#[derive(Serialize, Deserialize, Clone, Default, Debug)]
+pub struct SigningKeys {
+    #[serde(with = "serde_bytes")]
+    pub owner: Option<Vec<u8>>,
+    #[serde(with = "serde_bytes")]
+    pub write: Option<Vec<u8>>,
+    #[serde(with = "serde_bytes")]
+    pub read: Option<Vec<u8>>,
+}
+
+/// Signing public keys of a file
+#[derive(Serialize, Default, Debug)]
+pub struct PublicKeys {
+    #[serde(with = "serde_bytes")]
+    pub owner: Option<Vec<u8>>,
+    #[serde(with = "serde_bytes")]
+    pub write: Option<Vec<u8>>,
+    #[serde(with = "serde_bytes")]
+    pub read: Option<Vec<u8>>,
+}
+
+impl SigningKeys {
+    /// Generate the signing keypairs of a new file
+    pub fn generate() -> (Self, PublicKeys) {
+        let (owner, owner_pub) = crypto::generate_signing_key();
+        let (write, write_pub) = crypto::generate_signing_key();
+        let (read, read_pub) = crypto::generate_signing_key();
+
+        (
+            SigningKeys {
+                owner: Some(owner),
+                write: Some(write),
+                read: Some(read),
+            },
+            PublicKeys {
+                owner: Some(owner_pub),
+                write: Some(write_pub),
+                read: Some(read_pub),
+            },
+        )
+    }
+
+    pub fn get(&self, permission: Permission) -> Option<&Vec<u8>> {
+        match permission {
+            Permission::Read => self.read.as_ref(),
+            Permission::Write => self.write.as_ref(),
+            Permission::Owner => self.owner.as_ref(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.owner.is_none() && self.write.is_none() && self.read.is_none()
+    }
+
+    /// Keep only the keys up to the given access level
+    pub fn restrict(&self, permission: Permission) -> Self {
+        SigningKeys {
+            owner: self
+                .owner
+                .clone()
+                .filter(|_| permission >= Permission::Owner),
+            write: self
+                .write
+                .clone()
+                .filter(|_| permission >= Permission::Write),
+            read: self.read.clone(),
+        }
+    }
+
+    /// Encrypt each key with a user public key, for a root keyring
+    pub fn rsa_encrypt(&self, pubkey: &[u8]) -> Self {
+        let encrypt = |key: &Option<Vec<u8>>| {
+            key.as_ref()
+                .map(|key| crypto::rsa_encrypt(key, pubkey).unwrap())
+        };
+
+        SigningKeys {
+            owner: encrypt(&self.owner),
+            write: encrypt(&self.write),
+            read: encrypt(&self.read),
+        }
+    }
+
+    pub fn rsa_decrypt(&self, privkey: &[u8]) -> Self {
+        let decrypt = |key: &Option<Vec<u8>>| {
+            key.as_ref()
+                .and_then(|key| crypto::rsa_decrypt(key, privkey).ok())
+        };
+
+        SigningKeys {
+            owner: decrypt(&self.owner),
+            write: decrypt(&self.write),
+            read: decrypt(&self.read),
+        }
+    }
+
+    /// Encrypt each key for the keyring of a folder, with the key derived from the folder
+    /// signing key of the same level
+    ///
+    /// When the user doesn't own the folder, the owner key is encrypted for the folder
+    /// writers instead, they own the files they create.
+    pub fn encrypt_for(&self, folder_keys: &SigningKeys) -> Self {
+        let encrypt = |key: &Option<Vec<u8>>, folder_key: Option<&Vec<u8>>| {
+            let wrap_key = crypto::signing_wrap_key(folder_key?);
+            Some(crypto::chacha_encrypt(key.as_ref()?, &wrap_key).unwrap())
+        };
+
+        SigningKeys {
+            owner: encrypt(
+                &self.owner,
+                folder_keys.owner.as_ref().or(folder_keys.write.as_ref()),
+            ),
+            write: encrypt(&self.write, folder_keys.write.as_ref()),
+            read: encrypt(&self.read, folder_keys.read.as_ref()),
+        }
+    }
+
+    /// Decrypt the keys of a file in a folder, see `encrypt_for`
+    pub fn decrypt_with(&self, folder_keys: &SigningKeys) -> Self {
+        let decrypt = |key: &Option<Vec<u8>>, folder_key: Option<&Vec<u8>>| {
+            let wrap_key = crypto::signing_wrap_key(folder_key?);
+            crypto::chacha_decrypt(key.as_ref()?, &wrap_key).ok()
+        };
+
+        SigningKeys {
+            owner: decrypt(&self.owner, folder_keys.owner.as_ref())
+                .or_else(|| decrypt(&self.owner, folder_keys.write.as_ref())),
+            write: decrypt(&self.write, folder_keys.write.as_ref()),
+            read: decrypt(&self.read, folder_keys.read.as_ref()),
+        }
+    }
 }
 
 #[derive(Deserialize, Clone, Debug)]
@@ -68,7 +316,19 @@ pub struct KeyringWithKeysAndFiles {
 impl KeyringWithKeysAndFiles {
     /// Load from encrypted Keyring, return an unencrypted Keyring
     /// With a huge file tree, this can take quite a while
-    pub fn from_encrypted(encrypted_keyring: Self, key: &[u8], root: bool) -> Self {
+    ///
+    /// `folder_keys` are the signing keys of the folder owning the keyring, None for a root
+    /// keyring encrypted with the user public key
+    ///
+    /// Anyone can encrypt a key with a public key, the entries of a root keyring must be
+    /// signed for `keyring` by a user in `signer_keys`, the other ones are dropped.
+    pub fn from_encrypted(
+        encrypted_keyring: Self,
+        key: &[u8],
+        folder_keys: Option<&SigningKeys>,
+        keyring: &EntryKeyring,
+        signer_keys: &HashMap<String, Vec<u8>>,
+    ) -> Self {
         let mut decrypted_keyring = KeyringWithKeysAndFiles {
             id: encrypted_keyring.id,
             keys: Vec::new(),
@@ -77,24 +337,60 @@ impl KeyringWithKeysAndFiles {
         for mut key_entry in encrypted_keyring.keys {
             let dec_key;
 
+            let signing_keys;
+
             // If root, need to decrypt with RSA
             // Else with ChaCha20
-            if root {
-                dec_key = crypto::rsa_decrypt(&key_entry.key, key).unwrap();
+            if let Some(folder_keys) = folder_keys {
+                let Ok(chacha_key) = crypto::chacha_decrypt(&key_entry.key, key) else {
+                    log::warning(&format!(
+                        "Ignoring file {}, its key can't be decrypted",
+                        key_entry.file.id
+                    ));
+                    continue;
+                };
+
+                dec_key = chacha_key;
+                signing_keys = key_entry.signing_keys.decrypt_with(folder_keys);
             } else {
-                dec_key = crypto::chacha_decrypt(&key_entry.key, key).unwrap();
+                if !key_entry.verify_signature(keyring, signer_keys) {
+                    log::warning(&format!(
+                        "Ignoring file {}, it isn't signed by a trusted user",
+                        key_entry.file.id
+                    ));
+                    continue;
+                }
+
+                let Ok(rsa_key) = crypto::rsa_decrypt(&key_entry.key, key) else {
+                    log::warning(&format!(
+                        "Ignoring file {}, its key can't be decrypted",
+                        key_entry.file.id
+                    ));
+                    continue;
+                };
+
+                dec_key = rsa_key;
+                signing_keys = key_entry.signing_keys.rsa_decrypt(key);
             }
 
             // Decrypt file name
-            let filename_raw = BASE64_STANDARD.decode(key_entry.file.name).unwrap();
-            key_entry.file.name =
-                String::from_utf8(crypto::chacha_decrypt(&filename_raw, &dec_key).unwrap())
-                    .unwrap();
+            let Some(name) = decrypt_name(&key_entry.file.name, &dec_key) else {
+                log::warning(&format!(
+                    "Ignoring file {}, its name can't be decrypted",
+                    key_entry.file.id
+                ));
+                continue;
+            };
+            key_entry.file.name = name;
 
             let mut decrypted_key = KeyWithFile {
                 file: key_entry.file.clone(),
                 key: dec_key.clone(),
                 keyring_id: key_entry.keyring_id,
+                permission: key_entry.permission,
+                signing_keys,
+                signer: key_entry.signer,
+                signature: key_entry.signature,
             };
 
             // If folder, need to decrypt in depth
@@ -102,7 +398,9 @@ impl KeyringWithKeysAndFiles {
                 let decrypted_folder_keyring = KeyringWithKeysAndFiles::from_encrypted(
                     key_entry.file.keyring.unwrap(),
                     &dec_key,
-                    false,
+                    Some(&decrypted_key.signing_keys),
+                    keyring,
+                    signer_keys,
                 );
                 decrypted_key.file.keyring = Some(decrypted_folder_keyring);
             }
@@ -130,6 +428,23 @@ impl KeyringWithKeysAndFiles {
         None
     }
 
+    /// Find the folder owning the keyring with the given id
+    pub fn get_folder_by_keyring(&self, keyring_id: i32) -> Option<KeyWithFile> {
+        for key in &self.keys {
+            if let Some(folder_keyring) = &key.file.keyring {
+                if folder_keyring.id == keyring_id {
+                    return Some(key.clone());
+                }
+
+                if let Some(folder) = folder_keyring.get_folder_by_keyring(keyring_id) {
+                    return Some(folder);
+                }
+            }
+        }
+
+        None
+    }
+
     /// Find a file with the given name in this keyring level (no depth)
     pub fn get_file_by_name(&self, folder_name: &str) -> Option<KeyWithFile> {
         for key in self.keys.iter() {
@@ -141,3 +456,48 @@ impl KeyringWithKeysAndFiles {
         None
     }
 }
+
+/// Share waiting for the user answer, its key is encrypted with the user pubkey
+#[derive(Deserialize, Clone, Debug)]
+pub struct PendingInvitation {
+    pub key: KeyWithFile,
+    pub permission: Permission,
+    pub sender: String,
+    pub created_at: i64,
+}
+
+/// Group of the user, the keys of its keyring are encrypted with the group pubkey
+#[derive(Deserialize, Clone, Debug)]
+pub struct Group {
+    pub name: String,
+    pub owner: String,
+    pub members: Vec<String>,
+    /// Group private key, wrapped with the user pubkey
+    #[serde(with = "serde_bytes")]
+    pub priv_key: Vec<u8>,
+    pub keyring: KeyringWithKeysAndFiles,
+}
+
+/// Page of the users and groups the user can share with
+#[derive(Deserialize, Clone, Debug)]
+pub struct UsersPage {
+    pub users: Vec<DirectoryEntry>,
+    /// Whether there are more users on the next pages
+    pub has_more: bool,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct DirectoryEntry {
+    pub username: String,
+    pub group: bool,
+    /// Fingerprint of the public key given by the server
+    pub fingerprint: String,
+}
+
+/// File in the trash, its key is encrypted with the user pubkey
+#[derive(Deserialize, Clone, Debug)]
+pub struct TrashedFile {
+    pub key: KeyWithFile,
+    pub trashed_at: i64,
+    pub origin: Option<i32>,
+}
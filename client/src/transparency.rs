@@ -0,0 +1,548 @@
+use base64::prelude::*;
+use colored::Colorize;
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+use reqwest::{blocking::Client, StatusCode};
+use rsa::sha2::{Digest, Sha256};
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_bytes::ByteBuf;
+
+use crate::{
+    codec::ResponseExt,
+    log,
+    trust::{LogState, TrustStore},
+    TSFSContext,
+};
+
+type Hash = [u8; 32];
+
+#[derive(Deserialize)]
+pub struct SignedTreeHead {
+    size: u64,
+    #[serde(with = "serde_bytes")]
+    root_hash: Vec<u8>,
+    timestamp: i64,
+    #[serde(with = "serde_bytes")]
+    signature: Vec<u8>,
+}
+
+#[derive(Deserialize)]
+pub struct InclusionProof {
+    index: u64,
+    #[serde(with = "serde_bytes")]
+    pub_key: Vec<u8>,
+    head: SignedTreeHead,
+    path: Vec<ByteBuf>,
+}
+
+#[derive(Deserialize)]
+pub struct ConsistencyProof {
+    path: Vec<ByteBuf>,
+}
+
+/// Check the public key given by the server for a user is his latest key in the key log
+///
+/// The inclusion proof must lead to a tree head signed by the log, and this head must
+/// extend the last one seen by the client. Return false if any check fails.
+pub fn verify_key(ctx: &TSFSContext, client: &Client, username: &str, pubkey: &[u8]) -> bool {
+    let mut store = TrustStore::load();
+
+    let Some(mut state) = log_state(ctx, client, &store) else {
+        return false;
+    };
+
+    let proof = match get_log::<InclusionProof>(ctx, client, &format!("proof/{}", username)) {
+        Ok(proof) => proof,
+
+        Err(Some(StatusCode::NOT_FOUND)) => {
+            log::error(&format!(
+                "The key of {} is not in the key log, it can't be trusted",
+                username.red()
+            ));
+            return false;
+        }
+
+        Err(_) => return false,
+    };
+
+    if !verify_head(&state, &proof.head) {
+        log::error("The tree head of the key log has an invalid signature");
+        return false;
+    }
+
+    if proof.pub_key != pubkey {
+        log::error(&format!(
+            "{}",
+            format!(
+                "THE KEY OF {} GIVEN BY THE SERVER IS NOT HIS LATEST LOGGED KEY",
+                username.to_uppercase()
+            )
+            .red()
+            .bold()
+        ));
+        return false;
+    }
+
+    let Some(path) = to_hashes(&proof.path) else {
+        log::error("Invalid inclusion proof in the key log");
+        return false;
+    };
+
+    if !verify_inclusion(
+        proof.index,
+        proof.head.size,
+        &leaf_hash(username, pubkey),
+        &path,
+        &proof.head.root_hash,
+    ) {
+        log::error(&format!(
+            "The inclusion proof of the key of {} is invalid",
+            username.red()
+        ));
+        return false;
+    }
+
+    if !check_consistency(ctx, client, &state, &proof.head) {
+        return false;
+    }
+
+    // Keep the latest head, the next ones must extend it
+    if proof.head.size >= state.size {
+        state.size = proof.head.size;
+        state.root_hash = BASE64_STANDARD.encode(&proof.head.root_hash);
+        store.set_log(ctx, state);
+        store.store();
+    }
+
+    true
+}
+
+/// Get the state of the key log of the endpoint, its signing key is pinned on first use
+fn log_state(ctx: &TSFSContext, client: &Client, store: &TrustStore) -> Option<LogState> {
+    if let Some(state) = store.log(ctx) {
+        return Some(state.clone());
+    }
+
+    let log_key = get_log::<ByteBuf>(ctx, client, "key").ok()?;
+    let log_key = BASE64_STANDARD.encode(log_key);
+
+    log::info(&format!(
+        "First use of the key log, its signing key is pinned: {}",
+        log_key.cyan()
+    ));
+
+    Some(LogState {
+        key: log_key,
+        size: 0,
+        root_hash: String::new(),
+    })
+}
+
+/// Check a tree head extends the last one seen, with a consistency proof of the log
+fn check_consistency(
+    ctx: &TSFSContext,
+    client: &Client,
+    state: &LogState,
+    head: &SignedTreeHead,
+) -> bool {
+    let root_hash = BASE64_STANDARD.decode(&state.root_hash).unwrap_or_default();
+
+    if head.size < state.size {
+        log::error(&format!(
+            "{}",
+            "THE KEY LOG SHRANK SINCE THE LAST TIME, THE SERVER MAY HAVE REWRITTEN IT"
+                .red()
+                .bold()
+        ));
+        return false;
+    }
+
+    if head.size == state.size {
+        if state.size > 0 && head.root_hash != root_hash {
+            log::error(&format!(
+                "{}",
+                "THE KEY LOG CHANGED SINCE THE LAST TIME, THE SERVER MAY HAVE REWRITTEN IT"
+                    .red()
+                    .bold()
+            ));
+            return false;
+        }
+        return true;
+    }
+
+    // Every log extends the empty one
+    if state.size == 0 {
+        return true;
+    }
+
+    let Ok(proof) = get_log::<ConsistencyProof>(
+        ctx,
+        client,
+        &format!("consistency/{}/{}", state.size, head.size),
+    ) else {
+        return false;
+    };
+
+    let consistent = to_hashes(&proof.path).is_some_and(|path| {
+        verify_consistency(state.size, head.size, &root_hash, &head.root_hash, &path)
+    });
+
+    if !consistent {
+        log::error(&format!(
+            "{}",
+            "THE KEY LOG DOESN'T EXTEND THE ONE SEEN BEFORE, THE SERVER MAY HAVE REWRITTEN IT"
+                .red()
+                .bold()
+        ));
+    }
+
+    consistent
+}
+
+/// Check the signature of a tree head with the pinned key of the log
+fn verify_head(state: &LogState, head: &SignedTreeHead) -> bool {
+    let Some(log_key) = BASE64_STANDARD
+        .decode(&state.key)
+        .ok()
+        .and_then(|key| VerifyingKey::try_from(key.as_slice()).ok())
+    else {
+        return false;
+    };
+
+    let Ok(signature) = Signature::from_slice(&head.signature) else {
+        return false;
+    };
+
+    let mut payload = b"tsfs tree head".to_vec();
+    payload.extend_from_slice(&head.size.to_be_bytes());
+    payload.extend_from_slice(&head.timestamp.to_be_bytes());
+    payload.extend_from_slice(&head.root_hash);
+
+    log_key.verify(&payload, &signature).is_ok()
+}
+
+fn get_log<T: DeserializeOwned>(
+    ctx: &TSFSContext,
+    client: &Client,
+    route: &str,
+) -> Result<T, Option<StatusCode>> {
+    let res = client
+        .get(format!(
+            "{}:{}/log/{}",
+            ctx.endpoint_url.as_ref().unwrap(),
+            ctx.endpoint_port,
+            route
+        ))
+        .header(
+            "Authorization",
+            format!("Bearer {}", ctx.session_token.as_ref().unwrap()),
+        )
+        .send();
+
+    match res {
+        Ok(res) => match res.error_for_status() {
+            Ok(res) => res.msg::<T>().map_err(|e| {
+                log::error(&format!("Invalid answer of the key log: {}", e));
+                None
+            }),
+
+            Err(e) => {
+                let status = e.status().unwrap();
+
+                if status != StatusCode::NOT_FOUND {
+                    log::error(&format!(
+                        "Can't get the key log: {}",
+                        status.to_string().red()
+                    ));
+                }
+                Err(Some(status))
+            }
+        },
+
+        Err(e) => {
+            log::error(&format!("Error on key log: {}", e.to_string().red()));
+            Err(None)
+        }
+    }
+}
+
+fn to_hashes(path: &[ByteBuf]) -> Option<Vec<Hash>> {
+    path.iter()
+        .map(|hash| hash.as_slice().try_into().ok())
+        .collect()
+}
+
+// Merkle tree of RFC 9162, the server hashes the leaves and the nodes the same way
+
+fn leaf_hash(username: &str, pub_key: &[u8]) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update([0u8]);
+    hasher.update((username.len() as u32).to_be_bytes());
+    hasher.update(username.as_bytes());
+    hasher.update(pub_key);
+    hasher.finalize().into()
+}
+
+fn node_hash(left: &Hash, right: &Hash) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update([1u8]);
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+/// Verify an inclusion proof, RFC 9162 section 2.1.3.2
+fn verify_inclusion(index: u64, size: u64, leaf: &Hash, path: &[Hash], root: &[u8]) -> bool {
+    if index >= size {
+        return false;
+    }
+
+    let (mut f_n, mut s_n) = (index, size - 1);
+    let mut r = *leaf;
+
+    for p in path {
+        if s_n == 0 {
+            return false;
+        }
+
+        if f_n & 1 == 1 || f_n == s_n {
+            r = node_hash(p, &r);
+
+            while f_n & 1 == 0 && f_n != 0 {
+                f_n >>= 1;
+                s_n >>= 1;
+            }
+        } else {
+            r = node_hash(&r, p);
+        }
+
+        f_n >>= 1;
+        s_n >>= 1;
+    }
+
+    s_n == 0 && r == root
+}
+
+/// Verify a consistency proof between two sizes of the log, RFC 9162 section 2.1.4.2
+fn verify_consistency(
+    first: u64,
+    second: u64,
+    first_root: &[u8],
+    second_root: &[u8],
+    path: &[Hash],
+) -> bool {
+    if first == 0 || first >= second {
+        return false;
+    }
+
+    // The root of a complete subtree is the start of the proof
+    let mut path = path.to_vec();
+    if first.is_power_of_two() {
+        let Ok(root) = Hash::try_from(first_root) else {
+            return false;
+        };
+        path.insert(0, root);
+    }
+
+    let Some((first_hash, path)) = path.split_first() else {
+        return false;
+    };
+
+    let (mut f_n, mut s_n) = (first - 1, second - 1);
+    while f_n & 1 == 1 {
+        f_n >>= 1;
+        s_n >>= 1;
+    }
+
+    let (mut f_r, mut s_r) = (*first_hash, *first_hash);
+
+    for c in path {
+        if s_n == 0 {
+            return false;
+        }
+
+        if f_n & 1 == 1 || f_n == s_n {
+            f_r = node_hash(c, &f_r);
+            s_r = node_hash(c, &s_r);
+
+            while f_n & 1 == 0 && f_n != 0 {
+                f_n >>= 1;
+                s_n >>= 1;
+            }
+        } else {
+            s_r = node_hash(&s_r, c);
+        }
+
+        f_n >>= 1;
+        s_n >>= 1;
+    }
+
+    s_n == 0 && f_r == first_root && s_r == second_root
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Proofs built like the server does, see server/src/routes/transparency.rs
+
+    fn split(n: usize) -> usize {
+        let mut k = 1;
+        while k * 2 < n {
+            k *= 2;
+        }
+        k
+    }
+
+    fn root_hash(leaves: &[Hash]) -> Hash {
+        match leaves.len() {
+            0 => Sha256::digest([]).into(),
+            1 => leaves[0],
+            n => {
+                let k = split(n);
+                node_hash(&root_hash(&leaves[..k]), &root_hash(&leaves[k..]))
+            }
+        }
+    }
+
+    fn inclusion_path(index: usize, leaves: &[Hash]) -> Vec<Hash> {
+        let n = leaves.len();
+        if n <= 1 {
+            return Vec::new();
+        }
+
+        let k = split(n);
+        if index < k {
+            let mut path = inclusion_path(index, &leaves[..k]);
+            path.push(root_hash(&leaves[k..]));
+            path
+        } else {
+            let mut path = inclusion_path(index - k, &leaves[k..]);
+            path.push(root_hash(&leaves[..k]));
+            path
+        }
+    }
+
+    fn subproof(m: usize, leaves: &[Hash], complete: bool) -> Vec<Hash> {
+        let n = leaves.len();
+        if m == n {
+            return if complete {
+                Vec::new()
+            } else {
+                vec![root_hash(leaves)]
+            };
+        }
+
+        let k = split(n);
+        if m <= k {
+            let mut proof = subproof(m, &leaves[..k], complete);
+            proof.push(root_hash(&leaves[k..]));
+            proof
+        } else {
+            let mut proof = subproof(m - k, &leaves[k..], false);
+            proof.push(root_hash(&leaves[..k]));
+            proof
+        }
+    }
+
+    fn leaves(n: usize) -> Vec<Hash> {
+        (0..n)
+            .map(|i| leaf_hash(&format!("user{}", i), &[i as u8]))
+            .collect()
+    }
+
+    #[test]
+    fn inclusion_proofs() {
+        for size in 1..=33 {
+            let leaves = leaves(size);
+            let root = root_hash(&leaves);
+
+            for index in 0..size {
+                let path = inclusion_path(index, &leaves);
+                assert!(
+                    verify_inclusion(index as u64, size as u64, &leaves[index], &path, &root),
+                    "leaf {} of {}",
+                    index,
+                    size
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn inclusion_proofs_invalid() {
+        let leaves = leaves(7);
+        let root = root_hash(&leaves);
+        let path = inclusion_path(5, &leaves);
+
+        // Another leaf, index or size doesn't match the path
+        assert!(!verify_inclusion(5, 7, &leaves[4], &path, &root));
+        assert!(!verify_inclusion(4, 7, &leaves[5], &path, &root));
+        assert!(!verify_inclusion(5, 6, &leaves[5], &path, &root));
+        assert!(!verify_inclusion(7, 7, &leaves[5], &path, &root));
+
+        // The path is tampered, truncated or extended
+        let mut tampered = path.clone();
+        tampered[1][0] ^= 1;
+        assert!(!verify_inclusion(5, 7, &leaves[5], &tampered, &root));
+        assert!(!verify_inclusion(5, 7, &leaves[5], &path[1..], &root));
+
+        let mut extended = path.clone();
+        extended.push(leaves[0]);
+        assert!(!verify_inclusion(5, 7, &leaves[5], &extended, &root));
+    }
+
+    #[test]
+    fn consistency_proofs() {
+        let leaves = leaves(33);
+
+        for second in 2..=leaves.len() {
+            let second_root = root_hash(&leaves[..second]);
+
+            for first in 1..second {
+                let first_root = root_hash(&leaves[..first]);
+                let path = subproof(first, &leaves[..second], true);
+
+                assert!(
+                    verify_consistency(
+                        first as u64,
+                        second as u64,
+                        &first_root,
+                        &second_root,
+                        &path
+                    ),
+                    "from {} to {}",
+                    first,
+                    second
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn consistency_proofs_invalid() {
+        let leaves = leaves(11);
+        let first = root_hash(&leaves[..6]);
+        let second = root_hash(&leaves);
+        let path = subproof(6, &leaves, true);
+
+        // A log whose first part was rewritten has other roots
+        let mut rewritten = leaves.clone();
+        rewritten[2] = leaf_hash("mallory", &[0]);
+        let forged_first = root_hash(&rewritten[..6]);
+        let forged_second = root_hash(&rewritten);
+        assert!(!verify_consistency(6, 11, &forged_first, &second, &path));
+        assert!(!verify_consistency(6, 11, &first, &forged_second, &path));
+
+        // The sizes must match the proof, and the first one be smaller
+        assert!(!verify_consistency(5, 11, &first, &second, &path));
+        assert!(!verify_consistency(6, 8, &first, &second, &path));
+        assert!(!verify_consistency(11, 11, &second, &second, &[]));
+        assert!(!verify_consistency(0, 11, &first, &second, &path));
+
+        // The proof is tampered or truncated
+        let mut tampered = path.clone();
+        tampered[0][0] ^= 1;
+        assert!(!verify_consistency(6, 11, &first, &second, &tampered));
+        assert!(!verify_consistency(6, 11, &first, &second, &path[1..]));
+    }
+}
@@ -0,0 +1,162 @@
+use std::collections::HashMap;
+
+use colored::Colorize;
+use serde::{Deserialize, Serialize};
+
+use crate::{crypto, log, TSFSContext};
+
+/// Public keys of the other users, pinned the first time the client gets them
+///
+/// The server gives the public keys, it could give its own one to read the shares. Once
+/// pinned, a different key is refused until it is checked with `verify`.
+/// Usernames are per server, the keys are stored for each endpoint.
+/// The key log of each endpoint is kept as well, to check the next heads extend it.
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct TrustStore {
+    endpoints: HashMap<String, HashMap<String, PinnedKey>>,
+    #[serde(default)]
+    logs: HashMap<String, LogState>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PinnedKey {
+    /// Fingerprint of the RSA public key of the user
+    pub fingerprint: String,
+    /// Whether the fingerprint was confirmed out of band with `verify`
+    pub verified: bool,
+}
+
+/// Last tree head of the key log seen by the client
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct LogState {
+    /// Ed25519 public key signing the tree heads, pinned on first use (base64)
+    pub key: String,
+    /// Number of leaves of the last head, 0 until a head is seen
+    pub size: u64,
+    /// Root hash of the last head (base64)
+    pub root_hash: String,
+}
+
+impl TrustStore {
+    /// Load the trust store, empty if it doesn't exist yet
+    ///
+    /// A store that can't be read or parsed aborts the client, storing over it would
+    /// discard every pinned key without the user knowing.
+    pub fn load() -> Self {
+        match confy::load::<TrustStore>("tsfs_cli", "trust") {
+            Ok(store) => store,
+            Err(e) => {
+                let path = confy::get_configuration_file_path("tsfs_cli", "trust")
+                    .map(|path| path.display().to_string())
+                    .unwrap_or_default();
+
+                log::error(&format!("Invalid trust store {}: {}", path.cyan(), e));
+                log::error("Fix or remove it, removing it discards every pinned key");
+
+                std::process::exit(1);
+            }
+        }
+    }
+
+    pub fn store(&self) {
+        confy::store("tsfs_cli", "trust", self).unwrap();
+    }
+
+    pub fn get(&self, ctx: &TSFSContext, username: &str) -> Option<&PinnedKey> {
+        self.endpoints.get(&endpoint(ctx))?.get(username)
+    }
+
+    pub fn pin(&mut self, ctx: &TSFSContext, username: &str, fingerprint: String, verified: bool) {
+        self.endpoints.entry(endpoint(ctx)).or_default().insert(
+            username.to_string(),
+            PinnedKey {
+                fingerprint,
+                verified,
+            },
+        );
+    }
+
+    pub fn log(&self, ctx: &TSFSContext) -> Option<&LogState> {
+        self.logs.get(&endpoint(ctx))
+    }
+
+    pub fn set_log(&mut self, ctx: &TSFSContext, state: LogState) {
+        self.logs.insert(endpoint(ctx), state);
+    }
+}
+
+/// Check the public key given by the server for a user against the pinned one
+///
+/// The key is pinned on first use. Return false if it differs from the pinned one.
+pub fn check_key(ctx: &TSFSContext, username: &str, pubkey: &[u8]) -> bool {
+    let fingerprint = crypto::fingerprint(pubkey);
+    let mut store = TrustStore::load();
+
+    match store.get(ctx, username) {
+        Some(pinned) if pinned.fingerprint == fingerprint => true,
+
+        Some(pinned) => {
+            log::error(&format!(
+                "{}",
+                format!("THE PUBLIC KEY OF {} HAS CHANGED", username.to_uppercase())
+                    .red()
+                    .bold()
+            ));
+            log::error(&format!("Pinned key:     {}", pinned.fingerprint.green()));
+            log::error(&format!("Key of server:  {}", fingerprint.red()));
+            log::error(&format!(
+                "The server may be trying to read your shares, ask {} for his fingerprint and use {} if it is the new one",
+                username.green(),
+                "verify <user> <fingerprint>".green()
+            ));
+
+            false
+        }
+
+        None => {
+            log::info(&format!(
+                "First use of the key of {}, pinned with fingerprint {}",
+                username.green(),
+                fingerprint.cyan()
+            ));
+            log::info(&format!(
+                "Compare it with {} to be sure it is his",
+                format!("fingerprint {}", username).green()
+            ));
+
+            store.pin(ctx, username, fingerprint, false);
+            store.store();
+
+            true
+        }
+    }
+}
+
+/// Pin a key the user knows is right, like a group key he just rotated
+pub fn pin_key(ctx: &TSFSContext, username: &str, pubkey: &[u8], verified: bool) {
+    let mut store = TrustStore::load();
+
+    store.pin(ctx, username, crypto::fingerprint(pubkey), verified);
+    store.store();
+}
+
+/// Compare two fingerprints, ignoring the separators and the case
+pub fn same_fingerprint(a: &str, b: &str) -> bool {
+    let normalize = |fingerprint: &str| {
+        fingerprint
+            .chars()
+            .filter(|c| c.is_ascii_hexdigit())
+            .collect::<String>()
+            .to_uppercase()
+    };
+
+    normalize(a) == normalize(b)
+}
+
+fn endpoint(ctx: &TSFSContext) -> String {
+    format!(
+        "{}:{}",
+        ctx.endpoint_url.as_deref().unwrap_or_default(),
+        ctx.endpoint_port
+    )
+}
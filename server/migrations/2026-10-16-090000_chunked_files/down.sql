@@ -0,0 +1,26 @@
+-- Only the first chunk of each file can be restored in the data column
+CREATE TABLE files_old (
+    id VARCHAR PRIMARY KEY NOT NULL, -- UUIDv4 of the file
+    name VARCHAR NOT NULL,           -- name of the file [encrypted]
+    mtime BIGINT,           -- last modification time
+    sz INT,                 -- original file size
+    data BLOB,              -- content [encrypted], empty if folder
+    keyring_id INTEGER,     -- if folder, keyring of this folder
+    FOREIGN KEY(keyring_id) REFERENCES keyrings(id)
+);
+
+INSERT INTO files_old (id, name, mtime, sz, data, keyring_id)
+    SELECT
+        files.id,
+        files.name,
+        files.mtime,
+        files.sz,
+        (SELECT chunks.data FROM chunks WHERE chunks.blob = files.blob AND chunks.idx = 0),
+        files.keyring_id
+    FROM files;
+
+DROP TABLE files;
+ALTER TABLE files_old RENAME TO files;
+
+DROP TABLE uploads;
+DROP TABLE chunks;
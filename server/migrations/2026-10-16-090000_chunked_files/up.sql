@@ -0,0 +1,46 @@
+-- File contents are split in chunks, each chunk encrypted separately client side
+-- A set of chunks is identified by a blob id, referenced by a file or a pending upload
+CREATE TABLE chunks (
+    blob VARCHAR NOT NULL,  -- blob id (UUIDv4)
+    idx INTEGER NOT NULL,   -- position of the chunk in the blob
+    sz INTEGER NOT NULL,    -- size of the chunk
+    data BLOB NOT NULL,     -- content [encrypted]
+    PRIMARY KEY(blob, idx)
+);
+
+CREATE TABLE uploads (
+    id VARCHAR PRIMARY KEY NOT NULL,    -- UUIDv4 of the upload, also the blob id of its chunks
+    user VARCHAR NOT NULL,              -- user who started the upload
+    created_at BIGINT NOT NULL,
+    FOREIGN KEY(user) REFERENCES users(username)
+);
+
+-- Existing contents become single chunk blobs, using the file id as blob id
+INSERT INTO chunks (blob, idx, sz, data)
+    SELECT id, 0, LENGTH(data), data FROM files WHERE data IS NOT NULL;
+
+-- Rebuild files without the data column
+CREATE TABLE files_new (
+    id VARCHAR PRIMARY KEY NOT NULL, -- UUIDv4 of the file
+    name VARCHAR NOT NULL,           -- name of the file [encrypted]
+    mtime BIGINT,           -- last modification time
+    sz BIGINT,              -- content size [encrypted]
+    blob VARCHAR,           -- blob holding the content chunks, empty if folder
+    chunks INTEGER,         -- number of chunks in the blob, empty if folder
+    keyring_id INTEGER,     -- if folder, keyring of this folder
+    FOREIGN KEY(keyring_id) REFERENCES keyrings(id)
+);
+
+INSERT INTO files_new (id, name, mtime, sz, blob, chunks, keyring_id)
+    SELECT
+        id,
+        name,
+        mtime,
+        sz,
+        CASE WHEN data IS NULL THEN NULL ELSE id END,
+        CASE WHEN data IS NULL THEN NULL ELSE 1 END,
+        keyring_id
+    FROM files;
+
+DROP TABLE files;
+ALTER TABLE files_new RENAME TO files;
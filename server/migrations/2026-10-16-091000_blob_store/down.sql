@@ -0,0 +1,12 @@
+-- Only the chunks not yet moved to the blob store can be restored
+-- legacy_chunks is dropped by the server once all chunks are moved
+CREATE TABLE IF NOT EXISTS legacy_chunks (
+    blob VARCHAR NOT NULL,
+    idx INTEGER NOT NULL,
+    sz INTEGER NOT NULL,
+    data BLOB NOT NULL,
+    PRIMARY KEY(blob, idx)
+);
+
+DROP TABLE chunks;
+ALTER TABLE legacy_chunks RENAME TO chunks;
@@ -0,0 +1,13 @@
+-- Chunk contents are moved out of the database to the blob store
+-- Existing contents are kept in legacy_chunks until the server moves them at startup
+ALTER TABLE chunks RENAME TO legacy_chunks;
+
+CREATE TABLE chunks (
+    blob VARCHAR NOT NULL,  -- blob id (UUIDv4), content is in the blob store
+    idx INTEGER NOT NULL,   -- position of the chunk in the blob
+    sz INTEGER NOT NULL,    -- size of the chunk
+    PRIMARY KEY(blob, idx)
+);
+
+INSERT INTO chunks (blob, idx, sz)
+    SELECT blob, idx, sz FROM legacy_chunks;
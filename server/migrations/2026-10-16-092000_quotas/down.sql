@@ -0,0 +1,2 @@
+ALTER TABLE files DROP COLUMN owner;
+ALTER TABLE users DROP COLUMN quota;
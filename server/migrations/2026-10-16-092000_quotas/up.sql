@@ -0,0 +1,6 @@
+-- Storage quota of the user in bytes, NULL to use the server default
+ALTER TABLE users ADD COLUMN quota BIGINT;
+
+-- User charged for the content of the file
+-- Files created before quotas have no owner and are not charged
+ALTER TABLE files ADD COLUMN owner VARCHAR;
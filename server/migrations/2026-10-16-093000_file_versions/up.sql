@@ -0,0 +1,12 @@
+-- Previous contents of the files, kept when a file is overwritten
+-- Versions are encrypted with the same key as the file
+CREATE TABLE file_versions (
+    file_id VARCHAR NOT NULL,   -- UUIDv4 of the file
+    version INTEGER NOT NULL,   -- version number, the higher the newer
+    mtime BIGINT,               -- modification time of this content
+    sz BIGINT NOT NULL,         -- content size [encrypted]
+    blob VARCHAR NOT NULL,      -- blob holding the content chunks
+    chunks INTEGER NOT NULL,    -- number of chunks in the blob
+    PRIMARY KEY(file_id, version),
+    FOREIGN KEY(file_id) REFERENCES files(id)
+);
@@ -0,0 +1,4 @@
+-- Trashed keys are left in the trash keyrings, the garbage collector reclaims them
+ALTER TABLE keys DROP COLUMN origin;
+ALTER TABLE keys DROP COLUMN trashed_at;
+ALTER TABLE users DROP COLUMN trash;
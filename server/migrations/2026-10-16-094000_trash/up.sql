@@ -0,0 +1,6 @@
+-- Deleted files are moved to a per-user trash keyring, created on first delete
+ALTER TABLE users ADD COLUMN trash INTEGER;
+
+-- Set on the keys moved to a trash keyring
+ALTER TABLE keys ADD COLUMN trashed_at BIGINT;  -- time of the deletion
+ALTER TABLE keys ADD COLUMN origin INTEGER;     -- keyring the key was deleted from
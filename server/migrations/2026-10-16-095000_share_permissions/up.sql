@@ -0,0 +1,18 @@
+-- Access level granted by a key: 0 read, 1 write, 2 owner
+-- The keys inside a folder keep the full access, the key of the folder limits it
+ALTER TABLE keys ADD COLUMN permission INTEGER NOT NULL DEFAULT 2;
+
+-- Only the creator of a file keeps the owner access, the keys shared with the other users
+-- give write access. The creator of a file created before quotas isn't known, he holds
+-- the first key of the file.
+UPDATE keys SET permission = 1
+WHERE keyring_id IN (SELECT keyring FROM users UNION SELECT trash FROM users)
+    AND NOT EXISTS (
+        SELECT 1 FROM files
+        INNER JOIN users ON users.username = files.owner
+        WHERE files.id = keys.target AND keys.keyring_id IN (users.keyring, users.trash)
+    )
+    AND (
+        EXISTS (SELECT 1 FROM files WHERE files.id = keys.target AND files.owner IS NOT NULL)
+        OR keys.id > (SELECT MIN(first.id) FROM keys AS first WHERE first.target = keys.target)
+    );
@@ -0,0 +1,6 @@
+ALTER TABLE keys DROP COLUMN read_priv_key;
+ALTER TABLE keys DROP COLUMN write_priv_key;
+ALTER TABLE keys DROP COLUMN owner_priv_key;
+ALTER TABLE files DROP COLUMN read_pub_key;
+ALTER TABLE files DROP COLUMN write_pub_key;
+ALTER TABLE files DROP COLUMN owner_pub_key;
@@ -0,0 +1,10 @@
+-- Signing public keys of a file, a user proves his access level by signing a challenge
+-- Files created before have none, only their keyring access is checked
+ALTER TABLE files ADD COLUMN owner_pub_key BLOB;
+ALTER TABLE files ADD COLUMN write_pub_key BLOB;
+ALTER TABLE files ADD COLUMN read_pub_key BLOB;
+
+-- Signing private keys granted by a key, NULL above its access level
+ALTER TABLE keys ADD COLUMN owner_priv_key BLOB;    -- [encrypted]
+ALTER TABLE keys ADD COLUMN write_priv_key BLOB;    -- [encrypted]
+ALTER TABLE keys ADD COLUMN read_priv_key BLOB;     -- [encrypted]
@@ -0,0 +1,3 @@
+-- Pending keys are left in the inbox keyrings, the garbage collector reclaims them
+DROP TABLE invitations;
+ALTER TABLE users DROP COLUMN inbox;
@@ -0,0 +1,11 @@
+-- Shares wait in a per-user inbox keyring until the recipient accepts them
+ALTER TABLE users ADD COLUMN inbox INTEGER;
+
+-- Pending shares, their key is held by the recipient inbox keyring
+CREATE TABLE invitations (
+    key_id INTEGER PRIMARY KEY NOT NULL,    -- key in the inbox keyring
+    sender VARCHAR NOT NULL,                -- user who shared the file
+    created_at BIGINT NOT NULL,             -- time of the share, in millis
+    FOREIGN KEY(key_id) REFERENCES keys(id),
+    FOREIGN KEY(sender) REFERENCES users(username)
+);
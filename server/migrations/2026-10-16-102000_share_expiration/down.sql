@@ -0,0 +1,2 @@
+ALTER TABLE files DROP COLUMN expired_share_at;
+ALTER TABLE keys DROP COLUMN expires_at;
@@ -0,0 +1,5 @@
+-- Shares can be granted for a limited time, expired keys are removed by the server
+ALTER TABLE keys ADD COLUMN expires_at BIGINT;          -- end of the grant, in millis
+
+-- Set when a share of the file expired, until its owner rotates its keys
+ALTER TABLE files ADD COLUMN expired_share_at BIGINT;   -- time of the last expiration
@@ -0,0 +1,3 @@
+-- Keys left in the group keyrings are reclaimed by the garbage collector
+DROP TABLE group_members;
+DROP TABLE groups;
@@ -0,0 +1,19 @@
+-- Groups of users, files are shared with a group through its own keyring
+CREATE TABLE groups (
+    name VARCHAR PRIMARY KEY NOT NULL,      -- unique among the groups and the users
+    owner VARCHAR NOT NULL,                 -- user managing the members
+    pub_key BLOB NOT NULL,                  -- RSA public key of the group
+    keyring INTEGER NOT NULL,               -- keys of the files shared with the group
+    FOREIGN KEY(owner) REFERENCES users(username),
+    FOREIGN KEY(keyring) REFERENCES keyrings(id)
+);
+
+-- Members of a group, each one holds the group private key
+CREATE TABLE group_members (
+    group_name VARCHAR NOT NULL,
+    username VARCHAR NOT NULL,
+    priv_key BLOB NOT NULL,                 -- group private key, encrypted with the member public key
+    PRIMARY KEY(group_name, username),
+    FOREIGN KEY(group_name) REFERENCES groups(name),
+    FOREIGN KEY(username) REFERENCES users(username)
+);
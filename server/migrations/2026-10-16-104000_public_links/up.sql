@@ -0,0 +1,12 @@
+-- Anonymous download links, the file key is only known by the holder of the link
+CREATE TABLE public_links (
+    token VARCHAR PRIMARY KEY NOT NULL,     -- random token of the link
+    file_id VARCHAR NOT NULL,               -- file served by the link
+    creator VARCHAR NOT NULL,               -- user who created the link
+    created_at BIGINT NOT NULL,             -- time of the creation, in millis
+    expires_at BIGINT,                      -- end of the link in millis, NULL if it doesn't expire
+    max_downloads INTEGER,                  -- downloads allowed, NULL if unlimited
+    downloads INTEGER NOT NULL DEFAULT 0,   -- downloads started so far
+    FOREIGN KEY(file_id) REFERENCES files(id),
+    FOREIGN KEY(creator) REFERENCES users(username)
+);
@@ -0,0 +1,2 @@
+ALTER TABLE keys DROP COLUMN signature;
+ALTER TABLE keys DROP COLUMN signer;
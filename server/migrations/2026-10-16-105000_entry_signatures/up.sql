@@ -0,0 +1,4 @@
+-- Keyring entries are signed with the RSA key of the user who created them, checked in the
+-- key log, so the server can't forge one
+ALTER TABLE keys ADD COLUMN signer VARCHAR;         -- user who signed the entry
+ALTER TABLE keys ADD COLUMN signature BLOB;         -- signature of the entry and its keyring
@@ -0,0 +1,2 @@
+DROP INDEX key_log_username;
+DROP TABLE key_log;
@@ -0,0 +1,10 @@
+-- Append-only log of the public keys of users and groups, the leaves of a Merkle tree
+CREATE TABLE key_log (
+    idx INTEGER PRIMARY KEY NOT NULL,   -- position of the leaf in the log, from 0
+    username VARCHAR NOT NULL,          -- user or group owning the key
+    pub_key BLOB NOT NULL,              -- RSA public key registered or rotated
+    leaf_hash BLOB NOT NULL,            -- hash of the leaf in the Merkle tree
+    created_at BIGINT NOT NULL          -- time of the append, in millis
+);
+
+CREATE INDEX key_log_username ON key_log(username);
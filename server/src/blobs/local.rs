@@ -0,0 +1,74 @@
+use std::{io::ErrorKind, path::PathBuf};
+
+use axum::{async_trait, body::Bytes};
+use tokio::fs;
+use uuid::Uuid;
+
+use super::{BlobResult, BlobStore};
+
+/// Blob store on the local filesystem
+///
+/// Each blob is a directory under the root, holding one file per chunk named by its index.
+pub struct LocalBlobStore {
+    root: PathBuf,
+}
+
+impl LocalBlobStore {
+    pub fn new(root: PathBuf) -> std::io::Result<Self> {
+        std::fs::create_dir_all(&root)?;
+
+        Ok(Self { root })
+    }
+
+    fn blob_path(&self, blob: &str) -> BlobResult<PathBuf> {
+        // Blob ids are UUIDs, anything else could escape the root
+        let blob = Uuid::parse_str(blob)?;
+
+        Ok(self.root.join(blob.to_string()))
+    }
+}
+
+#[async_trait]
+impl BlobStore for LocalBlobStore {
+    async fn put_chunk(&self, blob: &str, index: i32, data: Bytes) -> BlobResult<()> {
+        let blob_path = self.blob_path(blob)?;
+        fs::create_dir_all(&blob_path).await?;
+
+        // Write to a temporary file first, a chunk is never seen partially written
+        let tmp_path = blob_path.join(format!("{}.tmp", Uuid::new_v4()));
+        fs::write(&tmp_path, &data).await?;
+        fs::rename(&tmp_path, blob_path.join(index.to_string())).await?;
+
+        Ok(())
+    }
+
+    async fn get_chunk(&self, blob: &str, index: i32) -> BlobResult<Option<Bytes>> {
+        let chunk_path = self.blob_path(blob)?.join(index.to_string());
+
+        match fs::read(chunk_path).await {
+            Ok(data) => Ok(Some(Bytes::from(data))),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn copy_chunk(&self, from_blob: &str, to_blob: &str, index: i32) -> BlobResult<()> {
+        let from_path = self.blob_path(from_blob)?.join(index.to_string());
+        let to_path = self.blob_path(to_blob)?;
+        fs::create_dir_all(&to_path).await?;
+
+        let tmp_path = to_path.join(format!("{}.tmp", Uuid::new_v4()));
+        fs::copy(&from_path, &tmp_path).await?;
+        fs::rename(&tmp_path, to_path.join(index.to_string())).await?;
+
+        Ok(())
+    }
+
+    async fn delete_blob(&self, blob: &str) -> BlobResult<()> {
+        match fs::remove_dir_all(self.blob_path(blob)?).await {
+            Ok(_) => Ok(()),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
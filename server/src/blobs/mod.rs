@@ -0,0 +1,211 @@
+use std::{env, error::Error, path::PathBuf, sync::Arc};
+
+use axum::{async_trait, body::Bytes};
+use deadpool_diesel::sqlite::Pool;
+use diesel::{dsl::sql, prelude::*, sql_types::Bool};
+
+use crate::{db::schema::legacy_chunks, log};
+
+pub mod local;
+pub mod s3;
+
+pub type BlobResult<T> = Result<T, Box<dyn Error + Send + Sync>>;
+
+/// Storage of the encrypted file contents
+///
+/// A blob is a set of chunks identified by a blob id (UUIDv4), the database only keeps
+/// the blob reference of each file and the size of each chunk. Chunks are opaque for the
+/// store, they are already encrypted by the client.
+#[async_trait]
+pub trait BlobStore: Send + Sync {
+    /// Store a chunk of a blob, replacing it if it already exists
+    async fn put_chunk(&self, blob: &str, index: i32, data: Bytes) -> BlobResult<()>;
+
+    /// Get a chunk of a blob, None if it doesn't exist
+    async fn get_chunk(&self, blob: &str, index: i32) -> BlobResult<Option<Bytes>>;
+
+    /// Copy a chunk of a blob to another blob, without going through the server
+    /// when the store allows it
+    async fn copy_chunk(&self, from_blob: &str, to_blob: &str, index: i32) -> BlobResult<()>;
+
+    /// Delete all chunks of a blob, deleting an unknown blob is not an error
+    async fn delete_blob(&self, blob: &str) -> BlobResult<()>;
+}
+
+/// Build the blob store configured by the `BLOB_STORE` env variable
+///
+/// - `local` (default): chunks are files under `BLOB_PATH`
+/// - `s3`: chunks are objects of `S3_BUCKET`, on AWS or any S3-compatible server set with `S3_ENDPOINT`
+pub fn from_env() -> Arc<dyn BlobStore> {
+    let kind = env::var("BLOB_STORE").unwrap_or("local".to_owned());
+
+    match kind.as_str() {
+        "local" => {
+            let path = env::var("BLOB_PATH").unwrap_or("./blobs".to_owned());
+            Arc::new(local::LocalBlobStore::new(PathBuf::from(path)).unwrap())
+        }
+
+        "s3" => Arc::new(s3::S3BlobStore::from_env().unwrap()),
+
+        _ => panic!("Unknown `BLOB_STORE` {}, expected `local` or `s3`", kind),
+    }
+}
+
+/// Delete blobs that are not referenced anymore
+///
+/// Called once the transaction removing the references has been committed, a failure
+/// only leaves unreachable chunks behind.
+pub async fn delete_blobs(store: &Arc<dyn BlobStore>, blobs: Vec<String>) {
+    for blob in blobs {
+        if let Err(e) = store.delete_blob(&blob).await {
+            log::error(&format!("Can't delete blob {}: {}", blob, e));
+        }
+    }
+}
+
+/// Move the chunks still stored in the database to the blob store
+///
+/// Contents were stored as BLOB in the database before the blob store, the
+/// `blob_store` migration leaves them in `legacy_chunks`. Each chunk is removed from the
+/// database once stored, so an interrupted move is resumed on next start. The table is
+/// dropped once emptied.
+pub async fn move_legacy_chunks(store: &Arc<dyn BlobStore>, pool: &Pool) {
+    let conn = pool.get().await.unwrap();
+    let mut moved = 0;
+
+    let pending: bool = conn
+        .interact(|conn| {
+            diesel::select(sql::<Bool>(
+                "EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'legacy_chunks')",
+            ))
+            .get_result(conn)
+        })
+        .await
+        .unwrap()
+        .unwrap();
+
+    if !pending {
+        return;
+    }
+
+    loop {
+        let chunk: Option<(String, i32, Vec<u8>)> = conn
+            .interact(|conn| {
+                legacy_chunks::table
+                    .select((legacy_chunks::blob, legacy_chunks::idx, legacy_chunks::data))
+                    .first(conn)
+                    .optional()
+            })
+            .await
+            .unwrap()
+            .unwrap();
+
+        let Some((blob, idx, data)) = chunk else {
+            break;
+        };
+
+        store
+            .put_chunk(&blob, idx, Bytes::from(data))
+            .await
+            .expect("Can't move chunk to the blob store");
+
+        conn.interact(move |conn| {
+            diesel::delete(legacy_chunks::table.find((blob, idx))).execute(conn)
+        })
+        .await
+        .unwrap()
+        .unwrap();
+
+        moved += 1;
+    }
+
+    conn.interact(|conn| diesel::sql_query("DROP TABLE legacy_chunks").execute(conn))
+        .await
+        .unwrap()
+        .unwrap();
+
+    if moved > 0 {
+        log::info(&format!(
+            "Moved {} chunks from the database to the blob store",
+            moved
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use object_store::memory::InMemory;
+    use uuid::Uuid;
+
+    use super::*;
+
+    /// Run the same checks on any store, each backend must behave the same
+    async fn check_store(store: &dyn BlobStore) {
+        let blob = Uuid::new_v4().to_string();
+        let copy = Uuid::new_v4().to_string();
+
+        assert!(store.get_chunk(&blob, 0).await.unwrap().is_none());
+
+        store
+            .put_chunk(&blob, 0, Bytes::from_static(b"first"))
+            .await
+            .unwrap();
+        store
+            .put_chunk(&blob, 1, Bytes::from_static(b"second"))
+            .await
+            .unwrap();
+        assert_eq!(
+            store.get_chunk(&blob, 0).await.unwrap().unwrap(),
+            Bytes::from_static(b"first")
+        );
+
+        // Putting a chunk again replaces it
+        store
+            .put_chunk(&blob, 1, Bytes::from_static(b"replaced"))
+            .await
+            .unwrap();
+        assert_eq!(
+            store.get_chunk(&blob, 1).await.unwrap().unwrap(),
+            Bytes::from_static(b"replaced")
+        );
+
+        store.copy_chunk(&blob, &copy, 1).await.unwrap();
+        assert_eq!(
+            store.get_chunk(&copy, 1).await.unwrap().unwrap(),
+            Bytes::from_static(b"replaced")
+        );
+        assert!(store.get_chunk(&copy, 0).await.unwrap().is_none());
+
+        // Deleting a blob leaves the copies
+        store.delete_blob(&blob).await.unwrap();
+        assert!(store.get_chunk(&blob, 0).await.unwrap().is_none());
+        assert!(store.get_chunk(&blob, 1).await.unwrap().is_none());
+        assert!(store.get_chunk(&copy, 1).await.unwrap().is_some());
+
+        // Deleting an unknown blob is not an error
+        store.delete_blob(&blob).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn local_store() {
+        let root = tempfile::tempdir().unwrap();
+        let store = local::LocalBlobStore::new(root.path().to_path_buf()).unwrap();
+
+        check_store(&store).await;
+    }
+
+    #[tokio::test]
+    async fn local_store_rejects_paths() {
+        let root = tempfile::tempdir().unwrap();
+        let store = local::LocalBlobStore::new(root.path().to_path_buf()).unwrap();
+
+        assert!(store.get_chunk("../escape", 0).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn s3_store() {
+        let store = s3::S3BlobStore::new(Arc::new(InMemory::new()));
+
+        check_store(&store).await;
+    }
+}
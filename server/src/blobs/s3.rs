@@ -0,0 +1,105 @@
+use std::{env, sync::Arc};
+
+use axum::{async_trait, body::Bytes};
+use futures::TryStreamExt;
+use object_store::{aws::AmazonS3Builder, path::Path, ObjectStore};
+
+use super::{BlobResult, BlobStore};
+
+/// Blob store on an S3-compatible object storage
+///
+/// Each chunk is an object named `<blob>/<index>`. Any S3-compatible server can be
+/// used by setting `S3_ENDPOINT`, for example a local MinIO for development. The store
+/// works on any `ObjectStore`, tests run it in memory.
+pub struct S3BlobStore {
+    store: Arc<dyn ObjectStore>,
+}
+
+impl S3BlobStore {
+    pub fn new(store: Arc<dyn ObjectStore>) -> Self {
+        Self { store }
+    }
+
+    /// Configure the store from the `S3_*` env variables
+    ///
+    /// Credentials and region fall back on the usual `AWS_*` env variables when not set.
+    pub fn from_env() -> BlobResult<Self> {
+        let bucket = env::var("S3_BUCKET").expect("Missing `S3_BUCKET` env variable");
+
+        let mut builder = AmazonS3Builder::from_env().with_bucket_name(bucket);
+
+        if let Ok(endpoint) = env::var("S3_ENDPOINT") {
+            // S3-compatible servers are usually reached by path and may not use TLS
+            builder = builder
+                .with_allow_http(endpoint.starts_with("http://"))
+                .with_virtual_hosted_style_request(false)
+                .with_endpoint(endpoint);
+        }
+        if let Ok(region) = env::var("S3_REGION") {
+            builder = builder.with_region(region);
+        }
+        if let Ok(access_key_id) = env::var("S3_ACCESS_KEY_ID") {
+            builder = builder.with_access_key_id(access_key_id);
+        }
+        if let Ok(secret_access_key) = env::var("S3_SECRET_ACCESS_KEY") {
+            builder = builder.with_secret_access_key(secret_access_key);
+        }
+
+        Ok(Self::new(Arc::new(builder.build()?)))
+    }
+
+    fn chunk_path(blob: &str, index: i32) -> Path {
+        Path::from(format!("{}/{}", blob, index))
+    }
+}
+
+#[async_trait]
+impl BlobStore for S3BlobStore {
+    async fn put_chunk(&self, blob: &str, index: i32, data: Bytes) -> BlobResult<()> {
+        self.store
+            .put(&S3BlobStore::chunk_path(blob, index), data)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn get_chunk(&self, blob: &str, index: i32) -> BlobResult<Option<Bytes>> {
+        match self.store.get(&S3BlobStore::chunk_path(blob, index)).await {
+            Ok(result) => Ok(Some(result.bytes().await?)),
+            Err(object_store::Error::NotFound { .. }) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn copy_chunk(&self, from_blob: &str, to_blob: &str, index: i32) -> BlobResult<()> {
+        // Copied by the S3 server
+        self.store
+            .copy(
+                &S3BlobStore::chunk_path(from_blob, index),
+                &S3BlobStore::chunk_path(to_blob, index),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn delete_blob(&self, blob: &str) -> BlobResult<()> {
+        let prefix = Path::from(blob);
+
+        let chunks: Vec<Path> = self
+            .store
+            .list(Some(&prefix))
+            .map_ok(|meta| meta.location)
+            .try_collect()
+            .await?;
+
+        for chunk in chunks {
+            match self.store.delete(&chunk).await {
+                Ok(_) | Err(object_store::Error::NotFound { .. }) => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        Ok(())
+    }
+}
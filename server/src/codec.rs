@@ -0,0 +1,174 @@
+use axum::{
+    async_trait,
+    body::Bytes,
+    extract::{FromRequest, Request},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde::{de::DeserializeOwned, Serialize};
+
+pub const MSGPACK: &str = "application/msgpack";
+pub const JSON: &str = "application/json";
+
+/// Encoding of structured messages
+///
+/// MessagePack is preferred as binary fields are sent as raw bytes,
+/// JSON is kept as a fallback for clients that don't support it.
+/// File contents don't go through this, chunks are sent as `application/octet-stream`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Format {
+    Json,
+    MsgPack,
+}
+
+impl Format {
+    pub fn mime(&self) -> &'static str {
+        match self {
+            Format::Json => JSON,
+            Format::MsgPack => MSGPACK,
+        }
+    }
+
+    fn from_mime(mime: &str) -> Option<Self> {
+        match mime.trim().to_ascii_lowercase().as_str() {
+            MSGPACK | "application/x-msgpack" => Some(Format::MsgPack),
+            JSON => Some(Format::Json),
+            _ => None,
+        }
+    }
+
+    /// Format of a request body, from its `Content-Type` header
+    fn from_content_type(headers: &HeaderMap) -> Option<Self> {
+        let content_type = headers.get(header::CONTENT_TYPE)?.to_str().ok()?;
+
+        // Strip parameters like charset
+        Format::from_mime(content_type.split(';').next().unwrap())
+    }
+
+    /// Preferred response format, from the `Accept` header
+    ///
+    /// The supported media type with the highest quality wins, JSON if none is supported
+    fn from_accept(headers: &HeaderMap) -> Self {
+        let Some(accept) = headers.get(header::ACCEPT).and_then(|a| a.to_str().ok()) else {
+            return Format::Json;
+        };
+
+        let mut best: Option<(Format, f32)> = None;
+
+        for media_range in accept.split(',') {
+            let mut params = media_range.split(';');
+            let Some(format) = Format::from_mime(params.next().unwrap()) else {
+                continue;
+            };
+
+            let quality = params
+                .filter_map(|p| p.trim().strip_prefix("q="))
+                .find_map(|q| q.parse::<f32>().ok())
+                .unwrap_or(1.0);
+
+            if quality > 0.0 && best.map_or(true, |(_, q)| quality > q) {
+                best = Some((format, quality));
+            }
+        }
+
+        best.map_or(Format::Json, |(format, _)| format)
+    }
+}
+
+tokio::task_local! {
+    /// Response format negotiated for the request being handled
+    static RESPONSE_FORMAT: Format;
+}
+
+/// Middleware negotiating the response format of a request from its `Accept` header
+pub async fn negotiate(request: Request, next: Next) -> Response {
+    let format = Format::from_accept(request.headers());
+
+    RESPONSE_FORMAT.scope(format, next.run(request)).await
+}
+
+/// Structured message, used in place of axum `Json`
+///
+/// As an extractor, the body is decoded according to the request `Content-Type`.
+/// As a response, the body is encoded in the format negotiated by the `negotiate` middleware.
+pub struct Msg<T>(pub T);
+
+#[async_trait]
+impl<T, S> FromRequest<S> for Msg<T>
+where
+    T: DeserializeOwned,
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request(request: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let Some(format) = Format::from_content_type(request.headers()) else {
+            return Err((
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                format!("Expected a `{}` or `{}` body", MSGPACK, JSON),
+            )
+                .into_response());
+        };
+
+        let body = Bytes::from_request(request, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+
+        let value = match format {
+            Format::MsgPack => rmp_serde::from_slice(&body).map_err(|e| e.to_string()),
+            Format::Json => serde_json::from_slice(&body).map_err(|e| e.to_string()),
+        };
+
+        value
+            .map(Msg)
+            .map_err(|e| (StatusCode::UNPROCESSABLE_ENTITY, e).into_response())
+    }
+}
+
+impl<T> IntoResponse for Msg<T>
+where
+    T: Serialize,
+{
+    fn into_response(self) -> Response {
+        let format = RESPONSE_FORMAT
+            .try_with(|format| *format)
+            .unwrap_or(Format::Json);
+
+        let body = match format {
+            Format::MsgPack => rmp_serde::to_vec_named(&self.0).map_err(|e| e.to_string()),
+            Format::Json => serde_json::to_vec(&self.0).map_err(|e| e.to_string()),
+        };
+
+        match body {
+            Ok(body) => (
+                [(header::CONTENT_TYPE, HeaderValue::from_static(format.mime()))],
+                body,
+            )
+                .into_response(),
+
+            Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e).into_response(),
+        }
+    }
+}
+
+/// Serialize a pair of byte buffers as raw bytes, like `serde_bytes` does for a single one
+pub mod bytes_pair {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use serde_bytes::{ByteBuf, Bytes};
+
+    pub fn serialize<S: Serializer>(
+        pair: &(Vec<u8>, Vec<u8>),
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        (Bytes::new(&pair.0), Bytes::new(&pair.1)).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<(Vec<u8>, Vec<u8>), D::Error> {
+        let (first, second) = <(ByteBuf, ByteBuf)>::deserialize(deserializer)?;
+
+        Ok((first.into_vec(), second.into_vec()))
+    }
+}
@@ -11,6 +11,9 @@ pub struct User {
     pub pub_key: Vec<u8>,
     pub priv_key: Vec<u8>,
     pub keyring: i32,
+    pub quota: Option<i64>,
+    pub trash: Option<i32>,
+    pub inbox: Option<i32>,
 }
 
 #[derive(Queryable, Clone, PartialEq, Debug)]
@@ -36,17 +39,212 @@ pub struct Session {
 pub struct Key {
     pub id: i32,
     pub target: String,
+    #[serde(with = "serde_bytes")]
     pub key: Vec<u8>,
     pub keyring_id: i32,
+    pub trashed_at: Option<i64>,
+    pub origin: Option<i32>,
+    pub permission: i32,
+    #[serde(with = "serde_bytes")]
+    pub owner_priv_key: Option<Vec<u8>>,
+    #[serde(with = "serde_bytes")]
+    pub write_priv_key: Option<Vec<u8>>,
+    #[serde(with = "serde_bytes")]
+    pub read_priv_key: Option<Vec<u8>>,
+    pub expires_at: Option<i64>,
+    pub signer: Option<String>,
+    #[serde(with = "serde_bytes")]
+    pub signature: Option<Vec<u8>>,
 }
 
-#[derive(Insertable, Queryable, Selectable, Serialize, Deserialize, Clone, PartialEq, Debug)]
+impl Key {
+    pub fn signing_keys(&self) -> SigningKeys {
+        SigningKeys {
+            owner: self.owner_priv_key.clone(),
+            write: self.write_priv_key.clone(),
+            read: self.read_priv_key.clone(),
+        }
+    }
+}
+
+/// Pending share, its key waits in the recipient inbox keyring until he accepts it
+#[derive(Insertable, Queryable, Selectable, Clone, PartialEq, Debug)]
+#[diesel(table_name = self::schema::invitations)]
+pub struct Invitation {
+    pub key_id: i32,
+    pub sender: String,
+    pub created_at: i64,
+}
+
+/// Anonymous download link of a file, its key is carried by the link and unknown here
+#[derive(Insertable, Queryable, Selectable, Clone, PartialEq, Debug)]
+#[diesel(table_name = self::schema::public_links)]
+pub struct PublicLink {
+    pub token: String,
+    pub file_id: String,
+    pub creator: String,
+    pub created_at: i64,
+    pub expires_at: Option<i64>,
+    pub max_downloads: Option<i32>,
+    pub downloads: i32,
+}
+
+/// Leaf of the key transparency log, a public key registered or rotated
+#[derive(Insertable, Queryable, Selectable, Clone, PartialEq, Debug)]
+#[diesel(table_name = self::schema::key_log)]
+pub struct LogEntry {
+    pub idx: i32,
+    pub username: String,
+    pub pub_key: Vec<u8>,
+    pub leaf_hash: Vec<u8>,
+    pub created_at: i64,
+}
+
+/// Group of users, the keys of the files shared with it are encrypted with its public key
+#[derive(Insertable, Queryable, Selectable, Clone, PartialEq, Debug)]
+#[diesel(table_name = self::schema::groups)]
+pub struct Group {
+    pub name: String,
+    pub owner: String,
+    pub pub_key: Vec<u8>,
+    pub keyring: i32,
+}
+
+#[derive(Insertable, Queryable, Selectable, Clone, PartialEq, Debug)]
+#[diesel(table_name = self::schema::group_members)]
+pub struct GroupMember {
+    pub group_name: String,
+    pub username: String,
+    /// Group private key, encrypted with the member public key
+    pub priv_key: Vec<u8>,
+}
+
+#[derive(Insertable, Serialize, Deserialize, Clone, PartialEq, Debug)]
 #[diesel(table_name = self::schema::keys)]
 pub struct NewKey {
     pub target: String,
+    #[serde(with = "serde_bytes")]
     pub key: Vec<u8>,
-    pub keyring_id: i32
-}   
+    pub keyring_id: i32,
+    pub permission: i32,
+    #[diesel(embed)]
+    pub signing_keys: SigningKeys,
+    /// End of the grant in millis, None if it doesn't expire
+    pub expires_at: Option<i64>,
+    #[diesel(embed)]
+    pub entry_signature: EntrySignature,
+}
+
+/// Signature of a keyring entry by the user who created it, over the target, the
+/// keyring, the access level and the encrypted key
+///
+/// The RSA public keys are public, only the signature keeps the server from adding an
+/// entry of its own to a root keyring, or from moving an entry or raising its level.
+/// Entries of a folder keyring are encrypted with the folder key and aren't signed.
+#[derive(Insertable, AsChangeset, Serialize, Deserialize, Clone, Default, PartialEq, Debug)]
+#[diesel(table_name = self::schema::keys, treat_none_as_null = true)]
+pub struct EntrySignature {
+    pub signer: Option<String>,
+    #[serde(with = "serde_bytes")]
+    pub signature: Option<Vec<u8>>,
+}
+
+impl EntrySignature {
+    /// Record the signature sent by a user, the server only stores who sent it
+    pub fn new(signer: &str, signature: Option<Vec<u8>>) -> Self {
+        EntrySignature {
+            signer: signature.as_ref().map(|_| signer.to_string()),
+            signature,
+        }
+    }
+}
+
+/// Signing private keys of a file granted by a key, encrypted like the file key for the
+/// keyring holding it (the folder ones are derived from the folder signing keys)
+///
+/// A key only holds the private keys up to its access level, the other ones are None.
+/// Files created before signing keys have none.
+#[derive(Insertable, AsChangeset, Serialize, Deserialize, Clone, Default, PartialEq, Debug)]
+#[diesel(table_name = self::schema::keys, treat_none_as_null = true)]
+pub struct SigningKeys {
+    #[diesel(column_name = owner_priv_key)]
+    #[serde(with = "serde_bytes")]
+    pub owner: Option<Vec<u8>>,
+    #[diesel(column_name = write_priv_key)]
+    #[serde(with = "serde_bytes")]
+    pub write: Option<Vec<u8>>,
+    #[diesel(column_name = read_priv_key)]
+    #[serde(with = "serde_bytes")]
+    pub read: Option<Vec<u8>>,
+}
+
+impl SigningKeys {
+    /// Drop the private keys above the given access level
+    pub fn restrict(self, permission: Permission) -> Self {
+        SigningKeys {
+            owner: self.owner.filter(|_| permission >= Permission::Owner),
+            write: self.write.filter(|_| permission >= Permission::Write),
+            read: self.read,
+        }
+    }
+}
+
+/// Signing public keys of a file, a user proves his access level on the file by signing
+/// a challenge with the private key of this level
+#[derive(Insertable, AsChangeset, Serialize, Deserialize, Clone, Default, PartialEq, Debug)]
+#[diesel(table_name = self::schema::files, treat_none_as_null = true)]
+pub struct PublicKeys {
+    #[diesel(column_name = owner_pub_key)]
+    #[serde(with = "serde_bytes")]
+    pub owner: Option<Vec<u8>>,
+    #[diesel(column_name = write_pub_key)]
+    #[serde(with = "serde_bytes")]
+    pub write: Option<Vec<u8>>,
+    #[diesel(column_name = read_pub_key)]
+    #[serde(with = "serde_bytes")]
+    pub read: Option<Vec<u8>>,
+}
+
+/// Access level granted by a key, each level includes the lower ones
+///
+/// Stored as an integer in `keys.permission`. The level of a user on a file is the lowest
+/// level along the path of keys leading to it from his root keyring.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Permission {
+    /// Download the file, list the folder
+    Read = 0,
+    /// Modify the file or the folder content
+    Write = 1,
+    /// Revoke the shares of the file
+    Owner = 2,
+}
+
+impl From<i32> for Permission {
+    fn from(level: i32) -> Self {
+        match level {
+            0 => Permission::Read,
+            1 => Permission::Write,
+            _ => Permission::Owner,
+        }
+    }
+}
+
+#[derive(Insertable, Queryable, Selectable, Clone, PartialEq, Debug)]
+#[diesel(table_name = self::schema::chunks)]
+pub struct Chunk {
+    pub blob: String,
+    pub idx: i32,
+    pub sz: i32,
+}
+
+#[derive(Insertable, Queryable, Selectable, Clone, PartialEq, Debug)]
+#[diesel(table_name = self::schema::uploads)]
+pub struct Upload {
+    pub id: String,
+    pub user: String,
+    pub created_at: i64,
+}
 
 #[derive(Identifiable, Queryable, Serialize, Deserialize, Clone, PartialEq, Debug)]
 #[diesel(table_name = self::schema::keyrings)]
@@ -68,15 +266,19 @@ pub struct NewKeyring {
     pub id: Option<i32>
 }
 
-#[derive(Insertable, Queryable, Selectable, Clone, PartialEq, Debug)]
+#[derive(Insertable, Clone, PartialEq, Debug)]
 #[diesel(table_name = self::schema::files)]
 pub struct NewFile {
     pub id: String,
     pub name: String,
     pub mtime: i64,
-    pub sz: i32,
-    pub data: Vec<u8>,
+    pub sz: i64,
+    pub blob: String,
+    pub chunks: i32,
     pub keyring_id: Option<i32>,
+    pub owner: String,
+    #[diesel(embed)]
+    pub public_keys: PublicKeys,
 }
 
 #[derive(Serialize, Insertable, Queryable, Clone, PartialEq, Debug)]
@@ -85,9 +287,43 @@ pub struct File {
     pub id: String,
     pub name: String,
     pub mtime: Option<i64>,
-    pub sz: Option<i32>,
-    pub data: Option<Vec<u8>>,
+    pub sz: Option<i64>,
+    #[serde(skip)]
+    pub blob: Option<String>,
+    pub chunks: Option<i32>,
     pub keyring_id: Option<i32>,
+    pub owner: Option<String>,
+    #[serde(skip)]
+    pub owner_pub_key: Option<Vec<u8>>,
+    #[serde(skip)]
+    pub write_pub_key: Option<Vec<u8>>,
+    #[serde(skip)]
+    pub read_pub_key: Option<Vec<u8>>,
+    #[serde(skip)]
+    pub expired_share_at: Option<i64>,
+}
+
+impl File {
+    /// Public key checking the challenges signed for the given access level
+    pub fn public_key(&self, permission: Permission) -> Option<&Vec<u8>> {
+        match permission {
+            Permission::Read => self.read_pub_key.as_ref(),
+            Permission::Write => self.write_pub_key.as_ref(),
+            Permission::Owner => self.owner_pub_key.as_ref(),
+        }
+    }
+}
+
+#[derive(Serialize, Insertable, Queryable, Clone, PartialEq, Debug)]
+#[diesel(table_name = self::schema::file_versions)]
+pub struct FileVersion {
+    pub file_id: String,
+    pub version: i32,
+    pub mtime: Option<i64>,
+    pub sz: i64,
+    #[serde(skip)]
+    pub blob: String,
+    pub chunks: i32,
 }
 
 #[derive(Queryable, Clone, PartialEq, Debug)]
@@ -104,6 +340,8 @@ pub struct FileWithoutData {
     pub id: String,
     pub name: String,
     pub keyring_id: Option<i32>,
+    pub owner: Option<String>,
+    pub expired_share_at: Option<i64>,
 }
 
 #[derive(Serialize, Clone, Debug)]
@@ -111,17 +349,79 @@ pub struct FileWithoutDataWithKeyring {
     pub id: String,
     pub name: String,
     pub keyring: Option<KeyringWithKeysAndFiles>,
+    /// User who created the file, None for files created before owners were recorded
+    pub owner: Option<String>,
+    /// Time a share of the file expired, its owner should rotate its keys
+    pub expired_share_at: Option<i64>,
 }
 
 #[derive(Serialize, Clone, Debug)]
 pub struct KeyWithFile {
     pub file: FileWithoutDataWithKeyring,
+    #[serde(with = "serde_bytes")]
     pub key: Vec<u8>,
     pub keyring_id: i32,
+    /// Access level granted by the key
+    pub permission: Permission,
+    pub signing_keys: SigningKeys,
+    /// User who signed the entry, None for unsigned entries
+    pub signer: Option<String>,
+    #[serde(with = "serde_bytes")]
+    pub signature: Option<Vec<u8>>,
 }
 
 #[derive(Serialize, Clone, Debug)]
 pub struct KeyringWithKeysAndFiles {
     pub id: i32,
     pub keys: Vec<KeyWithFile>
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn signing_keys() -> SigningKeys {
+        SigningKeys {
+            owner: Some(vec![2]),
+            write: Some(vec![1]),
+            read: Some(vec![0]),
+        }
+    }
+
+    #[test]
+    fn restrict_keeps_keys_up_to_level() {
+        assert_eq!(signing_keys().restrict(Permission::Owner), signing_keys());
+
+        let write = signing_keys().restrict(Permission::Write);
+        assert_eq!(write.owner, None);
+        assert_eq!(write.write, Some(vec![1]));
+        assert_eq!(write.read, Some(vec![0]));
+
+        let read = signing_keys().restrict(Permission::Read);
+        assert_eq!(read.owner, None);
+        assert_eq!(read.write, None);
+        assert_eq!(read.read, Some(vec![0]));
+    }
+
+    #[test]
+    fn restrict_legacy_keys() {
+        assert_eq!(
+            SigningKeys::default().restrict(Permission::Owner),
+            SigningKeys::default()
+        );
+    }
+
+    #[test]
+    fn permission_levels() {
+        assert!(Permission::Read < Permission::Write);
+        assert!(Permission::Write < Permission::Owner);
+
+        assert_eq!(Permission::from(0), Permission::Read);
+        assert_eq!(Permission::from(1), Permission::Write);
+        assert_eq!(Permission::from(2), Permission::Owner);
+        assert_eq!(
+            Permission::from(Permission::Write as i32),
+            Permission::Write
+        );
+    }
+}
@@ -1,13 +1,73 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    chunks (blob, idx) {
+        blob -> Text,
+        idx -> Integer,
+        sz -> Integer,
+    }
+}
+
+diesel::table! {
+    file_versions (file_id, version) {
+        file_id -> Text,
+        version -> Integer,
+        mtime -> Nullable<BigInt>,
+        sz -> BigInt,
+        blob -> Text,
+        chunks -> Integer,
+    }
+}
+
 diesel::table! {
     files (id) {
         id -> Text,
         name -> Text,
         mtime -> Nullable<BigInt>,
-        sz -> Nullable<Integer>,
-        data -> Nullable<Binary>,
+        sz -> Nullable<BigInt>,
+        blob -> Nullable<Text>,
+        chunks -> Nullable<Integer>,
         keyring_id -> Nullable<Integer>,
+        owner -> Nullable<Text>,
+        owner_pub_key -> Nullable<Binary>,
+        write_pub_key -> Nullable<Binary>,
+        read_pub_key -> Nullable<Binary>,
+        expired_share_at -> Nullable<BigInt>,
+    }
+}
+
+diesel::table! {
+    group_members (group_name, username) {
+        group_name -> Text,
+        username -> Text,
+        priv_key -> Binary,
+    }
+}
+
+diesel::table! {
+    groups (name) {
+        name -> Text,
+        owner -> Text,
+        pub_key -> Binary,
+        keyring -> Integer,
+    }
+}
+
+diesel::table! {
+    invitations (key_id) {
+        key_id -> Integer,
+        sender -> Text,
+        created_at -> BigInt,
+    }
+}
+
+diesel::table! {
+    key_log (idx) {
+        idx -> Integer,
+        username -> Text,
+        pub_key -> Binary,
+        leaf_hash -> Binary,
+        created_at -> BigInt,
     }
 }
 
@@ -23,6 +83,36 @@ diesel::table! {
         target -> Text,
         key -> Binary,
         keyring_id -> Integer,
+        trashed_at -> Nullable<BigInt>,
+        origin -> Nullable<Integer>,
+        permission -> Integer,
+        owner_priv_key -> Nullable<Binary>,
+        write_priv_key -> Nullable<Binary>,
+        read_priv_key -> Nullable<Binary>,
+        expires_at -> Nullable<BigInt>,
+        signer -> Nullable<Text>,
+        signature -> Nullable<Binary>,
+    }
+}
+
+diesel::table! {
+    legacy_chunks (blob, idx) {
+        blob -> Text,
+        idx -> Integer,
+        sz -> Integer,
+        data -> Binary,
+    }
+}
+
+diesel::table! {
+    public_links (token) {
+        token -> Text,
+        file_id -> Text,
+        creator -> Text,
+        created_at -> BigInt,
+        expires_at -> Nullable<BigInt>,
+        max_downloads -> Nullable<Integer>,
+        downloads -> Integer,
     }
 }
 
@@ -34,6 +124,14 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    uploads (id) {
+        id -> Text,
+        user -> Text,
+        created_at -> BigInt,
+    }
+}
+
 diesel::table! {
     users (username) {
         username -> Text,
@@ -41,19 +139,39 @@ diesel::table! {
         pub_key -> Binary,
         priv_key -> Binary,
         keyring -> Integer,
+        quota -> Nullable<BigInt>,
+        trash -> Nullable<Integer>,
+        inbox -> Nullable<Integer>,
     }
 }
 
+diesel::joinable!(file_versions -> files (file_id));
 diesel::joinable!(files -> keyrings (keyring_id));
+diesel::joinable!(group_members -> groups (group_name));
+diesel::joinable!(group_members -> users (username));
+diesel::joinable!(groups -> keyrings (keyring));
+diesel::joinable!(invitations -> keys (key_id));
 diesel::joinable!(keys -> files (target));
 diesel::joinable!(keys -> keyrings (keyring_id));
+diesel::joinable!(public_links -> files (file_id));
+diesel::joinable!(public_links -> users (creator));
 diesel::joinable!(sessions -> users (user));
+diesel::joinable!(uploads -> users (user));
 diesel::joinable!(users -> keyrings (keyring));
 
 diesel::allow_tables_to_appear_in_same_query!(
+    chunks,
+    file_versions,
     files,
+    group_members,
+    groups,
+    invitations,
+    key_log,
     keyrings,
     keys,
+    legacy_chunks,
+    public_links,
     sessions,
+    uploads,
     users,
 );
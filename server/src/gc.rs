@@ -0,0 +1,289 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use colored::Colorize;
+use deadpool_diesel::sqlite::Pool;
+use diesel::prelude::*;
+
+use crate::{
+    blobs::{self, BlobStore},
+    db::{
+        schema::{
+            chunks, file_versions, files, groups, invitations, keyrings, keys, public_links,
+            uploads, users,
+        },
+        Key, Upload,
+    },
+    log,
+    routes::files::UPLOAD_LIFETIME,
+};
+
+/// Rows of the keyring graph that can't be reached from any user anymore
+///
+/// Everything reachable is found by walking the graph from the user root, trash and inbox keyrings
+/// and the group keyrings: a keyring gives access to the files targeted by its keys, a folder gives access to its keyring.
+#[derive(Default, Debug)]
+pub struct GcReport {
+    /// Keys in an unreachable keyring or targeting a missing file
+    pub keys: Vec<i32>,
+    /// Keyrings of no user, no group and no reachable folder
+    pub keyrings: Vec<i32>,
+    /// Files targeted by no reachable key
+    pub files: Vec<String>,
+    /// Abandoned pending uploads
+    pub uploads: Vec<String>,
+    /// Blobs referenced by no reachable file or version and no pending upload
+    pub blobs: Vec<String>,
+    /// Size of the dangling keys
+    pub key_bytes: i64,
+    /// Size of the content of the dangling blobs
+    pub content_bytes: i64,
+}
+
+impl GcReport {
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+            && self.keyrings.is_empty()
+            && self.files.is_empty()
+            && self.uploads.is_empty()
+            && self.blobs.is_empty()
+    }
+
+    /// Print the summary of the report
+    pub fn print(&self, removed: bool) {
+        let action = if removed { "Reclaimed" } else { "Reclaimable" };
+
+        println!("{}", "Garbage collection".cyan());
+        println!("  Dangling keys:     {}", self.keys.len());
+        println!("  Dangling keyrings: {}", self.keyrings.len());
+        println!("  Dangling files:    {}", self.files.len());
+        println!("  Stale uploads:     {}", self.uploads.len());
+        println!("  Dangling blobs:    {}", self.blobs.len());
+        println!(
+            "{}: {} ({} of content, {} of keys)",
+            action,
+            format_bytes(self.content_bytes + self.key_bytes).green(),
+            format_bytes(self.content_bytes),
+            format_bytes(self.key_bytes)
+        );
+    }
+}
+
+/// Find the dangling rows of the database, and remove them if `remove` is set
+///
+/// Rows are removed in a single transaction, then the dangling blobs are deleted
+/// from the blob store.
+pub async fn collect(pool: &Pool, blob_store: &Arc<dyn BlobStore>, remove: bool) -> GcReport {
+    let conn = pool.get().await.unwrap();
+
+    let report = conn
+        .interact(move |conn| {
+            conn.transaction(|conn| {
+                let report = find_dangling(conn)?;
+
+                if remove {
+                    remove_dangling(&report, conn)?;
+                }
+
+                diesel::result::QueryResult::Ok(report)
+            })
+        })
+        .await
+        .unwrap()
+        .unwrap();
+
+    if remove {
+        blobs::delete_blobs(blob_store, report.blobs.clone()).await;
+    }
+
+    report
+}
+
+/// Run the garbage collector every `interval`, removing dangling rows
+pub fn spawn_periodic(pool: Pool, blob_store: Arc<dyn BlobStore>, interval: Duration) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(interval);
+
+        loop {
+            interval.tick().await;
+
+            let report = collect(&pool, &blob_store, true).await;
+            if !report.is_empty() {
+                log::info(&format!(
+                    "Garbage collection reclaimed {} keys, {} keyrings, {} files, {} uploads ({})",
+                    report.keys.len(),
+                    report.keyrings.len(),
+                    report.files.len(),
+                    report.uploads.len(),
+                    format_bytes(report.content_bytes + report.key_bytes)
+                ));
+            }
+        }
+    });
+}
+
+/// Keyrings from which the keyring graph is walked, the users root, trash and inbox keyrings
+/// and the group keyrings
+fn root_keyrings(conn: &mut SqliteConnection) -> QueryResult<Vec<i32>> {
+    let mut roots: Vec<i32> = users::table.select(users::keyring).load(conn)?;
+    roots.extend(
+        users::table
+            .select(users::trash)
+            .load::<Option<i32>>(conn)?
+            .into_iter()
+            .flatten(),
+    );
+    roots.extend(
+        users::table
+            .select(users::inbox)
+            .load::<Option<i32>>(conn)?
+            .into_iter()
+            .flatten(),
+    );
+    roots.extend(groups::table.select(groups::keyring).load::<i32>(conn)?);
+
+    Ok(roots)
+}
+
+fn find_dangling(conn: &mut SqliteConnection) -> QueryResult<GcReport> {
+    let all_keyrings: Vec<i32> = keyrings::table.select(keyrings::id).load(conn)?;
+    let all_keys: Vec<Key> = keys::table.load(conn)?;
+    let all_files: HashMap<String, (Option<String>, Option<i32>)> = files::table
+        .select((files::id, (files::blob, files::keyring_id)))
+        .load::<(String, (Option<String>, Option<i32>))>(conn)?
+        .into_iter()
+        .collect();
+    let all_versions: Vec<(String, String)> = file_versions::table
+        .select((file_versions::file_id, file_versions::blob))
+        .load(conn)?;
+    let all_uploads: Vec<Upload> = uploads::table.load(conn)?;
+    let all_chunks: Vec<(String, i32)> = chunks::table
+        .select((chunks::blob, chunks::sz))
+        .load(conn)?;
+
+    let mut keys_by_keyring: HashMap<i32, Vec<&Key>> = HashMap::new();
+    for key in &all_keys {
+        keys_by_keyring.entry(key.keyring_id).or_default().push(key);
+    }
+
+    // Walk the keyring graph from the users roots
+    let mut reachable_keyrings: HashSet<i32> = HashSet::new();
+    let mut reachable_files: HashSet<&str> = HashSet::new();
+    let mut queue = Vec::new();
+
+    for keyring in root_keyrings(conn)? {
+        if reachable_keyrings.insert(keyring) {
+            queue.push(keyring);
+        }
+    }
+
+    while let Some(keyring) = queue.pop() {
+        for key in keys_by_keyring.get(&keyring).into_iter().flatten() {
+            let Some((_, folder_keyring)) = all_files.get(&key.target) else {
+                continue;
+            };
+
+            if reachable_files.insert(&key.target) {
+                if let Some(folder_keyring) = folder_keyring {
+                    if reachable_keyrings.insert(*folder_keyring) {
+                        queue.push(*folder_keyring);
+                    }
+                }
+            }
+        }
+    }
+
+    let mut report = GcReport::default();
+
+    for key in &all_keys {
+        if !reachable_keyrings.contains(&key.keyring_id) || !all_files.contains_key(&key.target) {
+            report.keys.push(key.id);
+            report.key_bytes += key.key.len() as i64;
+        }
+    }
+
+    report.keyrings = all_keyrings
+        .into_iter()
+        .filter(|keyring| !reachable_keyrings.contains(keyring))
+        .collect();
+
+    report.files = all_files
+        .keys()
+        .filter(|file| !reachable_files.contains(file.as_str()))
+        .cloned()
+        .collect();
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as i64;
+
+    // Blobs still in use, by a reachable file, its versions or by a pending upload
+    let mut used_blobs: HashSet<&str> = reachable_files
+        .iter()
+        .filter_map(|file| all_files[*file].0.as_deref())
+        .collect();
+
+    for (file_id, blob) in &all_versions {
+        if reachable_files.contains(file_id.as_str()) {
+            used_blobs.insert(blob);
+        }
+    }
+
+    for upload in &all_uploads {
+        if upload.created_at < now - UPLOAD_LIFETIME {
+            report.uploads.push(upload.id.clone());
+        } else {
+            used_blobs.insert(&upload.id);
+        }
+    }
+
+    let mut dangling_blobs: HashSet<&str> = HashSet::new();
+    for (blob, sz) in &all_chunks {
+        if !used_blobs.contains(blob.as_str()) {
+            dangling_blobs.insert(blob);
+            report.content_bytes += *sz as i64;
+        }
+    }
+    report.blobs = dangling_blobs.into_iter().map(str::to_owned).collect();
+
+    Ok(report)
+}
+
+fn remove_dangling(report: &GcReport, conn: &mut SqliteConnection) -> QueryResult<()> {
+    // Keys and versions first, then files, then keyrings, as each references the next
+    diesel::delete(invitations::table.filter(invitations::key_id.eq_any(&report.keys)))
+        .execute(conn)?;
+    diesel::delete(keys::table.filter(keys::id.eq_any(&report.keys))).execute(conn)?;
+    diesel::delete(file_versions::table.filter(file_versions::file_id.eq_any(&report.files)))
+        .execute(conn)?;
+    diesel::delete(public_links::table.filter(public_links::file_id.eq_any(&report.files)))
+        .execute(conn)?;
+    diesel::delete(files::table.filter(files::id.eq_any(&report.files))).execute(conn)?;
+    diesel::delete(keyrings::table.filter(keyrings::id.eq_any(&report.keyrings))).execute(conn)?;
+    diesel::delete(uploads::table.filter(uploads::id.eq_any(&report.uploads))).execute(conn)?;
+    diesel::delete(chunks::table.filter(chunks::blob.eq_any(&report.blobs))).execute(conn)?;
+
+    Ok(())
+}
+
+/// Format a size in bytes in a human readable way
+pub fn format_bytes(bytes: i64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.2} {}", size, UNITS[unit])
+    }
+}
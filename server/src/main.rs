@@ -1,15 +1,25 @@
-use axum::{routing::post, Extension, Router};
+use axum::{
+    middleware,
+    routing::{get, post},
+    Extension, Router,
+};
 use axum_server::tls_rustls::RustlsConfig;
 use base64::{engine::general_purpose, Engine as _};
+use blobs::BlobStore;
 use colored::Colorize;
-use deadpool_diesel::{sqlite::Pool, Manager, Runtime};
+use deadpool_diesel::{
+    sqlite::{Hook, HookError, Pool},
+    Manager, Runtime,
+};
+use diesel::connection::SimpleConnection;
 use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
 use dotenv::dotenv;
+use ed25519_dalek::SigningKey;
 use opaque_ke::*;
-use rand::rngs::OsRng;
+use rand::{rngs::OsRng, RngCore};
 use routes::{
     auth::{self, DefaultCS},
-    authenticated_router,
+    authenticated_router, expiration, links, quota, signing, transparency, trash, versions,
 };
 use std::{
     collections::HashMap,
@@ -20,10 +30,14 @@ use std::{
     path::PathBuf,
     str::FromStr,
     sync::{Arc, RwLock},
+    time::Duration,
 };
 use tower::ServiceBuilder;
 
+mod blobs;
+mod codec;
 mod db;
+mod gc;
 mod log;
 mod routes;
 
@@ -36,6 +50,7 @@ async fn main() {
     // If --setup arg is passed, generate a fresh ServerSetup and print it's base64 serialization
     if env::args().find(|a| a == "--setup").is_some() {
         generate_opaque_setup();
+        generate_log_key();
         return;
     }
 
@@ -53,6 +68,22 @@ async fn main() {
         env::var("LISTENING_ADDRESS").expect("Missing `LISTENING_ADDRESS` env variable");
     let port = env::var("PORT").expect("Missing `PORT` env variable");
     let db_url = env::var("DATABASE_URL").expect("Missing `DATABASE_URL` env variable");
+    let log_signing_key =
+        env::var("LOG_SIGNING_KEY").expect("Missing `LOG_SIGNING_KEY` env variable");
+    let default_quota = env::var("DEFAULT_QUOTA").map_or(quota::DEFAULT_QUOTA, |q| {
+        q.parse::<i64>()
+            .expect("`DEFAULT_QUOTA` must be a number of bytes")
+    });
+    let version_retention =
+        env::var("VERSION_RETENTION").map_or(versions::DEFAULT_VERSION_RETENTION, |r| {
+            r.parse::<i64>()
+                .expect("`VERSION_RETENTION` must be a number of versions")
+        });
+    let trash_retention_days =
+        env::var("TRASH_RETENTION_DAYS").map_or(trash::DEFAULT_TRASH_RETENTION_DAYS, |d| {
+            d.parse::<i64>()
+                .expect("`TRASH_RETENTION_DAYS` must be a number of days")
+        });
 
     // Get the ServerSetup from env
     // Using a saved ServerSetup is needed to have persistence
@@ -67,9 +98,29 @@ async fn main() {
         ServerSetup::<DefaultCS>::deserialize(&server_setup_serialized).unwrap();
     let server_setup_state = Arc::new(server_setup);
 
+    // The key signing the tree heads of the key log must be kept as well
+    // The clients pin it and refuse heads signed by another one
+    let log_key: [u8; 32] = general_purpose::STANDARD_NO_PAD
+        .decode(log_signing_key)
+        .unwrap()
+        .try_into()
+        .expect("`LOG_SIGNING_KEY` must be 32 bytes");
+    let log_key = Arc::new(SigningKey::from_bytes(&log_key));
+
     // Init Database
+    // A transaction waits for the one writing instead of failing right away
     let manager = Manager::new(db_url, Runtime::Tokio1);
-    let pool = Pool::builder(manager).build().unwrap();
+    let pool = Pool::builder(manager)
+        .post_create(Hook::async_fn(|conn, _| {
+            Box::pin(async move {
+                conn.interact(|conn| conn.batch_execute("PRAGMA busy_timeout = 5000;"))
+                    .await
+                    .unwrap()
+                    .map_err(|e| HookError::Message(e.to_string().into()))
+            })
+        }))
+        .build()
+        .unwrap();
 
     // Run diesel migrations
     let conn = pool.get().await.unwrap();
@@ -78,12 +129,83 @@ async fn main() {
         .unwrap()
         .unwrap();
 
+    // Log the keys registered before the key log existed
+    transparency::log_missing_keys(&pool).await;
+
+    // Init blob store and move the contents still stored in the database
+    let blob_store = blobs::from_env();
+    blobs::move_legacy_chunks(&blob_store, &pool).await;
+
+    // If --set-quota <user> <bytes> args are passed, override the quota of the user
+    // Use `default` as bytes to go back to the default quota
+    if let Some(position) = env::args().position(|a| a == "--set-quota") {
+        let args: Vec<String> = env::args().skip(position + 1).take(2).collect();
+        let [user, bytes] = args.as_slice() else {
+            log::error("Usage: --set-quota <user> <bytes|default>");
+            return;
+        };
+
+        let quota = if bytes == "default" {
+            None
+        } else {
+            Some(
+                bytes
+                    .parse::<i64>()
+                    .expect("Quota must be a number of bytes"),
+            )
+        };
+
+        match quota::set_user_quota(&pool, user.clone(), quota).await {
+            Ok(0) => log::error(&format!("Unknown user {}", user)),
+            Ok(_) => log::info(&format!("Quota of {} updated", user)),
+            Err(e) => log::error(&format!("Can't update quota: {}", e)),
+        }
+        return;
+    }
+
+    // If --gc arg is passed, report the dangling rows of the database
+    // With --remove, they are also removed
+    if env::args().find(|a| a == "--gc").is_some() {
+        let remove = env::args().find(|a| a == "--remove").is_some();
+        let report = gc::collect(&pool, &blob_store, remove).await;
+        report.print(remove);
+        return;
+    }
+
+    // Periodic garbage collection, if GC_INTERVAL is set (in seconds)
+    if let Ok(interval) = env::var("GC_INTERVAL") {
+        let interval = interval
+            .parse::<u64>()
+            .ok()
+            .filter(|interval| *interval > 0)
+            .expect("`GC_INTERVAL` must be a positive number of seconds");
+        gc::spawn_periodic(
+            pool.clone(),
+            blob_store.clone(),
+            Duration::from_secs(interval),
+        );
+    }
+
+    // Trashed files are purged once TRASH_RETENTION_DAYS are elapsed
+    trash::spawn_purge(pool.clone(), blob_store.clone(), trash_retention_days);
+
+    // Shares given with an expiration are removed once it's reached
+    expiration::spawn_expiration(pool.clone(), blob_store.clone());
+
     let app_state = AppState {
         server_login_states: Arc::new(RwLock::new(HashMap::<
             String,
             ServerLoginStartResult<DefaultCS>,
         >::new())),
+        challenges: Arc::new(RwLock::new(HashMap::new())),
+        downloads: Arc::new(RwLock::new(HashMap::new())),
+        dummy_keys: auth::DummyKeys::new(server_setup_state.serialize().to_vec()),
         pool,
+        blob_store,
+        default_quota,
+        version_retention,
+        log_key,
+        log_tree: Arc::new(RwLock::new(transparency::MerkleTree::default())),
     };
 
     // Axum app
@@ -92,8 +214,17 @@ async fn main() {
         .route("/auth/register/finish", post(auth::register_finish))
         .route("/auth/login/start", post(auth::login_start))
         .route("/auth/login/finish", post(auth::login_finish))
+        .route("/public/:token", get(links::get_public_file))
+        .route(
+            "/public/:token/:download/:index",
+            get(links::download_public_chunk),
+        )
         .merge(authenticated_router(app_state.clone()))
-        .layer(ServiceBuilder::new().layer(Extension(server_setup_state)))
+        .layer(
+            ServiceBuilder::new()
+                .layer(Extension(server_setup_state))
+                .layer(middleware::from_fn(codec::negotiate)),
+        )
         .with_state(app_state);
 
     // Setup HTTPS Server
@@ -127,6 +258,15 @@ fn generate_opaque_setup() {
     println!("{}: {}", "OPAQUE ServerSetup".cyan(), b64_server_setup);
 }
 
+/// Generate a new signing key for the tree heads of the key log
+fn generate_log_key() {
+    println!("\nGenerating a fresh log signing key. Use it in your LOG_SIGNING_KEY env var.\n");
+    let mut signing_key = [0u8; 32];
+    OsRng.fill_bytes(&mut signing_key);
+    let b64_signing_key = general_purpose::STANDARD_NO_PAD.encode(signing_key);
+    println!("{}: {}", "Log signing key".cyan(), b64_signing_key);
+}
+
 /// Generate new self-signed certificate
 fn generate_ss_certs() {
     log::warning("Generating new self-signed certificate. Use only for development !\n");
@@ -154,5 +294,13 @@ fn generate_ss_certs() {
 #[derive(Clone)]
 pub struct AppState {
     server_login_states: Arc<RwLock<HashMap<String, ServerLoginStartResult<DefaultCS>>>>,
+    challenges: signing::Challenges,
+    downloads: links::Downloads,
+    dummy_keys: auth::DummyKeys,
     pool: Pool,
+    blob_store: Arc<dyn BlobStore>,
+    default_quota: i64,
+    version_retention: i64,
+    log_key: Arc<SigningKey>,
+    log_tree: transparency::LogTree,
 }
@@ -1,26 +1,36 @@
 use argon2::Argon2;
 use axum::extract::Path;
-use axum::{extract::State, http::StatusCode, Extension, Json};
+use axum::{extract::State, http::StatusCode, Extension};
 use base64::{engine::general_purpose, Engine as _};
 use colored::Colorize;
 use diesel::prelude::*;
+use hmac::{Hmac, Mac};
 use opaque_ke::{
     CipherSuite, CredentialFinalization, CredentialRequest, CredentialResponse, Identifiers,
     RegistrationRequest, RegistrationResponse, RegistrationUpload, ServerLogin,
     ServerLoginStartParameters, ServerRegistration, ServerSetup,
 };
-use rand::rngs::OsRng;
+use rand::{rngs::OsRng, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+use rsa::{pkcs1::EncodeRsaPublicKey, RsaPrivateKey, RsaPublicKey};
 use serde::{Deserialize, Serialize};
+use serde_bytes::ByteBuf;
+use sha2::Sha256;
+use std::collections::{HashMap, VecDeque};
 use std::ops::Add;
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::Semaphore;
 
-use crate::db::schema::{keyrings, sessions, users};
+use crate::db::schema::{groups, keyrings, sessions, users};
 use crate::db::{KeyringWithKeysAndFiles, NewKeyring, Session, User, UserWithKeyring};
 use crate::log;
+use crate::codec::{self, Msg};
 use crate::AppState;
 
 use super::files::get_user_tree;
+use super::groups::name_taken;
+use super::transparency;
 
 pub struct DefaultCS;
 impl CipherSuite for DefaultCS {
@@ -33,6 +43,23 @@ impl CipherSuite for DefaultCS {
 /// Token lifetime in secs
 const TOKEN_LIFETIME: u64 = 3600;
 
+/// Number of dummy keys kept in memory, the oldest one is dropped beyond
+const DUMMY_KEYS_CAPACITY: usize = 1024;
+
+/// Number of dummy keys generated at the same time
+const DUMMY_KEYS_WORKERS: usize = 2;
+
+/// Dummy public keys by name, and the names from the oldest to the newest
+type DummyKeysCache = (HashMap<String, Vec<u8>>, VecDeque<String>);
+
+/// Dummy public keys of the unknown names, see `DummyKeys::get`
+#[derive(Clone)]
+pub struct DummyKeys {
+    secret: Arc<Vec<u8>>,
+    cache: Arc<RwLock<DummyKeysCache>>,
+    workers: Arc<Semaphore>,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct RegisterRequest {
     username: String,
@@ -43,31 +70,12 @@ pub struct RegisterRequest {
 pub async fn register_start(
     Extension(server_setup): Extension<Arc<ServerSetup<DefaultCS>>>,
     State(app_state): State<AppState>,
-    Json(register_request): Json<RegisterRequest>,
-) -> Result<Json<RegistrationResponse<DefaultCS>>, StatusCode> {
+    Msg(register_request): Msg<RegisterRequest>,
+) -> Result<Msg<RegistrationResponse<DefaultCS>>, StatusCode> {
     log::debug("New registration request");
 
-    let conn = app_state.pool.get().await.unwrap();
-
-    // Check if a user with this username already exists
-    // If yes, return a 409 Conflict
-    let res: Result<User, _> = conn
-        .interact({
-            let username = register_request.username.clone();
-
-            |conn| {
-                users::table
-                    .filter(users::username.eq(username))
-                    .first(conn)
-            }
-        })
-        .await
-        .unwrap();
-
-    if res.is_ok() {
-        return Err(StatusCode::CONFLICT);
-    }
-
+    // Taken usernames are only refused at the end of the registration, the response is
+    // the same for any username so it doesn't tell which users exist
     // Create ServerRegistration
     let server_registration_start_result = ServerRegistration::<DefaultCS>::start(
         &server_setup,
@@ -77,44 +85,24 @@ pub async fn register_start(
     .unwrap();
 
     // Send back the RegistrationResponse to the Client
-    Ok(Json(server_registration_start_result.message))
+    Ok(Msg(server_registration_start_result.message))
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct RegisterFinishRequest {
     username: String,
     registration_upload: RegistrationUpload<DefaultCS>,
+    #[serde(with = "codec::bytes_pair")]
     user_keypair: (Vec<u8>, Vec<u8>),
 }
 
 /// OPAQUE Register Finish
 pub async fn register_finish(
     State(app_state): State<AppState>,
-    Json(register_request): Json<RegisterFinishRequest>,
+    Msg(register_request): Msg<RegisterFinishRequest>,
 ) -> StatusCode {
     log::debug(&format!("New registration finish request"));
 
-    let conn = app_state.pool.get().await.unwrap();
-
-    // Check if a user with this username already exists
-    // If yes, return a 409 Conflict
-    let res: Result<User, _> = conn
-        .interact({
-            let username = register_request.username.clone();
-
-            |conn| {
-                users::table
-                    .filter(users::username.eq(username))
-                    .first(conn)
-            }
-        })
-        .await
-        .unwrap();
-
-    if res.is_ok() {
-        return StatusCode::CONFLICT;
-    }
-
     // Finalize the registration and get the Password File from it
     // Serialize it and store it in redis
     let password_file =
@@ -123,37 +111,51 @@ pub async fn register_finish(
 
     let conn = app_state.pool.get().await.unwrap();
 
-    // Create user keyring
-    let user_keyring = NewKeyring { id: None };
-
-    let keyring_id: i32 = conn
+    // Create user keyring and store the User in DB in a single transaction, the garbage
+    // collector never sees the keyring without its user
+    // The key of the new user is appended to the key log along with him, the transaction
+    // is immediate so concurrent registrations don't take the same name or log position
+    let registered = conn
         .interact(|conn| {
-            diesel::insert_into(keyrings::table)
-                .values(user_keyring)
-                .returning(keyrings::id)
-                .get_result(conn)
+            conn.immediate_transaction(|conn| {
+                // Check if a user or a group with this username already exists
+                // If yes, return a 409 Conflict, only once the OPAQUE exchange is done
+                if name_taken(&register_request.username, conn)? {
+                    return Ok(false);
+                }
+
+                let keyring_id: i32 = diesel::insert_into(keyrings::table)
+                    .values(NewKeyring { id: None })
+                    .returning(keyrings::id)
+                    .get_result(conn)?;
+
+                let new_user = User {
+                    username: register_request.username,
+                    password: serialized_password,
+                    pub_key: register_request.user_keypair.0,
+                    priv_key: register_request.user_keypair.1,
+                    keyring: keyring_id,
+                    quota: None,
+                    trash: None,
+                    inbox: None,
+                };
+
+                diesel::insert_into(users::table)
+                    .values(&new_user)
+                    .execute(conn)?;
+
+                transparency::append_key(&new_user.username, &new_user.pub_key, conn)?;
+
+                diesel::result::QueryResult::Ok(true)
+            })
         })
         .await
         .unwrap()
         .unwrap();
 
-    // Create User and store it in DB
-    let new_user = User {
-        username: register_request.username,
-        password: serialized_password,
-        pub_key: register_request.user_keypair.0,
-        priv_key: register_request.user_keypair.1,
-        keyring: keyring_id,
-    };
-
-    conn.interact(|conn| {
-        diesel::insert_into(users::table)
-            .values(new_user)
-            .execute(conn)
-    })
-    .await
-    .unwrap()
-    .unwrap();
+    if !registered {
+        return StatusCode::CONFLICT;
+    }
 
     StatusCode::OK
 }
@@ -168,8 +170,8 @@ pub struct LoginRequest {
 pub async fn login_start(
     Extension(server_setup): Extension<Arc<ServerSetup<DefaultCS>>>,
     State(app_state): State<AppState>,
-    Json(login_request): Json<LoginRequest>,
-) -> Result<Json<CredentialResponse<DefaultCS>>, (StatusCode, String)> {
+    Msg(login_request): Msg<LoginRequest>,
+) -> Result<Msg<CredentialResponse<DefaultCS>>, (StatusCode, String)> {
     log::debug(&format!(
         "Login start initiated from {}",
         login_request.username.cyan()
@@ -222,7 +224,7 @@ pub async fn login_start(
         .insert(login_request.username, server_login_start_result.clone());
 
     // Send back the CredentialResponse to the Client
-    Ok(Json(server_login_start_result.message))
+    Ok(Msg(server_login_start_result.message))
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -233,6 +235,7 @@ pub struct LoginRequestFinish {
 
 #[derive(Serialize, Debug)]
 pub struct LoginRequestResult {
+    #[serde(with = "codec::bytes_pair")]
     keypair: (Vec<u8>, Vec<u8>),
     keyring_tree: KeyringWithKeysAndFiles,
 }
@@ -240,8 +243,8 @@ pub struct LoginRequestResult {
 /// OPAQUE Login Finish
 pub async fn login_finish(
     State(app_state): State<AppState>,
-    Json(login_request): Json<LoginRequestFinish>,
-) -> Json<LoginRequestResult> {
+    Msg(login_request): Msg<LoginRequestFinish>,
+) -> Msg<LoginRequestResult> {
     log::debug(&format!(
         "Login finish initiated from {}",
         login_request.username.cyan()
@@ -320,7 +323,7 @@ pub async fn login_finish(
 
     let user_keyring_tree = get_user_tree(user.username, app_state.pool).await.unwrap();
 
-    Json(LoginRequestResult {
+    Msg(LoginRequestResult {
         keypair: (user.pub_key, user.priv_key),
         keyring_tree: user_keyring_tree,
     })
@@ -329,8 +332,8 @@ pub async fn login_finish(
 /// Return the current user Session data (testing purpose)
 pub async fn check_session(
     Extension(user_session): Extension<Session>,
-) -> Result<Json<Session>, StatusCode> {
-    Ok(Json(user_session))
+) -> Result<Msg<Session>, StatusCode> {
+    Ok(Msg(user_session))
 }
 
 #[derive(Serialize, Debug)]
@@ -344,7 +347,7 @@ pub struct SessionInfo {
 pub async fn active_sessions(
     Extension(user_session): Extension<Session>,
     State(app_state): State<AppState>,
-) -> Json<Vec<SessionInfo>> {
+) -> Msg<Vec<SessionInfo>> {
     let conn = app_state.pool.get().await.unwrap();
     let sessions: Vec<Session> = conn
         .interact(|conn| {
@@ -365,7 +368,7 @@ pub async fn active_sessions(
         })
         .collect();
 
-    Json(sessions)
+    Msg(sessions)
 }
 
 /// Revoke the current user session
@@ -410,8 +413,8 @@ pub async fn revoke_all(
 pub async fn change_password_start(
     Extension(user_session): Extension<Session>,
     Extension(server_setup): Extension<Arc<ServerSetup<DefaultCS>>>,
-    Json(registration_request): Json<RegistrationRequest<DefaultCS>>,
-) -> Result<Json<RegistrationResponse<DefaultCS>>, StatusCode> {
+    Msg(registration_request): Msg<RegistrationRequest<DefaultCS>>,
+) -> Result<Msg<RegistrationResponse<DefaultCS>>, StatusCode> {
     // Create ServerRegistration
     let server_registration_start_result = ServerRegistration::<DefaultCS>::start(
         &server_setup,
@@ -421,19 +424,20 @@ pub async fn change_password_start(
     .unwrap();
 
     // Send back the RegistrationResponse to the Client
-    Ok(Json(server_registration_start_result.message))
+    Ok(Msg(server_registration_start_result.message))
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct PasswordChangeFinishRequest {
     registration_upload: RegistrationUpload<DefaultCS>,
+    #[serde(with = "serde_bytes")]
     user_new_private_key: Vec<u8>,
 }
 
 pub async fn change_password_finish(
     Extension(user_session): Extension<Session>,
     State(app_state): State<AppState>,
-    Json(password_change_request): Json<PasswordChangeFinishRequest>,
+    Msg(password_change_request): Msg<PasswordChangeFinishRequest>,
 ) -> StatusCode {
     log::debug(&format!("New registration finish request"));
 
@@ -462,30 +466,103 @@ pub async fn change_password_finish(
 }
 
 /// Request the public key of a given user
+///
+/// Unknown users get a dummy key, the answer doesn't tell which users exist.
 pub async fn get_user_public_key(
     Extension(_user_session): Extension<Session>,
     State(app_state): State<AppState>,
     Path(user): Path<String>,
-) -> Result<Json<Vec<u8>>, StatusCode> {
+) -> Msg<ByteBuf> {
     let conn = app_state.pool.get().await.unwrap();
 
+    // Files are shared with a group like with a user, using the group pubkey
     let user_pubkey = conn
-        .interact(|conn| {
-            users::table
-                .find(user)
-                .select(users::pub_key)
-                .first::<Vec<u8>>(conn)
+        .interact({
+            let user = user.clone();
+
+            move |conn| {
+                users::table
+                    .find(&user)
+                    .select(users::pub_key)
+                    .first::<Vec<u8>>(conn)
+                    .or_else(|_| {
+                        groups::table
+                            .find(&user)
+                            .select(groups::pub_key)
+                            .first::<Vec<u8>>(conn)
+                    })
+            }
         })
         .await
         .unwrap();
 
     if let Ok(pubkey) = user_pubkey {
-        Ok(Json(pubkey))
+        Msg(ByteBuf::from(pubkey))
     } else {
-        // Not good, might give informations about existing users
-        // (We can check on existings user through register though...)
-        // Need to send a dummy pubkey generated from the requested user name
-        // (every request with the same user must send the same pubkey)
-        Err(StatusCode::NOT_FOUND)
+        // Every request with the same user gets the same dummy pubkey, like a real one
+        let pubkey = app_state.dummy_keys.get(&user).await;
+
+        Msg(ByteBuf::from(pubkey))
+    }
+}
+
+impl DummyKeys {
+    /// The secret must not be known to the clients, the OPAQUE server setup is used
+    pub fn new(secret: Vec<u8>) -> Self {
+        DummyKeys {
+            secret: Arc::new(secret),
+            cache: Arc::new(RwLock::new((HashMap::new(), VecDeque::new()))),
+            workers: Arc::new(Semaphore::new(DUMMY_KEYS_WORKERS)),
+        }
+    }
+
+    /// Dummy RSA public key of an unknown name, the same one every time it is requested
+    ///
+    /// The key is generated from HMAC(secret, name), nothing is stored for the name. The
+    /// last `DUMMY_KEYS_CAPACITY` keys are kept in memory, the others are generated again.
+    /// At most `DUMMY_KEYS_WORKERS` keys are generated at the same time, a burst of unknown
+    /// names waits for them without slowing down the requests for the registered names.
+    pub async fn get(&self, name: &str) -> Vec<u8> {
+        if let Some(pubkey) = self.cache.read().unwrap().0.get(name) {
+            return pubkey.clone();
+        }
+
+        let _worker = self.workers.acquire().await.unwrap();
+
+        // Another request may have generated it in the meantime
+        if let Some(pubkey) = self.cache.read().unwrap().0.get(name) {
+            return pubkey.clone();
+        }
+
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.secret).unwrap();
+        mac.update(name.as_bytes());
+        let seed: [u8; 32] = mac.finalize().into_bytes().into();
+
+        // Same size and encoding as the keys generated by the clients
+        let pubkey = tokio::task::spawn_blocking(move || {
+            let mut rng = ChaCha20Rng::from_seed(seed);
+            let priv_key = RsaPrivateKey::new(&mut rng, 3072).unwrap();
+
+            RsaPublicKey::from(&priv_key)
+                .to_pkcs1_der()
+                .unwrap()
+                .to_vec()
+        })
+        .await
+        .unwrap();
+
+        let mut cache = self.cache.write().unwrap();
+        let (keys, order) = &mut *cache;
+
+        if keys.len() >= DUMMY_KEYS_CAPACITY {
+            if let Some(oldest) = order.pop_front() {
+                keys.remove(&oldest);
+            }
+        }
+
+        keys.insert(name.to_string(), pubkey.clone());
+        order.push_back(name.to_string());
+
+        pubkey
     }
 }
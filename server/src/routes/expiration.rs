@@ -0,0 +1,74 @@
+use std::{sync::Arc, time::Duration};
+
+use deadpool_diesel::sqlite::Pool;
+use diesel::prelude::*;
+
+use crate::{
+    blobs::{self, BlobStore},
+    db::{
+        schema::{files, keys, public_links},
+        Key,
+    },
+    log,
+    routes::{files::delete_key, signing::now_millis},
+};
+
+/// Interval between two removals of the expired shares
+const EXPIRATION_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Delete the keys whose share expired, along with the expired public links
+///
+/// Expired keys are already ignored when checking an access, they are removed here for
+/// good. The shared files are flagged so their owner is asked to rotate their keys, the
+/// recipient may have kept them.
+/// Return the number of removed keys.
+pub async fn remove_expired(pool: &Pool, blob_store: &Arc<dyn BlobStore>) -> usize {
+    let conn = pool.get().await.unwrap();
+
+    let now = now_millis();
+
+    let (removed, reclaimed_blobs) = conn
+        .interact(move |conn| {
+            conn.transaction(|conn| {
+                let expired: Vec<Key> = keys::table.filter(keys::expires_at.le(now)).load(conn)?;
+
+                let mut reclaimed_blobs = Vec::new();
+                for key in &expired {
+                    diesel::update(files::table.find(&key.target))
+                        .set(files::expired_share_at.eq(now))
+                        .execute(conn)?;
+
+                    reclaimed_blobs.extend(delete_key(key, conn)?);
+                }
+
+                // Expired public links can't be used anymore
+                diesel::delete(public_links::table.filter(public_links::expires_at.le(now)))
+                    .execute(conn)?;
+
+                diesel::result::QueryResult::Ok((expired.len(), reclaimed_blobs))
+            })
+        })
+        .await
+        .unwrap()
+        .unwrap();
+
+    blobs::delete_blobs(blob_store, reclaimed_blobs).await;
+
+    removed
+}
+
+/// Remove the expired shares every minute
+pub fn spawn_expiration(pool: Pool, blob_store: Arc<dyn BlobStore>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(EXPIRATION_INTERVAL);
+
+        loop {
+            interval.tick().await;
+
+            let removed = remove_expired(&pool, &blob_store).await;
+            if removed > 0 {
+                log::info(&format!("Removed {} expired shares", removed));
+            }
+        }
+    });
+}
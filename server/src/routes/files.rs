@@ -1,6 +1,13 @@
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::{
+    collections::{HashMap, HashSet},
+    time::{SystemTime, UNIX_EPOCH},
+};
 
-use axum::{extract::State, Extension, Json};
+use axum::{
+    body::Bytes,
+    extract::{Path, State},
+    Extension,
+};
 use deadpool_diesel::{sqlite::Pool, SyncGuard};
 use diesel::prelude::*;
 use hyper::StatusCode;
@@ -8,43 +15,222 @@ use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
 use crate::{
+    blobs,
+    codec::Msg,
     db::{
-        schema::{files, keyrings, keys, users},
-        File, FileWithoutData, FileWithoutDataWithKeyring, Folder, Key, KeyWithFile, Keyring,
-        KeyringWithKeys, KeyringWithKeysAndFiles, NewFile, NewKey, NewKeyring, Session, User,
+        schema::{
+            chunks, files, group_members, groups, invitations, keyrings, keys, uploads, users,
+        },
+        Chunk, EntrySignature, File, FileWithoutData, FileWithoutDataWithKeyring, Folder, Group,
+        Invitation, Key, KeyWithFile, Keyring, KeyringWithKeys, KeyringWithKeysAndFiles, NewFile,
+        NewKey, NewKeyring, Permission, PublicKeys, Session, SigningKeys, Upload, User,
         UserWithKeyring,
     },
+    log,
+    routes::{
+        groups::get_group_keyrings,
+        invitations::{delete_invitations, get_or_create_inbox},
+        links::{delete_links, delete_user_links},
+        quota, signing, trash, versions,
+    },
     AppState,
 };
 
+/// Pending uploads older than this are discarded, in millis
+pub const UPLOAD_LIFETIME: i64 = 24 * 3600 * 1000;
+
+#[derive(Serialize)]
+pub struct BeginUploadResponse {
+    upload_id: String,
+}
+
+/// Allow a user to start a chunked upload.
+///
+/// The file content is then sent chunk by chunk to the returned upload, each chunk
+/// being encrypted separately by the client. Nothing is visible in the user tree until
+/// the upload is committed.
+pub async fn begin_upload(
+    Extension(user_session): Extension<Session>,
+    State(app_state): State<AppState>,
+) -> Msg<BeginUploadResponse> {
+    let conn = app_state.pool.get().await.unwrap();
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as i64;
+
+    let upload = Upload {
+        id: Uuid::new_v4().to_string(),
+        user: user_session.user,
+        created_at: now,
+    };
+
+    let expired = conn
+        .interact({
+            let upload = upload.clone();
+            move |conn| {
+                conn.transaction(|conn| {
+                    // Discard the abandoned uploads of the user
+                    let expired: Vec<String> = uploads::table
+                        .filter(uploads::user.eq(&upload.user))
+                        .filter(uploads::created_at.lt(now - UPLOAD_LIFETIME))
+                        .select(uploads::id)
+                        .load(conn)?;
+
+                    diesel::delete(chunks::table.filter(chunks::blob.eq_any(&expired)))
+                        .execute(conn)?;
+                    diesel::delete(uploads::table.filter(uploads::id.eq_any(&expired)))
+                        .execute(conn)?;
+
+                    diesel::insert_into(uploads::table)
+                        .values(upload)
+                        .execute(conn)?;
+
+                    diesel::result::QueryResult::Ok(expired)
+                })
+            }
+        })
+        .await
+        .unwrap()
+        .unwrap();
+
+    blobs::delete_blobs(&app_state.blob_store, expired).await;
+
+    Msg(BeginUploadResponse {
+        upload_id: upload.id,
+    })
+}
+
+/// Allow a user to send an encrypted chunk of a pending upload
+///
+/// Chunks can be sent in any order, sending a chunk again replaces it.
+pub async fn upload_chunk(
+    Extension(user_session): Extension<Session>,
+    State(app_state): State<AppState>,
+    Path((upload_id, index)): Path<(String, i32)>,
+    chunk: Bytes,
+) -> StatusCode {
+    if index < 0 {
+        return StatusCode::BAD_REQUEST;
+    }
+
+    let conn = app_state.pool.get().await.unwrap();
+
+    // Check if the upload belongs to the user
+    if get_upload(&upload_id, &user_session.user, &mut conn.lock().unwrap()).is_none() {
+        return StatusCode::NOT_FOUND;
+    }
+
+    // Check if the chunk fits in the user quota, a chunk sent again replaces the previous one
+    let replaced_sz: i32 = conn
+        .interact({
+            let upload_id = upload_id.clone();
+            move |conn| {
+                chunks::table
+                    .find((upload_id, index))
+                    .select(chunks::sz)
+                    .first(conn)
+                    .optional()
+            }
+        })
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap_or(0);
+
+    if !quota::fits_quota(
+        &user_session.user,
+        chunk.len() as i64 - replaced_sz as i64,
+        app_state.default_quota,
+        &mut conn.lock().unwrap(),
+    ) {
+        return StatusCode::INSUFFICIENT_STORAGE;
+    }
+
+    let sz = chunk.len() as i32;
+
+    if let Err(e) = app_state
+        .blob_store
+        .put_chunk(&upload_id, index, chunk)
+        .await
+    {
+        log::error(&format!("Can't store chunk of upload {}: {}", upload_id, e));
+        return StatusCode::INTERNAL_SERVER_ERROR;
+    }
+
+    conn.interact(move |conn| {
+        diesel::replace_into(chunks::table)
+            .values(Chunk {
+                blob: upload_id,
+                idx: index,
+                sz,
+            })
+            .execute(conn)
+    })
+    .await
+    .unwrap()
+    .unwrap();
+
+    StatusCode::OK
+}
+
 #[derive(Deserialize)]
-pub struct UploadFileRequest {
+pub struct CommitUploadRequest {
     /// The parent folder to put the file in.
     /// None = root
     parent_uid: Option<String>,
     /// Encrypted filename
     filename: String,
-    /// Encrypted file content
-    file: Vec<u8>,
     /// Symmetric key of the file, encrypted with parent key
+    /// Unused when overwriting a file, its key doesn't change
+    #[serde(with = "serde_bytes")]
     encrypted_key: Vec<u8>,
+    /// Existing file to overwrite, None to create a new file
+    file_uid: Option<String>,
+    /// Id of a new file, chosen by the client to sign its entry with it
+    new_file_uid: Option<String>,
+    /// Signing public keys of a new file
+    public_keys: PublicKeys,
+    /// Signing private keys of a new file, encrypted like its key
+    signing_keys: SigningKeys,
+    /// Signature of the entry by the user, for a new file in root
+    #[serde(with = "serde_bytes")]
+    entry_signature: Option<Vec<u8>>,
+    /// Challenge of the file to overwrite, or of the parent folder for a new file,
+    /// signed with its write key
+    #[serde(with = "serde_bytes")]
+    signature: Option<Vec<u8>>,
 }
 
-/// Allow a user to upload a file.
+/// Allow a user to commit a chunked upload as a file.
+///
+/// The file content is made of the chunks received by the upload and his encrypted
+/// symmetric encryption key is send along with the commit. The file symmetric key is
+/// encrypted with the parent folder key, or with the user's public key in root.
+/// The file will be "placed" in the specified parent folder starting from the user's root.
 ///
-/// The file uploaded is encrypted and his encrypted symmetric encryption key
-/// is send along with it. The file symmetric key is encrypted with the user's public key.
-/// The file will be "placed" in the specified path starting from the user's root.
+/// When overwriting an existing file, the upload must be encrypted with the same key.
+/// The previous content is kept as a version of the file.
 ///
-/// If the specified path doesn't exist, return an error
-/// Else return a response with the updated user root keyring
-pub async fn upload_file(
+/// If the specified parent doesn't exist or if chunks are missing, return an error
+pub async fn commit_upload(
     Extension(user_session): Extension<Session>,
     State(app_state): State<AppState>,
-    Json(upload_request): Json<UploadFileRequest>,
+    Path(upload_id): Path<String>,
+    Msg(commit_request): Msg<CommitUploadRequest>,
 ) -> StatusCode {
     let conn = app_state.pool.get().await.unwrap();
 
+    // Check if the upload belongs to the user and is complete
+    if get_upload(&upload_id, &user_session.user, &mut conn.lock().unwrap()).is_none() {
+        return StatusCode::NOT_FOUND;
+    }
+
+    let Some((chunk_count, size)) = get_blob_size(&upload_id, &mut conn.lock().unwrap()) else {
+        return StatusCode::BAD_REQUEST;
+    };
+
     // Get user keyring informations
     let user: UserWithKeyring = conn
         .interact(|conn| {
@@ -64,14 +250,55 @@ pub async fn upload_file(
         .unwrap();
 
     // Check if user has access to parent folder
-    if let Some(parent_uid) = upload_request.parent_uid.clone() {
-        if !has_access(&user.keyring, parent_uid, &mut conn.lock().unwrap()) {
+    if let Some(parent_uid) = commit_request.parent_uid.clone() {
+        if !has_access(
+            &user.keyring,
+            parent_uid,
+            Permission::Write,
+            &mut conn.lock().unwrap(),
+        ) {
             return StatusCode::FORBIDDEN;
         }
     };
 
+    // Check the write key of the file to overwrite, or of the parent folder
+    if let Some(target) = commit_request
+        .file_uid
+        .as_ref()
+        .or(commit_request.parent_uid.as_ref())
+    {
+        if !signing::verify_challenge(
+            &app_state.challenges,
+            &user.username,
+            target,
+            Permission::Write,
+            commit_request.signature.as_deref(),
+            &mut conn.lock().unwrap(),
+        ) {
+            return StatusCode::FORBIDDEN;
+        }
+    }
+
+    // A new file must come with a new id and its signing public keys
+    if commit_request.file_uid.is_none() {
+        let new_id = commit_request
+            .new_file_uid
+            .as_ref()
+            .is_some_and(|id| is_new_file_id(id, &mut conn.lock().unwrap()));
+
+        if !new_id
+            || !signing::check_public_keys(
+                &commit_request.public_keys,
+                commit_request.parent_uid.as_deref(),
+                &mut conn.lock().unwrap(),
+            )
+        {
+            return StatusCode::BAD_REQUEST;
+        }
+    }
+
     // Get parent folder keyring
-    let parent_keyring = if let Some(parent_uid) = upload_request.parent_uid {
+    let parent_keyring = if let Some(parent_uid) = commit_request.parent_uid {
         let parent_folder: Folder = conn
             .interact(move |conn| {
                 files::table
@@ -86,79 +313,124 @@ pub async fn upload_file(
 
         parent_folder.keyring
     } else {
-        user.keyring
+        user.keyring.clone()
     };
 
-    // Check if file already exists
-    let file: Result<File, _> = conn
-        .interact({
-            let filename = upload_request.filename.clone();
-            |conn| {
-                keys::table
-                    .inner_join(files::table)
-                    .filter(files::name.eq(filename))
-                    .select(files::all_columns)
-                    .first::<File>(conn)
+    let mtime = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as i64;
+
+    if let Some(file_uid) = commit_request.file_uid {
+        // Check if user has access to the file to overwrite
+        let Some(permission) = get_permission(&user.keyring, &file_uid, &mut conn.lock().unwrap())
+            .filter(|permission| *permission >= Permission::Write)
+        else {
+            return StatusCode::FORBIDDEN;
+        };
+
+        let file: File = conn
+            .interact(move |conn| files::table.find(file_uid).first::<File>(conn))
+            .await
+            .unwrap()
+            .unwrap();
+
+        // Folders have no content
+        if file.blob.is_none() {
+            return StatusCode::BAD_REQUEST;
+        }
+
+        // The new content is charged to the file owner
+        // A file created before owners were recorded gets the uploader as owner only if he
+        // owns it through his keyring, a writer could revoke the shares otherwise
+        let owner = file
+            .owner
+            .clone()
+            .or_else(|| (permission == Permission::Owner).then(|| user.username.clone()));
+
+        // The upload is already charged to the uploader, only another owner must be checked
+        if let Some(owner) = owner.as_ref().filter(|owner| **owner != user.username) {
+            if !quota::fits_quota(
+                owner,
+                size,
+                app_state.default_quota,
+                &mut conn.lock().unwrap(),
+            ) {
+                return StatusCode::INSUFFICIENT_STORAGE;
             }
-        })
-        .await
-        .unwrap();
+        }
 
-    if let Ok(file) = file {
-        // File exists, update it
-        conn.interact(|conn| {
-            diesel::update(files::table)
-                .filter(files::id.eq(file.id))
-                .set((
-                    files::sz.eq(upload_request.file.len() as i32),
-                    files::data.eq(upload_request.file),
-                    files::mtime.eq(SystemTime::now()
-                        .duration_since(UNIX_EPOCH)
-                        .unwrap()
-                        .as_millis() as i64),
-                ))
-                .execute(conn)
-        })
-        .await
-        .unwrap()
-        .unwrap();
+        let retention = app_state.version_retention;
+
+        // Replace the file content with the uploaded one, keeping the previous one as a version
+        let pruned_blobs = conn
+            .interact(move |conn| {
+                conn.transaction(|conn| {
+                    versions::archive_content(&file, conn)?;
+
+                    diesel::update(files::table)
+                        .filter(files::id.eq(&file.id))
+                        .set((
+                            files::name.eq(commit_request.filename),
+                            files::sz.eq(size),
+                            files::blob.eq(&upload_id),
+                            files::chunks.eq(chunk_count),
+                            files::mtime.eq(mtime),
+                            files::owner.eq(owner),
+                        ))
+                        .execute(conn)?;
+
+                    diesel::delete(uploads::table.find(&upload_id)).execute(conn)?;
+
+                    versions::prune_versions(&file.id, retention, conn)
+                })
+            })
+            .await
+            .unwrap()
+            .unwrap();
+
+        blobs::delete_blobs(&app_state.blob_store, pruned_blobs).await;
 
         StatusCode::OK
     } else {
+        let entry_signature = EntrySignature::new(&user.username, commit_request.entry_signature);
+
         // File doesn't exists, create new file
         let file = NewFile {
-            id: Uuid::new_v4().to_string(),
-            name: upload_request.filename,
-            mtime: SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap()
-                .as_millis() as i64,
-            sz: upload_request.file.len() as i32,
-            data: upload_request.file,
+            id: commit_request.new_file_uid.unwrap(),
+            name: commit_request.filename,
+            mtime,
+            sz: size,
+            blob: upload_id.clone(),
+            chunks: chunk_count,
             keyring_id: None,
+            owner: user.username,
+            public_keys: commit_request.public_keys,
         };
 
-        // Insert new file in DB
-        conn.interact({
-            let file = file.clone();
-            |conn| diesel::insert_into(files::table).values(file).execute(conn)
-        })
-        .await
-        .unwrap()
-        .unwrap();
+        // Insert new file in DB and update keyring
+        conn.interact(move |conn| {
+            conn.transaction(|conn| {
+                diesel::insert_into(files::table)
+                    .values(&file)
+                    .execute(conn)?;
 
-        // Update keyring
-        conn.interact({
-            let file_id = file.id.clone();
-            move |conn| {
                 diesel::insert_into(keys::table)
                     .values(NewKey {
-                        target: file_id,
-                        key: upload_request.encrypted_key,
+                        target: file.id.clone(),
+                        key: commit_request.encrypted_key,
                         keyring_id: parent_keyring.id,
+                        permission: Permission::Owner as i32,
+                        signing_keys: commit_request.signing_keys,
+                        expires_at: None,
+                        entry_signature,
                     })
-                    .execute(conn)
-            }
+                    .execute(conn)?;
+
+                diesel::delete(uploads::table.find(&upload_id)).execute(conn)?;
+
+                diesel::result::QueryResult::Ok(())
+            })
         })
         .await
         .unwrap()
@@ -168,20 +440,88 @@ pub async fn upload_file(
     }
 }
 
+/// Get a pending upload of a user
+fn get_upload(
+    upload_id: &str,
+    user: &str,
+    conn: &mut SyncGuard<SqliteConnection>,
+) -> Option<Upload> {
+    uploads::table
+        .find(upload_id)
+        .filter(uploads::user.eq(user))
+        .first::<Upload>(conn.as_mut())
+        .ok()
+}
+
+/// Check the id of a new file chosen by a client is an unused UUID
+fn is_new_file_id(file_uid: &str, conn: &mut SyncGuard<SqliteConnection>) -> bool {
+    Uuid::parse_str(file_uid).is_ok()
+        && files::table
+            .find(file_uid)
+            .count()
+            .get_result::<i64>(conn.as_mut())
+            .unwrap()
+            == 0
+}
+
+/// Get the chunk count and total size of a blob
+///
+/// Return None if the blob is empty or if a chunk is missing
+fn get_blob_size(blob: &str, conn: &mut SyncGuard<SqliteConnection>) -> Option<(i32, i64)> {
+    let chunks: Vec<(i32, i32)> = chunks::table
+        .filter(chunks::blob.eq(blob))
+        .select((chunks::idx, chunks::sz))
+        .order(chunks::idx.asc())
+        .load(conn.as_mut())
+        .unwrap();
+
+    if chunks.is_empty() {
+        return None;
+    }
+
+    let mut size = 0;
+    for (position, (idx, sz)) in chunks.iter().enumerate() {
+        if *idx != position as i32 {
+            return None;
+        }
+
+        size += *sz as i64;
+    }
+
+    Some((chunks.len() as i32, size))
+}
+
 #[derive(Deserialize)]
 pub struct CreateFolderRequest {
     /// The parent folder to put the file in.
     /// None = root
     parent_uid: Option<String>,
+    /// Id of the folder, chosen by the client to sign its entry with it
+    folder_uid: String,
     /// Encrypted filename
     filename: String,
     /// Encrypted symmetric key with user pubkey
+    #[serde(with = "serde_bytes")]
     encrypted_key: Vec<u8>,
+    /// Signing public keys of the folder
+    public_keys: PublicKeys,
+    /// Signing private keys of the folder, encrypted like its key
+    signing_keys: SigningKeys,
+    /// Signature of the entry by the user, for a folder in root
+    #[serde(with = "serde_bytes")]
+    entry_signature: Option<Vec<u8>>,
+    /// Challenge of the parent folder signed with its write key
+    #[serde(with = "serde_bytes")]
+    signature: Option<Vec<u8>>,
 }
 
 #[derive(Serialize)]
 pub struct CreateFolderResponse {
     keyring: KeyringWithKeys,
+    /// Created folder
+    folder_uid: String,
+    /// Keyring of the created folder
+    keyring_id: i32,
 }
 
 /// Allow a user to create a folder at a given location
@@ -190,8 +530,8 @@ pub struct CreateFolderResponse {
 pub async fn create_folder(
     Extension(user_session): Extension<Session>,
     State(app_state): State<AppState>,
-    Json(create_folder_request): Json<CreateFolderRequest>,
-) -> Result<Json<CreateFolderResponse>, StatusCode> {
+    Msg(create_folder_request): Msg<CreateFolderRequest>,
+) -> Result<Msg<CreateFolderResponse>, StatusCode> {
     let conn = app_state.pool.get().await.unwrap();
 
     // Get user keyring informations
@@ -213,83 +553,110 @@ pub async fn create_folder(
         .unwrap();
 
     // Check if user has access to parent folder
-    if let Some(parent_uid) = create_folder_request.parent_uid.clone() {
-        if !has_access(&user.keyring, parent_uid, &mut conn.lock().unwrap()) {
+    if let Some(parent_uid) = &create_folder_request.parent_uid {
+        if !has_access(
+            &user.keyring,
+            parent_uid.clone(),
+            Permission::Write,
+            &mut conn.lock().unwrap(),
+        ) {
+            return Err(StatusCode::FORBIDDEN);
+        }
+
+        if !signing::verify_challenge(
+            &app_state.challenges,
+            &user.username,
+            parent_uid,
+            Permission::Write,
+            create_folder_request.signature.as_deref(),
+            &mut conn.lock().unwrap(),
+        ) {
             return Err(StatusCode::FORBIDDEN);
         }
     };
 
-    // Create folder keyring
-    let folder_keyring: Keyring = conn
-        .interact(|conn| {
-            diesel::insert_into(keyrings::table)
-                .values(NewKeyring { id: None })
-                .get_result(conn)
+    // A new folder must come with a new id and its signing public keys
+    if !is_new_file_id(&create_folder_request.folder_uid, &mut conn.lock().unwrap())
+        || !signing::check_public_keys(
+            &create_folder_request.public_keys,
+            create_folder_request.parent_uid.as_deref(),
+            &mut conn.lock().unwrap(),
+        )
+    {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let mtime = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as i64;
+    let owner = user.username.clone();
+    let user_keyring = user.keyring.clone();
+    let entry_signature =
+        EntrySignature::new(&user.username, create_folder_request.entry_signature);
+
+    // Create the folder keyring, the folder and its key in a single transaction, the
+    // garbage collector never sees a keyring or a folder that no key reaches yet
+    let (file, folder_keyring) = conn
+        .interact(move |conn| {
+            conn.transaction(|conn| {
+                // Create folder keyring
+                let folder_keyring: Keyring = diesel::insert_into(keyrings::table)
+                    .values(NewKeyring { id: None })
+                    .get_result(conn)?;
+
+                // Create new folder
+                let file = File {
+                    id: create_folder_request.folder_uid,
+                    name: create_folder_request.filename,
+                    mtime: Some(mtime),
+                    sz: None,
+                    blob: None,
+                    chunks: None,
+                    keyring_id: Some(folder_keyring.id),
+                    owner: Some(owner),
+                    owner_pub_key: create_folder_request.public_keys.owner,
+                    write_pub_key: create_folder_request.public_keys.write,
+                    read_pub_key: create_folder_request.public_keys.read,
+                    expired_share_at: None,
+                };
+
+                diesel::insert_into(files::table)
+                    .values(file.clone())
+                    .execute(conn)?;
+
+                // Get parent folder keyring
+                let parent_keyring = if let Some(parent_uid) = create_folder_request.parent_uid {
+                    files::table
+                        .find(parent_uid)
+                        .inner_join(keyrings::table)
+                        .select((files::id, files::name, (keyrings::all_columns)))
+                        .first::<Folder>(conn)?
+                        .keyring
+                } else {
+                    user_keyring
+                };
+
+                // Update keyring
+                diesel::insert_into(keys::table)
+                    .values(NewKey {
+                        target: file.id.clone(),
+                        key: create_folder_request.encrypted_key,
+                        keyring_id: parent_keyring.id,
+                        permission: Permission::Owner as i32,
+                        signing_keys: create_folder_request.signing_keys,
+                        expires_at: None,
+                        entry_signature,
+                    })
+                    .execute(conn)?;
+
+                diesel::result::QueryResult::Ok((file, folder_keyring))
+            })
         })
         .await
         .unwrap()
         .unwrap();
 
-    // Create new folder
-    let file = File {
-        id: Uuid::new_v4().to_string(),
-        name: create_folder_request.filename,
-        mtime: Some(
-            SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap()
-                .as_millis() as i64,
-        ),
-        sz: None,
-        data: None,
-        keyring_id: Some(folder_keyring.id),
-    };
-
-    // Insert new file in DB
-    conn.interact({
-        let file = file.clone();
-        |conn| diesel::insert_into(files::table).values(file).execute(conn)
-    })
-    .await
-    .unwrap()
-    .unwrap();
-
-    // Get parent folder keyring
-    let parent_keyring = if let Some(parent_uid) = create_folder_request.parent_uid {
-        let parent_folder: Folder = conn
-            .interact(move |conn| {
-                files::table
-                    .find(parent_uid)
-                    .inner_join(keyrings::table)
-                    .select((files::id, files::name, (keyrings::all_columns)))
-                    .first::<Folder>(conn)
-            })
-            .await
-            .unwrap()
-            .unwrap();
-
-        parent_folder.keyring
-    } else {
-        user.keyring.clone()
-    };
-
-    // Update keyring
-    conn.interact({
-        let file_id = file.id.clone();
-        move |conn| {
-            diesel::insert_into(keys::table)
-                .values(NewKey {
-                    target: file_id,
-                    key: create_folder_request.encrypted_key,
-                    keyring_id: parent_keyring.id,
-                })
-                .execute(conn)
-        }
-    })
-    .await
-    .unwrap()
-    .unwrap();
-
     let user_keys: Vec<Key> = conn
         .interact(move |conn| {
             keys::table
@@ -305,17 +672,14 @@ pub async fn create_folder(
         keys: user_keys,
     };
 
-    Ok(Json(CreateFolderResponse {
+    Ok(Msg(CreateFolderResponse {
         keyring: keyring_with_keys,
+        folder_uid: file.id,
+        keyring_id: folder_keyring.id,
     }))
 }
 
-#[derive(Deserialize)]
-pub struct DownloadFileRequest {
-    file_uid: String,
-}
-
-/// Allow a user to download a file.
+/// Allow a user to get the informations of a file to download.
 ///
 /// The file should not be sent to the user if he has no access to it.
 /// The path specified is the path starting from the user's root, by following the path
@@ -323,11 +687,13 @@ pub struct DownloadFileRequest {
 ///
 /// Note: the path is not a path by name, but a path by uuid. The client application transform
 /// the path input from the user to files uuid using the informations in the keyring chain.
+///
+/// The file content is then downloaded chunk by chunk with `download_chunk`.
 pub async fn download_file(
     Extension(user_session): Extension<Session>,
     State(app_state): State<AppState>,
-    Json(download_request): Json<DownloadFileRequest>,
-) -> Result<Json<File>, StatusCode> {
+    Path(file_uid): Path<String>,
+) -> Result<Msg<File>, StatusCode> {
     let conn = app_state.pool.get().await.unwrap();
 
     // Get user keyring informations
@@ -351,36 +717,28 @@ pub async fn download_file(
     // Check if aser has access to the file
     if !has_access(
         &user.keyring,
-        download_request.file_uid.clone(),
+        file_uid.clone(),
+        Permission::Read,
         &mut conn.lock().unwrap(),
     ) {
         return Err(StatusCode::FORBIDDEN);
     }
 
     let file = conn
-        .interact(move |conn| {
-            files::table
-                .find(download_request.file_uid)
-                .first::<File>(conn)
-        })
+        .interact(move |conn| files::table.find(file_uid).first::<File>(conn))
         .await
         .unwrap()
         .unwrap();
 
-    Ok(Json(file))
-}
-
-#[derive(Deserialize)]
-pub struct DeleteFileRequest {
-    file_uid: String,
+    Ok(Msg(file))
 }
 
-/// Allow a user to delete a file
-pub async fn delete_file(
+/// Allow a user to download an encrypted chunk of a file
+pub async fn download_chunk(
     Extension(user_session): Extension<Session>,
     State(app_state): State<AppState>,
-    Json(delete_request): Json<DeleteFileRequest>,
-) -> StatusCode {
+    Path((file_uid, index)): Path<(String, i32)>,
+) -> Result<Vec<u8>, StatusCode> {
     let conn = app_state.pool.get().await.unwrap();
 
     // Get user keyring informations
@@ -404,56 +762,62 @@ pub async fn delete_file(
     // Check if aser has access to the file
     if !has_access(
         &user.keyring,
-        delete_request.file_uid.clone(),
+        file_uid.clone(),
+        Permission::Read,
         &mut conn.lock().unwrap(),
     ) {
-        return StatusCode::FORBIDDEN;
+        return Err(StatusCode::FORBIDDEN);
     }
 
-    // Delete file
-    // TODO: Proper folder deletion
-    // Currently, if file is a folder, we lost access to all files and folders inside it, no problem
-    // but files and folders remains in the database, but nobody can access them anymore as the link to them is broken
-    conn.interact(move |conn| {
-        conn.transaction(|conn| {
-            // Delete all keys to this file
-            diesel::delete(keys::table.filter(keys::target.eq(&delete_request.file_uid)))
-                .execute(conn)?;
-            // Delete file
-            diesel::delete(files::table.find(&delete_request.file_uid)).execute(conn)?;
+    let blob: Option<String> = conn
+        .interact(move |conn| files::table.find(file_uid).select(files::blob).first(conn))
+        .await
+        .unwrap()
+        .unwrap();
 
-            diesel::result::QueryResult::Ok(())
-        })
-    })
-    .await
-    .unwrap()
-    .unwrap();
+    // Folders have no content
+    let Some(blob) = blob else {
+        return Err(StatusCode::NOT_FOUND);
+    };
 
-    StatusCode::OK
+    match app_state.blob_store.get_chunk(&blob, index).await {
+        Ok(Some(chunk)) => Ok(chunk.to_vec()),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+
+        Err(e) => {
+            log::error(&format!("Can't get chunk of blob {}: {}", blob, e));
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
 }
 
 #[derive(Deserialize)]
-pub struct ShareFileRequest {
-    /// File to share
+pub struct DeleteFileRequest {
     file_uid: String,
-    /// Symmetric key of the file, encrypted with target_user public key
+    /// Keyring holding the key of the file to delete
+    keyring_id: i32,
+    /// Symmetric key of the file, encrypted with user pubkey to be kept in his trash
+    #[serde(with = "serde_bytes")]
     encrypted_key: Vec<u8>,
-    /// The user to share the file with
-    target_user: String,
+    /// Signing private keys of the file, encrypted with user pubkey
+    signing_keys: SigningKeys,
+    /// Signature of the entry by the user
+    #[serde(with = "serde_bytes")]
+    entry_signature: Option<Vec<u8>>,
+    /// Challenge of the folder holding the file signed with its write key
+    #[serde(with = "serde_bytes")]
+    signature: Option<Vec<u8>>,
 }
 
-/// Allow a use to share a file with another user
-///
-/// Receive the file key encrypted with the destination user public key from the client
-/// push this key in the destination user root keyring.
+/// Allow a user to delete a file
 ///
-/// If it's a file, then the destination user will have access to this file from his root.
-/// If it's a folder, then the destination user will have access to this folder
-/// and all subsequent files/folder from his root.
-pub async fn share_file(
+/// The key of the file is moved to the trash keyring of the user, where it stays until
+/// the trash is emptied or purged. Its origin is kept so the file can be restored
+/// at the same place.
+pub async fn delete_file(
     Extension(user_session): Extension<Session>,
     State(app_state): State<AppState>,
-    Json(share_request): Json<ShareFileRequest>,
+    Msg(delete_request): Msg<DeleteFileRequest>,
 ) -> StatusCode {
     let conn = app_state.pool.get().await.unwrap();
 
@@ -475,66 +839,1429 @@ pub async fn share_file(
         .unwrap()
         .unwrap();
 
-    // Check if aser has access to the file
-    if !has_access(
+    // Check if user has access to the keyring holding the file
+    if !has_keyring_access(
+        &user.keyring,
+        delete_request.keyring_id,
+        Permission::Write,
+        &mut conn.lock().unwrap(),
+    ) || !signing::verify_keyring_challenge(
+        &app_state.challenges,
+        &user.username,
         &user.keyring,
-        share_request.file_uid.clone(),
+        delete_request.keyring_id,
+        Permission::Write,
+        delete_request.signature.as_deref(),
         &mut conn.lock().unwrap(),
     ) {
         return StatusCode::FORBIDDEN;
     }
 
-    conn.interact(move |conn| {
-        conn.transaction(|conn| {
-            // Get target_user keyring id
-            let target_user: User = users::table
-                .find(share_request.target_user)
-                .first::<User>(conn)?;
+    // Move the key to the trash
+    let trashed = conn
+        .interact(move |conn| {
+            conn.transaction(|conn| {
+                let key: Option<Key> = keys::table
+                    .filter(keys::target.eq(&delete_request.file_uid))
+                    .filter(keys::keyring_id.eq(delete_request.keyring_id))
+                    .first(conn)
+                    .optional()?;
+
+                let Some(key) = key else {
+                    return Ok(false);
+                };
+
+                let trash = trash::get_or_create_trash(&user.username, conn)?;
+
+                diesel::update(keys::table.find(key.id))
+                    .set((
+                        keys::keyring_id.eq(trash),
+                        keys::key.eq(delete_request.encrypted_key),
+                        keys::trashed_at.eq(SystemTime::now()
+                            .duration_since(UNIX_EPOCH)
+                            .unwrap()
+                            .as_millis() as i64),
+                        keys::origin.eq(key.keyring_id),
+                        delete_request.signing_keys,
+                        EntrySignature::new(&user.username, delete_request.entry_signature),
+                    ))
+                    .execute(conn)?;
+
+                // The links of the user to a trashed file stop serving it
+                delete_user_links(&user.username, &delete_request.file_uid, conn)?;
+
+                diesel::result::QueryResult::Ok(true)
+            })
+        })
+        .await
+        .unwrap()
+        .unwrap();
 
-            // Add shared key to the target_user keyring
-            diesel::insert_into(keys::table)
-                .values(NewKey {
-                    target: share_request.file_uid,
-                    key: share_request.encrypted_key,
-                    keyring_id: target_user.keyring,
-                })
-                .execute(conn)?;
+    if !trashed {
+        return StatusCode::NOT_FOUND;
+    }
 
-            diesel::result::QueryResult::Ok(())
-        })
+    StatusCode::OK
+}
+
+/// Delete a file for everyone, and everything inside if it's a folder
+///
+/// All keys targeting the file are removed, then the file is reclaimed.
+/// Return the blobs of the deleted files, to remove from the blob store once the
+/// transaction is committed.
+pub fn delete_permanently(file_uid: &str, conn: &mut SqliteConnection) -> QueryResult<Vec<String>> {
+    // Delete all keys to this file
+    delete_invitations(file_uid, conn)?;
+    diesel::delete(keys::table.filter(keys::target.eq(file_uid))).execute(conn)?;
+
+    let mut reclaimed_blobs = Vec::new();
+    reclaim_file(file_uid, conn, &mut reclaimed_blobs)?;
+
+    Ok(reclaimed_blobs)
+}
+
+/// Delete a single key, the file is reclaimed if no other key targets it anymore
+///
+/// Return the blobs of the deleted files, to remove from the blob store once the
+/// transaction is committed.
+pub fn delete_key(key: &Key, conn: &mut SqliteConnection) -> QueryResult<Vec<String>> {
+    diesel::delete(invitations::table.find(key.id)).execute(conn)?;
+    diesel::delete(keys::table.find(key.id)).execute(conn)?;
+
+    let still_reachable: i64 = keys::table
+        .filter(keys::target.eq(&key.target))
+        .count()
+        .get_result(conn)?;
+
+    let mut reclaimed_blobs = Vec::new();
+    if still_reachable == 0 {
+        reclaim_file(&key.target, conn, &mut reclaimed_blobs)?;
+    }
+
+    Ok(reclaimed_blobs)
+}
+
+/// Delete a file that is not reachable by any key anymore
+///
+/// If it's a folder, its keyring is emptied and each child is reclaimed in turn, unless
+/// another key still targets it (e.g. it was shared with another user, it stays
+/// reachable from his keyring).
+/// Blobs of the deleted files are pushed to `reclaimed_blobs`, they must be removed from
+/// the blob store once the transaction is committed.
+fn reclaim_file(
+    file_uid: &str,
+    conn: &mut SqliteConnection,
+    reclaimed_blobs: &mut Vec<String>,
+) -> QueryResult<()> {
+    let Some(file) = files::table.find(file_uid).first::<File>(conn).optional()? else {
+        return Ok(());
+    };
+
+    // Delete file content and its previous versions
+    if let Some(blob) = file.blob {
+        diesel::delete(chunks::table.filter(chunks::blob.eq(&blob))).execute(conn)?;
+        reclaimed_blobs.push(blob);
+    }
+    reclaimed_blobs.extend(versions::delete_all_versions(&file.id, conn)?);
+    delete_links(&file.id, conn)?;
+
+    diesel::delete(files::table.find(&file.id)).execute(conn)?;
+
+    // Reclaim the folder content
+    if let Some(keyring_id) = file.keyring_id {
+        let children: Vec<String> =
+            diesel::delete(keys::table.filter(keys::keyring_id.eq(keyring_id)))
+                .returning(keys::target)
+                .get_results(conn)?;
+
+        diesel::delete(keyrings::table.find(keyring_id)).execute(conn)?;
+
+        for child in children {
+            let still_reachable: i64 = keys::table
+                .filter(keys::target.eq(&child))
+                .count()
+                .get_result(conn)?;
+
+            if still_reachable == 0 {
+                reclaim_file(&child, conn, reclaimed_blobs)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Deserialize)]
+pub struct MoveFileRequest {
+    file_uid: String,
+    /// Keyring currently holding the key of the file
+    keyring_id: i32,
+    /// Keyring to move the key to, the user root keyring or the one of a folder
+    destination_keyring_id: i32,
+    /// Symmetric key of the file, encrypted with the destination folder key
+    /// or with user pubkey for root
+    #[serde(with = "serde_bytes")]
+    encrypted_key: Vec<u8>,
+    /// Signing private keys of the file, encrypted for the destination keyring
+    signing_keys: SigningKeys,
+    /// Signature of the entry by the user, when moving to root
+    #[serde(with = "serde_bytes")]
+    entry_signature: Option<Vec<u8>>,
+    /// Challenge of the current folder signed with its write key
+    #[serde(with = "serde_bytes")]
+    signature: Option<Vec<u8>>,
+    /// Challenge of the destination folder signed with its write key
+    #[serde(with = "serde_bytes")]
+    destination_signature: Option<Vec<u8>>,
+}
+
+/// Allow a user to move a file or folder to another folder
+///
+/// The placement of a file is defined by the keyring holding its key, the key entry is
+/// moved to the destination keyring along with the key wrapped for it by the client.
+pub async fn move_file(
+    Extension(user_session): Extension<Session>,
+    State(app_state): State<AppState>,
+    Msg(move_request): Msg<MoveFileRequest>,
+) -> StatusCode {
+    let conn = app_state.pool.get().await.unwrap();
+
+    // Get user keyring informations
+    let user: UserWithKeyring = conn
+        .interact(|conn| {
+            users::table
+                .find(user_session.user)
+                .inner_join(keyrings::table)
+                .select((
+                    users::username,
+                    users::pub_key,
+                    users::priv_key,
+                    (keyrings::all_columns),
+                ))
+                .first::<UserWithKeyring>(conn)
+        })
+        .await
+        .unwrap()
+        .unwrap();
+
+    // Check if user has access to both keyrings
+    if !has_keyring_access(
+        &user.keyring,
+        move_request.keyring_id,
+        Permission::Write,
+        &mut conn.lock().unwrap(),
+    ) || !has_keyring_access(
+        &user.keyring,
+        move_request.destination_keyring_id,
+        Permission::Write,
+        &mut conn.lock().unwrap(),
+    ) {
+        return StatusCode::FORBIDDEN;
+    }
+
+    if !signing::verify_keyring_challenge(
+        &app_state.challenges,
+        &user.username,
+        &user.keyring,
+        move_request.keyring_id,
+        Permission::Write,
+        move_request.signature.as_deref(),
+        &mut conn.lock().unwrap(),
+    ) || !signing::verify_keyring_challenge(
+        &app_state.challenges,
+        &user.username,
+        &user.keyring,
+        move_request.destination_keyring_id,
+        Permission::Write,
+        move_request.destination_signature.as_deref(),
+        &mut conn.lock().unwrap(),
+    ) {
+        return StatusCode::FORBIDDEN;
+    }
+
+    // A folder can't be moved inside itself, its keyring or the keyring of a subfolder
+    if is_inside_folder(
+        &move_request.file_uid,
+        move_request.destination_keyring_id,
+        &mut conn.lock().unwrap(),
+    ) {
+        return StatusCode::BAD_REQUEST;
+    }
+
+    let moved = conn
+        .interact(move |conn| {
+            diesel::update(
+                keys::table
+                    .filter(keys::target.eq(&move_request.file_uid))
+                    .filter(keys::keyring_id.eq(move_request.keyring_id)),
+            )
+            .set((
+                keys::keyring_id.eq(move_request.destination_keyring_id),
+                keys::key.eq(move_request.encrypted_key),
+                move_request.signing_keys,
+                EntrySignature::new(&user.username, move_request.entry_signature),
+            ))
+            .execute(conn)
+        })
+        .await
+        .unwrap()
+        .unwrap();
+
+    if moved == 0 {
+        return StatusCode::NOT_FOUND;
+    }
+
+    StatusCode::OK
+}
+
+#[derive(Deserialize)]
+pub struct RenameFileRequest {
+    file_uid: String,
+    /// New encrypted filename
+    filename: String,
+    /// Challenge of the file signed with its write key
+    #[serde(with = "serde_bytes")]
+    signature: Option<Vec<u8>>,
+}
+
+/// Allow a user to rename a file or folder
+///
+/// Names are encrypted with the file key, the new one is encrypted by the client.
+pub async fn rename_file(
+    Extension(user_session): Extension<Session>,
+    State(app_state): State<AppState>,
+    Msg(rename_request): Msg<RenameFileRequest>,
+) -> StatusCode {
+    let conn = app_state.pool.get().await.unwrap();
+
+    // Get user keyring informations
+    let user: UserWithKeyring = conn
+        .interact(|conn| {
+            users::table
+                .find(user_session.user)
+                .inner_join(keyrings::table)
+                .select((
+                    users::username,
+                    users::pub_key,
+                    users::priv_key,
+                    (keyrings::all_columns),
+                ))
+                .first::<UserWithKeyring>(conn)
+        })
+        .await
+        .unwrap()
+        .unwrap();
+
+    // Check if user has access to the file
+    if !has_access(
+        &user.keyring,
+        rename_request.file_uid.clone(),
+        Permission::Write,
+        &mut conn.lock().unwrap(),
+    ) || !signing::verify_challenge(
+        &app_state.challenges,
+        &user.username,
+        &rename_request.file_uid,
+        Permission::Write,
+        rename_request.signature.as_deref(),
+        &mut conn.lock().unwrap(),
+    ) {
+        return StatusCode::FORBIDDEN;
+    }
+
+    conn.interact(move |conn| {
+        diesel::update(files::table.find(rename_request.file_uid))
+            .set(files::name.eq(rename_request.filename))
+            .execute(conn)
+    })
+    .await
+    .unwrap()
+    .unwrap();
+
+    StatusCode::OK
+}
+
+#[derive(Deserialize)]
+pub struct CopyFileRequest {
+    /// File to copy
+    file_uid: String,
+    /// Keyring to put the copy in, the user root keyring or the one of a folder
+    destination_keyring_id: i32,
+    /// Symmetric key of the file, encrypted with the destination folder key
+    /// or with user pubkey for root
+    #[serde(with = "serde_bytes")]
+    encrypted_key: Vec<u8>,
+    /// Id of the copy, chosen by the client to sign its entry with it
+    copy_uid: String,
+    /// Encrypted filename of the copy
+    filename: String,
+    /// Signing public keys of the copy, it doesn't share the ones of the original
+    public_keys: PublicKeys,
+    /// Signing private keys of the copy, encrypted like its key
+    signing_keys: SigningKeys,
+    /// Signature of the entry by the user, for a copy in root
+    #[serde(with = "serde_bytes")]
+    entry_signature: Option<Vec<u8>>,
+    /// Challenge of the destination folder signed with its write key
+    #[serde(with = "serde_bytes")]
+    signature: Option<Vec<u8>>,
+}
+
+#[derive(Serialize)]
+pub struct CopyFileResponse {
+    file_uid: String,
+}
+
+/// Allow a user to copy a file without downloading it
+///
+/// The encrypted content is copied by the blob store to a new blob, the copy is encrypted
+/// with the same key as the original and charged to the user. Folders are copied by
+/// the client, by creating a folder and copying each file in it.
+pub async fn copy_file(
+    Extension(user_session): Extension<Session>,
+    State(app_state): State<AppState>,
+    Msg(copy_request): Msg<CopyFileRequest>,
+) -> Result<Msg<CopyFileResponse>, StatusCode> {
+    let conn = app_state.pool.get().await.unwrap();
+
+    // Get user keyring informations
+    let user: UserWithKeyring = conn
+        .interact(|conn| {
+            users::table
+                .find(user_session.user)
+                .inner_join(keyrings::table)
+                .select((
+                    users::username,
+                    users::pub_key,
+                    users::priv_key,
+                    (keyrings::all_columns),
+                ))
+                .first::<UserWithKeyring>(conn)
+        })
+        .await
+        .unwrap()
+        .unwrap();
+
+    // Check if user has access to the file and to the destination keyring
+    if !has_access(
+        &user.keyring,
+        copy_request.file_uid.clone(),
+        Permission::Read,
+        &mut conn.lock().unwrap(),
+    ) || !has_keyring_access(
+        &user.keyring,
+        copy_request.destination_keyring_id,
+        Permission::Write,
+        &mut conn.lock().unwrap(),
+    ) || !signing::verify_keyring_challenge(
+        &app_state.challenges,
+        &user.username,
+        &user.keyring,
+        copy_request.destination_keyring_id,
+        Permission::Write,
+        copy_request.signature.as_deref(),
+        &mut conn.lock().unwrap(),
+    ) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    // The copy is a new file, it must come with a new id and its own signing public keys
+    if !is_new_file_id(&copy_request.copy_uid, &mut conn.lock().unwrap())
+        || !signing::check_keyring_public_keys(
+            &copy_request.public_keys,
+            &user.keyring,
+            copy_request.destination_keyring_id,
+            &mut conn.lock().unwrap(),
+        )
+    {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let file: File = conn
+        .interact({
+            let file_uid = copy_request.file_uid.clone();
+            |conn| files::table.find(file_uid).first(conn)
+        })
+        .await
+        .unwrap()
+        .unwrap();
+
+    let (Some(sz), Some(blob)) = (file.sz, file.blob.clone()) else {
+        return Err(StatusCode::BAD_REQUEST);
+    };
+
+    // The copy is charged to the user
+    if !quota::fits_quota(
+        &user.username,
+        sz,
+        app_state.default_quota,
+        &mut conn.lock().unwrap(),
+    ) {
+        return Err(StatusCode::INSUFFICIENT_STORAGE);
+    }
+
+    let file_chunks: Vec<Chunk> = conn
+        .interact(move |conn| {
+            chunks::table
+                .filter(chunks::blob.eq(blob))
+                .load::<Chunk>(conn)
+        })
+        .await
+        .unwrap()
+        .unwrap();
+
+    // Copy the content first, the copy is unreachable until the transaction below
+    let new_blob = Uuid::new_v4().to_string();
+    for chunk in &file_chunks {
+        if let Err(e) = app_state
+            .blob_store
+            .copy_chunk(&chunk.blob, &new_blob, chunk.idx)
+            .await
+        {
+            log::error(&format!("Can't copy chunk of blob {}: {}", chunk.blob, e));
+            blobs::delete_blobs(&app_state.blob_store, vec![new_blob]).await;
+
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+    }
+
+    let entry_signature = EntrySignature::new(&user.username, copy_request.entry_signature);
+
+    let new_file = NewFile {
+        id: copy_request.copy_uid,
+        name: copy_request.filename,
+        mtime: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_millis() as i64,
+        sz,
+        blob: new_blob,
+        chunks: file.chunks.unwrap_or(file_chunks.len() as i32),
+        keyring_id: None,
+        owner: user.username,
+        public_keys: copy_request.public_keys,
+    };
+
+    let file_uid = new_file.id.clone();
+
+    conn.interact(move |conn| {
+        conn.transaction(|conn| {
+            diesel::insert_into(chunks::table)
+                .values(
+                    file_chunks
+                        .into_iter()
+                        .map(|chunk| Chunk {
+                            blob: new_file.blob.clone(),
+                            ..chunk
+                        })
+                        .collect::<Vec<Chunk>>(),
+                )
+                .execute(conn)?;
+
+            let target = new_file.id.clone();
+            diesel::insert_into(files::table)
+                .values(new_file)
+                .execute(conn)?;
+
+            diesel::insert_into(keys::table)
+                .values(NewKey {
+                    target,
+                    key: copy_request.encrypted_key,
+                    keyring_id: copy_request.destination_keyring_id,
+                    permission: Permission::Owner as i32,
+                    signing_keys: copy_request.signing_keys,
+                    expires_at: None,
+                    entry_signature,
+                })
+                .execute(conn)?;
+
+            diesel::result::QueryResult::Ok(())
+        })
+    })
+    .await
+    .unwrap()
+    .unwrap();
+
+    Ok(Msg(CopyFileResponse { file_uid }))
+}
+
+#[derive(Deserialize)]
+pub struct ShareFileRequest {
+    /// File to share
+    file_uid: String,
+    /// Symmetric key of the file, encrypted with target_user public key
+    #[serde(with = "serde_bytes")]
+    encrypted_key: Vec<u8>,
+    /// The user to share the file with
+    target_user: String,
+    /// Access level granted to target_user, read or write
+    permission: Permission,
+    /// Signing private keys of the file up to the granted level, encrypted with
+    /// target_user public key
+    signing_keys: SigningKeys,
+    /// Signature of the entry by the user, target_user checks it
+    #[serde(with = "serde_bytes")]
+    entry_signature: Option<Vec<u8>>,
+    /// Challenge of the file signed with the key of the granted level
+    #[serde(with = "serde_bytes")]
+    signature: Option<Vec<u8>>,
+    /// End of the share in millis, None if it doesn't expire
+    expires_at: Option<i64>,
+}
+
+/// Allow a use to share a file with another user
+///
+/// Receive the file key encrypted with the destination user public key from the client
+/// push this key in the destination user inbox keyring, as a pending invitation.
+///
+/// Once accepted, the destination user will have access to this file from the keyring he
+/// chooses. If it's a folder, he will have access to this folder and all subsequent
+/// files/folder.
+/// A file shared with a group goes right away in the group keyring, its key is encrypted
+/// with the group public key. The status tells them apart: ACCEPTED for an invitation,
+/// OK for a group. An unknown user gets ACCEPTED too, nothing is created for him.
+/// A user or a group already having access to the file gets CONFLICT.
+pub async fn share_file(
+    Extension(user_session): Extension<Session>,
+    State(app_state): State<AppState>,
+    Msg(share_request): Msg<ShareFileRequest>,
+) -> StatusCode {
+    let conn = app_state.pool.get().await.unwrap();
+
+    // Get user keyring informations
+    let user: UserWithKeyring = conn
+        .interact(|conn| {
+            users::table
+                .find(user_session.user)
+                .inner_join(keyrings::table)
+                .select((
+                    users::username,
+                    users::pub_key,
+                    users::priv_key,
+                    (keyrings::all_columns),
+                ))
+                .first::<UserWithKeyring>(conn)
+        })
+        .await
+        .unwrap()
+        .unwrap();
+
+    // Check if user has access to the file, a user can't grant more than he has
+    // Ownership can't be shared
+    let Some(permission) = get_permission(
+        &user.keyring,
+        &share_request.file_uid,
+        &mut conn.lock().unwrap(),
+    ) else {
+        return StatusCode::FORBIDDEN;
+    };
+
+    if share_request.permission == Permission::Owner {
+        return StatusCode::BAD_REQUEST;
+    }
+    if share_request.permission > permission {
+        return StatusCode::FORBIDDEN;
+    }
+    if share_request
+        .expires_at
+        .is_some_and(|expires_at| expires_at <= signing::now_millis())
+    {
+        return StatusCode::BAD_REQUEST;
+    }
+
+    // The user must hold the signing key he gives
+    if !signing::verify_challenge(
+        &app_state.challenges,
+        &user.username,
+        &share_request.file_uid,
+        share_request.permission,
+        share_request.signature.as_deref(),
+        &mut conn.lock().unwrap(),
+    ) {
+        return StatusCode::FORBIDDEN;
+    }
+
+    // A target already reaching the file can't get it twice, a user could otherwise
+    // accept a folder inside itself
+    let target_keyring: Option<i32> = conn
+        .interact({
+            let target = share_request.target_user.clone();
+
+            move |conn| {
+                let group_keyring: Option<i32> = groups::table
+                    .find(&target)
+                    .select(groups::keyring)
+                    .first(conn)
+                    .optional()?;
+
+                if group_keyring.is_some() {
+                    return Ok(group_keyring);
+                }
+
+                users::table
+                    .find(&target)
+                    .select(users::keyring)
+                    .first(conn)
+                    .optional()
+            }
+        })
+        .await
+        .unwrap()
+        .unwrap();
+
+    if let Some(target_keyring) = target_keyring {
+        if get_permission(
+            &Keyring { id: target_keyring },
+            &share_request.file_uid,
+            &mut conn.lock().unwrap(),
+        )
+        .is_some()
+        {
+            return StatusCode::CONFLICT;
+        }
+    }
+
+    let entry_signature = EntrySignature::new(&user.username, share_request.entry_signature);
+
+    conn.interact(move |conn| {
+        conn.transaction(|conn| {
+            // A group gets the key right away in its keyring, for all its members
+            let group: Option<Group> = groups::table
+                .find(&share_request.target_user)
+                .first(conn)
+                .optional()?;
+
+            if let Some(group) = group {
+                // Only a member can put a file in the group keyring, the others would
+                // push files to users who never accepted them
+                let member: i64 = group_members::table
+                    .find((&group.name, &user.username))
+                    .count()
+                    .get_result(conn)?;
+
+                if member == 0 {
+                    return Ok(StatusCode::FORBIDDEN);
+                }
+
+                // An expired key of the file is replaced
+                diesel::delete(
+                    keys::table
+                        .filter(keys::keyring_id.eq(group.keyring))
+                        .filter(keys::target.eq(&share_request.file_uid)),
+                )
+                .execute(conn)?;
+
+                diesel::insert_into(keys::table)
+                    .values(NewKey {
+                        target: share_request.file_uid,
+                        key: share_request.encrypted_key,
+                        keyring_id: group.keyring,
+                        permission: share_request.permission as i32,
+                        signing_keys: share_request
+                            .signing_keys
+                            .restrict(share_request.permission),
+                        expires_at: share_request.expires_at,
+                        entry_signature,
+                    })
+                    .execute(conn)?;
+
+                return Ok(StatusCode::OK);
+            }
+
+            // Get target_user keyring id
+            // The answer doesn't tell which users exist
+            let Some(target_user) = users::table
+                .find(share_request.target_user)
+                .first::<User>(conn)
+                .optional()?
+            else {
+                return Ok(StatusCode::ACCEPTED);
+            };
+
+            let inbox = get_or_create_inbox(&target_user.username, conn)?;
+
+            // A new share replaces the pending one of the same file
+            let pending: Vec<i32> = keys::table
+                .filter(keys::keyring_id.eq(inbox))
+                .filter(keys::target.eq(&share_request.file_uid))
+                .select(keys::id)
+                .load(conn)?;
+
+            diesel::delete(invitations::table.filter(invitations::key_id.eq_any(&pending)))
+                .execute(conn)?;
+            diesel::delete(keys::table.filter(keys::id.eq_any(&pending))).execute(conn)?;
+
+            // Add shared key to the target_user inbox keyring, until he accepts it
+            let key: Key = diesel::insert_into(keys::table)
+                .values(NewKey {
+                    target: share_request.file_uid,
+                    key: share_request.encrypted_key,
+                    keyring_id: inbox,
+                    permission: share_request.permission as i32,
+                    signing_keys: share_request
+                        .signing_keys
+                        .restrict(share_request.permission),
+                    expires_at: share_request.expires_at,
+                    entry_signature,
+                })
+                .get_result(conn)?;
+
+            diesel::insert_into(invitations::table)
+                .values(Invitation {
+                    key_id: key.id,
+                    sender: user.username,
+                    created_at: signing::now_millis(),
+                })
+                .execute(conn)?;
+
+            // The share is effective once the invitation is accepted
+            diesel::result::QueryResult::Ok(StatusCode::ACCEPTED)
+        })
+    })
+    .await
+    .unwrap()
+    .unwrap()
+}
+
+#[derive(Deserialize)]
+pub struct RevokeShareFileRequest {
+    /// File to revoke
+    file_uid: String,
+    /// A file can have multiple parents depending of sharing status
+    /// We need to know the parent the file must remain in
+    /// If user indicate a different parent on which he have also access
+    /// This will move the file with this current implementation
+    parent_uid: Option<String>,
+    /// New Symmetric key of the file, encrypted with parent
+    #[serde(with = "serde_bytes")]
+    encrypted_key: Vec<u8>,
+    /// New encrypted filename
+    filename: String,
+    /// Upload holding the new encrypted file content, None if folder
+    upload_id: Option<String>,
+    /// New signing public keys of the file, the revoked users may hold the previous ones
+    public_keys: PublicKeys,
+    /// New signing private keys of the file, encrypted with parent
+    signing_keys: SigningKeys,
+    /// Signature of the new encrypted key by the user, when kept in root
+    #[serde(with = "serde_bytes")]
+    entry_signature: Option<Vec<u8>>,
+    /// Challenge of the file signed with its current owner key
+    #[serde(with = "serde_bytes")]
+    signature: Option<Vec<u8>>,
+    /// New keys of everything inside a revoked folder, empty for a file
+    descendants: Vec<RevokedDescendant>,
+    /// User to revoke, the other users with a direct grant keep their access
+    /// None revokes everyone
+    user: Option<String>,
+    /// New key of each remaining user with a direct grant, empty when revoking everyone
+    grants: Vec<RenewedGrant>,
+}
+
+#[derive(Deserialize)]
+pub struct RenewedGrant {
+    /// User or group keeping his access to the file
+    username: String,
+    /// New Symmetric key of the file, encrypted with the user public key
+    #[serde(with = "serde_bytes")]
+    encrypted_key: Vec<u8>,
+    /// New signing private keys of the file up to his access level, encrypted with the
+    /// user public key
+    signing_keys: SigningKeys,
+    /// Signature of the new encrypted key by the owner, for the inbox of a user or the
+    /// keyring of a group
+    #[serde(with = "serde_bytes")]
+    entry_signature: Option<Vec<u8>>,
+}
+
+#[derive(Deserialize)]
+pub struct RevokedDescendant {
+    /// File or folder inside the revoked folder, at any depth
+    file_uid: String,
+    /// New Symmetric key of the file, encrypted with the new key of its folder
+    #[serde(with = "serde_bytes")]
+    encrypted_key: Vec<u8>,
+    /// New encrypted filename
+    filename: String,
+    /// Upload holding the new encrypted file content, None if folder
+    upload_id: Option<String>,
+    /// New signing public keys of the file
+    public_keys: PublicKeys,
+    /// New signing private keys of the file, encrypted with the new ones of its folder
+    signing_keys: SigningKeys,
+}
+
+/// Allow the owner of a file to revoke its shares, or the direct grant of a single user
+///
+/// Only the user who created the file, or a user owning it through his keyring, can
+/// revoke. The file is re-encrypted with a new key, only the key in the given parent and
+/// the renewed keys of the remaining users are kept, at their previous access level. The
+/// remaining users get their key as an invitation, it may have been in one of their
+/// folders. A group only trusts the keys signed by its members, a user who isn't a
+/// member of a remaining group gets CONFLICT and can only revoke every share.
+/// A folder is revoked along with everything inside: each file and folder gets a new key,
+/// kept in the same nested keyring, and all the keys are rotated in a single transaction.
+pub async fn unshare_file(
+    Extension(user_session): Extension<Session>,
+    State(app_state): State<AppState>,
+    Msg(revoke_share_request): Msg<RevokeShareFileRequest>,
+) -> StatusCode {
+    let conn = app_state.pool.get().await.unwrap();
+
+    // Get user keyring informations
+    let user: UserWithKeyring = conn
+        .interact(|conn| {
+            users::table
+                .find(user_session.user)
+                .inner_join(keyrings::table)
+                .select((
+                    users::username,
+                    users::pub_key,
+                    users::priv_key,
+                    (keyrings::all_columns),
+                ))
+                .first::<UserWithKeyring>(conn)
+        })
+        .await
+        .unwrap()
+        .unwrap();
+
+    // Check if user has access to the file and owns it
+    if !is_owner(
+        &user,
+        &revoke_share_request.file_uid,
+        &mut conn.lock().unwrap(),
+    ) || !signing::verify_challenge(
+        &app_state.challenges,
+        &user.username,
+        &revoke_share_request.file_uid,
+        Permission::Owner,
+        revoke_share_request.signature.as_deref(),
+        &mut conn.lock().unwrap(),
+    ) {
+        return StatusCode::FORBIDDEN;
+    }
+
+    // Check if user has access to parent folder
+    if let Some(parent_uid) = revoke_share_request.parent_uid.clone() {
+        if !has_access(
+            &user.keyring,
+            parent_uid,
+            Permission::Write,
+            &mut conn.lock().unwrap(),
+        ) {
+            return StatusCode::FORBIDDEN;
+        }
+    };
+
+    // Every remaining user must get the new key, or he would silently lose his access
+    let remaining_grants = if let Some(revoked_user) = &revoke_share_request.user {
+        let file_uid = revoke_share_request.file_uid.clone();
+        let grants = conn
+            .interact(move |conn| get_grants(&file_uid, conn))
+            .await
+            .unwrap()
+            .unwrap();
+
+        let Some(remaining_grants) = get_remaining_grants(grants, revoked_user, &user.username)
+        else {
+            return StatusCode::NOT_FOUND;
+        };
+
+        remaining_grants
+    } else {
+        HashMap::new()
+    };
+
+    let renewed: HashSet<&String> = revoke_share_request
+        .grants
+        .iter()
+        .map(|grant| &grant.username)
+        .collect();
+
+    if renewed.len() != revoke_share_request.grants.len()
+        || renewed.len() != remaining_grants.len()
+        || !renewed
+            .iter()
+            .all(|username| remaining_grants.contains_key(*username))
+    {
+        return StatusCode::BAD_REQUEST;
+    }
+
+    let remaining_groups: Vec<String> = remaining_grants
+        .values()
+        .filter(|grant| grant.group)
+        .map(|grant| grant.username.clone())
+        .collect();
+
+    if !remaining_groups.is_empty() {
+        let groups_count = remaining_groups.len() as i64;
+        let username = user.username.clone();
+        let member_of: i64 = conn
+            .interact(move |conn| {
+                group_members::table
+                    .filter(group_members::group_name.eq_any(remaining_groups))
+                    .filter(group_members::username.eq(username))
+                    .count()
+                    .get_result(conn)
+            })
+            .await
+            .unwrap()
+            .unwrap();
+
+        if member_of != groups_count {
+            return StatusCode::CONFLICT;
+        }
+    }
+
+    // Everything inside a revoked folder is re-keyed along with it, the client must
+    // send the new keys of the whole tree
+    let folder_keyring: Option<i32> = conn
+        .interact({
+            let file_uid = revoke_share_request.file_uid.clone();
+            move |conn| {
+                files::table
+                    .find(file_uid)
+                    .select(files::keyring_id)
+                    .first(conn)
+            }
+        })
+        .await
+        .unwrap()
+        .unwrap();
+
+    let subtree = match folder_keyring {
+        Some(keyring_id) => get_subtree(keyring_id, &mut conn.lock().unwrap()),
+        None => Some(HashMap::new()),
+    };
+
+    let Some(subtree) = subtree else {
+        return StatusCode::BAD_REQUEST;
+    };
+
+    let rekeyed: HashSet<&String> = revoke_share_request
+        .descendants
+        .iter()
+        .map(|descendant| &descendant.file_uid)
+        .collect();
+
+    if rekeyed.len() != revoke_share_request.descendants.len()
+        || rekeyed.len() != subtree.len()
+        || !rekeyed
+            .iter()
+            .all(|file_uid| subtree.contains_key(*file_uid))
+    {
+        return StatusCode::BAD_REQUEST;
+    }
+
+    // The new public keys are checked like the ones of a new file
+    if !check_rotated_public_keys(&revoke_share_request, &subtree, &mut conn.lock().unwrap()) {
+        return StatusCode::BAD_REQUEST;
+    }
+
+    // Check the uploads holding the re-encrypted contents
+    let new_content = match get_new_content(
+        revoke_share_request.upload_id.clone(),
+        &user.username,
+        &mut conn.lock().unwrap(),
+    ) {
+        Ok(new_content) => new_content,
+        Err(status) => return status,
+    };
+
+    let mut descendants = Vec::new();
+    for descendant in revoke_share_request.descendants {
+        let subtree_key = subtree[&descendant.file_uid];
+
+        // A file left with its previous content would stay readable with the revoked key
+        if descendant.upload_id.is_some() != subtree_key.2 {
+            return StatusCode::BAD_REQUEST;
+        }
+
+        let new_content = match get_new_content(
+            descendant.upload_id.clone(),
+            &user.username,
+            &mut conn.lock().unwrap(),
+        ) {
+            Ok(new_content) => new_content,
+            Err(status) => return status,
+        };
+
+        descendants.push((descendant, subtree_key, new_content));
+    }
+
+    // Get parent folder keyring
+    let parent_keyring = if let Some(parent_uid) = revoke_share_request.parent_uid {
+        let parent_folder: Folder = conn
+            .interact(move |conn| {
+                files::table
+                    .find(parent_uid)
+                    .inner_join(keyrings::table)
+                    .select((files::id, files::name, (keyrings::all_columns)))
+                    .first::<Folder>(conn)
+            })
+            .await
+            .unwrap()
+            .unwrap();
+
+        parent_folder.keyring
+    } else {
+        user.keyring
+    };
+
+    let mtime = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as i64;
+
+    // Rotate the keys of the whole tree at once, a failure leaves it untouched
+    let old_blobs = conn
+        .interact(move |conn| {
+            conn.transaction(|conn| {
+                replace_keys(
+                    NewKey {
+                        target: revoke_share_request.file_uid.clone(),
+                        key: revoke_share_request.encrypted_key,
+                        keyring_id: parent_keyring.id,
+                        permission: Permission::Owner as i32,
+                        signing_keys: revoke_share_request.signing_keys,
+                        expires_at: None,
+                        entry_signature: EntrySignature::new(
+                            &user.username,
+                            revoke_share_request.entry_signature,
+                        ),
+                    },
+                    revoke_share_request.grants,
+                    &remaining_grants,
+                    &user.username,
+                    conn,
+                )?;
+
+                let mut old_blobs = rekey_file(
+                    &revoke_share_request.file_uid,
+                    revoke_share_request.filename,
+                    revoke_share_request.public_keys,
+                    new_content,
+                    mtime,
+                    conn,
+                )?;
+
+                for (descendant, (keyring_id, permission, _, expires_at), new_content) in
+                    descendants
+                {
+                    delete_invitations(&descendant.file_uid, conn)?;
+                    diesel::delete(keys::table.filter(keys::target.eq(&descendant.file_uid)))
+                        .execute(conn)?;
+
+                    diesel::insert_into(keys::table)
+                        .values(NewKey {
+                            target: descendant.file_uid.clone(),
+                            key: descendant.encrypted_key,
+                            keyring_id,
+                            permission,
+                            signing_keys: descendant.signing_keys,
+                            expires_at,
+                            entry_signature: EntrySignature::default(),
+                        })
+                        .execute(conn)?;
+
+                    old_blobs.extend(rekey_file(
+                        &descendant.file_uid,
+                        descendant.filename,
+                        descendant.public_keys,
+                        new_content,
+                        mtime,
+                        conn,
+                    )?);
+                }
+
+                diesel::result::QueryResult::Ok(old_blobs)
+            })
+        })
+        .await
+        .unwrap()
+        .unwrap();
+
+    blobs::delete_blobs(&app_state.blob_store, old_blobs).await;
+
+    StatusCode::OK
+}
+
+/// Get the grants kept when revoking a single user, None if he has no grant
+///
+/// The user revoking keeps the key in the parent he gives, his own grants aren't renewed.
+fn get_remaining_grants(
+    grants: Vec<Grant>,
+    revoked_user: &str,
+    user: &str,
+) -> Option<HashMap<String, Grant>> {
+    if !grants.iter().any(|grant| grant.username == revoked_user) {
+        return None;
+    }
+
+    Some(
+        grants
+            .into_iter()
+            .filter(|grant| grant.username != revoked_user && grant.username != user)
+            .map(|grant| (grant.username.clone(), grant))
+            .collect(),
+    )
+}
+
+/// Replace every key of a revoked file by the new key of the owner and the renewed keys
+/// of the remaining grants
+///
+/// The owner can't encrypt the key for the folders of the other users, they get it back
+/// in their inbox and accept it again. Groups get it in their keyring.
+fn replace_keys(
+    owner_key: NewKey,
+    renewed_grants: Vec<RenewedGrant>,
+    remaining_grants: &HashMap<String, Grant>,
+    sender: &str,
+    conn: &mut SqliteConnection,
+) -> QueryResult<()> {
+    // Remove all occurence of the keys, only the new ones remain
+    // Pending shares hold the revoked key, they are dropped
+    delete_invitations(&owner_key.target, conn)?;
+    diesel::delete(keys::table.filter(keys::target.eq(&owner_key.target))).execute(conn)?;
+
+    let file_uid = owner_key.target.clone();
+    diesel::insert_into(keys::table)
+        .values(owner_key)
+        .execute(conn)?;
+
+    for renewed_grant in renewed_grants {
+        let grant = &remaining_grants[&renewed_grant.username];
+        let keyring_id = if grant.group {
+            grant.keyring_id
+        } else {
+            get_or_create_inbox(&grant.username, conn)?
+        };
+
+        let key: Key = diesel::insert_into(keys::table)
+            .values(NewKey {
+                target: file_uid.clone(),
+                key: renewed_grant.encrypted_key,
+                keyring_id,
+                permission: grant.permission as i32,
+                signing_keys: renewed_grant.signing_keys.restrict(grant.permission),
+                expires_at: grant.expires_at,
+                entry_signature: EntrySignature::new(sender, renewed_grant.entry_signature),
+            })
+            .get_result(conn)?;
+
+        if !grant.group {
+            diesel::insert_into(invitations::table)
+                .values(Invitation {
+                    key_id: key.id,
+                    sender: sender.to_string(),
+                    created_at: signing::now_millis(),
+                })
+                .execute(conn)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Check the new signing public keys of a revoked file and of everything inside
+///
+/// The file may only have none in a folder without signing keys, and a descendant if the
+/// new ones of its folder are missing too, see `signing::check_public_keys`.
+fn check_rotated_public_keys(
+    revoke_share_request: &RevokeShareFileRequest,
+    subtree: &Subtree,
+    conn: &mut SyncGuard<SqliteConnection>,
+) -> bool {
+    if !signing::check_public_keys(
+        &revoke_share_request.public_keys,
+        revoke_share_request.parent_uid.as_deref(),
+        conn,
+    ) {
+        return false;
+    }
+
+    let mut new_public_keys: HashMap<&str, &PublicKeys> = revoke_share_request
+        .descendants
+        .iter()
+        .map(|descendant| (descendant.file_uid.as_str(), &descendant.public_keys))
+        .collect();
+    new_public_keys.insert(
+        &revoke_share_request.file_uid,
+        &revoke_share_request.public_keys,
+    );
+
+    revoke_share_request.descendants.iter().all(|descendant| {
+        let folder: String = files::table
+            .filter(files::keyring_id.eq(subtree[&descendant.file_uid].0))
+            .select(files::id)
+            .first(conn.as_mut())
+            .unwrap();
+
+        signing::check_rotated_public_keys(
+            &descendant.public_keys,
+            new_public_keys[folder.as_str()],
+        )
     })
-    .await
-    .unwrap()
-    .unwrap();
+}
 
-    StatusCode::OK
+/// Files and folders inside a folder keyring, with the keyring holding their key, its
+/// access level, whether they have a content and the expiration of the key
+type Subtree = HashMap<String, (i32, i32, bool, Option<i64>)>;
+
+/// Get the files and folders inside a folder keyring, recursively
+///
+/// Return None if a file is reached twice, its keys couldn't be rotated consistently.
+fn get_subtree(keyring_id: i32, conn: &mut SyncGuard<SqliteConnection>) -> Option<Subtree> {
+    let mut subtree = HashMap::new();
+    let mut pending = vec![keyring_id];
+
+    while let Some(keyring_id) = pending.pop() {
+        let children = keys::table
+            .inner_join(files::table)
+            .filter(keys::keyring_id.eq(keyring_id))
+            .select((
+                keys::target,
+                keys::permission,
+                files::keyring_id,
+                files::blob.is_not_null(),
+                keys::expires_at,
+            ))
+            .load::<(String, i32, Option<i32>, bool, Option<i64>)>(conn.as_mut())
+            .unwrap();
+
+        for (target, permission, folder_keyring, has_content, expires_at) in children {
+            if subtree
+                .insert(target, (keyring_id, permission, has_content, expires_at))
+                .is_some()
+            {
+                return None;
+            }
+
+            pending.extend(folder_keyring);
+        }
+    }
+
+    Some(subtree)
 }
 
-#[derive(Deserialize)]
-pub struct RevokeShareFileRequest {
-    /// File to revoke
-    file_uid: String,
-    /// A file can have multiple parents depending of sharing status
-    /// We need to know the parent the file must remain in
-    /// If user indicate a different parent on which he have also access
-    /// This will move the file with this current implementation
-    parent_uid: Option<String>,
-    /// New Symmetric key of the file, encrypted with parent
-    encrypted_key: Vec<u8>,
-    /// New encrypted filename
+/// Check an upload holding the re-encrypted content of a file
+///
+/// Return the upload along with its chunk count and size, None if the file has no content.
+fn get_new_content(
+    upload_id: Option<String>,
+    user: &str,
+    conn: &mut SyncGuard<SqliteConnection>,
+) -> Result<Option<(String, i32, i64)>, StatusCode> {
+    let Some(upload_id) = upload_id else {
+        return Ok(None);
+    };
+
+    if get_upload(&upload_id, user, conn).is_none() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    let Some((chunk_count, size)) = get_blob_size(&upload_id, conn) else {
+        return Err(StatusCode::BAD_REQUEST);
+    };
+
+    Ok(Some((upload_id, chunk_count, size)))
+}
+
+/// Replace the name, the signing public keys and the content of a re-keyed file
+///
+/// Its public links carry the revoked key, they are deleted.
+/// Return the blobs encrypted with the revoked key, to delete once committed.
+fn rekey_file(
+    file_uid: &str,
     filename: String,
-    /// New encrypted file content
-    file: Option<Vec<u8>>,
+    public_keys: PublicKeys,
+    new_content: Option<(String, i32, i64)>,
+    mtime: i64,
+    conn: &mut SqliteConnection,
+) -> QueryResult<Vec<String>> {
+    let file: File = files::table.find(file_uid).first(conn)?;
+
+    diesel::update(files::table.find(&file.id))
+        .set((
+            files::name.eq(filename),
+            files::mtime.eq(mtime),
+            files::expired_share_at.eq(None::<i64>),
+            public_keys,
+        ))
+        .execute(conn)?;
+    delete_links(&file.id, conn)?;
+
+    // Swap the content for the re-encrypted one
+    let Some((upload_id, chunk_count, size)) = new_content else {
+        return Ok(Vec::new());
+    };
+
+    diesel::update(files::table.find(&file.id))
+        .set((
+            files::sz.eq(size),
+            files::blob.eq(&upload_id),
+            files::chunks.eq(chunk_count),
+        ))
+        .execute(conn)?;
+
+    if let Some(blob) = &file.blob {
+        diesel::delete(chunks::table.filter(chunks::blob.eq(blob))).execute(conn)?;
+    }
+
+    diesel::delete(uploads::table.find(&upload_id)).execute(conn)?;
+
+    // Previous versions are encrypted with the revoked key and can't be re-encrypted,
+    // they are dropped
+    let mut old_blobs = versions::delete_all_versions(&file.id, conn)?;
+    old_blobs.extend(file.blob);
+
+    Ok(old_blobs)
+}
+
+#[derive(Serialize)]
+pub struct Grant {
+    /// User or group holding a key to the file in his root keyring or in one of his folders
+    pub username: String,
+    /// Groups get their keys in their keyring, users in their inbox
+    pub group: bool,
+    /// Access level of the key
+    pub permission: Permission,
+    /// End of the grant in millis, None if it doesn't expire
+    pub expires_at: Option<i64>,
+    /// Keyring holding the key
+    #[serde(skip)]
+    pub keyring_id: i32,
+}
+
+#[derive(Serialize)]
+pub struct FileAccess {
+    /// Users with a direct grant on the file
+    users: Vec<Grant>,
+    /// Folders containing the file, at any depth, the users with access to one of them
+    /// reach the file through it
+    folders: Vec<FolderAccess>,
+}
+
+#[derive(Serialize)]
+pub struct FolderAccess {
+    /// Folder containing the file
+    id: String,
+    /// Users with a direct grant on the folder
+    users: Vec<Grant>,
 }
 
-/// Allow a user to revoke a share to a file he has access to
+/// Allow a user to list who has access to a file or folder
 ///
-/// Note: This method is unfinished and there is flaws when revoking folders
-pub async fn unshare_file(
+/// Users with a key in their root keyring or in one of their folders, where they accepted
+/// the share, are listed as direct grants along with their access level. The folders
+/// containing the file grant an indirect access to their own users, the ones the user
+/// reaches are listed up to his root.
+pub async fn get_access(
     Extension(user_session): Extension<Session>,
     State(app_state): State<AppState>,
-    Json(revoke_share_request): Json<RevokeShareFileRequest>,
-) -> StatusCode {
+    Path(file_uid): Path<String>,
+) -> Result<Msg<FileAccess>, StatusCode> {
     let conn = app_state.pool.get().await.unwrap();
 
     // Get user keyring informations
@@ -555,109 +2282,250 @@ pub async fn unshare_file(
         .unwrap()
         .unwrap();
 
-    // Check if aser has access to the file
+    // Check if user has access to the file
     if !has_access(
         &user.keyring,
-        revoke_share_request.file_uid.clone(),
+        file_uid.clone(),
+        Permission::Read,
         &mut conn.lock().unwrap(),
     ) {
-        return StatusCode::FORBIDDEN;
+        return Err(StatusCode::FORBIDDEN);
     }
 
-    // Check if user has access to parent folder
-    if let Some(parent_uid) = revoke_share_request.parent_uid.clone() {
-        if !has_access(&user.keyring, parent_uid, &mut conn.lock().unwrap()) {
-            return StatusCode::FORBIDDEN;
+    let access = conn
+        .interact(move |conn| {
+            let users = get_grants(&file_uid, conn)?;
+
+            let folders = get_ancestors(&file_uid, &user.username, user.keyring.id, conn)?
+                .into_iter()
+                .map(|id| {
+                    let users = get_grants(&id, conn)?;
+
+                    QueryResult::Ok(FolderAccess { id, users })
+                })
+                .collect::<QueryResult<Vec<_>>>()?;
+
+            QueryResult::Ok(FileAccess { users, folders })
+        })
+        .await
+        .unwrap()
+        .unwrap();
+
+    Ok(Msg(access))
+}
+
+/// Get the folders containing a file that a user reaches, from its parents up to the ones
+/// in his keyrings
+///
+/// The folders of other users containing the file are not listed, neither are their
+/// grants.
+fn get_ancestors(
+    file_uid: &str,
+    user: &str,
+    root_keyring: i32,
+    conn: &mut SqliteConnection,
+) -> QueryResult<Vec<String>> {
+    // Folders reachable from the root keyring of the user and the ones of his groups
+    let mut reachable: HashSet<String> = HashSet::new();
+    let mut keyrings = vec![root_keyring];
+    keyrings.extend(get_group_keyrings(user, conn)?);
+
+    let now = signing::now_millis();
+    while let Some(keyring) = keyrings.pop() {
+        let folders: Vec<(String, Option<i32>)> = keys::table
+            .inner_join(files::table.on(files::id.eq(keys::target)))
+            .filter(keys::keyring_id.eq(keyring))
+            .filter(keys::expires_at.is_null().or(keys::expires_at.gt(now)))
+            .select((files::id, files::keyring_id))
+            .load(conn)?;
+
+        for (folder, folder_keyring) in folders {
+            if let Some(folder_keyring) = folder_keyring {
+                if reachable.insert(folder) {
+                    keyrings.push(folder_keyring);
+                }
+            }
         }
-    };
+    }
 
-    // Remove all occurence of the key
-    conn.interact({
-        let file_uid = revoke_share_request.file_uid.clone();
-        |conn| diesel::delete(keys::table.filter(keys::target.eq(file_uid))).execute(conn)
-    })
-    .await
-    .unwrap()
-    .unwrap();
+    let mut ancestors: Vec<String> = Vec::new();
+    let mut pending = vec![file_uid.to_string()];
 
-    // Get parent folder keyring
-    let parent_keyring = if let Some(parent_uid) = revoke_share_request.parent_uid {
-        let parent_folder: Folder = conn
-            .interact(move |conn| {
-                files::table
-                    .find(parent_uid)
-                    .inner_join(keyrings::table)
-                    .select((files::id, files::name, (keyrings::all_columns)))
-                    .first::<Folder>(conn)
-            })
-            .await
-            .unwrap()
-            .unwrap();
+    while let Some(target) = pending.pop() {
+        let parents: Vec<String> = keys::table
+            .inner_join(files::table.on(files::keyring_id.eq(keys::keyring_id.nullable())))
+            .filter(keys::target.eq(&target))
+            .select(files::id)
+            .load(conn)?;
 
-        parent_folder.keyring
-    } else {
-        user.keyring
-    };
+        for parent in parents {
+            if reachable.contains(&parent) && !ancestors.contains(&parent) {
+                ancestors.push(parent.clone());
+                pending.push(parent);
+            }
+        }
+    }
 
-    // Add new key
-    conn.interact({
-        let file_uid = revoke_share_request.file_uid.clone();
-        move |conn| {
-            diesel::insert_into(keys::table)
-                .values(NewKey {
-                    target: file_uid,
-                    key: revoke_share_request.encrypted_key,
-                    keyring_id: parent_keyring.id,
-                })
-                .execute(conn)
+    Ok(ancestors)
+}
+
+/// Get the users and groups holding a key to a file, in their root keyring or in one of
+/// their folders
+pub fn get_grants(file_uid: &str, conn: &mut SqliteConnection) -> QueryResult<Vec<Grant>> {
+    let now = signing::now_millis();
+    let grants: Vec<(i32, i32, Option<i64>)> = keys::table
+        .filter(keys::target.eq(file_uid))
+        .filter(keys::expires_at.is_null().or(keys::expires_at.gt(now)))
+        .select((keys::keyring_id, keys::permission, keys::expires_at))
+        .load(conn)?;
+
+    let mut resolved = Vec::new();
+    for (keyring_id, permission, expires_at) in grants {
+        // Groups share their names with the users
+        let group: Option<String> = groups::table
+            .filter(groups::keyring.eq(keyring_id))
+            .select(groups::name)
+            .first(conn)
+            .optional()?;
+
+        let (username, group) = match group {
+            Some(group) => (group, true),
+            None => match get_keyring_holder(keyring_id, conn)? {
+                Some(username) => (username, false),
+                // Pending invitations and trashed keys give no access
+                None => continue,
+            },
+        };
+
+        resolved.push(Grant {
+            username,
+            group,
+            permission: permission.into(),
+            expires_at,
+            keyring_id,
+        });
+    }
+
+    Ok(resolved)
+}
+
+/// Get the user holding a keyring: the one of a root keyring, or the one who created the
+/// folder of a folder keyring
+///
+/// Folders created before their owner was recorded are held by the user reaching them
+/// from his root keyring.
+fn get_keyring_holder(keyring_id: i32, conn: &mut SqliteConnection) -> QueryResult<Option<String>> {
+    let mut pending = vec![keyring_id];
+    let mut visited = HashSet::new();
+
+    while let Some(keyring_id) = pending.pop() {
+        if !visited.insert(keyring_id) {
+            continue;
         }
-    })
-    .await
-    .unwrap()
-    .unwrap();
 
-    // Update file data
-    let file_size = if revoke_share_request.file.is_some() {
-        revoke_share_request.file.as_ref().unwrap().len() as i32
-    } else {
-        0
+        let user: Option<String> = users::table
+            .filter(users::keyring.eq(keyring_id))
+            .select(users::username)
+            .first(conn)
+            .optional()?;
+
+        if user.is_some() {
+            return Ok(user);
+        }
+
+        let folder: Option<(String, Option<String>)> = files::table
+            .filter(files::keyring_id.eq(keyring_id))
+            .select((files::id, files::owner))
+            .first(conn)
+            .optional()?;
+
+        let Some((folder, owner)) = folder else {
+            continue;
+        };
+
+        if owner.is_some() {
+            return Ok(owner);
+        }
+
+        pending.extend(
+            keys::table
+                .filter(keys::target.eq(folder))
+                .select(keys::keyring_id)
+                .load::<i32>(conn)?,
+        );
+    }
+
+    Ok(None)
+}
+
+/// Check if a user owns a file: he created it, or he has owner access through his keyring
+pub fn is_owner(
+    user: &UserWithKeyring,
+    file_uid: &str,
+    conn: &mut SyncGuard<SqliteConnection>,
+) -> bool {
+    let Some(permission) = get_permission(&user.keyring, file_uid, conn) else {
+        return false;
     };
 
-    conn.interact(move |conn| {
-        diesel::update(files::table)
-            .filter(files::id.eq(revoke_share_request.file_uid))
-            .set((
-                files::name.eq(revoke_share_request.filename),
-                files::sz.eq(file_size),
-                files::data.eq(revoke_share_request.file),
-                files::mtime.eq(SystemTime::now()
-                    .duration_since(UNIX_EPOCH)
-                    .unwrap()
-                    .as_millis() as i64),
-            ))
-            .execute(conn)
-    })
-    .await
-    .unwrap()
-    .unwrap();
+    let owner: Option<Option<String>> = files::table
+        .find(file_uid)
+        .select(files::owner)
+        .first(conn.as_mut())
+        .optional()
+        .unwrap();
 
-    StatusCode::OK
+    permission == Permission::Owner || owner.flatten().as_ref() == Some(&user.username)
 }
 
-/// Check if a user has access to a given file or folder
-fn has_access(
+/// Check if a user has at least `permission` on a given file or folder
+pub fn has_access(
     keyring: &Keyring,
     file_uuid: String,
+    permission: Permission,
     conn: &mut SyncGuard<SqliteConnection>,
 ) -> bool {
+    get_permission(keyring, &file_uuid, conn).is_some_and(|level| level >= permission)
+}
+
+/// Get the access level of a user on a given file or folder, None if he has no access
+///
+/// A path of keys grants the lowest level along it, the file may be reached by several
+/// paths (e.g. shared twice), the highest one is kept.
+/// The root keyring of a user also reaches the keyrings of his groups.
+pub fn get_permission(
+    keyring: &Keyring,
+    file_uuid: &str,
+    conn: &mut SyncGuard<SqliteConnection>,
+) -> Option<Permission> {
+    let mut keyrings = vec![keyring.id];
+
+    let user: Option<String> = users::table
+        .filter(users::keyring.eq(keyring.id))
+        .select(users::username)
+        .first(conn.as_mut())
+        .optional()
+        .unwrap();
+
+    if let Some(user) = user {
+        keyrings.extend(get_group_keyrings(&user, conn.as_mut()).unwrap());
+    }
+
+    // Expired shares are ignored until they are removed
+    let now = signing::now_millis();
     let keys: Vec<Key> = keys::table
-        .filter(keys::keyring_id.eq(keyring.id))
+        .filter(keys::keyring_id.eq_any(keyrings))
+        .filter(keys::expires_at.is_null().or(keys::expires_at.gt(now)))
         .load::<Key>(conn.as_mut())
         .unwrap();
 
+    let mut permission = None;
+
     for key in keys {
+        let level = Permission::from(key.permission);
+
         if key.target == file_uuid {
-            return true;
+            permission = permission.max(Some(level));
         }
 
         let folder = files::table
@@ -667,21 +2535,73 @@ fn has_access(
             .first::<Folder>(conn.as_mut());
 
         if let Ok(folder) = folder {
-            if has_access(&folder.keyring, file_uuid.clone(), conn) {
-                return true;
+            if let Some(folder_level) = get_permission(&folder.keyring, file_uuid, conn) {
+                permission = permission.max(Some(level.min(folder_level)));
             }
         }
+
+        if permission == Some(Permission::Owner) {
+            break;
+        }
+    }
+
+    permission
+}
+
+/// Check if a user has at least `permission` on a given keyring, his root keyring
+/// or the one of a folder he has access to
+pub fn has_keyring_access(
+    keyring: &Keyring,
+    keyring_id: i32,
+    permission: Permission,
+    conn: &mut SyncGuard<SqliteConnection>,
+) -> bool {
+    if keyring.id == keyring_id {
+        return true;
     }
 
-    false
+    let folder: Option<String> = files::table
+        .filter(files::keyring_id.eq(keyring_id))
+        .select(files::id)
+        .first(conn.as_mut())
+        .optional()
+        .unwrap();
+
+    folder.is_some_and(|folder| has_access(keyring, folder, permission, conn))
+}
+
+/// Check if a keyring is the one of a folder or of a folder inside it, at any depth
+///
+/// The folder can't be put in such a keyring, it would contain itself.
+pub fn is_inside_folder(
+    file_uid: &str,
+    keyring_id: i32,
+    conn: &mut SyncGuard<SqliteConnection>,
+) -> bool {
+    let folder_keyring: Option<i32> = files::table
+        .find(file_uid)
+        .select(files::keyring_id)
+        .first(conn.as_mut())
+        .optional()
+        .unwrap()
+        .flatten();
+
+    folder_keyring.is_some_and(|folder_keyring| {
+        has_keyring_access(
+            &Keyring { id: folder_keyring },
+            keyring_id,
+            Permission::Read,
+            conn,
+        )
+    })
 }
 
 /// Allow a user to get his Keyring Tree
 pub async fn get_tree(
     Extension(user_session): Extension<Session>,
     State(app_state): State<AppState>,
-) -> Json<KeyringWithKeysAndFiles> {
-    Json(
+) -> Msg<KeyringWithKeysAndFiles> {
+    Msg(
         get_user_tree(user_session.user, app_state.pool)
             .await
             .unwrap(),
@@ -720,21 +2640,32 @@ pub async fn get_user_tree(user: String, pool: Pool) -> Option<KeyringWithKeysAn
     }
 }
 
-fn get_files_in_keyring(
+pub fn get_files_in_keyring(
     keyring: &Keyring,
     conn: &mut SyncGuard<SqliteConnection>,
 ) -> Vec<KeyWithFile> {
     let mut files: Vec<KeyWithFile> = Vec::new();
 
+    // Expired shares are ignored until they are removed
+    let now = signing::now_millis();
     let keys: Vec<Key> = keys::table
         .filter(keys::keyring_id.eq(keyring.id))
+        .filter(keys::expires_at.is_null().or(keys::expires_at.gt(now)))
         .load::<Key>(conn.as_mut())
         .unwrap();
 
     for key in keys {
+        let signing_keys = key.signing_keys();
+
         let file: FileWithoutData = files::table
             .find(key.target)
-            .select((files::id, files::name, files::keyring_id))
+            .select((
+                files::id,
+                files::name,
+                files::keyring_id,
+                files::owner,
+                files::expired_share_at,
+            ))
             .first::<FileWithoutData>(conn.as_mut())
             .unwrap();
 
@@ -756,14 +2687,333 @@ fn get_files_in_keyring(
             id: file.id,
             name: file.name,
             keyring: file_keyring,
+            owner: file.owner,
+            expired_share_at: file.expired_share_at,
         };
 
+        // The client checks the signature with the key of the signer in the key log
         files.push(KeyWithFile {
             file,
             key: key.key,
             keyring_id: keyring.id,
+            permission: key.permission.into(),
+            signing_keys,
+            signer: key.signer,
+            signature: key.signature,
         });
     }
 
     files
 }
+
+#[cfg(test)]
+mod tests {
+    use deadpool_diesel::{Manager, Runtime};
+    use diesel_migrations::MigrationHarness;
+
+    use super::*;
+
+    /// Run a test on a database in a temporary directory with every migration applied
+    async fn with_test_db(test: impl FnOnce(&mut SyncGuard<SqliteConnection>)) {
+        let dir = tempfile::tempdir().unwrap();
+        let db_url = dir.path().join("tsfs.db").to_str().unwrap().to_string();
+        let pool = Pool::builder(Manager::new(db_url, Runtime::Tokio1))
+            .build()
+            .unwrap();
+
+        let conn = pool.get().await.unwrap();
+        conn.interact(|conn| conn.run_pending_migrations(crate::MIGRATIONS).map(|_| ()))
+            .await
+            .unwrap()
+            .unwrap();
+
+        test(&mut conn.lock().unwrap());
+    }
+
+    fn new_keyring(conn: &mut SqliteConnection) -> Keyring {
+        diesel::insert_into(keyrings::table)
+            .values(NewKeyring { id: None })
+            .get_result(conn)
+            .unwrap()
+    }
+
+    fn new_user(username: &str, conn: &mut SqliteConnection) -> Keyring {
+        let keyring = new_keyring(conn);
+
+        diesel::insert_into(users::table)
+            .values((
+                users::username.eq(username),
+                users::password.eq(Vec::<u8>::new()),
+                users::pub_key.eq(Vec::<u8>::new()),
+                users::priv_key.eq(Vec::<u8>::new()),
+                users::keyring.eq(keyring.id),
+            ))
+            .execute(conn)
+            .unwrap();
+
+        keyring
+    }
+
+    /// Create a file, or a folder with its own keyring
+    fn new_file(id: &str, folder: bool, conn: &mut SqliteConnection) -> Option<Keyring> {
+        let keyring = folder.then(|| new_keyring(conn));
+
+        diesel::insert_into(files::table)
+            .values((
+                files::id.eq(id),
+                files::name.eq(id),
+                files::keyring_id.eq(keyring.as_ref().map(|keyring| keyring.id)),
+            ))
+            .execute(conn)
+            .unwrap();
+
+        keyring
+    }
+
+    fn new_key(
+        keyring: &Keyring,
+        target: &str,
+        permission: Permission,
+        expires_at: Option<i64>,
+        conn: &mut SqliteConnection,
+    ) {
+        diesel::insert_into(keys::table)
+            .values((
+                keys::target.eq(target),
+                keys::key.eq(Vec::<u8>::new()),
+                keys::keyring_id.eq(keyring.id),
+                keys::permission.eq(permission as i32),
+                keys::expires_at.eq(expires_at),
+            ))
+            .execute(conn)
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn permission_through_folders() {
+        with_test_db(|conn| {
+            let alice = new_user("alice", conn);
+            let bob = new_user("bob", conn);
+            let carol = new_user("carol", conn);
+
+            // alice/folder/file, the folder is shared read only with bob
+            let folder = new_file("folder", true, conn).unwrap();
+            new_file("file", false, conn);
+            new_key(&alice, "folder", Permission::Owner, None, conn);
+            new_key(&folder, "file", Permission::Owner, None, conn);
+            new_key(&bob, "folder", Permission::Read, None, conn);
+
+            assert_eq!(
+                get_permission(&alice, "file", conn),
+                Some(Permission::Owner)
+            );
+            assert_eq!(
+                get_permission(&alice, "folder", conn),
+                Some(Permission::Owner)
+            );
+
+            // The lowest level along the path is kept
+            assert_eq!(get_permission(&bob, "file", conn), Some(Permission::Read));
+            assert_eq!(get_permission(&bob, "folder", conn), Some(Permission::Read));
+
+            assert_eq!(get_permission(&carol, "file", conn), None);
+            assert_eq!(get_permission(&alice, "unknown", conn), None);
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn permission_highest_path() {
+        with_test_db(|conn| {
+            let bob = new_user("bob", conn);
+
+            // The file is reached through the folder and shared directly with more rights
+            let folder = new_file("folder", true, conn).unwrap();
+            new_file("file", false, conn);
+            new_key(&folder, "file", Permission::Owner, None, conn);
+            new_key(&bob, "folder", Permission::Read, None, conn);
+            new_key(&bob, "file", Permission::Write, None, conn);
+
+            assert_eq!(get_permission(&bob, "file", conn), Some(Permission::Write));
+            assert_eq!(get_permission(&bob, "folder", conn), Some(Permission::Read));
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn permission_ignores_expired_keys() {
+        with_test_db(|conn| {
+            let bob = new_user("bob", conn);
+            let now = signing::now_millis();
+
+            new_file("expired", false, conn);
+            new_file("valid", false, conn);
+            new_key(&bob, "expired", Permission::Write, Some(now - 1000), conn);
+            new_key(&bob, "valid", Permission::Write, Some(now + 60_000), conn);
+
+            assert_eq!(get_permission(&bob, "expired", conn), None);
+            assert_eq!(get_permission(&bob, "valid", conn), Some(Permission::Write));
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn permission_through_groups() {
+        with_test_db(|conn| {
+            let alice = new_user("alice", conn);
+            let bob = new_user("bob", conn);
+            let group = new_keyring(conn);
+
+            diesel::insert_into(groups::table)
+                .values(Group {
+                    name: "team".to_string(),
+                    owner: "alice".to_string(),
+                    pub_key: Vec::new(),
+                    keyring: group.id,
+                })
+                .execute(conn.as_mut())
+                .unwrap();
+            diesel::insert_into(group_members::table)
+                .values((
+                    group_members::group_name.eq("team"),
+                    group_members::username.eq("alice"),
+                    group_members::priv_key.eq(Vec::<u8>::new()),
+                ))
+                .execute(conn.as_mut())
+                .unwrap();
+
+            new_file("file", false, conn);
+            new_key(&group, "file", Permission::Write, None, conn);
+
+            assert_eq!(
+                get_permission(&alice, "file", conn),
+                Some(Permission::Write)
+            );
+            assert_eq!(get_permission(&bob, "file", conn), None);
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn folder_inside_itself() {
+        with_test_db(|conn| {
+            let alice = new_user("alice", conn);
+
+            // alice/folder/subfolder and alice/other
+            let folder = new_file("folder", true, conn).unwrap();
+            let subfolder = new_file("subfolder", true, conn).unwrap();
+            let other = new_file("other", true, conn).unwrap();
+            new_file("file", false, conn);
+            new_key(&alice, "folder", Permission::Owner, None, conn);
+            new_key(&folder, "subfolder", Permission::Owner, None, conn);
+            new_key(&alice, "other", Permission::Owner, None, conn);
+
+            assert!(is_inside_folder("folder", folder.id, conn));
+            assert!(is_inside_folder("folder", subfolder.id, conn));
+            assert!(!is_inside_folder("folder", alice.id, conn));
+            assert!(!is_inside_folder("folder", other.id, conn));
+            assert!(!is_inside_folder("subfolder", folder.id, conn));
+            assert!(!is_inside_folder("file", alice.id, conn));
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn revoke_renews_grants_in_folders() {
+        with_test_db(|conn| {
+            let alice = new_user("alice", conn);
+            let bob = new_user("bob", conn);
+            let carol = new_user("carol", conn);
+
+            // alice shares her file with bob, who accepted it in one of his folders, and
+            // with carol, who accepted it in root
+            let folder = new_file("folder", true, conn).unwrap();
+            diesel::update(files::table.find("folder"))
+                .set(files::owner.eq("bob"))
+                .execute(conn.as_mut())
+                .unwrap();
+            new_file("file", false, conn);
+            new_key(&alice, "file", Permission::Owner, None, conn);
+            new_key(&bob, "folder", Permission::Owner, None, conn);
+            new_key(&folder, "file", Permission::Write, None, conn);
+            new_key(&carol, "file", Permission::Read, None, conn);
+
+            let grants = get_grants("file", conn).unwrap();
+            let mut usernames: Vec<&str> = grants.iter().map(|grant| &*grant.username).collect();
+            usernames.sort();
+            assert_eq!(usernames, ["alice", "bob", "carol"]);
+
+            // Revoking carol keeps bob
+            let remaining = get_remaining_grants(grants, "carol", "alice").unwrap();
+            assert_eq!(remaining.len(), 1);
+            assert_eq!(remaining["bob"].permission, Permission::Write);
+
+            replace_keys(
+                NewKey {
+                    target: "file".to_string(),
+                    key: Vec::new(),
+                    keyring_id: alice.id,
+                    permission: Permission::Owner as i32,
+                    signing_keys: SigningKeys::default(),
+                    expires_at: None,
+                    entry_signature: EntrySignature::default(),
+                },
+                vec![RenewedGrant {
+                    username: "bob".to_string(),
+                    encrypted_key: Vec::new(),
+                    signing_keys: SigningKeys::default(),
+                    entry_signature: None,
+                }],
+                &remaining,
+                "alice",
+                conn,
+            )
+            .unwrap();
+
+            assert_eq!(
+                get_permission(&alice, "file", conn),
+                Some(Permission::Owner)
+            );
+            assert_eq!(get_permission(&carol, "file", conn), None);
+
+            // bob gets the new key back as an invitation from alice, at his level
+            let (permission, sender): (i32, String) = keys::table
+                .inner_join(invitations::table)
+                .inner_join(users::table.on(users::inbox.eq(keys::keyring_id.nullable())))
+                .filter(users::username.eq("bob"))
+                .filter(keys::target.eq("file"))
+                .select((keys::permission, invitations::sender))
+                .first(conn.as_mut())
+                .unwrap();
+            assert_eq!(Permission::from(permission), Permission::Write);
+            assert_eq!(sender, "alice");
+
+            // carol has no grant anymore
+            let grants = get_grants("file", conn).unwrap();
+            assert!(get_remaining_grants(grants, "carol", "alice").is_none());
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn grants_in_legacy_folders() {
+        with_test_db(|conn| {
+            let dave = new_user("dave", conn);
+
+            // A folder without owner is held by the user reaching it
+            let folder = new_file("folder", true, conn).unwrap();
+            let subfolder = new_file("subfolder", true, conn).unwrap();
+            new_file("file", false, conn);
+            new_key(&dave, "folder", Permission::Owner, None, conn);
+            new_key(&folder, "subfolder", Permission::Owner, None, conn);
+            new_key(&subfolder, "file", Permission::Read, None, conn);
+
+            let grants = get_grants("file", conn).unwrap();
+            assert_eq!(grants.len(), 1);
+            assert_eq!(grants[0].username, "dave");
+            assert!(!grants[0].group);
+            assert_eq!(grants[0].keyring_id, subfolder.id);
+        })
+        .await;
+    }
+}
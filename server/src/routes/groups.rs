@@ -0,0 +1,408 @@
+use std::collections::HashSet;
+
+use axum::{extract::State, Extension};
+use diesel::prelude::*;
+use hyper::StatusCode;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    codec::Msg,
+    db::{
+        schema::{files, group_members, groups, keyrings, keys, users},
+        EntrySignature, Group, GroupMember, Key, Keyring, KeyringWithKeysAndFiles, NewKeyring,
+        Session, SigningKeys,
+    },
+    routes::{files::get_files_in_keyring, signing, transparency},
+    AppState,
+};
+
+#[derive(Serialize)]
+pub struct GroupWithKeyring {
+    name: String,
+    /// User managing the members
+    owner: String,
+    members: Vec<String>,
+    /// Group private key, encrypted with the user pubkey
+    #[serde(with = "serde_bytes")]
+    priv_key: Vec<u8>,
+    /// Files shared with the group, their keys are encrypted with the group pubkey
+    keyring: KeyringWithKeysAndFiles,
+}
+
+/// Allow a user to list his groups, along with the files shared with them
+pub async fn get_groups(
+    Extension(user_session): Extension<Session>,
+    State(app_state): State<AppState>,
+) -> Msg<Vec<GroupWithKeyring>> {
+    let conn = app_state.pool.get().await.unwrap();
+
+    let memberships: Vec<(Group, Vec<u8>)> = conn
+        .interact(|conn| {
+            group_members::table
+                .inner_join(groups::table)
+                .filter(group_members::username.eq(user_session.user))
+                .select((Group::as_select(), group_members::priv_key))
+                .load(conn)
+        })
+        .await
+        .unwrap()
+        .unwrap();
+
+    let mut user_groups = Vec::new();
+
+    for (group, priv_key) in memberships {
+        let members: Vec<String> = conn
+            .interact({
+                let name = group.name.clone();
+                |conn| {
+                    group_members::table
+                        .filter(group_members::group_name.eq(name))
+                        .select(group_members::username)
+                        .load(conn)
+                }
+            })
+            .await
+            .unwrap()
+            .unwrap();
+
+        let keys = get_files_in_keyring(&Keyring { id: group.keyring }, &mut conn.lock().unwrap());
+
+        user_groups.push(GroupWithKeyring {
+            name: group.name,
+            owner: group.owner,
+            members,
+            priv_key,
+            keyring: KeyringWithKeysAndFiles {
+                id: group.keyring,
+                keys,
+            },
+        });
+    }
+
+    Msg(user_groups)
+}
+
+#[derive(Deserialize)]
+pub struct CreateGroupRequest {
+    /// Name of the group, it can't be the one of a user
+    name: String,
+    /// RSA public key of the group
+    #[serde(with = "serde_bytes")]
+    pub_key: Vec<u8>,
+    /// Group private key, encrypted with the user pubkey
+    #[serde(with = "serde_bytes")]
+    priv_key: Vec<u8>,
+}
+
+/// Allow a user to create a group, he manages its members
+///
+/// Groups and users share the same names, files are shared with a group like with a user.
+pub async fn create_group(
+    Extension(user_session): Extension<Session>,
+    State(app_state): State<AppState>,
+    Msg(create_request): Msg<CreateGroupRequest>,
+) -> StatusCode {
+    if create_request.name.is_empty() {
+        return StatusCode::BAD_REQUEST;
+    }
+
+    let conn = app_state.pool.get().await.unwrap();
+
+    let created = conn
+        .interact(move |conn| {
+            conn.immediate_transaction(|conn| {
+                if name_taken(&create_request.name, conn)? {
+                    return Ok(false);
+                }
+
+                let keyring: Keyring = diesel::insert_into(keyrings::table)
+                    .values(NewKeyring { id: None })
+                    .get_result(conn)?;
+
+                transparency::append_key(&create_request.name, &create_request.pub_key, conn)?;
+
+                diesel::insert_into(groups::table)
+                    .values(Group {
+                        name: create_request.name.clone(),
+                        owner: user_session.user.clone(),
+                        pub_key: create_request.pub_key,
+                        keyring: keyring.id,
+                    })
+                    .execute(conn)?;
+
+                diesel::insert_into(group_members::table)
+                    .values(GroupMember {
+                        group_name: create_request.name,
+                        username: user_session.user,
+                        priv_key: create_request.priv_key,
+                    })
+                    .execute(conn)?;
+
+                diesel::result::QueryResult::Ok(true)
+            })
+        })
+        .await
+        .unwrap()
+        .unwrap();
+
+    if !created {
+        return StatusCode::CONFLICT;
+    }
+
+    StatusCode::OK
+}
+
+#[derive(Deserialize)]
+pub struct AddMemberRequest {
+    group: String,
+    username: String,
+    /// Group private key, encrypted with the new member pubkey
+    #[serde(with = "serde_bytes")]
+    priv_key: Vec<u8>,
+}
+
+/// Allow the owner of a group to add a member, who gets access to everything shared
+/// with the group
+pub async fn add_member(
+    Extension(user_session): Extension<Session>,
+    State(app_state): State<AppState>,
+    Msg(add_request): Msg<AddMemberRequest>,
+) -> StatusCode {
+    let conn = app_state.pool.get().await.unwrap();
+
+    let group = conn
+        .interact({
+            let group = add_request.group.clone();
+            |conn| groups::table.find(group).first::<Group>(conn).optional()
+        })
+        .await
+        .unwrap()
+        .unwrap();
+
+    let Some(group) = group else {
+        return StatusCode::NOT_FOUND;
+    };
+
+    if group.owner != user_session.user {
+        return StatusCode::FORBIDDEN;
+    }
+
+    conn.interact(move |conn| {
+        conn.transaction(|conn| {
+            let user_exists: i64 = users::table
+                .find(&add_request.username)
+                .count()
+                .get_result(conn)?;
+
+            if user_exists == 0 {
+                return Ok(StatusCode::NOT_FOUND);
+            }
+
+            let already_member: i64 = group_members::table
+                .find((&group.name, &add_request.username))
+                .count()
+                .get_result(conn)?;
+
+            if already_member > 0 {
+                return Ok(StatusCode::CONFLICT);
+            }
+
+            diesel::insert_into(group_members::table)
+                .values(GroupMember {
+                    group_name: group.name,
+                    username: add_request.username,
+                    priv_key: add_request.priv_key,
+                })
+                .execute(conn)?;
+
+            diesel::result::QueryResult::Ok(StatusCode::OK)
+        })
+    })
+    .await
+    .unwrap()
+    .unwrap()
+}
+
+#[derive(Deserialize)]
+pub struct RemoveMemberRequest {
+    group: String,
+    /// Member to remove
+    username: String,
+    /// New RSA public key of the group, the removed member holds the previous private key
+    #[serde(with = "serde_bytes")]
+    pub_key: Vec<u8>,
+    /// New group private key of each remaining member
+    members: Vec<RenewedMember>,
+    /// Keys of the group keyring, encrypted with the new group pubkey
+    keys: Vec<RenewedGroupKey>,
+}
+
+#[derive(Deserialize)]
+pub struct RenewedMember {
+    username: String,
+    /// New group private key, encrypted with the member pubkey
+    #[serde(with = "serde_bytes")]
+    priv_key: Vec<u8>,
+}
+
+#[derive(Deserialize)]
+pub struct RenewedGroupKey {
+    /// File shared with the group
+    file_uid: String,
+    /// Symmetric key of the file, encrypted with the new group pubkey
+    #[serde(with = "serde_bytes")]
+    encrypted_key: Vec<u8>,
+    /// Signing private keys of the file, encrypted with the new group pubkey
+    signing_keys: SigningKeys,
+    /// Signature of the new encrypted key by the owner of the group
+    #[serde(with = "serde_bytes")]
+    entry_signature: Option<Vec<u8>>,
+}
+
+/// Allow the owner of a group to remove a member
+///
+/// The group keypair is rotated: the client sends the new group private key of each
+/// remaining member and every key of the group keyring encrypted with the new pubkey,
+/// all of them are swapped in a single transaction.
+/// The removed member may have kept the keys of the shared files, they are flagged like
+/// an expired share so their owners are asked to rotate them.
+pub async fn remove_member(
+    Extension(user_session): Extension<Session>,
+    State(app_state): State<AppState>,
+    Msg(remove_request): Msg<RemoveMemberRequest>,
+) -> StatusCode {
+    let conn = app_state.pool.get().await.unwrap();
+
+    let group = conn
+        .interact({
+            let group = remove_request.group.clone();
+            |conn| groups::table.find(group).first::<Group>(conn).optional()
+        })
+        .await
+        .unwrap()
+        .unwrap();
+
+    let Some(group) = group else {
+        return StatusCode::NOT_FOUND;
+    };
+
+    if group.owner != user_session.user {
+        return StatusCode::FORBIDDEN;
+    }
+
+    // The owner can't leave the group he manages
+    if remove_request.username == group.owner {
+        return StatusCode::BAD_REQUEST;
+    }
+
+    conn.interact(move |conn| {
+        conn.immediate_transaction(|conn| {
+            let members: HashSet<String> = group_members::table
+                .filter(group_members::group_name.eq(&group.name))
+                .select(group_members::username)
+                .load::<String>(conn)?
+                .into_iter()
+                .collect();
+
+            if !members.contains(&remove_request.username) {
+                return Ok(StatusCode::NOT_FOUND);
+            }
+
+            // Every remaining member must get the new group private key
+            let renewed_members: HashSet<&String> = remove_request
+                .members
+                .iter()
+                .map(|member| &member.username)
+                .collect();
+
+            if renewed_members.len() != remove_request.members.len()
+                || renewed_members.len() != members.len() - 1
+                || renewed_members.contains(&remove_request.username)
+                || !renewed_members
+                    .iter()
+                    .all(|member| members.contains(*member))
+            {
+                return Ok(StatusCode::BAD_REQUEST);
+            }
+
+            // And every key of the group keyring must be encrypted with the new pubkey
+            // Expired shares are ignored, they are removed anyway
+            let now = signing::now_millis();
+            let group_keys: Vec<Key> = keys::table
+                .filter(keys::keyring_id.eq(group.keyring))
+                .filter(keys::expires_at.is_null().or(keys::expires_at.gt(now)))
+                .load(conn)?;
+
+            let renewed_keys: HashSet<&String> = remove_request
+                .keys
+                .iter()
+                .map(|key| &key.file_uid)
+                .collect();
+
+            if renewed_keys.len() != remove_request.keys.len()
+                || renewed_keys.len() != group_keys.len()
+                || !group_keys
+                    .iter()
+                    .all(|key| renewed_keys.contains(&key.target))
+            {
+                return Ok(StatusCode::BAD_REQUEST);
+            }
+
+            transparency::append_key(&group.name, &remove_request.pub_key, conn)?;
+
+            diesel::update(groups::table.find(&group.name))
+                .set(groups::pub_key.eq(remove_request.pub_key))
+                .execute(conn)?;
+
+            diesel::delete(group_members::table.find((&group.name, &remove_request.username)))
+                .execute(conn)?;
+
+            for member in remove_request.members {
+                diesel::update(group_members::table.find((&group.name, &member.username)))
+                    .set(group_members::priv_key.eq(member.priv_key))
+                    .execute(conn)?;
+            }
+
+            for renewed_key in remove_request.keys {
+                let key = group_keys
+                    .iter()
+                    .find(|key| key.target == renewed_key.file_uid)
+                    .unwrap();
+
+                diesel::update(keys::table.find(key.id))
+                    .set((
+                        keys::key.eq(renewed_key.encrypted_key),
+                        renewed_key.signing_keys.restrict(key.permission.into()),
+                        EntrySignature::new(&group.owner, renewed_key.entry_signature),
+                    ))
+                    .execute(conn)?;
+
+                diesel::update(files::table.find(&key.target))
+                    .set(files::expired_share_at.eq(now))
+                    .execute(conn)?;
+            }
+
+            diesel::result::QueryResult::Ok(StatusCode::OK)
+        })
+    })
+    .await
+    .unwrap()
+    .unwrap()
+}
+
+/// Get the keyrings of the groups a user is a member of
+pub fn get_group_keyrings(user: &str, conn: &mut SqliteConnection) -> QueryResult<Vec<i32>> {
+    group_members::table
+        .inner_join(groups::table)
+        .filter(group_members::username.eq(user))
+        .select(groups::keyring)
+        .load(conn)
+}
+
+/// Check if a name is already used by a user or a group
+pub fn name_taken(name: &str, conn: &mut SqliteConnection) -> QueryResult<bool> {
+    let users: i64 = users::table.find(name).count().get_result(conn)?;
+    let groups: i64 = groups::table.find(name).count().get_result(conn)?;
+
+    Ok(users + groups > 0)
+}
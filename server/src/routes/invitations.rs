@@ -0,0 +1,292 @@
+use std::collections::HashMap;
+
+use axum::{extract::State, Extension};
+use diesel::prelude::*;
+use hyper::StatusCode;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    blobs,
+    codec::Msg,
+    db::{
+        schema::{invitations, keyrings, keys, users},
+        EntrySignature, Invitation, Key, KeyWithFile, Keyring, NewKeyring, Permission, Session,
+        SigningKeys, UserWithKeyring,
+    },
+    routes::{
+        files::{delete_key, get_files_in_keyring, has_keyring_access, is_inside_folder},
+        signing,
+    },
+    AppState,
+};
+
+#[derive(Serialize)]
+pub struct PendingInvitation {
+    /// Key of the shared file, encrypted with the user pubkey
+    key: KeyWithFile,
+    /// Access level granted by the share
+    permission: Permission,
+    /// User who shared the file
+    sender: String,
+    /// Time of the share, in millis
+    created_at: i64,
+}
+
+/// Allow a user to list the shares waiting for his answer
+pub async fn get_invitations(
+    Extension(user_session): Extension<Session>,
+    State(app_state): State<AppState>,
+) -> Msg<Vec<PendingInvitation>> {
+    let conn = app_state.pool.get().await.unwrap();
+
+    let inbox: Option<i32> = conn
+        .interact(|conn| {
+            users::table
+                .find(user_session.user)
+                .select(users::inbox)
+                .first(conn)
+        })
+        .await
+        .unwrap()
+        .unwrap();
+
+    // Inbox keyring is created on first share
+    let Some(inbox) = inbox else {
+        return Msg(Vec::new());
+    };
+
+    let pending: HashMap<String, (Key, Invitation)> = conn
+        .interact(move |conn| {
+            keys::table
+                .inner_join(invitations::table)
+                .filter(keys::keyring_id.eq(inbox))
+                .select((keys::all_columns, invitations::all_columns))
+                .load::<(Key, Invitation)>(conn)
+        })
+        .await
+        .unwrap()
+        .unwrap()
+        .into_iter()
+        .map(|(key, invitation)| (key.target.clone(), (key, invitation)))
+        .collect();
+
+    let files = get_files_in_keyring(&Keyring { id: inbox }, &mut conn.lock().unwrap());
+
+    Msg(files
+        .into_iter()
+        .filter_map(|key| {
+            let (pending_key, invitation) = pending.get(&key.file.id)?;
+
+            Some(PendingInvitation {
+                permission: pending_key.permission.into(),
+                sender: invitation.sender.clone(),
+                created_at: invitation.created_at,
+                key,
+            })
+        })
+        .collect())
+}
+
+#[derive(Deserialize)]
+pub struct AcceptInvitationRequest {
+    /// Shared file to accept
+    file_uid: String,
+    /// Keyring to put the file in, the user root keyring or one of his folders
+    keyring_id: i32,
+    /// Symmetric key of the file, encrypted for the destination keyring
+    #[serde(with = "serde_bytes")]
+    encrypted_key: Vec<u8>,
+    /// Signing private keys of the file, encrypted for the destination keyring
+    signing_keys: SigningKeys,
+    /// Signature of the entry by the user, when accepted in root
+    #[serde(with = "serde_bytes")]
+    entry_signature: Option<Vec<u8>>,
+    /// Challenge of the destination folder signed with its write key
+    #[serde(with = "serde_bytes")]
+    signature: Option<Vec<u8>>,
+}
+
+/// Allow a user to accept a share
+///
+/// The key is moved from the inbox keyring to the chosen keyring. The client encrypts
+/// it again for this keyring, with the user pubkey for his root or with the folder key.
+pub async fn accept_invitation(
+    Extension(user_session): Extension<Session>,
+    State(app_state): State<AppState>,
+    Msg(accept_request): Msg<AcceptInvitationRequest>,
+) -> StatusCode {
+    let conn = app_state.pool.get().await.unwrap();
+
+    // Get user keyring informations
+    let user: UserWithKeyring = conn
+        .interact(|conn| {
+            users::table
+                .find(user_session.user)
+                .inner_join(keyrings::table)
+                .select((
+                    users::username,
+                    users::pub_key,
+                    users::priv_key,
+                    (keyrings::all_columns),
+                ))
+                .first::<UserWithKeyring>(conn)
+        })
+        .await
+        .unwrap()
+        .unwrap();
+
+    // Check if user has access to the destination keyring
+    if !has_keyring_access(
+        &user.keyring,
+        accept_request.keyring_id,
+        Permission::Write,
+        &mut conn.lock().unwrap(),
+    ) || !signing::verify_keyring_challenge(
+        &app_state.challenges,
+        &user.username,
+        &user.keyring,
+        accept_request.keyring_id,
+        Permission::Write,
+        accept_request.signature.as_deref(),
+        &mut conn.lock().unwrap(),
+    ) {
+        return StatusCode::FORBIDDEN;
+    }
+
+    // A folder can't be accepted inside itself, its keyring or the keyring of a subfolder
+    if is_inside_folder(
+        &accept_request.file_uid,
+        accept_request.keyring_id,
+        &mut conn.lock().unwrap(),
+    ) {
+        return StatusCode::BAD_REQUEST;
+    }
+
+    let accepted = conn
+        .interact(move |conn| {
+            conn.transaction(|conn| {
+                let Some(key) = get_pending_key(&user.username, &accept_request.file_uid, conn)?
+                else {
+                    return Ok(false);
+                };
+
+                diesel::delete(invitations::table.find(key.id)).execute(conn)?;
+
+                // The granted level can't be raised by the recipient
+                diesel::update(keys::table.find(key.id))
+                    .set((
+                        keys::keyring_id.eq(accept_request.keyring_id),
+                        keys::key.eq(accept_request.encrypted_key),
+                        accept_request.signing_keys.restrict(key.permission.into()),
+                        EntrySignature::new(&user.username, accept_request.entry_signature),
+                    ))
+                    .execute(conn)
+                    .map(|updated| updated > 0)
+            })
+        })
+        .await
+        .unwrap()
+        .unwrap();
+
+    if !accepted {
+        return StatusCode::NOT_FOUND;
+    }
+
+    StatusCode::OK
+}
+
+#[derive(Deserialize)]
+pub struct DeclineInvitationRequest {
+    /// Shared file to decline
+    file_uid: String,
+}
+
+/// Allow a user to decline a share, the pending key is deleted
+pub async fn decline_invitation(
+    Extension(user_session): Extension<Session>,
+    State(app_state): State<AppState>,
+    Msg(decline_request): Msg<DeclineInvitationRequest>,
+) -> StatusCode {
+    let conn = app_state.pool.get().await.unwrap();
+
+    let declined = conn
+        .interact(move |conn| {
+            conn.transaction(|conn| {
+                let Some(key) =
+                    get_pending_key(&user_session.user, &decline_request.file_uid, conn)?
+                else {
+                    return Ok(None);
+                };
+
+                diesel::delete(invitations::table.find(key.id)).execute(conn)?;
+
+                delete_key(&key, conn).map(Some)
+            })
+        })
+        .await
+        .unwrap()
+        .unwrap();
+
+    let Some(reclaimed_blobs) = declined else {
+        return StatusCode::NOT_FOUND;
+    };
+
+    // Contents are removed once the transaction is committed
+    blobs::delete_blobs(&app_state.blob_store, reclaimed_blobs).await;
+
+    StatusCode::OK
+}
+
+/// Get the inbox keyring of a user, creating it if he has none yet
+pub fn get_or_create_inbox(user: &str, conn: &mut SqliteConnection) -> QueryResult<i32> {
+    let inbox: Option<i32> = users::table.find(user).select(users::inbox).first(conn)?;
+
+    if let Some(inbox) = inbox {
+        return Ok(inbox);
+    }
+
+    let inbox: Keyring = diesel::insert_into(keyrings::table)
+        .values(NewKeyring { id: None })
+        .get_result(conn)?;
+
+    diesel::update(users::table.find(user))
+        .set(users::inbox.eq(inbox.id))
+        .execute(conn)?;
+
+    Ok(inbox.id)
+}
+
+/// Delete the pending shares of a file, before its keys are deleted or replaced
+pub fn delete_invitations(file_uid: &str, conn: &mut SqliteConnection) -> QueryResult<usize> {
+    diesel::delete(
+        invitations::table.filter(
+            invitations::key_id.eq_any(
+                keys::table
+                    .filter(keys::target.eq(file_uid))
+                    .select(keys::id),
+            ),
+        ),
+    )
+    .execute(conn)
+}
+
+/// Get the pending key of a file in the inbox of a user
+fn get_pending_key(
+    user: &str,
+    file_uid: &str,
+    conn: &mut SqliteConnection,
+) -> QueryResult<Option<Key>> {
+    let inbox: Option<i32> = users::table.find(user).select(users::inbox).first(conn)?;
+
+    let Some(inbox) = inbox else {
+        return Ok(None);
+    };
+
+    keys::table
+        .inner_join(invitations::table)
+        .filter(keys::keyring_id.eq(inbox))
+        .filter(keys::target.eq(file_uid))
+        .select(keys::all_columns)
+        .first::<Key>(conn)
+        .optional()
+}
@@ -0,0 +1,347 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, RwLock},
+};
+
+use axum::{
+    extract::{Path, State},
+    Extension,
+};
+use base64::{engine::general_purpose, Engine as _};
+use diesel::prelude::*;
+use hyper::StatusCode;
+use rand::{rngs::OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    codec::Msg,
+    db::{
+        schema::{files, keyrings, keys, public_links, users},
+        File, Permission, PublicLink, Session, UserWithKeyring,
+    },
+    log,
+    routes::{files::has_access, signing},
+    AppState,
+};
+
+/// Time left to fetch the chunks of a download started from a link, in millis
+const DOWNLOAD_LIFETIME: i64 = 24 * 3600 * 1000;
+
+/// Downloads from links kept at once, no more can start until the oldest end
+const MAX_DOWNLOADS: usize = 10_000;
+
+/// Downloads started from the links by their token, along with the token of the link
+/// and their start time
+pub type Downloads = Arc<RwLock<HashMap<String, (String, i64)>>>;
+
+#[derive(Deserialize)]
+pub struct CreateLinkRequest {
+    /// File to publish, folders can't be
+    file_uid: String,
+    /// End of the link in millis, None if it doesn't expire
+    expires_at: Option<i64>,
+    /// Downloads allowed, None if unlimited
+    max_downloads: Option<i32>,
+    /// Challenge of the file signed with its read key
+    #[serde(with = "serde_bytes")]
+    signature: Option<Vec<u8>>,
+}
+
+#[derive(Serialize)]
+pub struct CreateLinkResponse {
+    /// Token of the link, the client adds the file key in the fragment of the URL
+    token: String,
+}
+
+/// Allow a user to create an anonymous download link for a file
+///
+/// The server only knows the token, the file key is carried in the fragment of the link
+/// which is never sent to the server. Anyone holding the link can download the file until
+/// it expires or runs out of downloads.
+pub async fn create_link(
+    Extension(user_session): Extension<Session>,
+    State(app_state): State<AppState>,
+    Msg(link_request): Msg<CreateLinkRequest>,
+) -> Result<Msg<CreateLinkResponse>, StatusCode> {
+    let conn = app_state.pool.get().await.unwrap();
+
+    // Get user keyring informations
+    let user: UserWithKeyring = conn
+        .interact(|conn| {
+            users::table
+                .find(user_session.user)
+                .inner_join(keyrings::table)
+                .select((
+                    users::username,
+                    users::pub_key,
+                    users::priv_key,
+                    (keyrings::all_columns),
+                ))
+                .first::<UserWithKeyring>(conn)
+        })
+        .await
+        .unwrap()
+        .unwrap();
+
+    // The user must be able to read the file, the link gives the same access
+    if !has_access(
+        &user.keyring,
+        link_request.file_uid.clone(),
+        Permission::Read,
+        &mut conn.lock().unwrap(),
+    ) || !signing::verify_challenge(
+        &app_state.challenges,
+        &user.username,
+        &link_request.file_uid,
+        Permission::Read,
+        link_request.signature.as_deref(),
+        &mut conn.lock().unwrap(),
+    ) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    if link_request
+        .expires_at
+        .is_some_and(|expires_at| expires_at <= signing::now_millis())
+        || link_request.max_downloads.is_some_and(|max| max <= 0)
+    {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let blob: Option<String> = conn
+        .interact({
+            let file_uid = link_request.file_uid.clone();
+            |conn| files::table.find(file_uid).select(files::blob).first(conn)
+        })
+        .await
+        .unwrap()
+        .unwrap();
+
+    // Folders have no content to download
+    if blob.is_none() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let mut token = vec![0u8; 32];
+    OsRng.fill_bytes(&mut token);
+    let token = general_purpose::URL_SAFE_NO_PAD.encode(token);
+
+    conn.interact({
+        let link = PublicLink {
+            token: token.clone(),
+            file_id: link_request.file_uid,
+            creator: user.username,
+            created_at: signing::now_millis(),
+            expires_at: link_request.expires_at,
+            max_downloads: link_request.max_downloads,
+            downloads: 0,
+        };
+
+        |conn| {
+            diesel::insert_into(public_links::table)
+                .values(link)
+                .execute(conn)
+        }
+    })
+    .await
+    .unwrap()
+    .unwrap();
+
+    Ok(Msg(CreateLinkResponse { token }))
+}
+
+#[derive(Serialize)]
+pub struct PublicFile {
+    /// Encrypted filename
+    name: String,
+    mtime: Option<i64>,
+    sz: Option<i64>,
+    chunks: Option<i32>,
+    /// Token of this download, the chunks are only served along with it
+    download: String,
+}
+
+/// Allow anyone holding a link to get the informations of its file, no session needed
+///
+/// Each call counts as a download and gives a new download token, the chunks are only
+/// served with it so they can't be fetched without counting a download.
+/// An expired or exhausted link is GONE, the server is UNAVAILABLE while it already keeps
+/// `MAX_DOWNLOADS` downloads since anonymous callers could otherwise grow its memory.
+pub async fn get_public_file(
+    State(app_state): State<AppState>,
+    Path(token): Path<String>,
+) -> Result<Msg<PublicFile>, StatusCode> {
+    {
+        let now = signing::now_millis();
+        let mut downloads = app_state.downloads.write().unwrap();
+
+        // Forget the downloads older than their lifetime
+        downloads.retain(|_, (_, started_at)| *started_at + DOWNLOAD_LIFETIME > now);
+
+        if downloads.len() >= MAX_DOWNLOADS {
+            return Err(StatusCode::SERVICE_UNAVAILABLE);
+        }
+    }
+
+    let conn = app_state.pool.get().await.unwrap();
+
+    let mut download = vec![0u8; 32];
+    OsRng.fill_bytes(&mut download);
+    let download = general_purpose::URL_SAFE_NO_PAD.encode(download);
+
+    let public_file = conn
+        .interact({
+            let token = token.clone();
+            let download = download.clone();
+
+            move |conn| {
+                conn.transaction(|conn| {
+                    let Some(link) = public_links::table
+                        .find(&token)
+                        .first::<PublicLink>(conn)
+                        .optional()?
+                    else {
+                        return Ok(Err(StatusCode::NOT_FOUND));
+                    };
+
+                    if is_expired(&link)
+                        || link.max_downloads.is_some_and(|max| link.downloads >= max)
+                    {
+                        return Ok(Err(StatusCode::GONE));
+                    }
+
+                    diesel::update(public_links::table.find(&token))
+                        .set(public_links::downloads.eq(public_links::downloads + 1))
+                        .execute(conn)?;
+
+                    let file: File = files::table.find(&link.file_id).first(conn)?;
+
+                    diesel::result::QueryResult::Ok(Ok(Msg(PublicFile {
+                        name: file.name,
+                        mtime: file.mtime,
+                        sz: file.sz,
+                        chunks: file.chunks,
+                        download,
+                    })))
+                })
+            }
+        })
+        .await
+        .unwrap()
+        .unwrap()?;
+
+    app_state
+        .downloads
+        .write()
+        .unwrap()
+        .insert(download, (token, signing::now_millis()));
+
+    Ok(public_file)
+}
+
+/// Allow anyone holding a link to download an encrypted chunk of its file, along with
+/// the token of a download started with `get_public_file`
+pub async fn download_public_chunk(
+    State(app_state): State<AppState>,
+    Path((token, download, index)): Path<(String, String, i32)>,
+) -> Result<Vec<u8>, StatusCode> {
+    let started = app_state
+        .downloads
+        .read()
+        .unwrap()
+        .get(&download)
+        .is_some_and(|(link, started_at)| {
+            *link == token && *started_at + DOWNLOAD_LIFETIME > signing::now_millis()
+        });
+
+    if !started {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let conn = app_state.pool.get().await.unwrap();
+
+    let link: Option<(PublicLink, Option<String>)> = conn
+        .interact(|conn| {
+            public_links::table
+                .find(token)
+                .inner_join(files::table)
+                .select((PublicLink::as_select(), files::blob))
+                .first(conn)
+                .optional()
+        })
+        .await
+        .unwrap()
+        .unwrap();
+
+    let Some((link, blob)) = link else {
+        return Err(StatusCode::NOT_FOUND);
+    };
+
+    // The download was counted when it started, only the end of the link stops it
+    if is_expired(&link) {
+        return Err(StatusCode::GONE);
+    }
+
+    let Some(blob) = blob else {
+        return Err(StatusCode::NOT_FOUND);
+    };
+
+    match app_state.blob_store.get_chunk(&blob, index).await {
+        Ok(Some(chunk)) => Ok(chunk.to_vec()),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+
+        Err(e) => {
+            log::error(&format!("Can't get chunk of blob {}: {}", blob, e));
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+/// Delete the links created by a user to a file and to everything inside, once it is trashed
+pub fn delete_user_links(
+    username: &str,
+    file_uid: &str,
+    conn: &mut SqliteConnection,
+) -> QueryResult<()> {
+    let mut pending = vec![file_uid.to_string()];
+    let mut seen = HashSet::new();
+
+    while let Some(file_uid) = pending.pop() {
+        if !seen.insert(file_uid.clone()) {
+            continue;
+        }
+
+        diesel::delete(
+            public_links::table
+                .filter(public_links::file_id.eq(&file_uid))
+                .filter(public_links::creator.eq(username)),
+        )
+        .execute(conn)?;
+
+        let keyring_id: Option<i32> = files::table
+            .find(&file_uid)
+            .select(files::keyring_id)
+            .first(conn)?;
+
+        if let Some(keyring_id) = keyring_id {
+            pending.extend(
+                keys::table
+                    .filter(keys::keyring_id.eq(keyring_id))
+                    .select(keys::target)
+                    .load::<String>(conn)?,
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Delete the links of a file, their key can't decrypt it anymore once it is rotated
+pub fn delete_links(file_uid: &str, conn: &mut SqliteConnection) -> QueryResult<usize> {
+    diesel::delete(public_links::table.filter(public_links::file_id.eq(file_uid))).execute(conn)
+}
+
+fn is_expired(link: &PublicLink) -> bool {
+    link.expires_at
+        .is_some_and(|expires_at| expires_at <= signing::now_millis())
+}
@@ -4,7 +4,7 @@ use axum::{
     extract::{Request, State},
     middleware::Next,
     response::Response,
-    routing::{get, post, delete},
+    routing::{delete, get, post, put},
     Router,
 };
 use diesel::prelude::*;
@@ -16,7 +16,17 @@ use crate::{
 };
 
 pub mod auth;
+pub mod expiration;
 pub mod files;
+pub mod groups;
+pub mod invitations;
+pub mod links;
+pub mod quota;
+pub mod signing;
+pub mod transparency;
+pub mod trash;
+pub mod users;
+pub mod versions;
 
 pub fn authenticated_router(state: AppState) -> Router<AppState> {
     Router::new()
@@ -33,13 +43,55 @@ pub fn authenticated_router(state: AppState) -> Router<AppState> {
             post(auth::change_password_finish),
         )
         .route("/pubkey/:user", get(auth::get_user_public_key))
+        .route("/users", get(users::search_users))
         .route("/keyring", get(files::get_tree))
-        .route("/file/upload", post(files::upload_file))
-        .route("/file/download", get(files::download_file))
+        .route("/file/upload", post(files::begin_upload))
+        .route(
+            "/file/upload/:upload_id/chunk/:index",
+            put(files::upload_chunk),
+        )
+        .route("/file/upload/:upload_id/commit", post(files::commit_upload))
+        .route("/file/download/:file_uid", get(files::download_file))
+        .route("/file/download/:file_uid/:index", get(files::download_chunk))
+        .route("/file/:file_uid/challenge", post(signing::get_challenge))
+        .route("/file/:file_uid/access", get(files::get_access))
+        .route("/file/:file_uid/versions", get(versions::list_versions))
+        .route(
+            "/file/:file_uid/versions/:version/:index",
+            get(versions::download_version_chunk),
+        )
+        .route(
+            "/file/:file_uid/versions/:version/restore",
+            post(versions::restore_version),
+        )
         .route("/file/delete", delete(files::delete_file))
+        .route("/file/move", post(files::move_file))
+        .route("/file/rename", post(files::rename_file))
+        .route("/file/copy", post(files::copy_file))
         .route("/file/share", post(files::share_file))
         .route("/file/unshare", post(files::unshare_file))
+        .route("/file/link", post(links::create_link))
         .route("/folder/create", post(files::create_folder))
+        .route("/quota", get(quota::get_quota))
+        .route("/trash", get(trash::get_trash).delete(trash::empty_trash))
+        .route("/trash/restore", post(trash::restore_file))
+        .route("/invitations", get(invitations::get_invitations))
+        .route("/invitations/accept", post(invitations::accept_invitation))
+        .route(
+            "/invitations/decline",
+            post(invitations::decline_invitation),
+        )
+        .route("/groups", get(groups::get_groups))
+        .route("/groups/create", post(groups::create_group))
+        .route("/groups/add", post(groups::add_member))
+        .route("/groups/remove", post(groups::remove_member))
+        .route("/log/key", get(transparency::get_log_key))
+        .route("/log/head", get(transparency::get_tree_head))
+        .route("/log/proof/:user", get(transparency::get_inclusion_proof))
+        .route(
+            "/log/consistency/:from/:to",
+            get(transparency::get_consistency_proof),
+        )
         .route_layer(axum::middleware::from_fn_with_state(
             state.clone(),
             auth_middleware,
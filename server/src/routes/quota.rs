@@ -0,0 +1,104 @@
+use axum::{extract::State, Extension};
+use deadpool_diesel::{sqlite::Pool, SyncGuard};
+use diesel::{dsl::sum, prelude::*};
+use serde::Serialize;
+
+use crate::{
+    codec::Msg,
+    db::{
+        schema::{chunks, file_versions, files, uploads, users},
+        Session,
+    },
+    AppState,
+};
+
+/// Quota of the users without override, in bytes
+pub const DEFAULT_QUOTA: i64 = 1024 * 1024 * 1024;
+
+#[derive(Serialize)]
+pub struct QuotaResponse {
+    /// Bytes used by the user
+    used: i64,
+    /// Bytes the user is allowed to use
+    limit: i64,
+}
+
+/// Allow a user to get his storage usage and quota
+pub async fn get_quota(
+    Extension(user_session): Extension<Session>,
+    State(app_state): State<AppState>,
+) -> Msg<QuotaResponse> {
+    let conn = app_state.pool.get().await.unwrap();
+
+    let used = get_usage(&user_session.user, &mut conn.lock().unwrap());
+    let limit = get_limit(
+        &user_session.user,
+        app_state.default_quota,
+        &mut conn.lock().unwrap(),
+    );
+
+    Msg(QuotaResponse { used, limit })
+}
+
+/// Get the bytes used by a user
+///
+/// The ciphertext size of the owned files and their versions is charged, along with
+/// the chunks of the pending uploads of the user.
+pub fn get_usage(user: &str, conn: &mut SyncGuard<SqliteConnection>) -> i64 {
+    let owned_files = files::table.filter(files::owner.eq(user)).select(files::id);
+    let owned_blobs = files::table
+        .filter(files::owner.eq(user))
+        .select(files::blob);
+    let version_blobs = file_versions::table
+        .filter(file_versions::file_id.eq_any(owned_files))
+        .select(file_versions::blob);
+    let pending_blobs = uploads::table
+        .filter(uploads::user.eq(user))
+        .select(uploads::id);
+
+    chunks::table
+        .filter(
+            chunks::blob
+                .nullable()
+                .eq_any(owned_blobs)
+                .or(chunks::blob.eq_any(version_blobs))
+                .or(chunks::blob.eq_any(pending_blobs)),
+        )
+        .select(sum(chunks::sz))
+        .first::<Option<i64>>(conn.as_mut())
+        .unwrap()
+        .unwrap_or(0)
+}
+
+/// Get the quota of a user, his override or the default one
+pub fn get_limit(user: &str, default_quota: i64, conn: &mut SyncGuard<SqliteConnection>) -> i64 {
+    users::table
+        .find(user)
+        .select(users::quota)
+        .first::<Option<i64>>(conn.as_mut())
+        .unwrap()
+        .unwrap_or(default_quota)
+}
+
+/// Check if `additional` bytes can be charged to a user
+pub fn fits_quota(
+    user: &str,
+    additional: i64,
+    default_quota: i64,
+    conn: &mut SyncGuard<SqliteConnection>,
+) -> bool {
+    additional <= 0 || get_usage(user, conn) + additional <= get_limit(user, default_quota, conn)
+}
+
+/// Override the quota of a user, None to use the default one
+pub async fn set_user_quota(pool: &Pool, user: String, quota: Option<i64>) -> QueryResult<usize> {
+    let conn = pool.get().await.unwrap();
+
+    conn.interact(move |conn| {
+        diesel::update(users::table.find(user))
+            .set(users::quota.eq(quota))
+            .execute(conn)
+    })
+    .await
+    .unwrap()
+}
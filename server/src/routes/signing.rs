@@ -0,0 +1,247 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+    extract::{Path, State},
+    Extension,
+};
+use deadpool_diesel::SyncGuard;
+use diesel::prelude::*;
+use ed25519_dalek::{Signature, VerifyingKey};
+use hyper::StatusCode;
+use rand::{rngs::OsRng, RngCore};
+use serde::Serialize;
+
+use crate::{
+    codec::Msg,
+    db::{
+        schema::{files, keyrings, users},
+        File, Keyring, Permission, PublicKeys, Session, UserWithKeyring,
+    },
+    routes::files::has_access,
+    AppState,
+};
+
+/// Time left to sign a challenge, in millis
+const CHALLENGE_LIFETIME: i64 = 5 * 60 * 1000;
+
+/// Pending challenges by user and file, along with their creation time
+pub type Challenges = Arc<RwLock<HashMap<(String, String), (Vec<u8>, i64)>>>;
+
+#[derive(Serialize)]
+pub struct ChallengeResponse {
+    /// Random bytes to sign with a signing private key of the file
+    #[serde(with = "serde_bytes")]
+    challenge: Vec<u8>,
+}
+
+/// Allow a user to get a challenge to sign for a file or folder
+///
+/// The signed challenge is sent along with a write, a delete or a share to prove the user
+/// holds the signing private key of the required access level. Keyring access alone is
+/// not enough for files having signing keys.
+/// A challenge can only be used once, a new one replaces the previous one of the file.
+pub async fn get_challenge(
+    Extension(user_session): Extension<Session>,
+    State(app_state): State<AppState>,
+    Path(file_uid): Path<String>,
+) -> Result<Msg<ChallengeResponse>, StatusCode> {
+    let conn = app_state.pool.get().await.unwrap();
+
+    // Get user keyring informations
+    let user: UserWithKeyring = conn
+        .interact(|conn| {
+            users::table
+                .find(user_session.user)
+                .inner_join(keyrings::table)
+                .select((
+                    users::username,
+                    users::pub_key,
+                    users::priv_key,
+                    (keyrings::all_columns),
+                ))
+                .first::<UserWithKeyring>(conn)
+        })
+        .await
+        .unwrap()
+        .unwrap();
+
+    // Check if user has access to the file
+    if !has_access(
+        &user.keyring,
+        file_uid.clone(),
+        Permission::Read,
+        &mut conn.lock().unwrap(),
+    ) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let mut challenge = vec![0u8; 32];
+    OsRng.fill_bytes(&mut challenge);
+
+    let now = now_millis();
+    let mut challenges = app_state.challenges.write().unwrap();
+
+    // Forget the challenges that were never signed
+    challenges.retain(|_, (_, created_at)| *created_at + CHALLENGE_LIFETIME > now);
+    challenges.insert((user.username, file_uid), (challenge.clone(), now));
+
+    Ok(Msg(ChallengeResponse { challenge }))
+}
+
+/// Check the challenge of a file signed by a user with the private key of `permission`
+///
+/// The challenge is consumed even if the signature is wrong.
+/// Files created before signing keys have no public keys, they only rely on the keyring
+/// access checked by the caller. New files can't be created without them, see
+/// `check_public_keys`.
+pub fn verify_challenge(
+    challenges: &Challenges,
+    user: &str,
+    file_uid: &str,
+    permission: Permission,
+    signature: Option<&[u8]>,
+    conn: &mut SyncGuard<SqliteConnection>,
+) -> bool {
+    let Some(file) = files::table
+        .find(file_uid)
+        .first::<File>(conn.as_mut())
+        .optional()
+        .unwrap()
+    else {
+        return false;
+    };
+
+    let Some(public_key) = file.public_key(permission) else {
+        return true;
+    };
+
+    let challenge = challenges
+        .write()
+        .unwrap()
+        .remove(&(user.to_string(), file_uid.to_string()));
+
+    let Some((challenge, created_at)) = challenge else {
+        return false;
+    };
+
+    if created_at + CHALLENGE_LIFETIME <= now_millis() {
+        return false;
+    }
+
+    let (Ok(public_key), Some(Ok(signature))) = (
+        VerifyingKey::try_from(public_key.as_slice()),
+        signature.map(Signature::from_slice),
+    ) else {
+        return false;
+    };
+
+    public_key.verify_strict(&challenge, &signature).is_ok()
+}
+
+/// Check the challenge signed for the folder owning a keyring, see `verify_challenge`
+///
+/// The user root keyring has no folder and needs no signature.
+pub fn verify_keyring_challenge(
+    challenges: &Challenges,
+    user: &str,
+    root_keyring: &Keyring,
+    keyring_id: i32,
+    permission: Permission,
+    signature: Option<&[u8]>,
+    conn: &mut SyncGuard<SqliteConnection>,
+) -> bool {
+    if root_keyring.id == keyring_id {
+        return true;
+    }
+
+    let folder: Option<String> = files::table
+        .filter(files::keyring_id.eq(keyring_id))
+        .select(files::id)
+        .first(conn.as_mut())
+        .optional()
+        .unwrap();
+
+    folder.is_some_and(|folder| {
+        verify_challenge(challenges, user, &folder, permission, signature, conn)
+    })
+}
+
+/// Check the signing public keys given for a new file or folder in `parent`, or in root
+///
+/// New items must have a valid key for each access level. Only the items created in a
+/// folder without signing keys may have none, like their parent.
+pub fn check_public_keys(
+    public_keys: &PublicKeys,
+    parent: Option<&str>,
+    conn: &mut SyncGuard<SqliteConnection>,
+) -> bool {
+    if is_complete(public_keys) {
+        return true;
+    }
+
+    if *public_keys != PublicKeys::default() {
+        return false;
+    }
+
+    let Some(parent) = parent else {
+        return false;
+    };
+
+    files::table
+        .find(parent)
+        .first::<File>(conn.as_mut())
+        .optional()
+        .unwrap()
+        .is_some_and(|folder| folder.public_key(Permission::Owner).is_none())
+}
+
+/// Check the new signing public keys of an item inside a folder whose keys are rotated
+/// along with it, see `check_public_keys`
+///
+/// `folder_keys` are the new public keys of the folder.
+pub fn check_rotated_public_keys(public_keys: &PublicKeys, folder_keys: &PublicKeys) -> bool {
+    is_complete(public_keys)
+        || (*public_keys == PublicKeys::default() && *folder_keys == PublicKeys::default())
+}
+
+/// Whether there is a valid key for each access level
+fn is_complete(public_keys: &PublicKeys) -> bool {
+    [&public_keys.owner, &public_keys.write, &public_keys.read]
+        .into_iter()
+        .all(|key| {
+            key.as_deref()
+                .is_some_and(|key| VerifyingKey::try_from(key).is_ok())
+        })
+}
+
+/// Check the signing public keys given for a new item of a keyring, see `check_public_keys`
+pub fn check_keyring_public_keys(
+    public_keys: &PublicKeys,
+    root_keyring: &Keyring,
+    keyring_id: i32,
+    conn: &mut SyncGuard<SqliteConnection>,
+) -> bool {
+    if root_keyring.id == keyring_id {
+        return check_public_keys(public_keys, None, conn);
+    }
+
+    let folder: Option<String> = files::table
+        .filter(files::keyring_id.eq(keyring_id))
+        .select(files::id)
+        .first(conn.as_mut())
+        .optional()
+        .unwrap();
+
+    folder.is_some_and(|folder| check_public_keys(public_keys, Some(&folder), conn))
+}
+
+pub fn now_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as i64
+}
@@ -0,0 +1,394 @@
+use axum::extract::{Path, State};
+use deadpool_diesel::sqlite::Pool;
+use diesel::prelude::*;
+use ed25519_dalek::{Signer, SigningKey};
+use hyper::StatusCode;
+use serde::Serialize;
+use serde_bytes::ByteBuf;
+use sha2::{Digest, Sha256};
+use std::sync::{Arc, RwLock};
+
+use crate::{
+    codec::Msg,
+    db::{
+        schema::{groups, key_log, users},
+        LogEntry,
+    },
+    log,
+    routes::signing,
+    AppState,
+};
+
+type Hash = [u8; 32];
+
+/// Merkle tree of the log kept in memory, see `update_tree`
+pub type LogTree = Arc<RwLock<MerkleTree>>;
+
+/// Hashes of the complete subtrees of the log, the other nodes are computed from them
+#[derive(Default)]
+pub struct MerkleTree {
+    /// Hashes of the subtrees of 2^level leaves, from left to right
+    levels: Vec<Vec<Hash>>,
+}
+
+/// Tree head signed by the server, the root of the log at a given size
+#[derive(Serialize)]
+pub struct SignedTreeHead {
+    /// Number of leaves in the log
+    size: u64,
+    #[serde(with = "serde_bytes")]
+    root_hash: Vec<u8>,
+    /// Time of the signature, in millis
+    timestamp: i64,
+    /// Signature of the head with the log signing key
+    #[serde(with = "serde_bytes")]
+    signature: Vec<u8>,
+}
+
+#[derive(Serialize)]
+pub struct InclusionProof {
+    /// Position of the latest leaf of the user
+    index: u64,
+    /// Public key logged in this leaf
+    #[serde(with = "serde_bytes")]
+    pub_key: Vec<u8>,
+    head: SignedTreeHead,
+    /// Hashes from the leaf to the root of the head
+    path: Vec<ByteBuf>,
+}
+
+#[derive(Serialize)]
+pub struct ConsistencyProof {
+    /// Hashes proving the tree of the second size extends the one of the first size
+    path: Vec<ByteBuf>,
+}
+
+/// Allow a user to get the public key signing the tree heads
+pub async fn get_log_key(State(app_state): State<AppState>) -> Msg<ByteBuf> {
+    Msg(ByteBuf::from(
+        app_state.log_key.verifying_key().to_bytes().to_vec(),
+    ))
+}
+
+/// Allow a user to get the current signed tree head of the log
+pub async fn get_tree_head(State(app_state): State<AppState>) -> Msg<SignedTreeHead> {
+    let conn = app_state.pool.get().await.unwrap();
+
+    let size = conn
+        .interact({
+            let log_tree = app_state.log_tree.clone();
+            move |conn| update_tree(&log_tree, conn)
+        })
+        .await
+        .unwrap()
+        .unwrap();
+
+    let tree = app_state.log_tree.read().unwrap();
+
+    Msg(sign_head(&app_state.log_key, &tree, size))
+}
+
+/// Allow a user to prove the latest key of a user or a group is in the log
+///
+/// The client checks the key given by `/pubkey/:user` is this one before using it.
+/// Unknown names get NOT_FOUND, so the key log tells which users and groups exist.
+pub async fn get_inclusion_proof(
+    State(app_state): State<AppState>,
+    Path(username): Path<String>,
+) -> Result<Msg<InclusionProof>, StatusCode> {
+    let conn = app_state.pool.get().await.unwrap();
+
+    // The proof is given at the size seen along with the entry, it's the latest one there
+    let (entry, size) = conn
+        .interact({
+            let log_tree = app_state.log_tree.clone();
+
+            move |conn| {
+                conn.transaction(|conn| {
+                    let entry = key_log::table
+                        .filter(key_log::username.eq(username))
+                        .order(key_log::idx.desc())
+                        .first::<LogEntry>(conn)
+                        .optional()?;
+
+                    diesel::result::QueryResult::Ok((entry, update_tree(&log_tree, conn)?))
+                })
+            }
+        })
+        .await
+        .unwrap()
+        .unwrap();
+
+    let Some(entry) = entry else {
+        return Err(StatusCode::NOT_FOUND);
+    };
+
+    let tree = app_state.log_tree.read().unwrap();
+    let path = inclusion_path(&tree, entry.idx as usize, 0, size);
+
+    Ok(Msg(InclusionProof {
+        index: entry.idx as u64,
+        pub_key: entry.pub_key,
+        head: sign_head(&app_state.log_key, &tree, size),
+        path: to_bufs(path),
+    }))
+}
+
+/// Allow a user to prove the log at a size extends the log he saw at a previous size
+///
+/// The client keeps the last head it saw and checks the new heads extend it, a log
+/// rewriting a logged key can't give a valid proof.
+pub async fn get_consistency_proof(
+    State(app_state): State<AppState>,
+    Path((from, to)): Path<(u64, u64)>,
+) -> Result<Msg<ConsistencyProof>, StatusCode> {
+    let conn = app_state.pool.get().await.unwrap();
+
+    let size = conn
+        .interact({
+            let log_tree = app_state.log_tree.clone();
+            move |conn| update_tree(&log_tree, conn)
+        })
+        .await
+        .unwrap()
+        .unwrap();
+
+    if from > to || to > size as u64 {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    // Every log extends the empty one
+    let path = if from == 0 {
+        Vec::new()
+    } else {
+        let tree = app_state.log_tree.read().unwrap();
+        subproof(&tree, from as usize, 0, to as usize, true)
+    };
+
+    Ok(Msg(ConsistencyProof {
+        path: to_bufs(path),
+    }))
+}
+
+/// Append a new public key of a user or a group to the log
+///
+/// The position is the size of the log, the caller runs it in an immediate transaction so
+/// concurrent appends can't read the same size.
+pub fn append_key(username: &str, pub_key: &[u8], conn: &mut SqliteConnection) -> QueryResult<()> {
+    let size: i64 = key_log::table.count().get_result(conn)?;
+
+    diesel::insert_into(key_log::table)
+        .values(LogEntry {
+            idx: size as i32,
+            username: username.to_string(),
+            pub_key: pub_key.to_vec(),
+            leaf_hash: leaf_hash(username, pub_key).to_vec(),
+            created_at: signing::now_millis(),
+        })
+        .execute(conn)?;
+
+    Ok(())
+}
+
+/// Append the keys missing from the log, the ones registered before it existed
+pub async fn log_missing_keys(pool: &Pool) {
+    let conn = pool.get().await.unwrap();
+
+    let appended = conn
+        .interact(|conn| {
+            conn.immediate_transaction(|conn| {
+                let mut keys: Vec<(String, Vec<u8>)> = users::table
+                    .select((users::username, users::pub_key))
+                    .load(conn)?;
+                keys.extend(
+                    groups::table
+                        .select((groups::name, groups::pub_key))
+                        .load::<(String, Vec<u8>)>(conn)?,
+                );
+
+                let mut appended = 0;
+                for (username, pub_key) in keys {
+                    let logged: Option<Vec<u8>> = key_log::table
+                        .filter(key_log::username.eq(&username))
+                        .order(key_log::idx.desc())
+                        .select(key_log::pub_key)
+                        .first(conn)
+                        .optional()?;
+
+                    if logged.as_ref() != Some(&pub_key) {
+                        append_key(&username, &pub_key, conn)?;
+                        appended += 1;
+                    }
+                }
+
+                diesel::result::QueryResult::Ok(appended)
+            })
+        })
+        .await
+        .unwrap()
+        .unwrap();
+
+    if appended > 0 {
+        log::info(&format!("{} keys appended to the key log", appended));
+    }
+}
+
+/// Add the leaves appended to the log since the last request to the tree, return the size
+/// of the log
+///
+/// The log is append-only, the leaves already in the tree are never loaded again.
+fn update_tree(log_tree: &LogTree, conn: &mut SqliteConnection) -> QueryResult<usize> {
+    let known = log_tree.read().unwrap().size();
+
+    let leaves: Vec<(i32, Vec<u8>)> = key_log::table
+        .filter(key_log::idx.ge(known as i32))
+        .order(key_log::idx)
+        .select((key_log::idx, key_log::leaf_hash))
+        .load(conn)?;
+
+    let size = known + leaves.len();
+
+    let mut tree = log_tree.write().unwrap();
+    for (idx, leaf) in leaves {
+        // Another request may have added it in the meantime
+        if idx as usize == tree.size() {
+            tree.push(leaf.try_into().unwrap());
+        }
+    }
+
+    Ok(size)
+}
+
+fn sign_head(log_key: &SigningKey, tree: &MerkleTree, size: usize) -> SignedTreeHead {
+    let root_hash = tree.root_hash(0, size);
+    let size = size as u64;
+    let timestamp = signing::now_millis();
+
+    let mut payload = b"tsfs tree head".to_vec();
+    payload.extend_from_slice(&size.to_be_bytes());
+    payload.extend_from_slice(&timestamp.to_be_bytes());
+    payload.extend_from_slice(&root_hash);
+
+    SignedTreeHead {
+        size,
+        root_hash: root_hash.to_vec(),
+        timestamp,
+        signature: log_key.sign(&payload).to_bytes().to_vec(),
+    }
+}
+
+fn to_bufs(hashes: Vec<Hash>) -> Vec<ByteBuf> {
+    hashes
+        .into_iter()
+        .map(|hash| ByteBuf::from(hash.to_vec()))
+        .collect()
+}
+
+// Merkle tree of RFC 9162, leaves and nodes are hashed with a different prefix
+
+fn leaf_hash(username: &str, pub_key: &[u8]) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update([0u8]);
+    hasher.update((username.len() as u32).to_be_bytes());
+    hasher.update(username.as_bytes());
+    hasher.update(pub_key);
+    hasher.finalize().into()
+}
+
+fn node_hash(left: &Hash, right: &Hash) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update([1u8]);
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+/// Largest power of 2 smaller than n, the size of the left subtree
+fn split(n: usize) -> usize {
+    let mut k = 1;
+    while k * 2 < n {
+        k *= 2;
+    }
+    k
+}
+
+impl MerkleTree {
+    fn size(&self) -> usize {
+        self.levels.first().map_or(0, Vec::len)
+    }
+
+    /// Add a leaf, along with the subtrees it completes
+    fn push(&mut self, leaf: Hash) {
+        let mut hash = leaf;
+        let mut level = 0;
+
+        loop {
+            if self.levels.len() == level {
+                self.levels.push(Vec::new());
+            }
+
+            let nodes = &mut self.levels[level];
+            nodes.push(hash);
+            if nodes.len() % 2 == 1 {
+                break;
+            }
+
+            hash = node_hash(&nodes[nodes.len() - 2], &nodes[nodes.len() - 1]);
+            level += 1;
+        }
+    }
+
+    /// Root hash of the `n` leaves from `start`
+    ///
+    /// The left subtrees are complete, only the right edge of the tree is computed.
+    fn root_hash(&self, start: usize, n: usize) -> Hash {
+        match n {
+            0 => Sha256::digest([]).into(),
+            n if n.is_power_of_two() && start.is_multiple_of(n) => {
+                self.levels[n.trailing_zeros() as usize][start / n]
+            }
+            n => {
+                let k = split(n);
+                node_hash(&self.root_hash(start, k), &self.root_hash(start + k, n - k))
+            }
+        }
+    }
+}
+
+fn inclusion_path(tree: &MerkleTree, index: usize, start: usize, n: usize) -> Vec<Hash> {
+    if n <= 1 {
+        return Vec::new();
+    }
+
+    let k = split(n);
+    if index < k {
+        let mut path = inclusion_path(tree, index, start, k);
+        path.push(tree.root_hash(start + k, n - k));
+        path
+    } else {
+        let mut path = inclusion_path(tree, index - k, start + k, n - k);
+        path.push(tree.root_hash(start, k));
+        path
+    }
+}
+
+fn subproof(tree: &MerkleTree, m: usize, start: usize, n: usize, complete: bool) -> Vec<Hash> {
+    if m == n {
+        return if complete {
+            Vec::new()
+        } else {
+            vec![tree.root_hash(start, n)]
+        };
+    }
+
+    let k = split(n);
+    if m <= k {
+        let mut proof = subproof(tree, m, start, k, complete);
+        proof.push(tree.root_hash(start + k, n - k));
+        proof
+    } else {
+        let mut proof = subproof(tree, m - k, start + k, n - k, false);
+        proof.push(tree.root_hash(start, k));
+        proof
+    }
+}
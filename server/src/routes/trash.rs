@@ -0,0 +1,341 @@
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use axum::{extract::State, Extension};
+use deadpool_diesel::sqlite::Pool;
+use diesel::prelude::*;
+use hyper::StatusCode;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    blobs::{self, BlobStore},
+    codec::Msg,
+    db::{
+        schema::{files, keyrings, keys, users},
+        EntrySignature, Key, KeyWithFile, Keyring, NewKeyring, Permission, Session, SigningKeys,
+        UserWithKeyring,
+    },
+    log,
+    routes::{
+        files::{delete_key, delete_permanently, get_files_in_keyring, has_keyring_access},
+        signing,
+    },
+    AppState,
+};
+
+/// Days a deleted file stays in the trash when not configured
+pub const DEFAULT_TRASH_RETENTION_DAYS: i64 = 30;
+
+/// Interval between two purges of the expired trashed files
+const PURGE_INTERVAL: Duration = Duration::from_secs(3600);
+
+#[derive(Serialize)]
+pub struct TrashedFile {
+    /// Key of the file, encrypted with the user pubkey
+    key: KeyWithFile,
+    /// Time of the deletion, in millis
+    trashed_at: i64,
+    /// Keyring the file was deleted from
+    origin: Option<i32>,
+}
+
+/// Allow a user to list the files in his trash
+pub async fn get_trash(
+    Extension(user_session): Extension<Session>,
+    State(app_state): State<AppState>,
+) -> Msg<Vec<TrashedFile>> {
+    let conn = app_state.pool.get().await.unwrap();
+
+    let trash: Option<i32> = conn
+        .interact(|conn| {
+            users::table
+                .find(user_session.user)
+                .select(users::trash)
+                .first(conn)
+        })
+        .await
+        .unwrap()
+        .unwrap();
+
+    // Trash keyring is created on first delete
+    let Some(trash) = trash else {
+        return Msg(Vec::new());
+    };
+
+    let trashed_keys: HashMap<String, Key> = conn
+        .interact(move |conn| {
+            keys::table
+                .filter(keys::keyring_id.eq(trash))
+                .load::<Key>(conn)
+        })
+        .await
+        .unwrap()
+        .unwrap()
+        .into_iter()
+        .map(|key| (key.target.clone(), key))
+        .collect();
+
+    let files = get_files_in_keyring(&Keyring { id: trash }, &mut conn.lock().unwrap());
+
+    Msg(files
+        .into_iter()
+        .filter_map(|key| {
+            let trashed_key = trashed_keys.get(&key.file.id)?;
+
+            Some(TrashedFile {
+                trashed_at: trashed_key.trashed_at.unwrap_or(0),
+                origin: trashed_key.origin,
+                key,
+            })
+        })
+        .collect())
+}
+
+#[derive(Deserialize)]
+pub struct RestoreFileRequest {
+    /// Trashed file to restore
+    file_uid: String,
+    /// Keyring to restore the file in, the origin one or the user root keyring
+    keyring_id: i32,
+    /// Symmetric key of the file, encrypted for the destination keyring
+    #[serde(with = "serde_bytes")]
+    encrypted_key: Vec<u8>,
+    /// Signing private keys of the file, encrypted for the destination keyring
+    signing_keys: SigningKeys,
+    /// Signature of the entry by the user, when restored in root
+    #[serde(with = "serde_bytes")]
+    entry_signature: Option<Vec<u8>>,
+    /// Challenge of the destination folder signed with its write key
+    #[serde(with = "serde_bytes")]
+    signature: Option<Vec<u8>>,
+}
+
+/// Allow a user to restore a file from his trash
+///
+/// The key is moved back from the trash keyring to the given keyring. The client encrypts
+/// it again for this keyring, with the user pubkey for his root or with the folder key.
+pub async fn restore_file(
+    Extension(user_session): Extension<Session>,
+    State(app_state): State<AppState>,
+    Msg(restore_request): Msg<RestoreFileRequest>,
+) -> StatusCode {
+    let conn = app_state.pool.get().await.unwrap();
+
+    // Get user keyring informations
+    let user: UserWithKeyring = conn
+        .interact(|conn| {
+            users::table
+                .find(user_session.user)
+                .inner_join(keyrings::table)
+                .select((
+                    users::username,
+                    users::pub_key,
+                    users::priv_key,
+                    (keyrings::all_columns),
+                ))
+                .first::<UserWithKeyring>(conn)
+        })
+        .await
+        .unwrap()
+        .unwrap();
+
+    // Check if user has access to the destination keyring
+    if !has_keyring_access(
+        &user.keyring,
+        restore_request.keyring_id,
+        Permission::Write,
+        &mut conn.lock().unwrap(),
+    ) || !signing::verify_keyring_challenge(
+        &app_state.challenges,
+        &user.username,
+        &user.keyring,
+        restore_request.keyring_id,
+        Permission::Write,
+        restore_request.signature.as_deref(),
+        &mut conn.lock().unwrap(),
+    ) {
+        return StatusCode::FORBIDDEN;
+    }
+
+    let restored = conn
+        .interact(move |conn| {
+            conn.transaction(|conn| {
+                let trash: Option<i32> = users::table
+                    .find(&user.username)
+                    .select(users::trash)
+                    .first(conn)?;
+
+                let Some(trash) = trash else {
+                    return Ok(false);
+                };
+
+                diesel::update(
+                    keys::table
+                        .filter(keys::target.eq(&restore_request.file_uid))
+                        .filter(keys::keyring_id.eq(trash)),
+                )
+                .set((
+                    keys::keyring_id.eq(restore_request.keyring_id),
+                    keys::key.eq(restore_request.encrypted_key),
+                    keys::trashed_at.eq(None::<i64>),
+                    keys::origin.eq(None::<i32>),
+                    restore_request.signing_keys,
+                    EntrySignature::new(&user.username, restore_request.entry_signature),
+                ))
+                .execute(conn)
+                .map(|updated| updated > 0)
+            })
+        })
+        .await
+        .unwrap()
+        .unwrap();
+
+    if !restored {
+        return StatusCode::NOT_FOUND;
+    }
+
+    StatusCode::OK
+}
+
+/// Allow a user to empty his trash
+///
+/// Trashed files owned by the user are deleted for good, for every user they are shared
+/// with. Other files are only removed from his trash.
+pub async fn empty_trash(
+    Extension(user_session): Extension<Session>,
+    State(app_state): State<AppState>,
+) -> StatusCode {
+    let conn = app_state.pool.get().await.unwrap();
+
+    let reclaimed_blobs = conn
+        .interact(move |conn| {
+            conn.transaction(|conn| {
+                let trash: Option<i32> = users::table
+                    .find(&user_session.user)
+                    .select(users::trash)
+                    .first(conn)?;
+
+                let trashed: Vec<(Key, String)> = keys::table
+                    .filter(keys::keyring_id.nullable().eq(trash))
+                    .load::<Key>(conn)?
+                    .into_iter()
+                    .map(|key| (key, user_session.user.clone()))
+                    .collect();
+
+                delete_trashed(&trashed, conn)
+            })
+        })
+        .await
+        .unwrap()
+        .unwrap();
+
+    // Contents are removed once the transaction is committed
+    blobs::delete_blobs(&app_state.blob_store, reclaimed_blobs).await;
+
+    StatusCode::OK
+}
+
+/// Get the trash keyring of a user, creating it if he has none yet
+pub fn get_or_create_trash(user: &str, conn: &mut SqliteConnection) -> QueryResult<i32> {
+    let trash: Option<i32> = users::table.find(user).select(users::trash).first(conn)?;
+
+    if let Some(trash) = trash {
+        return Ok(trash);
+    }
+
+    let trash: Keyring = diesel::insert_into(keyrings::table)
+        .values(NewKeyring { id: None })
+        .get_result(conn)?;
+
+    diesel::update(users::table.find(user))
+        .set(users::trash.eq(trash.id))
+        .execute(conn)?;
+
+    Ok(trash.id)
+}
+
+/// Delete for good the files trashed more than `retention_days` ago
+///
+/// Return the number of purged files.
+pub async fn purge_expired(
+    pool: &Pool,
+    blob_store: &Arc<dyn BlobStore>,
+    retention_days: i64,
+) -> usize {
+    let conn = pool.get().await.unwrap();
+
+    let expiration = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as i64
+        - retention_days * 24 * 3600 * 1000;
+
+    let (purged, reclaimed_blobs) = conn
+        .interact(move |conn| {
+            conn.transaction(|conn| {
+                // Trashed keys along with the user owning the trash
+                let expired: Vec<(Key, String)> = keys::table
+                    .inner_join(users::table.on(users::trash.eq(keys::keyring_id.nullable())))
+                    .filter(keys::trashed_at.lt(expiration))
+                    .select((keys::all_columns, users::username))
+                    .load(conn)?;
+
+                let reclaimed_blobs = delete_trashed(&expired, conn)?;
+
+                diesel::result::QueryResult::Ok((expired.len(), reclaimed_blobs))
+            })
+        })
+        .await
+        .unwrap()
+        .unwrap();
+
+    blobs::delete_blobs(blob_store, reclaimed_blobs).await;
+
+    purged
+}
+
+/// Purge the expired trashed files every hour
+pub fn spawn_purge(pool: Pool, blob_store: Arc<dyn BlobStore>, retention_days: i64) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(PURGE_INTERVAL);
+
+        loop {
+            interval.tick().await;
+
+            let purged = purge_expired(&pool, &blob_store, retention_days).await;
+            if purged > 0 {
+                log::info(&format!("Purged {} files from the trash", purged));
+            }
+        }
+    });
+}
+
+/// Delete trashed keys, along with the user owning the trash
+///
+/// A file is deleted for everyone when the user is its owner, otherwise only his key
+/// is removed and the file is reclaimed if nobody else has a key to it.
+fn delete_trashed(
+    trashed: &[(Key, String)],
+    conn: &mut SqliteConnection,
+) -> QueryResult<Vec<String>> {
+    let mut reclaimed_blobs = Vec::new();
+
+    for (key, user) in trashed {
+        let owner: Option<Option<String>> = files::table
+            .find(&key.target)
+            .select(files::owner)
+            .first(conn)
+            .optional()?;
+
+        if owner.flatten().as_ref() == Some(user) {
+            reclaimed_blobs.extend(delete_permanently(&key.target, conn)?);
+        } else {
+            reclaimed_blobs.extend(delete_key(key, conn)?);
+        }
+    }
+
+    Ok(reclaimed_blobs)
+}
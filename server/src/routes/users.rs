@@ -0,0 +1,114 @@
+use axum::{
+    extract::{Query, State},
+    Extension,
+};
+use diesel::{prelude::*, query_dsl::positional_order_dsl::PositionalOrderDsl, sql_types::Bool};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    codec::Msg,
+    db::{
+        schema::{groups, users},
+        Session,
+    },
+    AppState,
+};
+
+/// Users and groups listed by page
+const USERS_PAGE_SIZE: usize = 20;
+
+#[derive(Deserialize)]
+pub struct UsersQuery {
+    /// Start of the names to list, all of them if omitted
+    prefix: Option<String>,
+    /// Page to list, from 0
+    page: Option<usize>,
+}
+
+#[derive(Serialize)]
+pub struct UsersPage {
+    users: Vec<DirectoryEntry>,
+    /// Whether there are more users on the next pages
+    has_more: bool,
+}
+
+#[derive(Serialize)]
+pub struct DirectoryEntry {
+    username: String,
+    /// Groups are shared with like users
+    group: bool,
+    /// Fingerprint of the public key, to compare with the one the user gives
+    fingerprint: String,
+}
+
+/// Allow a user to list the users and groups he can share with, searching them by prefix
+///
+/// The list of users is not secret, it helps to find the name of the users to share with.
+pub async fn search_users(
+    Extension(_user_session): Extension<Session>,
+    State(app_state): State<AppState>,
+    Query(query): Query<UsersQuery>,
+) -> Msg<UsersPage> {
+    let conn = app_state.pool.get().await.unwrap();
+
+    // `%` and `_` are wildcards of LIKE, they are escaped to match the prefix as is
+    let pattern = format!(
+        "{}%",
+        query
+            .prefix
+            .unwrap_or_default()
+            .replace('\\', "\\\\")
+            .replace('%', "\\%")
+            .replace('_', "\\_")
+    );
+
+    let start = query.page.unwrap_or(0).saturating_mul(USERS_PAGE_SIZE);
+    let offset = i64::try_from(start).unwrap_or(i64::MAX);
+
+    // One more entry than the page is loaded to know if there are more
+    let entries: Vec<(String, bool, Vec<u8>)> = conn
+        .interact(move |conn| {
+            users::table
+                .filter(users::username.like(&pattern).escape('\\'))
+                .select((users::username, false.into_sql::<Bool>(), users::pub_key))
+                .union_all(
+                    groups::table
+                        .filter(groups::name.like(&pattern).escape('\\'))
+                        .select((groups::name, true.into_sql::<Bool>(), groups::pub_key)),
+                )
+                .positional_order_by(1)
+                .limit(USERS_PAGE_SIZE as i64 + 1)
+                .offset(offset)
+                .load(conn)
+        })
+        .await
+        .unwrap()
+        .unwrap();
+
+    let has_more = entries.len() > USERS_PAGE_SIZE;
+
+    let users = entries
+        .into_iter()
+        .take(USERS_PAGE_SIZE)
+        .map(|(username, group, pub_key)| DirectoryEntry {
+            username,
+            group,
+            fingerprint: fingerprint(&pub_key),
+        })
+        .collect();
+
+    Msg(UsersPage { users, has_more })
+}
+
+/// Fingerprint of a public key, the same as the one shown by the clients: the SHA-256 of
+/// the key in groups of 4 hex digits
+fn fingerprint(pub_key: &[u8]) -> String {
+    let digest = Sha256::digest(pub_key);
+
+    digest
+        .chunks(2)
+        .map(|group| format!("{:02X}{:02X}", group[0], group[1]))
+        .collect::<Vec<String>>()
+        .join(" ")
+}
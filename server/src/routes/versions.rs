@@ -0,0 +1,315 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use axum::{
+    extract::{Path, State},
+    Extension,
+};
+use diesel::{dsl::max, prelude::*};
+use hyper::StatusCode;
+use serde::Deserialize;
+
+use crate::{
+    blobs,
+    codec::Msg,
+    db::{
+        schema::{chunks, file_versions, files, keyrings, users},
+        File, FileVersion, Permission, Session, UserWithKeyring,
+    },
+    log,
+    routes::{files::has_access, signing},
+    AppState,
+};
+
+/// Number of previous versions kept per file when not configured
+pub const DEFAULT_VERSION_RETENTION: i64 = 10;
+
+/// Allow a user to list the previous versions of a file, newest first
+pub async fn list_versions(
+    Extension(user_session): Extension<Session>,
+    State(app_state): State<AppState>,
+    Path(file_uid): Path<String>,
+) -> Result<Msg<Vec<FileVersion>>, StatusCode> {
+    let conn = app_state.pool.get().await.unwrap();
+
+    // Get user keyring informations
+    let user: UserWithKeyring = conn
+        .interact(|conn| {
+            users::table
+                .find(user_session.user)
+                .inner_join(keyrings::table)
+                .select((
+                    users::username,
+                    users::pub_key,
+                    users::priv_key,
+                    (keyrings::all_columns),
+                ))
+                .first::<UserWithKeyring>(conn)
+        })
+        .await
+        .unwrap()
+        .unwrap();
+
+    // Check if user has access to the file
+    if !has_access(
+        &user.keyring,
+        file_uid.clone(),
+        Permission::Read,
+        &mut conn.lock().unwrap(),
+    ) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let versions = conn
+        .interact(move |conn| {
+            file_versions::table
+                .filter(file_versions::file_id.eq(file_uid))
+                .order(file_versions::version.desc())
+                .load::<FileVersion>(conn)
+        })
+        .await
+        .unwrap()
+        .unwrap();
+
+    Ok(Msg(versions))
+}
+
+/// Allow a user to download an encrypted chunk of a previous version of a file
+pub async fn download_version_chunk(
+    Extension(user_session): Extension<Session>,
+    State(app_state): State<AppState>,
+    Path((file_uid, version, index)): Path<(String, i32, i32)>,
+) -> Result<Vec<u8>, StatusCode> {
+    let conn = app_state.pool.get().await.unwrap();
+
+    // Get user keyring informations
+    let user: UserWithKeyring = conn
+        .interact(|conn| {
+            users::table
+                .find(user_session.user)
+                .inner_join(keyrings::table)
+                .select((
+                    users::username,
+                    users::pub_key,
+                    users::priv_key,
+                    (keyrings::all_columns),
+                ))
+                .first::<UserWithKeyring>(conn)
+        })
+        .await
+        .unwrap()
+        .unwrap();
+
+    // Check if user has access to the file
+    if !has_access(
+        &user.keyring,
+        file_uid.clone(),
+        Permission::Read,
+        &mut conn.lock().unwrap(),
+    ) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let blob: Option<String> = conn
+        .interact(move |conn| {
+            file_versions::table
+                .find((file_uid, version))
+                .select(file_versions::blob)
+                .first(conn)
+                .optional()
+        })
+        .await
+        .unwrap()
+        .unwrap();
+
+    let Some(blob) = blob else {
+        return Err(StatusCode::NOT_FOUND);
+    };
+
+    match app_state.blob_store.get_chunk(&blob, index).await {
+        Ok(Some(chunk)) => Ok(chunk.to_vec()),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+
+        Err(e) => {
+            log::error(&format!("Can't get chunk of blob {}: {}", blob, e));
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct RestoreVersionRequest {
+    /// Challenge of the file signed with its write key
+    #[serde(with = "serde_bytes")]
+    signature: Option<Vec<u8>>,
+}
+
+/// Allow a user to restore a previous version of a file
+///
+/// The restored version becomes the current content, and the current content is kept
+/// as the newest version. Nothing is re-encrypted as all versions share the file key.
+pub async fn restore_version(
+    Extension(user_session): Extension<Session>,
+    State(app_state): State<AppState>,
+    Path((file_uid, version)): Path<(String, i32)>,
+    Msg(restore_request): Msg<RestoreVersionRequest>,
+) -> StatusCode {
+    let conn = app_state.pool.get().await.unwrap();
+
+    // Get user keyring informations
+    let user: UserWithKeyring = conn
+        .interact(|conn| {
+            users::table
+                .find(user_session.user)
+                .inner_join(keyrings::table)
+                .select((
+                    users::username,
+                    users::pub_key,
+                    users::priv_key,
+                    (keyrings::all_columns),
+                ))
+                .first::<UserWithKeyring>(conn)
+        })
+        .await
+        .unwrap()
+        .unwrap();
+
+    // Check if user has access to the file
+    if !has_access(
+        &user.keyring,
+        file_uid.clone(),
+        Permission::Write,
+        &mut conn.lock().unwrap(),
+    ) || !signing::verify_challenge(
+        &app_state.challenges,
+        &user.username,
+        &file_uid,
+        Permission::Write,
+        restore_request.signature.as_deref(),
+        &mut conn.lock().unwrap(),
+    ) {
+        return StatusCode::FORBIDDEN;
+    }
+
+    let retention = app_state.version_retention;
+
+    let pruned_blobs = conn
+        .interact(move |conn| {
+            conn.transaction(|conn| {
+                let file: File = files::table.find(&file_uid).first(conn)?;
+                let restored: Option<FileVersion> = file_versions::table
+                    .find((&file_uid, version))
+                    .first(conn)
+                    .optional()?;
+
+                let Some(restored) = restored else {
+                    return Ok(None);
+                };
+
+                archive_content(&file, conn)?;
+                diesel::delete(file_versions::table.find((&file_uid, version))).execute(conn)?;
+
+                diesel::update(files::table.find(&file_uid))
+                    .set((
+                        files::sz.eq(restored.sz),
+                        files::blob.eq(restored.blob),
+                        files::chunks.eq(restored.chunks),
+                        files::mtime.eq(SystemTime::now()
+                            .duration_since(UNIX_EPOCH)
+                            .unwrap()
+                            .as_millis() as i64),
+                    ))
+                    .execute(conn)?;
+
+                prune_versions(&file_uid, retention, conn).map(Some)
+            })
+        })
+        .await
+        .unwrap()
+        .unwrap();
+
+    let Some(pruned_blobs) = pruned_blobs else {
+        return StatusCode::NOT_FOUND;
+    };
+
+    blobs::delete_blobs(&app_state.blob_store, pruned_blobs).await;
+
+    StatusCode::OK
+}
+
+/// Keep the current content of a file as its newest version
+///
+/// The file content must be replaced afterwards, the blob now belongs to the version.
+pub fn archive_content(file: &File, conn: &mut SqliteConnection) -> QueryResult<()> {
+    // Folders have no content
+    let (Some(sz), Some(blob), Some(chunks)) = (file.sz, file.blob.clone(), file.chunks) else {
+        return Ok(());
+    };
+
+    let last_version: Option<i32> = file_versions::table
+        .filter(file_versions::file_id.eq(&file.id))
+        .select(max(file_versions::version))
+        .first(conn)?;
+
+    diesel::insert_into(file_versions::table)
+        .values(FileVersion {
+            file_id: file.id.clone(),
+            version: last_version.unwrap_or(0) + 1,
+            mtime: file.mtime,
+            sz,
+            blob,
+            chunks,
+        })
+        .execute(conn)?;
+
+    Ok(())
+}
+
+/// Delete the versions of a file beyond the `retention` newest ones
+///
+/// Return the blobs of the deleted versions, to remove from the blob store
+/// once the transaction is committed.
+pub fn prune_versions(
+    file_uid: &str,
+    retention: i64,
+    conn: &mut SqliteConnection,
+) -> QueryResult<Vec<String>> {
+    let expired: Vec<FileVersion> = file_versions::table
+        .filter(file_versions::file_id.eq(file_uid))
+        .order(file_versions::version.desc())
+        .offset(retention)
+        .load(conn)?;
+
+    delete_versions(&expired, conn)
+}
+
+/// Delete all versions of a file
+///
+/// Return the blobs of the deleted versions, to remove from the blob store
+/// once the transaction is committed.
+pub fn delete_all_versions(
+    file_uid: &str,
+    conn: &mut SqliteConnection,
+) -> QueryResult<Vec<String>> {
+    let versions: Vec<FileVersion> = file_versions::table
+        .filter(file_versions::file_id.eq(file_uid))
+        .load(conn)?;
+
+    delete_versions(&versions, conn)
+}
+
+fn delete_versions(
+    versions: &[FileVersion],
+    conn: &mut SqliteConnection,
+) -> QueryResult<Vec<String>> {
+    let mut blobs = Vec::new();
+
+    for version in versions {
+        diesel::delete(file_versions::table.find((&version.file_id, version.version)))
+            .execute(conn)?;
+        diesel::delete(chunks::table.filter(chunks::blob.eq(&version.blob))).execute(conn)?;
+
+        blobs.push(version.blob.clone());
+    }
+
+    Ok(blobs)
+}